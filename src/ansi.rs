@@ -0,0 +1,134 @@
+use std::borrow::Cow;
+
+const ESC: char = '\x1b';
+const BEL: char = '\x07';
+// The 8-bit forms of CSI, OSC and ST.
+const C1_CSI: char = '\u{9b}';
+const C1_OSC: char = '\u{9d}';
+const C1_ST: char = '\u{9c}';
+
+#[derive(Clone, Copy, PartialEq)]
+enum State {
+    Text,
+    Escape,
+    // An escape sequence with intermediate bytes, such as a character set selection.
+    EscapeIntermediate,
+    Csi,
+    // OSC sequences, such as hyperlinks and window titles, and the other string sequences (DCS,
+    // SOS, PM and APC), up to BEL or ST.
+    String,
+    StringEscape,
+}
+
+/// Removes ANSI escape sequences from `text`: CSI sequences such as colors and cursor movements,
+/// OSC sequences such as hyperlinks and window titles (the text they wrap is kept), and the other
+/// escape sequences. A sequence that is cut off at the end is removed too. Returns `text` as is
+/// if it has none.
+pub fn strip_ansi(text: &str) -> Cow<'_, str> {
+    let Some(start) = text.find([ESC, C1_CSI, C1_OSC]) else {
+        return Cow::Borrowed(text);
+    };
+    let mut plain = String::with_capacity(text.len());
+    plain.push_str(&text[..start]);
+    let mut state = State::Text;
+    for c in text[start..].chars() {
+        state = match (state, c) {
+            (State::Text, ESC) => State::Escape,
+            (State::Text, C1_CSI) => State::Csi,
+            (State::Text, C1_OSC) => State::String,
+            (State::Text, c) => {
+                plain.push(c);
+                State::Text
+            }
+            (State::Escape | State::StringEscape, '[') => State::Csi,
+            (State::Escape | State::StringEscape, ']' | 'P' | 'X' | '^' | '_') => State::String,
+            (State::StringEscape, '\\') => State::Text,
+            (State::Escape | State::StringEscape, ESC) => State::Escape,
+            (State::Escape | State::StringEscape | State::EscapeIntermediate, '\x20'..='\x2f') => {
+                State::EscapeIntermediate
+            }
+            (State::Escape | State::StringEscape | State::EscapeIntermediate, '\x30'..='\x7e') => {
+                State::Text
+            }
+            // Not an escape sequence after all.
+            (State::Escape | State::StringEscape | State::EscapeIntermediate, c) => {
+                plain.push(c);
+                State::Text
+            }
+            (State::Csi, '\x40'..='\x7e') => State::Text,
+            (State::Csi, ESC) => State::Escape,
+            (State::Csi, _) => State::Csi,
+            (State::String, BEL | C1_ST) => State::Text,
+            (State::String, ESC) => State::StringEscape,
+            (State::String, _) => State::String,
+        };
+    }
+    Cow::Owned(plain)
+}
+
+pub(crate) fn strip_ansi_cow(text: Cow<'_, str>) -> Cow<'_, str> {
+    match text {
+        Cow::Borrowed(text) => strip_ansi(text),
+        Cow::Owned(text) => match strip_ansi(&text) {
+            Cow::Borrowed(_) => Cow::Owned(text),
+            Cow::Owned(plain) => Cow::Owned(plain),
+        },
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{shell, CommandExt};
+
+    #[test]
+    fn plain_text_is_borrowed() {
+        assert!(matches!(strip_ansi("no escapes here"), Cow::Borrowed("no escapes here")));
+        assert!(matches!(strip_ansi_cow(Cow::Owned("plain".to_owned())), Cow::Owned(_)));
+    }
+
+    #[test]
+    fn csi_sequences() {
+        assert_eq!(strip_ansi("\x1b[32mok\x1b[0m \x1b[1;31mfail\x1b[m"), "ok fail");
+        assert_eq!(strip_ansi("\x1b[2K\x1b[1Gprogress\x1b[?25h"), "progress");
+        assert_eq!(strip_ansi("\u{9b}33mC1\u{9b}0m"), "C1");
+        // An escape inside a CSI sequence starts a new sequence.
+        assert_eq!(strip_ansi("\x1b[3\x1b[1mnested\x1b[0m"), "nested");
+    }
+
+    #[test]
+    fn osc_hyperlinks_keep_their_text() {
+        let link = "see \x1b]8;;https://example.com/a\x1b\\the docs\x1b]8;;\x1b\\ now";
+        assert_eq!(strip_ansi(link), "see the docs now");
+        assert_eq!(strip_ansi("\x1b]0;window title\x07text"), "text");
+        assert_eq!(strip_ansi("\u{9d}8;;url\u{9c}link\u{9d}8;;\u{9c}"), "link");
+        let styled = "\x1b]8;;file:///x\x07\x1b[4m\x1b[34mx\x1b[0m\x1b]8;;\x07";
+        assert_eq!(strip_ansi(styled), "x");
+    }
+
+    #[test]
+    fn cut_off_sequences_are_removed() {
+        assert_eq!(strip_ansi("done\x1b"), "done");
+        assert_eq!(strip_ansi("done\x1b[1;3"), "done");
+        assert_eq!(strip_ansi("done\x1b]8;;https://exam"), "done");
+        assert_eq!(strip_ansi("done\x1b]8;;url\x1b"), "done");
+        // Other escape sequences, and an escape that doesn't start one.
+        assert_eq!(strip_ansi("\x1b(Bcharset\x1b7saved\x1b8"), "charsetsaved");
+        assert_eq!(strip_ansi("\x1b\u{e9}t\u{e9}"), "\u{e9}t\u{e9}");
+    }
+
+    #[test]
+    fn captured_output_keeps_its_escapes() {
+        let out = shell(r"printf '\033[32mgreen\033[0m'; printf '\033[31mred\033[0m' >&2")
+            .exec_stdout_string()
+            .unwrap();
+        assert_eq!(out.stdout, "\x1b[32mgreen\x1b[0m");
+        assert_eq!((out.stdout_plain(), out.stderr_plain()), ("green".into(), "red".into()));
+        let err = shell(r"printf '\033[31merror: boom\033[0m\n' >&2; exit 1")
+            .exec_stdout_string()
+            .err()
+            .unwrap();
+        let message = format!("{:#}", err);
+        assert!(message.contains("error: boom") && !message.contains("\x1b"), "{}", message);
+    }
+}
@@ -0,0 +1,282 @@
+use std::io::{self, Write};
+use std::ops::Range;
+
+use termcolor::{Buffer, ColorSpec};
+
+use crate::{color, theme, CommandExt, Output, StatusSummary, TermColorStandardStreamExt};
+
+// Lines of the expected and actual stdout shown before the first difference.
+const DIFF_CONTEXT_LINES: usize = 2;
+
+enum Expectation<'a> {
+    StdoutContains(&'a str),
+    StdoutEq(&'a str),
+    StderrEmpty,
+    ExitCode(i32),
+}
+
+// The `assert_*` methods panic with the command, the expectation, and the actual output, with
+// the mismatch highlighted when stderr takes colors. The `check_*` methods return the same
+// message, uncolored, as an error.
+impl Output {
+    #[track_caller]
+    pub fn assert_stdout_contains(&self, needle: &str) {
+        self.assert(Expectation::StdoutContains(needle));
+    }
+
+    /// Trailing newlines are ignored on both sides.
+    #[track_caller]
+    pub fn assert_stdout_eq(&self, expected: &str) {
+        self.assert(Expectation::StdoutEq(expected));
+    }
+
+    #[track_caller]
+    pub fn assert_stderr_empty(&self) {
+        self.assert(Expectation::StderrEmpty);
+    }
+
+    #[track_caller]
+    pub fn assert_exit_code(&self, code: i32) {
+        self.assert(Expectation::ExitCode(code));
+    }
+
+    pub fn check_stdout_contains(&self, needle: &str) -> anyhow::Result<()> {
+        self.check(Expectation::StdoutContains(needle))
+    }
+
+    pub fn check_stdout_eq(&self, expected: &str) -> anyhow::Result<()> {
+        self.check(Expectation::StdoutEq(expected))
+    }
+
+    pub fn check_stderr_empty(&self) -> anyhow::Result<()> {
+        self.check(Expectation::StderrEmpty)
+    }
+
+    pub fn check_exit_code(&self, code: i32) -> anyhow::Result<()> {
+        self.check(Expectation::ExitCode(code))
+    }
+
+    #[track_caller]
+    fn assert(&self, expectation: Expectation) {
+        if self.meets(&expectation) {
+            return;
+        }
+        let mut message = color::stderr_buffer();
+        self.write_failure(&mut message, &expectation).unwrap();
+        panic!("{}", String::from_utf8_lossy(message.as_slice()));
+    }
+
+    fn check(&self, expectation: Expectation) -> anyhow::Result<()> {
+        if self.meets(&expectation) {
+            return Ok(());
+        }
+        let mut message = Buffer::no_color();
+        self.write_failure(&mut message, &expectation).unwrap();
+        anyhow::bail!("{}", String::from_utf8_lossy(message.as_slice()))
+    }
+
+    fn meets(&self, expectation: &Expectation) -> bool {
+        match *expectation {
+            Expectation::StdoutContains(needle) => self.stdout.contains(needle),
+            Expectation::StdoutEq(expected) => {
+                trim_newlines(&self.stdout) == trim_newlines(expected)
+            }
+            Expectation::StderrEmpty => self.stderr.is_empty(),
+            Expectation::ExitCode(code) => self.status.code() == Some(code),
+        }
+    }
+
+    fn write_failure(&self, out: &mut Buffer, expectation: &Expectation) -> io::Result<()> {
+        let theme = theme::theme();
+        out.with_color(&theme.failure_text, |out| match *expectation {
+            Expectation::StdoutContains(needle) => {
+                write!(out, "Expected stdout to contain {:?}", needle)
+            }
+            Expectation::StdoutEq(_) => write!(out, "Expected stdout to equal the expected text"),
+            Expectation::StderrEmpty => write!(out, "Expected stderr to be empty"),
+            Expectation::ExitCode(code) => {
+                write!(out, "Expected exit code {}, got {}", code, StatusSummary(self.status))
+            }
+        })?;
+        writeln!(out, "\ncommand: {}", self.command.description())?;
+        let stderr = String::from_utf8_lossy(&self.stderr);
+        match *expectation {
+            Expectation::StdoutContains(needle) => {
+                // The longest prefix of the needle that does occur, and where it stops matching.
+                let (start, matched) = longest_prefix_match(&self.stdout, needle);
+                let rest = &self.stdout[start + matched..];
+                let len = rest
+                    .char_indices()
+                    .nth(needle[matched..].chars().count())
+                    .map_or(rest.len(), |(i, _)| i);
+                let mismatch = start + matched..start + matched + len;
+                if matched > 0 {
+                    writeln!(out, "longest match: {:?}", &needle[..matched])?;
+                }
+                write_section(out, "stdout", &self.stdout, Some(mismatch).filter(|_| matched > 0))
+            }
+            Expectation::StdoutEq(expected) => write_diff(out, expected, &self.stdout),
+            Expectation::StderrEmpty => {
+                write_section(out, "stderr", &stderr, Some(0..stderr.len()))
+            }
+            Expectation::ExitCode(_) => {
+                write_section(out, "stdout", &self.stdout, None)?;
+                write_section(out, "stderr", &stderr, None)
+            }
+        }
+    }
+}
+
+fn trim_newlines(s: &str) -> &str {
+    s.trim_end_matches(['\r', '\n'])
+}
+
+// The start in `haystack` and the length of the longest prefix of `needle` found in it.
+fn longest_prefix_match(haystack: &str, needle: &str) -> (usize, usize) {
+    let mut ends: Vec<usize> = needle.char_indices().map(|(i, _)| i).skip(1).collect();
+    ends.push(needle.len());
+    ends.into_iter()
+        .rev()
+        .find_map(|end| haystack.find(&needle[..end]).map(|start| (start, end)))
+        .unwrap_or((0, 0))
+}
+
+fn write_section(
+    out: &mut Buffer,
+    name: &str,
+    text: &str,
+    highlight: Option<Range<usize>>,
+) -> io::Result<()> {
+    if text.is_empty() {
+        return writeln!(out, "{}: (empty)", name);
+    }
+    writeln!(out, "{}:", name)?;
+    match highlight {
+        Some(range) => {
+            let spec = theme::theme().failure_text;
+            let text = text.as_bytes();
+            out.write_all(&text[..range.start])?;
+            out.with_color(&spec, |out| out.write_all(&text[range.clone()]))?;
+            out.write_all(&text[range.end..])?;
+        }
+        None => out.write_all(text.as_bytes())?,
+    }
+    if !text.ends_with('\n') {
+        writeln!(out)?;
+    }
+    Ok(())
+}
+
+// Shows the lines leading up to the first line that differs, that line as expected and as
+// actually written, and a caret under the first differing character.
+fn write_diff(out: &mut Buffer, expected: &str, actual: &str) -> io::Result<()> {
+    let theme = theme::theme();
+    let expected: Vec<&str> = trim_newlines(expected).lines().collect();
+    let actual: Vec<&str> = trim_newlines(actual).lines().collect();
+    let line = expected.iter().zip(&actual).take_while(|(e, a)| e == a).count();
+    writeln!(
+        out,
+        "first difference at line {} of {} expected, {} actual (- expected, + actual):",
+        line + 1,
+        expected.len(),
+        actual.len(),
+    )?;
+    for context in &actual[line.saturating_sub(DIFF_CONTEXT_LINES)..line] {
+        writeln!(out, "    {}", context)?;
+    }
+    write_diff_line(out, &theme.failure_text, '-', expected.get(line).copied())?;
+    write_diff_line(out, &theme.success_text, '+', actual.get(line).copied())?;
+    if let (Some(e), Some(a)) = (expected.get(line), actual.get(line)) {
+        let column = e.chars().zip(a.chars()).take_while(|(e, a)| e == a).count();
+        writeln!(out, "    {:column$}^", "")?;
+    }
+    Ok(())
+}
+
+fn write_diff_line(
+    out: &mut Buffer,
+    spec: &ColorSpec,
+    sign: char,
+    line: Option<&str>,
+) -> io::Result<()> {
+    out.with_color(spec, |out| match line {
+        Some(line) => write!(out, "  {} {}", sign, line),
+        None => write!(out, "  {} (no more lines)", sign),
+    })?;
+    writeln!(out)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::ExitStatus;
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{cmd, shell};
+
+    fn output(stdout: &str, stderr: &str) -> Output {
+        let status = ExitStatus::default();
+        Output::new(cmd("tool"), status, stdout.to_owned(), stderr.into(), Duration::ZERO)
+    }
+
+    fn failure(out: &Output, expectation: Expectation) -> String {
+        let message = out.check(expectation).unwrap_err().to_string();
+        assert!(message.contains("\ncommand: program = \"tool\""), "{}", message);
+        message
+    }
+
+    #[test]
+    fn passing_checks() {
+        let out = output("one\ntwo\n\n", "");
+        out.assert_stdout_contains("e\ntw");
+        out.assert_stdout_eq("one\ntwo");
+        out.assert_stderr_empty();
+        out.assert_exit_code(0);
+        out.check_stdout_eq("one\ntwo\n").unwrap();
+    }
+
+    #[test]
+    fn stdout_eq_shows_the_first_difference() {
+        let out = output("a\nb\nc\nd\nxyz\n", "");
+        let message = failure(&out, Expectation::StdoutEq("a\nb\nc\nd\nxYz\nmore"));
+        let diff =
+            "first difference at line 5 of 6 expected, 5 actual (- expected, + actual):\n    \
+                    c\n    d\n  - xYz\n  + xyz\n     ^\n";
+        assert!(message.ends_with(diff), "{}", message);
+        let message = failure(&output("a\n", ""), Expectation::StdoutEq("a\nb"));
+        assert!(message.ends_with("  - b\n  + (no more lines)\n"), "{}", message);
+    }
+
+    #[test]
+    fn stdout_contains_shows_the_longest_match() {
+        let out = output("hello world\n", "");
+        let message = failure(&out, Expectation::StdoutContains("worms"));
+        assert!(message.starts_with("Expected stdout to contain \"worms\""), "{}", message);
+        assert!(message.ends_with("longest match: \"wor\"\nstdout:\nhello world\n"), "{}", message);
+        // Under the default theme, which other tests may swap out.
+        let _serial = crate::test_support::serial();
+        let mut colored = Buffer::ansi();
+        out.write_failure(&mut colored, &Expectation::StdoutContains("worms")).unwrap();
+        let colored = String::from_utf8(colored.into_inner()).unwrap();
+        assert!(colored.ends_with("hello wor\x1b[0m\x1b[31mld\x1b[0m\n"), "{:?}", colored);
+        let message = failure(&out, Expectation::StdoutContains("q"));
+        assert!(!message.contains("longest match"), "{}", message);
+    }
+
+    #[test]
+    fn stderr_and_exit_code() {
+        let message = failure(&output("", "oops\n"), Expectation::StderrEmpty);
+        assert!(message.ends_with("stderr:\noops\n"), "{}", message);
+        let status = shell("exit 2").status().unwrap();
+        let out = Output::new(cmd("tool"), status, "out".into(), Vec::new(), Duration::ZERO);
+        let message = failure(&out, Expectation::ExitCode(0));
+        assert!(message.starts_with("Expected exit code 0, got exit code 2\n"), "{}", message);
+        assert!(message.ends_with("stdout:\nout\nstderr: (empty)\n"), "{}", message);
+    }
+
+    #[test]
+    #[should_panic(expected = "Expected stderr to be empty")]
+    fn assertions_panic() {
+        output("", "oops").assert_stderr_empty();
+    }
+}
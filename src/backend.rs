@@ -0,0 +1,208 @@
+use std::io::{self, Read, Write};
+use std::process::{Child, Command, ExitStatus, Stdio};
+
+#[cfg(feature = "mock")]
+use crate::mock::{self, MockProcess};
+use crate::{group, ConfiguredCommand};
+
+// A spawned command. Every method goes through `spawn`, `status`, or `output` here instead of the
+// `Command` methods of the same names, so that with the `mock` feature a `MockRunner` can stand
+// in for real processes.
+pub(crate) struct Process {
+    inner: Inner,
+    // Whether the child leads its own process group, which is then signalled as a whole.
+    #[cfg_attr(not(unix), allow(dead_code))]
+    group: bool,
+}
+
+enum Inner {
+    Real(Child),
+    #[cfg(feature = "mock")]
+    Mock(MockProcess),
+}
+
+impl From<Child> for Process {
+    fn from(child: Child) -> Self {
+        Process { inner: Inner::Real(child), group: false }
+    }
+}
+
+pub(crate) fn spawn(cmd: &mut ConfiguredCommand) -> io::Result<Process> {
+    #[cfg(feature = "mock")]
+    if let Some(process) = mock::spawn(cmd) {
+        return Ok(Process { inner: Inner::Mock(process), group: false });
+    }
+    let group = group::is_set(cmd);
+    cmd.spawn().map(|child| Process { inner: Inner::Real(child), group })
+}
+
+// Like `Command::status`. A mocked command writes its scripted output to this process's stdout
+// and stderr, as a real child inheriting them would.
+pub(crate) fn status(cmd: &mut Command) -> io::Result<ExitStatus> {
+    #[cfg(feature = "mock")]
+    if let Some(mut process) = mock::spawn(cmd) {
+        if let Some(mut stdout) = process.take_stdout() {
+            io::copy(&mut stdout, &mut io::stdout())?;
+        }
+        if let Some(mut stderr) = process.take_stderr() {
+            io::copy(&mut stderr, &mut io::stderr())?;
+        }
+        return Ok(process.wait());
+    }
+    cmd.status()
+}
+
+// Like `Command::output` with stdout going to `file` and stderr piped. A mocked command writes
+// its scripted stdout to `file`, as a real child would.
+pub(crate) fn output_to_file(
+    cmd: &mut Command,
+    file: std::fs::File,
+) -> io::Result<std::process::Output> {
+    #[cfg(feature = "mock")]
+    if let Some(mut process) = mock::spawn(cmd) {
+        let mut stderr = Vec::new();
+        if let Some(mut pipe) = process.take_stdout() {
+            io::copy(&mut pipe, &mut &file)?;
+        }
+        if let Some(mut pipe) = process.take_stderr() {
+            pipe.read_to_end(&mut stderr)?;
+        }
+        return Ok(std::process::Output { status: process.wait(), stdout: Vec::new(), stderr });
+    }
+    cmd.stdout(Stdio::from(file)).stderr(Stdio::piped()).output()
+}
+
+impl Process {
+    pub(crate) fn id(&self) -> u32 {
+        match &self.inner {
+            Inner::Real(child) => child.id(),
+            #[cfg(feature = "mock")]
+            Inner::Mock(process) => process.id(),
+        }
+    }
+
+    pub(crate) fn take_stdin(&mut self) -> Option<Box<dyn Write + Send>> {
+        match &mut self.inner {
+            Inner::Real(child) => child.stdin.take().map(|pipe| Box::new(pipe) as _),
+            #[cfg(feature = "mock")]
+            Inner::Mock(process) => process.take_stdin(),
+        }
+    }
+
+    pub(crate) fn take_stdout(&mut self) -> Option<Box<dyn Read + Send>> {
+        match &mut self.inner {
+            Inner::Real(child) => child.stdout.take().map(|pipe| Box::new(pipe) as _),
+            #[cfg(feature = "mock")]
+            Inner::Mock(process) => process.take_stdout(),
+        }
+    }
+
+    pub(crate) fn take_stderr(&mut self) -> Option<Box<dyn Read + Send>> {
+        match &mut self.inner {
+            Inner::Real(child) => child.stderr.take().map(|pipe| Box::new(pipe) as _),
+            #[cfg(feature = "mock")]
+            Inner::Mock(process) => process.take_stderr(),
+        }
+    }
+
+    // The stdout pipe as the stdin of the next stage of a pipeline. Mocked stages have no real
+    // pipe, and the next stage is then mocked too.
+    pub(crate) fn take_stdout_for_pipe(&mut self) -> Option<Stdio> {
+        match &mut self.inner {
+            Inner::Real(child) => child.stdout.take().map(Stdio::from),
+            #[cfg(feature = "mock")]
+            Inner::Mock(_) => None,
+        }
+    }
+
+    // The argument to `kill` that reaches the child, and its descendants if it leads a group.
+    #[cfg(unix)]
+    pub(crate) fn signal_target(&self) -> String {
+        match self.group {
+            true => format!("-{}", self.id()),
+            false => self.id().to_string(),
+        }
+    }
+
+    // The real child and whether it leads its own process group, or `None` if it is mocked.
+    pub(crate) fn into_child(self) -> Option<(Child, bool)> {
+        match self.inner {
+            Inner::Real(child) => Some((child, self.group)),
+            #[cfg(feature = "mock")]
+            Inner::Mock(_) => None,
+        }
+    }
+
+    pub(crate) fn is_mock(&self) -> bool {
+        match &self.inner {
+            Inner::Real(_) => false,
+            #[cfg(feature = "mock")]
+            Inner::Mock(_) => true,
+        }
+    }
+
+    pub(crate) fn wait(&mut self) -> io::Result<ExitStatus> {
+        match &mut self.inner {
+            Inner::Real(child) => child.wait(),
+            #[cfg(feature = "mock")]
+            Inner::Mock(process) => Ok(process.wait()),
+        }
+    }
+
+    pub(crate) fn try_wait(&mut self) -> io::Result<Option<ExitStatus>> {
+        match &mut self.inner {
+            Inner::Real(child) => child.try_wait(),
+            #[cfg(feature = "mock")]
+            Inner::Mock(process) => Ok(process.try_wait()),
+        }
+    }
+
+    pub(crate) fn kill(&mut self) -> io::Result<()> {
+        match &mut self.inner {
+            Inner::Real(child) => {
+                #[cfg(unix)]
+                if self.group {
+                    kill_group(child.id());
+                }
+                child.kill()
+            }
+            #[cfg(feature = "mock")]
+            Inner::Mock(process) => {
+                process.kill();
+                Ok(())
+            }
+        }
+    }
+}
+
+// Sends SIGKILL to the process group led by `id`, ignoring errors.
+#[cfg(unix)]
+pub(crate) fn kill_group(id: u32) {
+    let _ = std::process::Command::new("kill")
+        .args(["-s", "KILL", "--", &format!("-{}", id)])
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status();
+}
+
+// The backend of the calling thread, to be lent to threads that run commands on its behalf.
+pub(crate) struct Context {
+    #[cfg(feature = "mock")]
+    mock: Option<mock::SharedState>,
+}
+
+pub(crate) fn context() -> Context {
+    Context {
+        #[cfg(feature = "mock")]
+        mock: mock::current(),
+    }
+}
+
+impl Context {
+    pub(crate) fn run<T>(&self, f: impl FnOnce() -> T) -> T {
+        #[cfg(feature = "mock")]
+        let _entered = mock::enter(self.mock.clone());
+        f()
+    }
+}
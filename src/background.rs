@@ -0,0 +1,144 @@
+use std::process::{Command, ExitStatus};
+
+use anyhow::Context;
+
+use crate::backend::Process;
+use crate::capture::Captured;
+use crate::execution::BackgroundExecution;
+use crate::{child, CommandExt, Output};
+
+/// A command running in the background. The process is killed when this is dropped, unless it
+/// has already been waited for.
+pub struct BackgroundChild {
+    child: Process,
+    command: Option<Command>,
+    execution: BackgroundExecution,
+}
+
+impl BackgroundChild {
+    pub(crate) fn new(child: Process, command: Command, execution: BackgroundExecution) -> Self {
+        BackgroundChild { child, command: Some(command), execution }
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    pub fn command(&self) -> &Command {
+        self.command.as_ref().unwrap()
+    }
+
+    pub fn wait(&mut self) -> anyhow::Result<ExitStatus> {
+        let status = self.child.wait();
+        self.execution.finish(status.as_ref().ok(), None);
+        status.with_context(|| {
+            format!("Failed to wait for command ({})", self.command().description())
+        })
+    }
+
+    pub fn try_wait(&mut self) -> anyhow::Result<Option<ExitStatus>> {
+        let status = self.child.try_wait();
+        if let Ok(Some(status)) = &status {
+            self.execution.finish(Some(status), None);
+        }
+        status.with_context(|| {
+            format!("Failed to wait for command ({})", self.command().description())
+        })
+    }
+
+    pub fn kill(&mut self) -> anyhow::Result<()> {
+        self.child.kill().with_context(|| {
+            format!("Failed to kill command ({})", self.command().description())
+        })?;
+        self.wait().map(|_| ())
+    }
+
+    /// Waits for the process and collects whatever it wrote to stdout and stderr. Only streams
+    /// that were configured as `Stdio::piped()` before spawning are captured.
+    pub fn wait_for_output(mut self) -> anyhow::Result<Output> {
+        let readers = child::spawn_output_readers(&mut self.child);
+        let status = self.child.wait();
+        let (stdout, stderr) = readers.join();
+        let duration = self.execution.finish(status.as_ref().ok(), Some((&stdout, &stderr)));
+        let command = self.command.take().unwrap();
+        let status = status
+            .with_context(|| format!("Failed to wait for command ({})", command.description()))?;
+        let captured = Captured::new(status, stdout, stderr, duration.unwrap_or_default());
+        captured.check(&command)?;
+        captured.into_output(command)
+    }
+}
+
+impl Drop for BackgroundChild {
+    fn drop(&mut self) {
+        let status = match self.child.try_wait() {
+            Ok(None) => {
+                let _ = self.child.kill();
+                self.child.wait().ok()
+            }
+            status => status.ok().flatten(),
+        };
+        self.execution.finish(status.as_ref(), None);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Stdio;
+
+    use crate::test_support::record_exits;
+    use crate::{shell, CommandExt};
+
+    #[test]
+    fn wait_finishes_the_execution() {
+        let script = "exit 4 # background wait";
+        let (_guard, exits) = record_exits(script);
+        let mut child = shell(script).exec_background().unwrap();
+        assert_eq!(child.wait().unwrap().code(), Some(4));
+        drop(child);
+        assert_eq!(*exits.lock().unwrap(), [Some(4)]);
+    }
+
+    #[test]
+    fn wait_for_output_fails_with_the_output() {
+        let script = "echo partial; exit 1 # background output";
+        let (_guard, exits) = record_exits(script);
+        let child = shell(script).stdout_(Stdio::piped()).exec_background().unwrap();
+        let err = child.wait_for_output().err().unwrap();
+        assert!(format!("{:#}", err).contains(r#"stdout = "partial\n""#), "{:#}", err);
+        assert_eq!(*exits.lock().unwrap(), [Some(1)]);
+    }
+
+    #[test]
+    fn drop_kills_and_finishes_the_execution() {
+        let script = "sleep 10 # background drop";
+        let (_guard, exits) = record_exits(script);
+        drop(shell(script).exec_background().unwrap());
+        assert_eq!(*exits.lock().unwrap(), [None]);
+    }
+
+    fn is_running(pid: u32) -> bool {
+        shell(format!("kill -0 {} 2>/dev/null", pid)).status().unwrap().success()
+    }
+
+    #[test]
+    fn try_wait_and_kill_a_running_child() {
+        let mut child = shell("exec sleep 10").exec_background().unwrap();
+        assert!(child.try_wait().unwrap().is_none());
+        let pid = child.id();
+        child.kill().unwrap();
+        assert!(!is_running(pid));
+    }
+
+    #[test]
+    fn a_panic_does_not_leave_the_child_running() {
+        let mut pid = 0;
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let child = shell("exec sleep 10").exec_background().unwrap();
+            pid = child.id();
+            panic!("script failed");
+        }));
+        assert!(result.is_err());
+        assert!(!is_running(pid));
+    }
+}
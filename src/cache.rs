@@ -0,0 +1,291 @@
+use std::collections::HashMap;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+
+use crate::{copy_command, effective_dir, is_dry_run, warn, CommandExt, ConfiguredCommand, Output};
+
+// Bumped whenever the layout of cache files changes, so that files written by another version
+// are ignored instead of misread.
+const SCHEMA_VERSION: u32 = 1;
+const HEADER: &str = "cmd-utils command cache";
+
+/// Captured output of successful runs, for `exec_stdout_string_cached`, keyed by the program, the
+/// arguments, the environment variables set on the command, and its directory. Either kept in
+/// memory or in one file per command in a directory, to be shared between processes.
+pub struct CommandCache {
+    store: Store,
+    ttl: Option<Duration>,
+}
+
+enum Store {
+    Memory(Mutex<HashMap<Vec<u8>, Entry>>),
+    Dir(PathBuf),
+}
+
+struct Entry {
+    created: SystemTime,
+    stdout: String,
+    stderr: Vec<u8>,
+}
+
+impl CommandCache {
+    pub fn in_memory() -> Self {
+        CommandCache { store: Store::Memory(Mutex::new(HashMap::new())), ttl: None }
+    }
+
+    /// The directory is created when the first output is stored.
+    pub fn in_dir(dir: impl AsRef<Path>) -> Self {
+        CommandCache { store: Store::Dir(dir.as_ref().to_path_buf()), ttl: None }
+    }
+
+    /// Output stored longer ago than `ttl` is ignored and replaced by a new run.
+    pub fn ttl(self, ttl: Duration) -> Self {
+        CommandCache { ttl: Some(ttl), ..self }
+    }
+
+    pub fn invalidate(&self, cmd: &Command) -> anyhow::Result<()> {
+        let key = key(cmd);
+        match &self.store {
+            Store::Memory(entries) => {
+                entries.lock().unwrap().remove(&key);
+            }
+            Store::Dir(dir) => {
+                let path = entry_path(dir, &key);
+                match std::fs::remove_file(&path) {
+                    Err(e) if e.kind() != io::ErrorKind::NotFound => {
+                        return Err(e).with_context(|| {
+                            format!("Failed to remove cached output {}", path.display())
+                        });
+                    }
+                    _ => {}
+                }
+            }
+        }
+        Ok(())
+    }
+
+    pub(crate) fn run(&self, cmd: &mut ConfiguredCommand) -> anyhow::Result<Output> {
+        if is_dry_run() {
+            return cmd.exec_stdout_string_ref();
+        }
+        let key = key(cmd);
+        if let Some(entry) = self.get(&key) {
+            let status = ExitStatus::default();
+            let command = copy_command(cmd);
+            return Ok(Output::new(command, status, entry.stdout, entry.stderr, Duration::ZERO));
+        }
+        let out = cmd.exec_stdout_string_ref()?;
+        let entry = Entry {
+            created: SystemTime::now(),
+            stdout: out.stdout.clone(),
+            stderr: out.stderr.clone(),
+        };
+        if let Err(e) = self.put(key, entry) {
+            warn(format_args!("{:#}", e));
+        }
+        Ok(out)
+    }
+
+    fn get(&self, key: &[u8]) -> Option<Entry> {
+        let entry = match &self.store {
+            Store::Memory(entries) => {
+                let entries = entries.lock().unwrap();
+                let entry = entries.get(key)?;
+                Entry {
+                    created: entry.created,
+                    stdout: entry.stdout.clone(),
+                    stderr: entry.stderr.clone(),
+                }
+            }
+            Store::Dir(dir) => read_entry(&entry_path(dir, key), key)?,
+        };
+        let age = SystemTime::now().duration_since(entry.created).unwrap_or_default();
+        match self.ttl {
+            Some(ttl) if age > ttl => None,
+            _ => Some(entry),
+        }
+    }
+
+    fn put(&self, key: Vec<u8>, entry: Entry) -> anyhow::Result<()> {
+        match &self.store {
+            Store::Memory(entries) => {
+                entries.lock().unwrap().insert(key, entry);
+                Ok(())
+            }
+            Store::Dir(dir) => {
+                let path = entry_path(dir, &key);
+                write_entry(dir, &path, &key, &entry)
+                    .with_context(|| format!("Failed to cache output in {}", path.display()))
+            }
+        }
+    }
+}
+
+// Each part length-prefixed, so that no two commands have the same key.
+fn key(cmd: &Command) -> Vec<u8> {
+    let mut key = Vec::new();
+    let mut push = |tag: u8, bytes: &[u8]| {
+        key.push(tag);
+        key.extend_from_slice(&(bytes.len() as u64).to_le_bytes());
+        key.extend_from_slice(bytes);
+    };
+    push(b'p', cmd.get_program().as_encoded_bytes());
+    for arg in cmd.get_args() {
+        push(b'a', arg.as_encoded_bytes());
+    }
+    for (name, value) in cmd.get_envs() {
+        push(b'e', name.as_encoded_bytes());
+        match value {
+            Some(value) => push(b'v', value.as_encoded_bytes()),
+            None => push(b'r', &[]),
+        }
+    }
+    if let Some(dir) = effective_dir(cmd) {
+        push(b'd', dir.as_os_str().as_encoded_bytes());
+    }
+    key
+}
+
+fn entry_path(dir: &Path, key: &[u8]) -> PathBuf {
+    // FNV-1a
+    let hash = key
+        .iter()
+        .fold(0xcbf29ce484222325u64, |hash, &b| (hash ^ u64::from(b)).wrapping_mul(0x100000001b3));
+    dir.join(format!("{:016x}.cache", hash))
+}
+
+// The header line with the schema version, the creation time in seconds since the epoch, then
+// the key, stdout and stderr, each as its length on a line of its own followed by the bytes.
+fn write_entry(dir: &Path, path: &Path, key: &[u8], entry: &Entry) -> io::Result<()> {
+    std::fs::create_dir_all(dir)?;
+    let created = entry.created.duration_since(UNIX_EPOCH).unwrap_or_default().as_secs();
+    let mut data = format!("{} {}\n{}\n", HEADER, SCHEMA_VERSION, created).into_bytes();
+    for part in [key, entry.stdout.as_bytes(), &entry.stderr] {
+        data.extend_from_slice(format!("{}\n", part.len()).as_bytes());
+        data.extend_from_slice(part);
+    }
+    // Renamed into place, so that a concurrent reader never sees half of it.
+    let temporary = path.with_extension(format!("{}.tmp", std::process::id()));
+    std::fs::write(&temporary, data)?;
+    std::fs::rename(&temporary, path)
+}
+
+// `None` for a missing, unreadable or corrupt file, one of another schema version, and one with
+// another key whose hash is the same.
+fn read_entry(path: &Path, key: &[u8]) -> Option<Entry> {
+    let data = std::fs::read(path).ok()?;
+    let mut rest = &data[..];
+    let mut line = || {
+        let end = rest.iter().position(|&b| b == b'\n')?;
+        let line = std::str::from_utf8(&rest[..end]).ok()?.to_owned();
+        rest = &rest[end + 1..];
+        Some(line)
+    };
+    if line()? != format!("{} {}", HEADER, SCHEMA_VERSION) {
+        return None;
+    }
+    let created = UNIX_EPOCH + Duration::from_secs(line()?.parse().ok()?);
+    let mut parts = Vec::with_capacity(3);
+    for _ in 0..3 {
+        let end = rest.iter().position(|&b| b == b'\n')?;
+        let len: usize = std::str::from_utf8(&rest[..end]).ok()?.parse().ok()?;
+        let part = rest.get(end + 1..end + 1 + len)?;
+        parts.push(part.to_vec());
+        rest = &rest[end + 1 + len..];
+    }
+    let stderr = parts.pop()?;
+    let stdout = String::from_utf8(parts.pop()?).ok()?;
+    if parts.pop()? != key {
+        return None;
+    }
+    Some(Entry { created, stdout, stderr })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell;
+    use crate::test_support::temp_path;
+
+    // A command that appends to `counter` each time it really runs, and prints how often it has.
+    fn counting(counter: &Path) -> Command {
+        let path = counter.display();
+        shell(format!("echo run >> '{}'; wc -l < '{}'", path, path))
+    }
+
+    fn runs(counter: &Path) -> usize {
+        std::fs::read_to_string(counter).map_or(0, |text| text.lines().count())
+    }
+
+    #[test]
+    fn cached_output_is_returned_without_running_again() {
+        let counter = temp_path("cache-memory");
+        let cache = CommandCache::in_memory();
+        let mut cmd = counting(&counter);
+        let first = cmd.exec_stdout_string_cached(&cache).unwrap();
+        let second = cmd.exec_stdout_string_cached(&cache).unwrap();
+        assert_eq!((first.stdout.trim(), second.stdout.trim()), ("1", "1"));
+        assert!(second.status.success() && second.duration.is_zero());
+        assert_eq!(runs(&counter), 1);
+        // Another environment is another command.
+        let mut other = counting(&counter);
+        other.env("CACHE_TEST", "1");
+        assert_eq!(other.exec_stdout_string_cached(&cache).unwrap().stdout.trim(), "2");
+        cache.invalidate(&cmd).unwrap();
+        assert_eq!(cmd.exec_stdout_string_cached(&cache).unwrap().stdout.trim(), "3");
+        assert_eq!(cmd.exec_stdout_string_cached(&cache).unwrap().stdout.trim(), "3");
+        std::fs::remove_file(&counter).unwrap();
+    }
+
+    #[test]
+    fn failures_and_stale_output_are_not_used() {
+        let counter = temp_path("cache-failures");
+        let cache = CommandCache::in_memory();
+        let mut failing = shell(format!("echo run >> '{}'; exit 1", counter.display()));
+        assert!(failing.exec_stdout_string_cached(&cache).is_err());
+        assert!(failing.exec_stdout_string_cached(&cache).is_err());
+        assert_eq!(runs(&counter), 2);
+        let cache = CommandCache::in_memory().ttl(Duration::from_millis(1));
+        let mut cmd = counting(&counter);
+        cmd.exec_stdout_string_cached(&cache).unwrap();
+        std::thread::sleep(Duration::from_millis(10));
+        cmd.exec_stdout_string_cached(&cache).unwrap();
+        assert_eq!(runs(&counter), 4);
+        std::fs::remove_file(&counter).unwrap();
+    }
+
+    #[test]
+    fn directories_are_shared_and_other_schema_versions_ignored() {
+        let counter = temp_path("cache-dir-counter");
+        let dir = temp_path("cache-dir").join("nested");
+        let mut cmd = counting(&counter);
+        cmd.exec_stdout_string_cached(&CommandCache::in_dir(&dir)).unwrap();
+        let out = cmd.exec_stdout_string_cached(&CommandCache::in_dir(&dir)).unwrap();
+        assert_eq!((out.stdout.trim(), runs(&counter)), ("1", 1));
+
+        let path = entry_path(&dir, &key(&cmd));
+        let data = std::fs::read(&path).unwrap();
+        let header = format!("{} {}\n", HEADER, SCHEMA_VERSION);
+        assert!(data.starts_with(header.as_bytes()));
+        let old = [format!("{} 0\n", HEADER).as_bytes(), &data[header.len()..]].concat();
+        std::fs::write(&path, old).unwrap();
+        let out = cmd.exec_stdout_string_cached(&CommandCache::in_dir(&dir)).unwrap();
+        assert_eq!(out.stdout.trim(), "2");
+        std::fs::write(&path, &data).unwrap();
+        assert_eq!(read_entry(&path, &key(&cmd)).unwrap().stdout.trim(), "1");
+        assert!(read_entry(&path, b"another key").is_none());
+        std::fs::write(&path, &data[..data.len() - 1]).unwrap();
+        assert!(read_entry(&path, &key(&cmd)).is_none());
+
+        CommandCache::in_dir(&dir).invalidate(&cmd).unwrap();
+        assert!(!path.exists());
+        CommandCache::in_dir(&dir).invalidate(&cmd).unwrap();
+        std::fs::remove_dir_all(temp_path("cache-dir")).unwrap();
+        std::fs::remove_file(&counter).unwrap();
+    }
+}
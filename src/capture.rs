@@ -0,0 +1,500 @@
+use std::io::{self, Read, Write};
+use std::process::{Command, ExitStatus, Stdio};
+use std::sync::mpsc;
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::backend::{self, Process};
+use crate::which::SpawnContext;
+use crate::{
+    check_stdin_written, child, decoration_stream, dry_run, exec_failed, execution, is_allowed,
+    label, stdout_to_string, timed_out, trim_partial_char, write_banner,
+    write_end_output_with_summary, write_fenced, CommandExt, ConfiguredCommand, Embedded,
+    EscapedBytes, Output, StatusSummary, Stream, Truncation,
+};
+
+// Which of a command's streams a run replaces with pipes.
+#[derive(Clone, Copy, Default, PartialEq)]
+pub(crate) struct Pipes {
+    pub(crate) stdin: bool,
+    pub(crate) stdout: bool,
+    pub(crate) stderr: bool,
+}
+
+// Spawns `cmd` with the streams in `pipes` piped, and sets those back to inherited afterwards, so
+// that a later run of the same `Command` isn't left with pipes nobody reads.
+pub(crate) fn spawn_piped(cmd: &mut ConfiguredCommand, pipes: Pipes) -> anyhow::Result<Process> {
+    check_redirected(cmd, pipes)?;
+    if pipes.stdin {
+        cmd.stdin(Stdio::piped());
+    }
+    if pipes.stdout {
+        cmd.stdout(Stdio::piped());
+    }
+    if pipes.stderr {
+        cmd.stderr(Stdio::piped());
+    }
+    let child = backend::spawn(cmd);
+    if pipes.stdin {
+        cmd.stdin(Stdio::inherit());
+    }
+    if pipes.stdout {
+        cmd.stdout(Stdio::inherit());
+    }
+    if pipes.stderr {
+        cmd.stderr(Stdio::inherit());
+    }
+    child.spawn_context(cmd, || format!("Failed to execute command ({})", cmd.description()))
+}
+
+// Fails if one of the streams in `replaced` was set with `stdin_`, `stdout_` or `stderr_`, which
+// the run would silently override.
+pub(crate) fn check_redirected(cmd: &ConfiguredCommand, replaced: Pipes) -> anyhow::Result<()> {
+    let redirected = cmd.settings().redirected;
+    let streams = [
+        ("stdin", replaced.stdin && redirected.stdin),
+        ("stdout", replaced.stdout && redirected.stdout),
+        ("stderr", replaced.stderr && redirected.stderr),
+    ];
+    for (name, conflict) in streams {
+        if conflict {
+            anyhow::bail!(
+                "The {} of command ({}) was set with `{}_`, which this method replaces",
+                name,
+                cmd.description(),
+                name,
+            );
+        }
+    }
+    Ok(())
+}
+
+pub(crate) enum Input<'a> {
+    Bytes(&'a [u8]),
+    Reader(Box<dyn Read + Send + 'a>),
+}
+
+// Where captured output is copied to as soon as it is read, besides being kept.
+pub(crate) enum Forward<'a> {
+    Nowhere,
+    // This process's own stdout and stderr, each line tagged with the label, as in `exec_tee`.
+    Terminal,
+    Sinks(&'a mut dyn Write, &'a mut dyn Write),
+}
+
+// Which statuses `capture` returns rather than failing with.
+pub(crate) enum Check<'a> {
+    Success,
+    Allowing(&'a [i32]),
+    Unchecked,
+}
+
+// How the captured output is shown in the message of a failure.
+#[derive(Clone, Copy)]
+pub(crate) enum Info {
+    Inline,
+    // Fenced blocks under the multi-line description, for output a person is likely to read.
+    Pretty,
+    // Escaped bytes, for output that isn't expected to be text.
+    Bytes,
+}
+
+// How `capture` runs a command. The default captures stdout and stderr in full, writes no
+// decorations and fails unless the command exits successfully.
+pub(crate) struct CaptureOptions<'a> {
+    // A stream that isn't captured keeps whatever the command was configured with.
+    pub(crate) stdout: bool,
+    pub(crate) stderr: bool,
+    pub(crate) input: Option<Input<'a>>,
+    // Bytes kept of each stream; the rest is read and dropped, and only counted.
+    pub(crate) limit: usize,
+    pub(crate) timeout: Option<Duration>,
+    pub(crate) forward: Forward<'a>,
+    // Whether the banner and the END OUTPUT marker are written around the run.
+    pub(crate) decorated: bool,
+    pub(crate) check: Check<'a>,
+    pub(crate) info: Info,
+}
+
+impl Default for CaptureOptions<'_> {
+    fn default() -> Self {
+        CaptureOptions {
+            stdout: true,
+            stderr: true,
+            input: None,
+            limit: usize::MAX,
+            timeout: None,
+            forward: Forward::Nowhere,
+            decorated: false,
+            check: Check::Success,
+            info: Info::Inline,
+        }
+    }
+}
+
+// A finished run, with whatever was captured of its output.
+pub(crate) struct Captured {
+    pub(crate) status: ExitStatus,
+    pub(crate) stdout: Vec<u8>,
+    pub(crate) stderr: Vec<u8>,
+    pub(crate) duration: Duration,
+    pub(crate) truncation: Option<Truncation>,
+    captured: Pipes,
+    info: Info,
+    // Bytes written to stdin, out of how many if that is known.
+    written: Option<(u64, Option<usize>)>,
+}
+
+// Runs `cmd` to completion the way every method that waits for it does: the dry-run check, the
+// decorations, the execution record, the pipes, the input, the readers, the timeout and the
+// check of the status all happen here, and the methods only pick the options.
+pub(crate) fn capture(
+    cmd: &mut ConfiguredCommand,
+    options: CaptureOptions,
+) -> anyhow::Result<Captured> {
+    let CaptureOptions { stdout, stderr, input, limit, timeout, forward, decorated, check, info } =
+        options;
+    let pipes = Pipes { stdin: input.is_some(), stdout, stderr };
+    if dry_run(cmd)? {
+        let status = ExitStatus::default();
+        return Ok(
+            Captured::new(status, Vec::new(), Vec::new(), Duration::ZERO).with_info(pipes, info)
+        );
+    }
+    let mut decorations = decoration_stream();
+    if decorated {
+        write_banner(&mut decorations, cmd)?;
+    }
+    let execution = execution::start(cmd)?;
+    let mut child = spawn_piped(cmd, pipes)?;
+    forward_uncaptured(&mut child, pipes);
+    let stdin = child.take_stdin();
+    let (readers, chunks) = match forward {
+        Forward::Nowhere => (child::spawn_limited_output_readers(&mut child, limit), None),
+        Forward::Terminal => (child::spawn_tee_readers(&mut child, label::get(cmd)), None),
+        Forward::Sinks(stdout_sink, stderr_sink) => {
+            let (readers, chunks) = child::spawn_channel_readers(&mut child);
+            (readers, Some((stdout_sink, stderr_sink, chunks)))
+        }
+    };
+    let input_len = input.as_ref().and_then(|input| match input {
+        Input::Bytes(bytes) => Some(bytes.len()),
+        Input::Reader(_) => None,
+    });
+    let (status, written, sunk) = std::thread::scope(|s| {
+        let writer = input.map(|input| {
+            s.spawn(move || match input {
+                Input::Bytes(bytes) => child::copy_to_stdin(stdin, bytes),
+                Input::Reader(reader) => child::copy_to_stdin(stdin, reader),
+            })
+        });
+        let sunk = chunks.map(|(stdout_sink, stderr_sink, chunks)| {
+            write_to_sinks(stdout_sink, stderr_sink, chunks)
+        });
+        let status = match timeout {
+            Some(timeout) => child::wait_timeout(&mut child, timeout),
+            None => child.wait().map(Some),
+        };
+        if let Ok(None) = status {
+            child::terminate(&mut child);
+        }
+        (status, writer.map(|writer| writer.join().unwrap()), sunk)
+    });
+    let ((stdout_bytes, stdout_total), (stderr_bytes, stderr_total)) = match status {
+        Ok(None) => {
+            let (stdout, stderr) = readers.join_timeout(child::ORPHANED_PIPE_TIMEOUT);
+            let (stdout_len, stderr_len) = (stdout.len() as u64, stderr.len() as u64);
+            ((stdout, stdout_len), (stderr, stderr_len))
+        }
+        _ => readers.join_counted(),
+    };
+    let output = (stdout || stderr).then_some((&stdout_bytes[..], &stderr_bytes[..]));
+    let duration = execution.finish(cmd, status.as_ref().ok().and_then(Option::as_ref), output);
+    let summarized = output.is_some();
+    let status =
+        status.with_context(|| format!("Failed to wait for command ({})", cmd.description()))?;
+    if let Some(sunk) = sunk {
+        sunk.with_context(|| format!("Failed to forward process output ({})", cmd.description()))?;
+    }
+    let truncated =
+        stdout_total > stdout_bytes.len() as u64 || stderr_total > stderr_bytes.len() as u64;
+    let mut captured = Captured {
+        status: status.unwrap_or_default(),
+        stdout: stdout_bytes,
+        stderr: stderr_bytes,
+        duration,
+        truncation: truncated.then_some(Truncation { stdout_total, stderr_total }),
+        captured: pipes,
+        info,
+        written: None,
+    };
+    let passed = match (status, &check) {
+        (None, _) => false,
+        (Some(status), Check::Success | Check::Unchecked) => status.success(),
+        (Some(status), Check::Allowing(codes)) => is_allowed(status, codes),
+    };
+    if decorated {
+        let annotation = status.is_none().then_some("TIMED OUT");
+        let output = summarized.then_some((&captured.stdout[..], &captured.stderr[..]));
+        let output = output.filter(|_| status.is_some());
+        write_end_output_with_summary(&mut decorations, cmd, passed, annotation, duration, output);
+    }
+    if let Some((written, result)) = written {
+        check_stdin_written(cmd, input_len, written, result)?;
+        captured.written = Some((written, input_len));
+    }
+    if status.is_none() {
+        return Err(timed_out(
+            cmd,
+            timeout.unwrap(),
+            format!("Process timed out after {:?} ({})", duration, captured.info(cmd)),
+        ));
+    }
+    if !passed && !matches!(check, Check::Unchecked) {
+        return Err(captured.failure(cmd, ""));
+    }
+    if stdout_total > captured.stdout.len() as u64 {
+        trim_partial_char(&mut captured.stdout);
+    }
+    Ok(captured)
+}
+
+// A mocked process offers its scripted output whatever the command's stdio, so the streams that
+// aren't captured are copied to this process's own, as `backend::status` does.
+fn forward_uncaptured(child: &mut Process, pipes: Pipes) {
+    if !child.is_mock() {
+        return;
+    }
+    if !pipes.stdout {
+        if let Some(mut stdout) = child.take_stdout() {
+            let _ = io::copy(&mut stdout, &mut io::stdout());
+        }
+    }
+    if !pipes.stderr {
+        if let Some(mut stderr) = child.take_stderr() {
+            let _ = io::copy(&mut stderr, &mut io::stderr());
+        }
+    }
+}
+
+// Writes every chunk to the sink of its stream until both streams are done. A sink that fails
+// gets nothing more, and its error is returned once the child's output has all been read.
+fn write_to_sinks(
+    stdout_sink: &mut dyn Write,
+    stderr_sink: &mut dyn Write,
+    chunks: mpsc::Receiver<(Stream, Vec<u8>)>,
+) -> anyhow::Result<()> {
+    let mut stdout_result = Ok(());
+    let mut stderr_result = Ok(());
+    for (stream, chunk) in chunks {
+        match stream {
+            Stream::Stdout if stdout_result.is_ok() => {
+                stdout_result = stdout_sink.write_all(&chunk)
+            }
+            Stream::Stderr if stderr_result.is_ok() => {
+                stderr_result = stderr_sink.write_all(&chunk)
+            }
+            _ => {}
+        }
+    }
+    let stdout_result = stdout_result.and_then(|()| stdout_sink.flush());
+    let stderr_result = stderr_result.and_then(|()| stderr_sink.flush());
+    stdout_result.context("Failed to write process stdout to the stdout sink")?;
+    stderr_result.context("Failed to write process stderr to the stderr sink")?;
+    Ok(())
+}
+
+impl Captured {
+    // A run that finished without `capture`, such as a pipeline stage or a background child,
+    // with both streams captured.
+    pub(crate) fn new(
+        status: ExitStatus,
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+        duration: Duration,
+    ) -> Self {
+        Captured {
+            status,
+            stdout,
+            stderr,
+            duration,
+            truncation: None,
+            captured: Pipes { stdin: false, stdout: true, stderr: true },
+            info: Info::Inline,
+            written: None,
+        }
+    }
+
+    pub(crate) fn with_info(self, captured: Pipes, info: Info) -> Self {
+        Captured { captured, info, ..self }
+    }
+
+    pub(crate) fn check(&self, cmd: &Command) -> anyhow::Result<()> {
+        match self.status.success() {
+            true => Ok(()),
+            false => Err(self.failure(cmd, "")),
+        }
+    }
+
+    // The error for the status the run ended with. `after` goes right after "successfully", as
+    // in " after 3 attempt(s)".
+    pub(crate) fn failure(&self, cmd: &Command, after: &str) -> anyhow::Error {
+        let mut message =
+            format!("Process did not exit successfully{}, {}", after, StatusSummary(self.status));
+        match self.written {
+            Some((written, Some(len))) => {
+                message.push_str(&format!(", {} of {} bytes of input written", written, len))
+            }
+            Some((written, None)) => {
+                message.push_str(&format!(", {} bytes of input written", written))
+            }
+            None => {}
+        }
+        let message = match self.info(cmd) {
+            info if info.contains('\n') => format!("{}:\n{}", message, info.trim_end()),
+            info => format!("{} ({})", message, info),
+        };
+        exec_failed(cmd, self.status, &self.stdout, &self.stderr, message)
+    }
+
+    // The description of the command, followed by the streams that were captured.
+    fn info(&self, cmd: &Command) -> String {
+        let (stdout_total, stderr_total) = match self.truncation {
+            Some(truncation) => (truncation.stdout_total, truncation.stderr_total),
+            None => (self.stdout.len() as u64, self.stderr.len() as u64),
+        };
+        let streams = [
+            ("stdout", self.captured.stdout, Embedded { bytes: &self.stdout, total: stdout_total }),
+            ("stderr", self.captured.stderr, Embedded { bytes: &self.stderr, total: stderr_total }),
+        ];
+        let streams = streams.into_iter().filter(|&(_, captured, _)| captured);
+        match self.info {
+            Info::Pretty => {
+                let mut info = cmd.description().pretty();
+                for (name, _, output) in streams {
+                    write_fenced(&mut info, name, output).unwrap();
+                }
+                info
+            }
+            Info::Inline => {
+                let mut info = cmd.description().to_string();
+                for (name, _, output) in streams {
+                    info.push_str(&format!(", {} = {}", name, output));
+                }
+                info
+            }
+            Info::Bytes => {
+                let mut info = cmd.description().to_string();
+                for (name, _, output) in streams {
+                    info.push_str(&format!(", {} = {}", name, EscapedBytes(output.bytes)));
+                }
+                info
+            }
+        }
+    }
+
+    // Fails unless stdout is UTF-8.
+    pub(crate) fn into_output(self, command: impl Into<Command>) -> anyhow::Result<Output> {
+        let command = command.into();
+        let stdout = stdout_to_string(&command, self.stdout, &self.stderr)?;
+        let mut output = Output::new(command, self.status, stdout, self.stderr, self.duration);
+        output.truncation = self.truncation;
+        Ok(output)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::path::Path;
+    use std::process::{Command, Stdio};
+
+    use crate::{shell, CommandExt};
+
+    #[test]
+    fn capturing_a_redirected_stream_fails() {
+        let err = shell("echo hi").stdout_(Stdio::null()).exec_stdout_string().err().unwrap();
+        assert!(format!("{:#}", err).contains("was set with `stdout_`"), "{:#}", err);
+        let err = shell("cat").stdin_(Stdio::null()).exec_stdout_string_with_stdin("x").err();
+        assert!(format!("{:#}", err.unwrap()).contains("was set with `stdin_`"));
+    }
+
+    #[test]
+    fn streams_that_are_not_replaced_may_be_redirected() {
+        let out = shell("cat; echo hi").stdin_(Stdio::null()).exec_stdout_string().unwrap();
+        assert_eq!(out.stdout, "hi\n");
+        shell("echo hi").stdout_(Stdio::null()).exec().unwrap();
+    }
+
+    // Redirected commands used to be recorded by the address of their program, which a new
+    // command could be allocated at once they were dropped.
+    #[test]
+    fn a_new_command_is_not_redirected_by_a_dropped_one() {
+        for _ in 0..20 {
+            drop(Command::new("sh").stdout_(Stdio::null()));
+            let mut cmd = Command::new("sh");
+            cmd.args(["-c", "echo hi"]);
+            let out = cmd.exec_stdout_string().unwrap();
+            assert_eq!(out.stdout, "hi\n");
+        }
+    }
+
+    #[test]
+    fn a_fully_built_command_runs_in_one_expression() {
+        let dir = std::env::temp_dir();
+        let out = shell("pwd; echo hidden >&2").current_dir_(&dir).stdin_(Stdio::null());
+        let out = out.exec_stdout_string().unwrap();
+        assert_eq!(Path::new(out.stdout.trim_end()), dir.canonicalize().unwrap());
+        shell("echo hidden >&2").stderr_(Stdio::null()).exec().unwrap();
+        let err = shell("true").stderr_(Stdio::null()).exec_stdout_string().err().unwrap();
+        assert!(format!("{:#}", err).contains("was set with `stderr_`"), "{:#}", err);
+    }
+
+    #[test]
+    fn tee_sinks_get_exactly_what_is_captured() {
+        let (mut stdout, mut stderr) = (Vec::new(), Vec::new());
+        let script = "seq 20000; printf 'err\\377' >&2; printf 'no newline'";
+        let out = shell(script).exec_stdout_string_tee(&mut stdout, &mut stderr).unwrap();
+        assert_eq!((out.stdout.as_bytes(), &out.stderr[..]), (&stdout[..], &stderr[..]));
+        assert!(stdout.ends_with(b"20000\nno newline") && stderr == b"err\xff");
+        let mut buffered = std::io::BufWriter::new(Vec::new());
+        shell("echo hi").exec_stdout_string_tee(&mut buffered, &mut Vec::new()).unwrap();
+        assert!(buffered.buffer().is_empty() && buffered.get_ref() == b"hi\n");
+    }
+
+    #[test]
+    fn a_failing_sink_fails_after_the_process_exits() {
+        struct Failing {
+            writes: usize,
+            flushed: bool,
+        }
+        impl std::io::Write for Failing {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.writes += 1;
+                match self.writes {
+                    1 => Ok(buf.len()),
+                    _ => Err(std::io::Error::other("disk full")),
+                }
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                self.flushed = true;
+                Ok(())
+            }
+        }
+        let script = "for i in 1 2 3; do echo $i >&2; sleep 0.1; done # tee failing sink";
+        let (_guard, exits) = crate::test_support::record_exits(script);
+        let mut stdout = Vec::new();
+        let mut stderr = Failing { writes: 0, flushed: false };
+        let err = shell(script).exec_stdout_string_tee(&mut stdout, &mut stderr).err().unwrap();
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains("Failed to write process stderr to the stderr sink"),
+            "{}",
+            message
+        );
+        assert!(message.ends_with("disk full"), "{}", message);
+        // The sink got nothing after its error, and the process still ran to the end.
+        assert_eq!((stderr.writes, stderr.flushed), (2, false));
+        assert_eq!(*exits.lock().unwrap(), [Some(0)]);
+    }
+}
@@ -0,0 +1,327 @@
+use std::io::{self, BufRead, BufReader, Read, Write};
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{mpsc, Arc, Mutex};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use termcolor::{ColorSpec, StandardStream, WriteColor};
+
+use crate::backend::Process;
+use crate::color::DECORATION_LOCK;
+use crate::merged::{self, SharedChunks};
+use crate::{
+    label, scope, stderr_stream, stdout_stream, theme, Stream, TermColorStandardStreamExt,
+};
+
+const POLL_INTERVAL: Duration = Duration::from_millis(10);
+pub(crate) const TERMINATE_GRACE_PERIOD: Duration = Duration::from_secs(1);
+pub(crate) const ORPHANED_PIPE_TIMEOUT: Duration = Duration::from_millis(100);
+
+pub(crate) fn wait_timeout(
+    child: &mut Process,
+    timeout: Duration,
+) -> io::Result<Option<ExitStatus>> {
+    let deadline = Instant::now() + timeout;
+    loop {
+        if let Some(status) = child.try_wait()? {
+            return Ok(Some(status));
+        }
+        let now = Instant::now();
+        if now >= deadline {
+            return Ok(None);
+        }
+        thread::sleep(POLL_INTERVAL.min(deadline - now));
+    }
+}
+
+// Asks the child to exit (SIGTERM on Unix), escalating to `Child::kill` once the grace period
+// runs out, and reaps it. Errors are ignored since the child may already be gone.
+pub(crate) fn terminate(child: &mut Process) {
+    let _ = terminate_within(child, TERMINATE_GRACE_PERIOD);
+}
+
+// Like `terminate`, with a given grace period, returning the status the child was reaped with.
+pub(crate) fn terminate_within(child: &mut Process, grace: Duration) -> io::Result<ExitStatus> {
+    #[cfg(unix)]
+    if !child.is_mock() {
+        let _ = std::process::Command::new("kill")
+            .args(["-s", "TERM", "--", &child.signal_target()])
+            .stdin(std::process::Stdio::null())
+            .stdout(std::process::Stdio::null())
+            .stderr(std::process::Stdio::null())
+            .status();
+        if let Ok(Some(status)) = wait_timeout(child, grace) {
+            return Ok(status);
+        }
+    }
+    // Fails if the child has exited in the meantime, which `wait` then reports.
+    let _ = child.kill();
+    child.wait()
+}
+
+// Copies all of `input` into the child and closes the pipe, returning how many bytes made it
+// through.
+pub(crate) fn copy_to_stdin<W: Write, R: Read>(
+    stdin: Option<W>,
+    mut input: R,
+) -> (u64, io::Result<()>) {
+    let Some(mut stdin) = stdin else {
+        return (0, Ok(()));
+    };
+    let mut chunk = [0; 8192];
+    let mut written = 0;
+    loop {
+        let n = match input.read(&mut chunk) {
+            Ok(0) => return (written, Ok(())),
+            Ok(n) => n,
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(e) => return (written, Err(e)),
+        };
+        if let Err(e) = stdin.write_all(&chunk[..n]) {
+            return (written, Err(e));
+        }
+        written += n as u64;
+    }
+}
+
+// Calls `f` with every line read from `pipe` (without the line terminator) until EOF. Invalid
+// UTF-8 is replaced rather than ending the stream early.
+pub(crate) fn for_each_line<R: Read>(pipe: R, mut f: impl FnMut(&str)) {
+    let mut reader = BufReader::new(pipe);
+    let mut line = Vec::new();
+    loop {
+        line.clear();
+        match reader.read_until(b'\n', &mut line) {
+            Ok(0) => break,
+            Ok(_) => {
+                let trimmed = line.strip_suffix(b"\n").unwrap_or(&line);
+                let trimmed = trimmed.strip_suffix(b"\r").unwrap_or(trimmed);
+                f(&String::from_utf8_lossy(trimmed));
+            }
+            Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+            Err(_) => break,
+        }
+    }
+}
+
+pub(crate) struct OutputReaders {
+    stdout: Option<Reader>,
+    stderr: Option<Reader>,
+}
+
+impl OutputReaders {
+    pub(crate) fn join(self) -> (Vec<u8>, Vec<u8>) {
+        (
+            self.stdout.map(Reader::join).unwrap_or_default(),
+            self.stderr.map(Reader::join).unwrap_or_default(),
+        )
+    }
+
+    // Like `join`, along with how many bytes each stream carried in total, including any dropped
+    // past the limit given to `spawn_limited_output_readers`.
+    pub(crate) fn join_counted(self) -> ((Vec<u8>, u64), (Vec<u8>, u64)) {
+        let join = |reader: Option<Reader>| reader.map(Reader::join_counted).unwrap_or_default();
+        (join(self.stdout), join(self.stderr))
+    }
+
+    // Like `join`, but gives up after `timeout` and returns whatever has been read so far. Used
+    // after killing a child whose pipes may still be held open by its own descendants.
+    pub(crate) fn join_timeout(self, timeout: Duration) -> (Vec<u8>, Vec<u8>) {
+        let deadline = Instant::now() + timeout;
+        let join =
+            |reader: Option<Reader>| reader.map(|r| r.join_deadline(deadline)).unwrap_or_default();
+        (join(self.stdout), join(self.stderr))
+    }
+}
+
+pub(crate) fn spawn_output_readers(child: &mut Process) -> OutputReaders {
+    spawn_limited_output_readers(child, usize::MAX)
+}
+
+// Keeps the first `limit` bytes of each stream, and reads and drops the rest so the child never
+// blocks on a full pipe.
+pub(crate) fn spawn_limited_output_readers(child: &mut Process, limit: usize) -> OutputReaders {
+    OutputReaders {
+        stdout: child.take_stdout().map(|pipe| Reader::spawn_limited(pipe, |_| (), limit)),
+        stderr: child.take_stderr().map(|pipe| Reader::spawn_limited(pipe, |_| (), limit)),
+    }
+}
+
+// Like `spawn_output_readers`, but also sends every chunk to the returned receiver, which
+// disconnects once both streams are done.
+pub(crate) fn spawn_channel_readers(
+    child: &mut Process,
+) -> (OutputReaders, mpsc::Receiver<(Stream, Vec<u8>)>) {
+    let (sender, receiver) = mpsc::channel();
+    let reader = |pipe, stream| {
+        let sender = sender.clone();
+        Reader::spawn(pipe, move |chunk: &[u8]| {
+            let _ = sender.send((stream, chunk.to_vec()));
+        })
+    };
+    let readers = OutputReaders {
+        stdout: child.take_stdout().map(|pipe| reader(pipe, Stream::Stdout)),
+        stderr: child.take_stderr().map(|pipe| reader(pipe, Stream::Stderr)),
+    };
+    (readers, receiver)
+}
+
+// Reads both streams into `chunks` in arrival order, without keeping them apart as well.
+pub(crate) fn spawn_merged_readers(child: &mut Process, chunks: &SharedChunks) -> OutputReaders {
+    let reader = |pipe, stream| {
+        let chunks = Arc::clone(chunks);
+        Reader::spawn_limited(pipe, move |chunk| merged::push_chunk(&chunks, stream, chunk), 0)
+    };
+    OutputReaders {
+        stdout: child.take_stdout().map(|pipe| reader(pipe, Stream::Stdout)),
+        stderr: child.take_stderr().map(|pipe| reader(pipe, Stream::Stderr)),
+    }
+}
+
+// Like `spawn_output_readers`, but also forwards every chunk to the parent's own stdout/stderr as
+// soon as it is read. With a label, every forwarded line starts with its tag, indented for the
+// scopes of the calling thread.
+pub(crate) fn spawn_tee_readers(child: &mut Process, label: Option<&str>) -> OutputReaders {
+    fn forward(mut out: StandardStream, label: Option<&str>, indent: usize) -> impl FnMut(&[u8]) {
+        let label = label.map(str::to_owned);
+        let mut line_start = true;
+        move |chunk| {
+            let _lock = DECORATION_LOCK.lock().unwrap();
+            let written = match &label {
+                Some(label) => write_labeled(
+                    &mut out,
+                    (label, indent),
+                    chunk,
+                    &ColorSpec::new(),
+                    &mut line_start,
+                ),
+                None => out.write_all(chunk),
+            };
+            let _ = written.and_then(|()| out.flush());
+        }
+    }
+    let indent = scope::indent_width();
+    OutputReaders {
+        stdout: child
+            .take_stdout()
+            .map(|pipe| Reader::spawn(pipe, forward(stdout_stream(), label, indent))),
+        stderr: child
+            .take_stderr()
+            .map(|pipe| Reader::spawn(pipe, forward(stderr_stream(), label, indent))),
+    }
+}
+
+// Writes output that was captured without being forwarded, as `spawn_tee_readers` would have
+// forwarded it but all at once: stdout to stdout, then stderr to stderr in
+// `Theme::failure_text`, each finished with a newline.
+pub(crate) fn replay_output(label: Option<&str>, stdout: &[u8], stderr: &[u8]) -> io::Result<()> {
+    let indent = scope::indent_width();
+    let _lock = DECORATION_LOCK.lock().unwrap();
+    for (mut out, bytes, spec) in [
+        (stdout_stream(), stdout, ColorSpec::new()),
+        (stderr_stream(), stderr, theme::theme().failure_text),
+    ] {
+        if bytes.is_empty() {
+            continue;
+        }
+        match label {
+            Some(label) => write_labeled(&mut out, (label, indent), bytes, &spec, &mut true)?,
+            None => out.with_color(&spec, |out| out.write_all(bytes))?,
+        }
+        if !bytes.ends_with(b"\n") {
+            writeln!(out)?;
+        }
+        out.flush()?;
+    }
+    Ok(())
+}
+
+// Writes `chunk` with the tag of a label, after the indentation in spaces, at the start of every
+// line, and the rest of each line in `spec`.
+fn write_labeled(
+    out: &mut impl WriteColor,
+    (label, indent): (&str, usize),
+    chunk: &[u8],
+    spec: &ColorSpec,
+    line_start: &mut bool,
+) -> io::Result<()> {
+    for line in chunk.split_inclusive(|&b| b == b'\n') {
+        if *line_start {
+            write!(out, "{:indent$}", "")?;
+            label::write_tag(out, label)?;
+        }
+        out.with_color(spec, |out| out.write_all(line))?;
+        *line_start = line.ends_with(b"\n");
+    }
+    Ok(())
+}
+
+struct Reader {
+    buf: Arc<Mutex<Vec<u8>>>,
+    total: Arc<AtomicU64>,
+    handle: JoinHandle<()>,
+}
+
+impl Reader {
+    fn spawn<R, F>(pipe: R, on_chunk: F) -> Self
+    where
+        R: Read + Send + 'static,
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        Reader::spawn_limited(pipe, on_chunk, usize::MAX)
+    }
+
+    // Every chunk goes to `on_chunk`, but only the first `limit` bytes are kept.
+    fn spawn_limited<R, F>(mut pipe: R, mut on_chunk: F, limit: usize) -> Self
+    where
+        R: Read + Send + 'static,
+        F: FnMut(&[u8]) + Send + 'static,
+    {
+        let buf = Arc::new(Mutex::new(Vec::new()));
+        let total = Arc::new(AtomicU64::new(0));
+        let handle = thread::spawn({
+            let buf = Arc::clone(&buf);
+            let total = Arc::clone(&total);
+            move || {
+                let mut chunk = [0; 8192];
+                loop {
+                    match pipe.read(&mut chunk) {
+                        Ok(0) => break,
+                        Ok(n) => {
+                            on_chunk(&chunk[..n]);
+                            let mut buf = buf.lock().unwrap();
+                            let kept = n.min(limit.saturating_sub(buf.len()));
+                            buf.extend_from_slice(&chunk[..kept]);
+                            total.fetch_add(n as u64, Ordering::SeqCst);
+                        }
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(_) => break,
+                    }
+                }
+            }
+        });
+        Reader { buf, total, handle }
+    }
+
+    fn join(self) -> Vec<u8> {
+        self.join_counted().0
+    }
+
+    fn join_counted(self) -> (Vec<u8>, u64) {
+        let Reader { buf, total, handle } = self;
+        let _ = handle.join();
+        (take_buf(&buf), total.load(Ordering::SeqCst))
+    }
+
+    fn join_deadline(self, deadline: Instant) -> Vec<u8> {
+        while !self.handle.is_finished() && Instant::now() < deadline {
+            thread::sleep(POLL_INTERVAL);
+        }
+        take_buf(&self.buf)
+    }
+}
+
+fn take_buf(buf: &Mutex<Vec<u8>>) -> Vec<u8> {
+    std::mem::take(&mut *buf.lock().unwrap())
+}
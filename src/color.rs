@@ -0,0 +1,280 @@
+use std::any::Any;
+use std::io::{self, IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+
+use termcolor::{Buffer, BufferWriter, ColorChoice, ColorSpec, StandardStream, WriteColor};
+
+use crate::scope;
+
+static COLOR_OVERRIDE: Mutex<Option<ColorChoice>> = Mutex::new(None);
+// The choice forced by `NO_COLOR` or `CLICOLOR_FORCE`, read once. The outer `None` means the
+// environment has not been read yet.
+static ENV_COLOR_CHOICE: Mutex<Option<Option<ColorChoice>>> = Mutex::new(None);
+
+/// Overrides how every banner and `stdout_with_color`/`stderr_with_color` decide on color.
+///
+/// Color is decided in this order, the first match winning:
+/// 1. the choice passed here,
+/// 2. `NO_COLOR` set to a non-empty value disables color,
+/// 3. `CLICOLOR_FORCE` set to a non-empty value other than `0` enables color,
+/// 4. color is used if the stream is a terminal.
+pub fn set_color_choice(choice: ColorChoice) {
+    *COLOR_OVERRIDE.lock().unwrap() = Some(choice);
+}
+
+pub(crate) fn color_override() -> Option<ColorChoice> {
+    *COLOR_OVERRIDE.lock().unwrap()
+}
+
+pub(crate) fn set_color_override(choice: Option<ColorChoice>) {
+    *COLOR_OVERRIDE.lock().unwrap() = choice;
+}
+
+/// Removes the override set by `set_color_choice` and reads `NO_COLOR` and `CLICOLOR_FORCE`
+/// again on the next decision, since they are otherwise only read once.
+pub fn reset_color_choice() {
+    *COLOR_OVERRIDE.lock().unwrap() = None;
+    *ENV_COLOR_CHOICE.lock().unwrap() = None;
+}
+
+pub(crate) fn stdout_stream() -> StandardStream {
+    StandardStream::stdout(resolve(std::io::stdout().is_terminal()))
+}
+
+pub(crate) fn stderr_stream() -> StandardStream {
+    StandardStream::stderr(resolve(std::io::stderr().is_terminal()))
+}
+
+// For text that ends up on stderr some other way, such as a panic message.
+pub(crate) fn stderr_buffer() -> Buffer {
+    BufferWriter::stderr(resolve(std::io::stderr().is_terminal())).buffer()
+}
+
+trait DecorationWriter: WriteColor + Send {
+    fn into_any(self: Box<Self>) -> Box<dyn Any>;
+}
+
+impl<W: WriteColor + Send + 'static> DecorationWriter for W {
+    fn into_any(self: Box<Self>) -> Box<dyn Any> {
+        self
+    }
+}
+
+// Held for each banner, END OUTPUT marker, summary and chunk of forwarded output, so that the
+// crate's own writes from different threads don't interleave mid-line.
+pub(crate) static DECORATION_LOCK: Mutex<()> = Mutex::new(());
+
+static DECORATION_WRITER: Mutex<Option<Box<dyn DecorationWriter>>> = Mutex::new(None);
+static DECORATIONS_DISABLED: AtomicBool = AtomicBool::new(false);
+
+/// Turns banners, `END OUTPUT` markers and summaries off entirely, for example when executions
+/// are already reported through the `tracing` feature.
+pub fn set_decorations_enabled(enabled: bool) {
+    DECORATIONS_DISABLED.store(!enabled, Ordering::SeqCst);
+}
+
+pub(crate) fn decorations_enabled() -> bool {
+    !DECORATIONS_DISABLED.load(Ordering::SeqCst)
+}
+
+/// Sends banners, `END OUTPUT` markers and summaries to `writer` instead of stderr. The writer
+/// decides on color itself: `set_color_choice` and the environment only apply to stderr.
+pub fn set_decoration_writer<W: WriteColor + Send + 'static>(writer: W) {
+    *DECORATION_WRITER.lock().unwrap() = Some(Box::new(writer));
+}
+
+/// Removes the writer set by `set_decoration_writer`, so decorations go to stderr again, and
+/// returns it if it is a `W`.
+pub fn take_decoration_writer<W: 'static>() -> Option<W> {
+    let writer = DECORATION_WRITER.lock().unwrap().take()?;
+    writer.into_any().downcast().ok().map(|writer| *writer)
+}
+
+// Where decorations are written: stderr unless a writer is installed. The installed writer is
+// looked up on every write, so decorations written while it is being taken are dropped. Every
+// line is indented for the scopes (see `scope.rs`) the stream was created in, before any color
+// is set on it.
+pub(crate) struct DecorationStream {
+    target: Target,
+    indent: usize,
+    line_start: bool,
+}
+
+enum Target {
+    Stderr(StandardStream),
+    Writer,
+    Disabled,
+}
+
+pub(crate) fn decoration_stream() -> DecorationStream {
+    let target = if DECORATIONS_DISABLED.load(Ordering::SeqCst) {
+        Target::Disabled
+    } else {
+        match DECORATION_WRITER.lock().unwrap().is_some() {
+            true => Target::Writer,
+            false => Target::Stderr(stderr_stream()),
+        }
+    };
+    DecorationStream { target, indent: scope::indent_width(), line_start: true }
+}
+
+impl DecorationStream {
+    fn with_writer<T>(
+        &mut self,
+        default: T,
+        f: impl FnOnce(&mut dyn WriteColor) -> io::Result<T>,
+    ) -> io::Result<T> {
+        match &mut self.target {
+            Target::Stderr(stderr) => f(stderr),
+            Target::Writer => match DECORATION_WRITER.lock().unwrap().as_mut() {
+                Some(writer) => f(writer.as_mut()),
+                None => Ok(default),
+            },
+            Target::Disabled => Ok(default),
+        }
+    }
+
+    fn write_indent(&mut self) -> io::Result<()> {
+        if self.line_start && self.indent > 0 {
+            let indent = " ".repeat(self.indent);
+            self.with_writer((), |w| w.write_all(indent.as_bytes()))?;
+        }
+        self.line_start = false;
+        Ok(())
+    }
+}
+
+impl Write for DecorationStream {
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if self.indent == 0 {
+            return self.with_writer(buf.len(), |w| w.write(buf));
+        }
+        for line in buf.split_inclusive(|&b| b == b'\n') {
+            self.write_indent()?;
+            self.with_writer((), |w| w.write_all(line))?;
+            self.line_start = line.ends_with(b"\n");
+        }
+        Ok(buf.len())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.with_writer((), |w| w.flush())
+    }
+}
+
+impl WriteColor for DecorationStream {
+    fn supports_color(&self) -> bool {
+        match &self.target {
+            Target::Stderr(stderr) => stderr.supports_color(),
+            Target::Writer => {
+                DECORATION_WRITER.lock().unwrap().as_ref().is_some_and(|w| w.supports_color())
+            }
+            Target::Disabled => false,
+        }
+    }
+
+    fn set_color(&mut self, spec: &ColorSpec) -> io::Result<()> {
+        self.write_indent()?;
+        self.with_writer((), |w| w.set_color(spec))
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        self.with_writer((), |w| w.reset())
+    }
+}
+
+fn resolve(is_terminal: bool) -> ColorChoice {
+    if let Some(choice) = *COLOR_OVERRIDE.lock().unwrap() {
+        return choice;
+    }
+    let env_choice = *ENV_COLOR_CHOICE.lock().unwrap().get_or_insert_with(env_color_choice);
+    match (env_choice, is_terminal) {
+        (Some(choice), _) => choice,
+        (None, true) => ColorChoice::Auto,
+        (None, false) => ColorChoice::Never,
+    }
+}
+
+fn env_color_choice() -> Option<ColorChoice> {
+    if std::env::var_os("NO_COLOR").is_some_and(|value| !value.is_empty()) {
+        return Some(ColorChoice::Never);
+    }
+    match std::env::var_os("CLICOLOR_FORCE") {
+        Some(value) if !value.is_empty() && value != "0" => Some(ColorChoice::Always),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::serial;
+    use crate::CommandExt;
+
+    // Whether text colored through `stderr_buffer` comes out with escapes, with `NO_COLOR` and
+    // `CLICOLOR_FORCE` set to `no_color` and `force`.
+    fn colored(no_color: Option<&str>, force: Option<&str>) -> bool {
+        for (key, value) in [("NO_COLOR", no_color), ("CLICOLOR_FORCE", force)] {
+            match value {
+                Some(value) => std::env::set_var(key, value),
+                None => std::env::remove_var(key),
+            }
+        }
+        reset_color_choice();
+        let mut buffer = stderr_buffer();
+        buffer.set_color(ColorSpec::new().set_fg(Some(termcolor::Color::Red))).unwrap();
+        buffer.write_all(b"text").unwrap();
+        buffer.reset().unwrap();
+        buffer.as_slice().contains(&b'\x1b')
+    }
+
+    #[test]
+    fn environment_decides_color() {
+        let _serial = serial();
+        let saved = [std::env::var_os("NO_COLOR"), std::env::var_os("CLICOLOR_FORCE")];
+        assert!(colored(None, Some("1")));
+        assert!(!colored(Some("1"), Some("1")));
+        assert!(colored(Some(""), Some("1")));
+        assert!(!colored(None, Some("0")));
+        assert!(!colored(None, Some("")));
+        // Read once until reset.
+        assert!(colored(None, Some("1")));
+        std::env::remove_var("CLICOLOR_FORCE");
+        assert_eq!(resolve(false), ColorChoice::Always);
+        reset_color_choice();
+        assert_eq!((resolve(false), resolve(true)), (ColorChoice::Never, ColorChoice::Auto));
+        std::env::set_var("NO_COLOR", "1");
+        set_color_choice(ColorChoice::Always);
+        assert_eq!(resolve(false), ColorChoice::Always);
+        reset_color_choice();
+        assert_eq!(resolve(true), ColorChoice::Never);
+        for (key, value) in ["NO_COLOR", "CLICOLOR_FORCE"].into_iter().zip(saved) {
+            match value {
+                Some(value) => std::env::set_var(key, value),
+                None => std::env::remove_var(key),
+            }
+        }
+        reset_color_choice();
+    }
+
+    #[test]
+    fn decorations_go_to_the_installed_writer() {
+        let text = crate::test_support::decorations(|| {
+            crate::cmd("echo").arg("sink-buffer").exec().unwrap();
+        });
+        let cwd = std::env::current_dir().unwrap();
+        let banner = format!("\x1b[0m\x1b[30m\x1b[46m{}\x1b[0m echo sink-buffer\n", cwd.display());
+        assert!(text.contains(&banner), "{:?}", text);
+        assert!(text.contains(" END OUTPUT ("), "{:?}", text);
+
+        let _serial = serial();
+        set_decoration_writer(termcolor::NoColor::new(Vec::new()));
+        crate::cmd("echo").arg("sink-no-color").exec().unwrap();
+        let text = take_decoration_writer::<termcolor::NoColor<Vec<u8>>>().unwrap().into_inner();
+        let text = String::from_utf8(text).unwrap();
+        let banner = format!("{} echo sink-no-color\n", cwd.display());
+        assert!(text.contains(&banner) && !text.contains('\x1b'), "{:?}", text);
+        assert!(take_decoration_writer::<termcolor::NoColor<Vec<u8>>>().is_none());
+    }
+}
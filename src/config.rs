@@ -0,0 +1,229 @@
+use std::time::Duration;
+
+use termcolor::ColorChoice;
+
+use crate::{
+    color, heartbeat, redact, scoped_env, slow, stats, theme, timestamp, Theme, TimestampFormat,
+};
+
+/// Several of the process-wide settings at once, set with `apply` or, until the returned guard
+/// is dropped, with `scoped`. Each setting is the same as the function it names, and settings
+/// that aren't given are left as they are.
+///
+/// `scoped` restores every setting `Config` covers to what it was when the guard was created,
+/// including ones changed by the individual functions in the meantime. The settings are shared
+/// by every thread, so a scoped config applies to commands run by other threads too. Since each
+/// `ConfigGuard` holds a full snapshot, dropping an outer guard before an inner one brings back
+/// the outer snapshot, which the inner guard then overwrites with the settings of the outer
+/// config when it is dropped.
+#[derive(Clone, Debug, Default)]
+pub struct Config {
+    color: Option<ColorChoice>,
+    decorations: Option<bool>,
+    dry_run: Option<bool>,
+    heartbeat_template: Option<String>,
+    heartbeat_when_not_terminal: Option<bool>,
+    output_summary: Option<bool>,
+    redacted_env_keys: Option<Vec<String>>,
+    redacted_values: Option<Vec<String>>,
+    show_scoped_env: Option<bool>,
+    slow_command_warning: Option<Option<Duration>>,
+    stats: Option<bool>,
+    theme: Option<Theme>,
+    timestamps: Option<Option<TimestampFormat>>,
+}
+
+impl Config {
+    pub fn new() -> Self {
+        Config::default()
+    }
+
+    /// `set_color_choice`
+    pub fn color(self, choice: ColorChoice) -> Self {
+        Config { color: Some(choice), ..self }
+    }
+
+    /// `set_decorations_enabled`
+    pub fn decorations(self, enabled: bool) -> Self {
+        Config { decorations: Some(enabled), ..self }
+    }
+
+    /// `set_dry_run`
+    pub fn dry_run(self, enabled: bool) -> Self {
+        Config { dry_run: Some(enabled), ..self }
+    }
+
+    /// `set_heartbeat_template`
+    pub fn heartbeat_template(self, template: impl Into<String>) -> Self {
+        Config { heartbeat_template: Some(template.into()), ..self }
+    }
+
+    /// `set_heartbeat_when_not_terminal`
+    pub fn heartbeat_when_not_terminal(self, enabled: bool) -> Self {
+        Config { heartbeat_when_not_terminal: Some(enabled), ..self }
+    }
+
+    /// `set_output_summary`
+    pub fn output_summary(self, enabled: bool) -> Self {
+        Config { output_summary: Some(enabled), ..self }
+    }
+
+    /// `set_redacted_env_keys`
+    pub fn redact_env<I, S>(self, keys: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let keys = keys.into_iter().map(Into::into).collect();
+        Config { redacted_env_keys: Some(keys), ..self }
+    }
+
+    /// `set_redacted_values`
+    pub fn redact_values<I, S>(self, values: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let values = values.into_iter().map(Into::into).collect();
+        Config { redacted_values: Some(values), ..self }
+    }
+
+    /// `set_show_scoped_env`
+    pub fn show_scoped_env(self, enabled: bool) -> Self {
+        Config { show_scoped_env: Some(enabled), ..self }
+    }
+
+    /// `set_slow_command_warning`
+    pub fn slow_command_warning(self, after: Option<Duration>) -> Self {
+        Config { slow_command_warning: Some(after), ..self }
+    }
+
+    /// `set_stats_enabled`
+    pub fn stats(self, enabled: bool) -> Self {
+        Config { stats: Some(enabled), ..self }
+    }
+
+    /// `set_theme`
+    pub fn theme(self, theme: Theme) -> Self {
+        Config { theme: Some(theme), ..self }
+    }
+
+    /// `set_timestamps`
+    pub fn timestamps(self, format: Option<TimestampFormat>) -> Self {
+        Config { timestamps: Some(format), ..self }
+    }
+
+    pub fn apply(self) {
+        if let Some(choice) = self.color {
+            crate::set_color_choice(choice);
+        }
+        if let Some(enabled) = self.decorations {
+            crate::set_decorations_enabled(enabled);
+        }
+        if let Some(enabled) = self.dry_run {
+            crate::set_dry_run(enabled);
+        }
+        if let Some(template) = self.heartbeat_template {
+            crate::set_heartbeat_template(template);
+        }
+        if let Some(enabled) = self.heartbeat_when_not_terminal {
+            crate::set_heartbeat_when_not_terminal(enabled);
+        }
+        if let Some(enabled) = self.output_summary {
+            crate::set_output_summary(enabled);
+        }
+        if let Some(keys) = self.redacted_env_keys {
+            crate::set_redacted_env_keys(keys);
+        }
+        if let Some(values) = self.redacted_values {
+            crate::set_redacted_values(values);
+        }
+        if let Some(enabled) = self.show_scoped_env {
+            crate::set_show_scoped_env(enabled);
+        }
+        if let Some(after) = self.slow_command_warning {
+            crate::set_slow_command_warning(after);
+        }
+        if let Some(enabled) = self.stats {
+            crate::set_stats_enabled(enabled);
+        }
+        if let Some(theme) = self.theme {
+            crate::set_theme(theme);
+        }
+        if let Some(format) = self.timestamps {
+            crate::set_timestamps(format);
+        }
+    }
+
+    pub fn scoped(self) -> ConfigGuard {
+        let previous = Settings::current();
+        self.apply();
+        ConfigGuard { previous: Some(previous) }
+    }
+}
+
+#[must_use = "the previous settings are restored as soon as the guard is dropped"]
+pub struct ConfigGuard {
+    previous: Option<Settings>,
+}
+
+impl Drop for ConfigGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            previous.restore();
+        }
+    }
+}
+
+// Everything `Config` can set, as stored, so that unset defaults are restored as unset.
+struct Settings {
+    color: Option<ColorChoice>,
+    decorations: bool,
+    dry_run: bool,
+    heartbeat_template: Option<String>,
+    heartbeat_when_not_terminal: bool,
+    output_summary: bool,
+    redacted_env_keys: Option<Vec<String>>,
+    redacted_values: Vec<String>,
+    show_scoped_env: bool,
+    slow_command_warning: Option<Duration>,
+    stats: bool,
+    theme: Option<Theme>,
+    timestamps: Option<TimestampFormat>,
+}
+
+impl Settings {
+    fn current() -> Self {
+        let (redacted_env_keys, redacted_values) = redact::settings();
+        Settings {
+            color: color::color_override(),
+            decorations: color::decorations_enabled(),
+            dry_run: crate::is_dry_run(),
+            heartbeat_template: heartbeat::template_override(),
+            heartbeat_when_not_terminal: heartbeat::when_not_terminal(),
+            output_summary: crate::output_summary_enabled(),
+            redacted_env_keys,
+            redacted_values,
+            show_scoped_env: scoped_env::show_scoped_env(),
+            slow_command_warning: slow::default_threshold(),
+            stats: stats::stats_enabled(),
+            theme: theme::theme_override(),
+            timestamps: timestamp::format(),
+        }
+    }
+
+    fn restore(self) {
+        color::set_color_override(self.color);
+        crate::set_decorations_enabled(self.decorations);
+        crate::set_dry_run(self.dry_run);
+        heartbeat::set_template_override(self.heartbeat_template);
+        crate::set_heartbeat_when_not_terminal(self.heartbeat_when_not_terminal);
+        crate::set_output_summary(self.output_summary);
+        redact::restore(self.redacted_env_keys, self.redacted_values);
+        crate::set_show_scoped_env(self.show_scoped_env);
+        crate::set_slow_command_warning(self.slow_command_warning);
+        crate::set_stats_enabled(self.stats);
+        theme::set_theme_override(self.theme);
+        crate::set_timestamps(self.timestamps);
+    }
+}
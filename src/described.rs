@@ -0,0 +1,162 @@
+use std::ops::{Deref, DerefMut};
+use std::process::{Child, ChildStderr, ChildStdin, ChildStdout, ExitStatus};
+
+use anyhow::Context;
+
+use crate::execution::BackgroundExecution;
+use crate::{backend, exec_failed_with, CommandSnapshot, StatusSummary};
+
+/// A `std::process::Child` spawned by `spawn_described`, for APIs that need the child itself. It
+/// keeps a snapshot of the command's description for the errors of its methods, and derefs to the
+/// `Child` for everything else. Like a `Child`, the process is left running when this is dropped.
+pub struct DescribedChild {
+    child: Child,
+    description: String,
+    snapshot: CommandSnapshot,
+    #[cfg_attr(not(unix), allow(dead_code))]
+    group: bool,
+    // Finished by the methods that wait, and dropped unfinished if the `Child` is waited for
+    // directly or handed out with `into_child`.
+    execution: BackgroundExecution,
+}
+
+impl DescribedChild {
+    pub(crate) fn new(
+        child: Child,
+        description: String,
+        snapshot: CommandSnapshot,
+        group: bool,
+        execution: BackgroundExecution,
+    ) -> Self {
+        DescribedChild { child, description, snapshot, group, execution }
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn snapshot(&self) -> &CommandSnapshot {
+        &self.snapshot
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    pub fn take_stdin(&mut self) -> Option<ChildStdin> {
+        self.child.stdin.take()
+    }
+
+    pub fn take_stdout(&mut self) -> Option<ChildStdout> {
+        self.child.stdout.take()
+    }
+
+    pub fn take_stderr(&mut self) -> Option<ChildStderr> {
+        self.child.stderr.take()
+    }
+
+    pub fn wait(&mut self) -> anyhow::Result<ExitStatus> {
+        let status = self.child.wait();
+        self.execution.finish(status.as_ref().ok(), None);
+        status.with_context(|| format!("Failed to wait for command ({})", self.description))
+    }
+
+    pub fn try_wait(&mut self) -> anyhow::Result<Option<ExitStatus>> {
+        let status = self.child.try_wait();
+        if let Ok(Some(status)) = &status {
+            self.execution.finish(Some(status), None);
+        }
+        status.with_context(|| format!("Failed to wait for command ({})", self.description))
+    }
+
+    /// Kills the process, or its whole group with `new_process_group`, without waiting for it.
+    pub fn kill(&mut self) -> anyhow::Result<()> {
+        #[cfg(unix)]
+        if self.group {
+            backend::kill_group(self.child.id());
+        }
+        self.child.kill().with_context(|| format!("Failed to kill command ({})", self.description))
+    }
+
+    /// Like `wait`, but an unsuccessful exit is an error, with a `CmdError` as for `exec`. Output
+    /// the caller has piped and not read is not part of it.
+    pub fn wait_checked(&mut self) -> anyhow::Result<ExitStatus> {
+        let status = self.wait()?;
+        if !status.success() {
+            let message = format!(
+                "Process did not exit successfully, {} ({})",
+                StatusSummary(status),
+                self.description,
+            );
+            let snapshot = self.snapshot.clone();
+            return Err(exec_failed_with(&self.description, snapshot, status, &[], &[], message));
+        }
+        Ok(status)
+    }
+
+    pub fn into_child(self) -> Child {
+        self.child
+    }
+}
+
+impl Deref for DescribedChild {
+    type Target = Child;
+
+    fn deref(&self) -> &Child {
+        &self.child
+    }
+}
+
+impl DerefMut for DescribedChild {
+    fn deref_mut(&mut self) -> &mut Child {
+        &mut self.child
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Read;
+    use std::process::Stdio;
+
+    use crate::test_support::{self, record_exits};
+    use crate::{cmd, shell, CmdError, CommandExt};
+
+    #[test]
+    fn wait_checked_finishes_the_execution() {
+        let script = "exit 6 # described wait";
+        let (_guard, exits) = record_exits(script);
+        let mut child = shell(script).spawn_described().unwrap();
+        let err = child.wait_checked().unwrap_err();
+        assert_eq!(err.downcast_ref::<CmdError>().unwrap().exit_code(), Some(6));
+        assert_eq!(*exits.lock().unwrap(), [Some(6)]);
+    }
+
+    #[test]
+    fn wait_checked_errors_name_the_program_and_args() {
+        let mut child =
+            cmd("sh").args(["-c", "exit 2", "described-args"]).spawn_described().unwrap();
+        let message = format!("{:#}", child.wait_checked().unwrap_err());
+        assert!(message.contains("exit code 2"), "{}", message);
+        assert!(message.contains(r#"program = "sh""#), "{}", message);
+        assert!(message.contains(r#"args = ["-c", "exit 2", "described-args"]"#), "{}", message);
+        assert_eq!(child.snapshot().args, ["-c", "exit 2", "described-args"]);
+    }
+
+    #[test]
+    fn the_child_is_still_at_hand() {
+        let mut child = None;
+        let text = test_support::decorations(|| {
+            let mut echo = cmd("echo").arg_("described-child").label("described");
+            echo.stdout(Stdio::piped());
+            child = Some(echo.spawn_described().unwrap());
+        });
+        assert!(text.contains("[described]") && text.contains("echo described-child"), "{}", text);
+        let mut child = child.unwrap();
+        assert_eq!(child.id(), std::process::Child::id(&child));
+        let mut stdout = String::new();
+        child.take_stdout().unwrap().read_to_string(&mut stdout).unwrap();
+        assert_eq!(stdout, "described-child\n");
+        assert!(child.wait_checked().unwrap().success());
+        assert!(child.try_wait().unwrap().is_some());
+    }
+}
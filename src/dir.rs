@@ -0,0 +1,70 @@
+use std::env;
+use std::marker::PhantomData;
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::warn;
+
+/// Changes the current directory of the process to `dir` until the returned guard is dropped or
+/// restored, for running a group of commands from one place.
+///
+/// The current directory is shared by every thread of the process: while a guard is alive, any
+/// other thread resolving a relative path or spawning a command without its own `current_dir`
+/// sees `dir` instead of the directory it expects, so `DirGuard` isn't `Send`. Nested `pushd`
+/// calls unwind like a stack: dropping an outer guard before an inner one changes back to the
+/// outer guard's starting point, and the inner guard then changes back into the outer `dir`.
+pub fn pushd(dir: impl AsRef<Path>) -> anyhow::Result<DirGuard> {
+    let dir = dir.as_ref();
+    let previous = env::current_dir().context("Failed to read the current directory")?;
+    env::set_current_dir(dir).with_context(|| {
+        format!(
+            "Failed to change the current directory from {} to {}",
+            previous.display(),
+            dir.display(),
+        )
+    })?;
+    let dir = env::current_dir().unwrap_or_else(|_| dir.to_path_buf());
+    Ok(DirGuard { previous: Some(previous), dir, _not_send: PhantomData })
+}
+
+#[must_use = "the previous directory is restored as soon as the guard is dropped"]
+pub struct DirGuard {
+    previous: Option<PathBuf>,
+    dir: PathBuf,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl DirGuard {
+    /// The directory the guard changes back to.
+    pub fn previous(&self) -> &Path {
+        self.previous.as_deref().unwrap()
+    }
+
+    /// Changes back to the previous directory, unlike dropping the guard, which only prints a
+    /// warning if that fails, for example because the directory was deleted in the meantime.
+    pub fn restore(mut self) -> anyhow::Result<()> {
+        let previous = self.previous.take().unwrap();
+        restore(&self.dir, &previous)
+    }
+}
+
+impl Drop for DirGuard {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            if let Err(e) = restore(&self.dir, &previous) {
+                warn(format_args!("{:#}", e));
+            }
+        }
+    }
+}
+
+fn restore(dir: &Path, previous: &Path) -> anyhow::Result<()> {
+    env::set_current_dir(previous).with_context(|| {
+        format!(
+            "Failed to change the current directory back from {} to {}",
+            dir.display(),
+            previous.display(),
+        )
+    })
+}
@@ -0,0 +1,283 @@
+use std::io::{self, BufRead, BufReader, Write};
+use std::process::{Command, ExitStatus};
+use std::sync::mpsc::{self, Receiver, RecvTimeoutError};
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+
+use crate::backend::Process;
+use crate::capture::Captured;
+use crate::child::{self, OutputReaders};
+use crate::execution::DetachedExecution;
+use crate::{copy_command, invalid_utf8, CommandExt, HumanDuration, Output, Stream};
+
+/// A running command to hold a dialog with, returned by `spawn_duplex`: lines are written to its
+/// stdin while its stdout is read line by line. Stdout and stderr are read by threads of their
+/// own as soon as they are written, so the command never blocks on a full pipe whatever is read
+/// or written. A line of stdout is only seen once it ends, with a newline or with stdout.
+///
+/// Dropping it before `finish` kills the command and waits for it.
+pub struct Duplex {
+    command: Command,
+    child: Process,
+    stdin: Option<Box<dyn Write + Send>>,
+    lines: Receiver<io::Result<Vec<u8>>>,
+    stdout_reader: Option<JoinHandle<()>>,
+    stderr: Option<OutputReaders>,
+    // Every line read so far, with its line terminator, for the `Output` of `finish`.
+    stdout: Vec<u8>,
+    execution: Option<DetachedExecution>,
+    status: Option<ExitStatus>,
+    line_number: usize,
+}
+
+impl Duplex {
+    pub(crate) fn new(command: Command, mut child: Process, execution: DetachedExecution) -> Self {
+        let stdin = child.take_stdin();
+        let (sender, lines) = mpsc::channel();
+        let stdout_reader = child.take_stdout().map(|stdout| {
+            thread::spawn(move || {
+                let mut stdout = BufReader::new(stdout);
+                loop {
+                    let mut line = Vec::new();
+                    let read = match stdout.read_until(b'\n', &mut line) {
+                        Ok(0) => return,
+                        Ok(_) => Ok(line),
+                        Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                        Err(e) => Err(e),
+                    };
+                    let failed = read.is_err();
+                    if sender.send(read).is_err() || failed {
+                        return;
+                    }
+                }
+            })
+        });
+        // Only stderr is left for the readers.
+        let stderr = child::spawn_output_readers(&mut child);
+        Duplex {
+            command,
+            child,
+            stdin,
+            lines,
+            stdout_reader,
+            stderr: Some(stderr),
+            stdout: Vec::new(),
+            execution: Some(execution),
+            status: None,
+            line_number: 0,
+        }
+    }
+
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /// Writes `line` and a newline to stdin, and flushes it.
+    pub fn send_line(&mut self, line: &str) -> anyhow::Result<()> {
+        let description = self.command.description();
+        let stdin = self
+            .stdin
+            .as_mut()
+            .with_context(|| format!("Process stdin is closed ({})", description))?;
+        stdin
+            .write_all(line.as_bytes())
+            .and_then(|()| stdin.write_all(b"\n"))
+            .and_then(|()| stdin.flush())
+            .with_context(|| format!("Failed to write to process stdin ({})", description))
+    }
+
+    /// The next line of stdout without its line terminator, waiting for it as long as it takes.
+    /// `None` once stdout has ended.
+    pub fn read_line(&mut self) -> anyhow::Result<Option<String>> {
+        match self.lines.recv() {
+            Ok(line) => self.line(line).map(Some),
+            Err(_) => Ok(None),
+        }
+    }
+
+    /// Reads lines of stdout until one contains `needle`, and returns it. Fails if none does
+    /// within `timeout` or before stdout ends. The lines before it are skipped.
+    pub fn expect_line_containing(
+        &mut self,
+        needle: &str,
+        timeout: Duration,
+    ) -> anyhow::Result<String> {
+        let deadline = Instant::now() + timeout;
+        let mut last = None;
+        loop {
+            let line =
+                match self.lines.recv_timeout(deadline.saturating_duration_since(Instant::now())) {
+                    Ok(line) => self.line(line)?,
+                    Err(RecvTimeoutError::Timeout) => anyhow::bail!(
+                        "No line of process stdout contained {:?} within {} ({}, last line = {:?})",
+                        needle,
+                        HumanDuration(timeout),
+                        self.command.description(),
+                        last,
+                    ),
+                    Err(RecvTimeoutError::Disconnected) => anyhow::bail!(
+                    "Process stdout ended without a line containing {:?} ({}, last line = {:?})",
+                    needle,
+                    self.command.description(),
+                    last,
+                ),
+                };
+            if line.contains(needle) {
+                return Ok(line);
+            }
+            last = Some(line);
+        }
+    }
+
+    fn line(&mut self, line: io::Result<Vec<u8>>) -> anyhow::Result<String> {
+        let mut line = line.with_context(|| {
+            format!("Failed to read process stdout ({})", self.command.description())
+        })?;
+        self.stdout.extend_from_slice(&line);
+        self.line_number += 1;
+        if line.ends_with(b"\n") {
+            line.pop();
+            if line.ends_with(b"\r") {
+                line.pop();
+            }
+        }
+        String::from_utf8(line).map_err(|e| {
+            let context = format!(
+                "Line {} of process stdout is not UTF-8 ({}, line = {:?})",
+                self.line_number,
+                self.command.description(),
+                String::from_utf8_lossy(e.as_bytes()),
+            );
+            invalid_utf8(&self.command, Stream::Stdout, e.utf8_error(), context)
+        })
+    }
+
+    /// Closes stdin, reads the rest of stdout and stderr, and waits for the command. The output
+    /// holds all of stdout, including the lines already read.
+    pub fn finish(mut self) -> anyhow::Result<Output> {
+        drop(self.stdin.take());
+        while let Ok(line) = self.lines.recv() {
+            match line {
+                Ok(line) => self.stdout.extend_from_slice(&line),
+                Err(_) => break,
+            }
+        }
+        if let Some(reader) = self.stdout_reader.take() {
+            let _ = reader.join();
+        }
+        let status = self.child.wait();
+        let (_, stderr) = self.stderr.take().map(OutputReaders::join).unwrap_or_default();
+        let stdout = std::mem::take(&mut self.stdout);
+        let duration = match self.execution.take() {
+            Some(execution) => {
+                execution.finish(&self.command, status.as_ref().ok(), Some((&stdout, &stderr)))
+            }
+            None => Duration::ZERO,
+        };
+        let status = status.with_context(|| {
+            format!("Failed to wait for command ({})", self.command.description())
+        })?;
+        self.status = Some(status);
+        let captured = Captured::new(status, stdout, stderr, duration);
+        captured.check(&self.command)?;
+        captured.into_output(copy_command(&self.command))
+    }
+}
+
+impl Drop for Duplex {
+    fn drop(&mut self) {
+        if self.status.is_some() {
+            return;
+        }
+        drop(self.stdin.take());
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+        if let Some(stderr) = self.stderr.take() {
+            stderr.join_timeout(child::ORPHANED_PIPE_TIMEOUT);
+        }
+        if let Some(execution) = self.execution.take() {
+            execution.finish(&self.command, None, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cmd, shell};
+
+    const SECOND: Duration = Duration::from_secs(1);
+
+    #[test]
+    fn a_dialog_with_cat() {
+        let mut cat = cmd("cat").spawn_duplex().unwrap();
+        cat.send_line("hello").unwrap();
+        assert_eq!(cat.read_line().unwrap().as_deref(), Some("hello"));
+        cat.send_line("one").unwrap();
+        cat.send_line("two").unwrap();
+        assert_eq!(cat.expect_line_containing("tw", SECOND).unwrap(), "two");
+        cat.send_line("left unread").unwrap();
+        let out = cat.finish().unwrap();
+        assert_eq!(out.stdout, "hello\none\ntwo\nleft unread\n");
+    }
+
+    #[test]
+    fn a_scripted_installer() {
+        let script = r#"
+            echo 'Install to?'; read dir
+            echo "Installing to $dir" >&2
+            printf 'Continue? [y/n]\r\n'; read answer
+            [ "$answer" = y ] && echo done || exit 5
+        "#;
+        let mut installer = shell(script).spawn_duplex().unwrap();
+        installer.expect_line_containing("Install to", SECOND).unwrap();
+        installer.send_line("/opt/tool").unwrap();
+        assert_eq!(installer.read_line().unwrap().as_deref(), Some("Continue? [y/n]"));
+        installer.send_line("y").unwrap();
+        assert_eq!(installer.read_line().unwrap().as_deref(), Some("done"));
+        assert_eq!(installer.read_line().unwrap(), None);
+        let out = installer.finish().unwrap();
+        assert_eq!(out.stderr, b"Installing to /opt/tool\n");
+
+        let mut refused = shell(script).spawn_duplex().unwrap();
+        refused.send_line("/opt/tool").unwrap();
+        refused.send_line("n").unwrap();
+        let message = format!("{:#}", refused.finish().err().unwrap());
+        assert!(message.contains("exit code 5") && message.contains("Continue?"), "{}", message);
+    }
+
+    #[test]
+    fn expectations_that_are_not_met() {
+        let mut quiet = shell("echo first; sleep 10").spawn_duplex().unwrap();
+        let err = quiet.expect_line_containing("never", Duration::from_millis(100)).unwrap_err();
+        let message = err.to_string();
+        assert!(message.starts_with(r#"No line of process stdout contained "never" within "#));
+        assert!(message.ends_with(r#", last line = Some("first"))"#), "{}", message);
+        // Dropping the dialog kills the command instead of waiting for it.
+        let start = Instant::now();
+        drop(quiet);
+        assert!(start.elapsed() < Duration::from_secs(5));
+
+        let mut ended = shell("echo only").spawn_duplex().unwrap();
+        let message = ended.expect_line_containing("never", SECOND).unwrap_err().to_string();
+        assert!(message.starts_with(r#"Process stdout ended without a line containing "never""#));
+        let mut invalid = shell(r"printf 'ok\n\377\n'").spawn_duplex().unwrap();
+        assert_eq!(invalid.read_line().unwrap().as_deref(), Some("ok"));
+        let message = invalid.read_line().unwrap_err().to_string();
+        assert!(message.starts_with("Line 2 of process stdout is not UTF-8 ("), "{}", message);
+    }
+
+    #[test]
+    fn large_output_does_not_block_writes() {
+        let mut cat = cmd("cat").spawn_duplex().unwrap();
+        let line = "x".repeat(1000);
+        for _ in 0..1000 {
+            cat.send_line(&line).unwrap();
+        }
+        assert_eq!(cat.finish().unwrap().stdout.len(), 1001 * 1000);
+    }
+}
@@ -0,0 +1,170 @@
+use std::borrow::Cow;
+use std::fmt::Display;
+
+use crate::capture::{capture, CaptureOptions, Captured, Check};
+use crate::{
+    exec_failed, CmdError, CommandExt, ConfiguredCommand, Embedded, Output, StatusSummary, Stream,
+};
+
+// Windows-1252 from 0x80 to 0x9F, where it differs from Latin-1. The five bytes it leaves
+// undefined are U+FFFD.
+const WINDOWS_1252_HIGH: &str =
+    "€\u{FFFD}‚ƒ„…†‡ˆ‰Š‹Œ\u{FFFD}Ž\u{FFFD}\u{FFFD}‘’“”•–—˜™š›œ\u{FFFD}žŸ";
+
+/// The encodings output can be decoded from with `exec_stdout_encoded`, besides UTF-8. They are
+/// single-byte encodings, which need no tables beyond the one above. Multi-byte code pages such
+/// as CP936 (GBK) are not supported, and neither is detecting the encoding of the output.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Encoding {
+    Utf8,
+    /// ISO-8859-1, where every byte is the code point of the same value.
+    Latin1,
+    /// The Western European code page of Windows, also known as CP1252.
+    Windows1252,
+}
+
+impl Encoding {
+    /// The text of `bytes`, or the offset of the first byte that isn't valid in the encoding.
+    pub fn decode(self, bytes: &[u8]) -> Result<Cow<'_, str>, usize> {
+        match self {
+            Encoding::Utf8 => {
+                std::str::from_utf8(bytes).map(Cow::Borrowed).map_err(|e| e.valid_up_to())
+            }
+            // Borrowed, since ASCII is valid UTF-8.
+            _ if bytes.is_ascii() => Ok(String::from_utf8_lossy(bytes)),
+            Encoding::Latin1 => Ok(Cow::Owned(bytes.iter().map(|&b| char::from(b)).collect())),
+            Encoding::Windows1252 => bytes
+                .iter()
+                .enumerate()
+                .map(|(i, &b)| match b {
+                    0x80..=0x9F => WINDOWS_1252_HIGH
+                        .chars()
+                        .nth(usize::from(b - 0x80))
+                        .filter(|&c| c != char::REPLACEMENT_CHARACTER)
+                        .ok_or(i),
+                    b => Ok(char::from(b)),
+                })
+                .collect::<Result<String, usize>>()
+                .map(Cow::Owned),
+        }
+    }
+}
+
+impl Display for Encoding {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(match self {
+            Encoding::Utf8 => "UTF-8",
+            Encoding::Latin1 => "Latin-1",
+            Encoding::Windows1252 => "Windows-1252",
+        })
+    }
+}
+
+pub(crate) fn exec_stdout_encoded(
+    cmd: ConfiguredCommand,
+    encoding: Encoding,
+) -> anyhow::Result<Output> {
+    let mut cmd = cmd;
+    let options = CaptureOptions { check: Check::Unchecked, ..CaptureOptions::default() };
+    let Captured { status, stdout, stderr, duration, .. } = capture(&mut cmd, options)?;
+    // Stderr is decoded as far as it can be, since it is only for people to read.
+    let stderr = match encoding.decode(&stderr) {
+        Ok(decoded) => decoded.into_owned().into_bytes(),
+        Err(_) => stderr,
+    };
+    if !status.success() {
+        let decoded_stdout = encoding.decode(&stdout).map(Cow::into_owned);
+        let shown_stdout = decoded_stdout.as_ref().map_or(&stdout[..], |s| s.as_bytes());
+        return Err(exec_failed(
+            &cmd,
+            status,
+            &stdout,
+            &stderr,
+            format!(
+                "Process did not exit successfully, {} ({}, stdout = {}, stderr = {})",
+                StatusSummary(status),
+                cmd.description(),
+                Embedded::new(shown_stdout),
+                Embedded::new(&stderr),
+            ),
+        ));
+    }
+    let stdout = match encoding.decode(&stdout) {
+        Ok(decoded) => decoded.into_owned(),
+        Err(offset) => {
+            let message = format!(
+                "Process stdout is not valid {} at byte {} of {} ({}, stderr = {})",
+                encoding,
+                offset,
+                stdout.len(),
+                cmd.description(),
+                Embedded::new(&stderr),
+            );
+            let undecodable = CmdError::Undecodable {
+                stream: Stream::Stdout,
+                encoding,
+                description: cmd.description().to_snapshot(),
+                offset,
+                bytes: stdout,
+            };
+            return Err(anyhow::Error::new(undecodable).context(message));
+        }
+    };
+    Ok(Output::new(cmd.into_command(), status, stdout, stderr, duration))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::path::PathBuf;
+
+    use super::Encoding;
+    use crate::{cmd, shell, CmdError, CommandExt, Stream};
+
+    fn fixture() -> PathBuf {
+        PathBuf::from(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/cp1252.txt")
+    }
+
+    #[test]
+    fn decodes_windows_1252_from_a_child() {
+        let out = cmd("cat").arg_(fixture()).exec_stdout_encoded(Encoding::Windows1252).unwrap();
+        assert_eq!(out.stdout, "café “quoted” € 5\n");
+        let out = cmd("cat").arg_(fixture()).exec_stdout_encoded(Encoding::Latin1).unwrap();
+        assert_eq!(out.stdout, "café \u{93}quoted\u{94} \u{80} 5\n");
+    }
+
+    #[test]
+    fn invalid_bytes_are_reported_with_their_offset() {
+        let e = cmd("cat").arg_(fixture()).exec_stdout_encoded(Encoding::Utf8).err().unwrap();
+        let message = format!("{:#}", e);
+        assert!(
+            message.starts_with("Process stdout is not valid UTF-8 at byte 3 of 18 ("),
+            "{}",
+            message
+        );
+        assert!(message.contains("cp1252.txt"), "{}", message);
+        match e.downcast_ref::<CmdError>() {
+            Some(CmdError::Undecodable { stream, encoding, offset, bytes, .. }) => {
+                assert_eq!((*stream, *encoding, *offset), (Stream::Stdout, Encoding::Utf8, 3));
+                assert_eq!(bytes, &std::fs::read(fixture()).unwrap());
+            }
+            other => panic!("{:?}", other),
+        }
+
+        // 0x81 is one of the bytes Windows-1252 leaves undefined.
+        let e =
+            shell(r"printf 'ok \201'").exec_stdout_encoded(Encoding::Windows1252).err().unwrap();
+        let message = format!("{:#}", e);
+        assert!(message.contains("not valid Windows-1252 at byte 3 of 4"), "{}", message);
+        assert!(message.contains("printf"), "{}", message);
+    }
+
+    #[test]
+    fn failures_show_the_decoded_output() {
+        let script =
+            format!("cat '{}'; cat '{}' >&2; exit 3", fixture().display(), fixture().display());
+        let e = shell(&script).exec_stdout_encoded(Encoding::Windows1252).err().unwrap();
+        let message = format!("{:#}", e);
+        assert!(message.contains("exit code 3"), "{}", message);
+        assert_eq!(message.matches("café “quoted” €").count(), 2, "{}", message);
+    }
+}
@@ -0,0 +1,245 @@
+use std::fmt::Display;
+use std::io;
+use std::process::ExitStatus;
+use std::str::Utf8Error;
+use std::time::Duration;
+
+#[cfg(feature = "encoding")]
+use crate::Encoding;
+use crate::{exit_signal, CommandSnapshot, StatusSummary};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Stream {
+    Stdout,
+    Stderr,
+}
+
+/// Attached to the error chain of every failed `CommandExt` execution, so the reason can be
+/// inspected with `err.downcast_ref::<CmdError>()` instead of matching on messages.
+#[derive(Debug)]
+pub enum CmdError {
+    SpawnFailed {
+        source: io::Error,
+        description: CommandSnapshot,
+    },
+    UnsuccessfulExit {
+        status: ExitStatus,
+        description: CommandSnapshot,
+        stdout: Vec<u8>,
+        stderr: Vec<u8>,
+    },
+    InvalidUtf8 {
+        stream: Stream,
+        description: CommandSnapshot,
+        source: Utf8Error,
+    },
+    /// Output that isn't valid in the encoding given to `exec_stdout_encoded`. `offset` is that
+    /// of the first invalid byte in `bytes`, the whole output.
+    #[cfg(feature = "encoding")]
+    Undecodable {
+        stream: Stream,
+        encoding: Encoding,
+        description: CommandSnapshot,
+        offset: usize,
+        bytes: Vec<u8>,
+    },
+    TimedOut {
+        timeout: Duration,
+        description: CommandSnapshot,
+    },
+    /// The child died of a Ctrl-C noted by `install_interrupt_handler`.
+    Interrupted {
+        status: ExitStatus,
+        description: CommandSnapshot,
+    },
+}
+
+impl CmdError {
+    pub fn description(&self) -> &CommandSnapshot {
+        match self {
+            CmdError::SpawnFailed { description, .. }
+            | CmdError::UnsuccessfulExit { description, .. }
+            | CmdError::InvalidUtf8 { description, .. }
+            | CmdError::TimedOut { description, .. }
+            | CmdError::Interrupted { description, .. } => description,
+            #[cfg(feature = "encoding")]
+            CmdError::Undecodable { description, .. } => description,
+        }
+    }
+
+    pub fn status(&self) -> Option<ExitStatus> {
+        match self {
+            CmdError::UnsuccessfulExit { status, .. } | CmdError::Interrupted { status, .. } => {
+                Some(*status)
+            }
+            _ => None,
+        }
+    }
+
+    /// The exit code of an unsuccessful or interrupted exit, if the process exited normally.
+    pub fn exit_code(&self) -> Option<i32> {
+        self.status().and_then(|status| status.code())
+    }
+
+    /// The signal that killed the process of an unsuccessful or interrupted exit, on Unix.
+    pub fn signal(&self) -> Option<i32> {
+        self.status().and_then(exit_signal)
+    }
+}
+
+impl Display for Stream {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Stream::Stdout => write!(f, "stdout"),
+            Stream::Stderr => write!(f, "stderr"),
+        }
+    }
+}
+
+impl Display for CmdError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CmdError::SpawnFailed { description, .. } => {
+                write!(f, "Failed to spawn process ({})", description)
+            }
+            CmdError::UnsuccessfulExit { status, description, .. } => {
+                write!(
+                    f,
+                    "Process exited unsuccessfully, {} ({})",
+                    StatusSummary(*status),
+                    description
+                )
+            }
+            CmdError::InvalidUtf8 { stream, description, .. } => {
+                write!(f, "Process {} is not UTF-8 ({})", stream, description)
+            }
+            #[cfg(feature = "encoding")]
+            CmdError::Undecodable { stream, encoding, description, offset, bytes } => {
+                write!(
+                    f,
+                    "Process {} is not valid {} at byte {} of {} ({})",
+                    stream,
+                    encoding,
+                    offset,
+                    bytes.len(),
+                    description
+                )
+            }
+            CmdError::TimedOut { timeout, description } => {
+                write!(f, "Process timed out after {:?} ({})", timeout, description)
+            }
+            CmdError::Interrupted { status, description } => {
+                write!(f, "Process interrupted, {} ({})", StatusSummary(*status), description)
+            }
+        }
+    }
+}
+
+impl std::error::Error for CmdError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            CmdError::SpawnFailed { source, .. } => Some(source),
+            CmdError::InvalidUtf8 { source, .. } => Some(source),
+            _ => None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::{cmd, shell, CommandExt};
+
+    fn cmd_error(err: &anyhow::Error) -> &CmdError {
+        err.downcast_ref::<CmdError>().unwrap_or_else(|| panic!("no CmdError in {:#}", err))
+    }
+
+    #[test]
+    fn spawn_failures() {
+        let err = cmd("cmd-utils-no-such-program").arg("x").exec().err().unwrap();
+        match cmd_error(&err) {
+            CmdError::SpawnFailed { source, description } => {
+                assert_eq!(source.kind(), io::ErrorKind::NotFound);
+                assert_eq!(description.program, "cmd-utils-no-such-program");
+                assert_eq!(description.args, ["x"]);
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(cmd_error(&err).status(), None);
+    }
+
+    #[test]
+    fn unsuccessful_exits() {
+        let mut script = shell("echo out; echo err >&2; exit 3");
+        script.env("CMD_UTILS_ERROR_TEST", "1");
+        let err = script.exec_stdout_string().err().unwrap();
+        match cmd_error(&err) {
+            CmdError::UnsuccessfulExit { status, description, stdout, stderr } => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(description.program, "sh");
+                assert_eq!(description.envs["CMD_UTILS_ERROR_TEST"].as_deref(), Some("1"));
+                assert_eq!((&stdout[..], &stderr[..]), (&b"out\n"[..], &b"err\n"[..]));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!((cmd_error(&err).exit_code(), cmd_error(&err).signal()), (Some(3), None));
+        let message = cmd_error(&err).to_string();
+        assert!(message.starts_with("Process exited unsuccessfully, exit code 3 ("), "{}", message);
+    }
+
+    #[test]
+    fn invalid_utf8() {
+        let err = shell("printf 'a\\377'").exec_stdout_string().err().unwrap();
+        match cmd_error(&err) {
+            CmdError::InvalidUtf8 { stream, source, .. } => {
+                assert_eq!((*stream, source.valid_up_to()), (Stream::Stdout, 1));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(std::error::Error::source(cmd_error(&err)).is_some());
+        assert!(cmd_error(&err).to_string().starts_with("Process stdout is not UTF-8 ("));
+    }
+
+    #[test]
+    fn timeouts() {
+        let timeout = Duration::from_millis(100);
+        let err = cmd("sleep").arg("10").exec_with_timeout(timeout).err().unwrap();
+        match cmd_error(&err) {
+            CmdError::TimedOut { timeout: t, description } => {
+                assert_eq!((*t, &description.args[..]), (timeout, &["10".to_owned()][..]));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert_eq!(cmd_error(&err).status(), None);
+    }
+
+    // A real interrupt needs a Ctrl-C handler for the whole process, so only the accessors and
+    // the message are checked here.
+    #[test]
+    fn interruptions() {
+        let status = shell("exit 130").status().unwrap();
+        let description = cmd("tool").description().to_snapshot();
+        let err = CmdError::Interrupted { status, description };
+        assert_eq!((err.exit_code(), err.description().program.as_str()), (Some(130), "tool"));
+        assert!(err.to_string().starts_with("Process interrupted, exit code 130 ("), "{}", err);
+    }
+
+    #[test]
+    fn exec_failures_name_the_command_and_status() {
+        let err = cmd("sh").args(["-c", "exit 2", "exec-failure"]).exec().err().unwrap();
+        let message = format!("{:#}", err);
+        assert!(
+            message.starts_with("Process did not exit successfully, exit code 2 ("),
+            "{}",
+            message
+        );
+        assert!(message.contains(r#"program = "sh""#), "{}", message);
+        assert!(message.contains(r#"args = ["-c", "exit 2", "exec-failure"]"#), "{}", message);
+        assert_eq!(cmd_error(&err).exit_code(), Some(2));
+        let err = cmd("cmd-utils-no-such-program").exec().err().unwrap();
+        let message = format!("{:#}", err);
+        assert!(message.contains(r#"program = "cmd-utils-no-such-program""#), "{}", message);
+    }
+}
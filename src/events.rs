@@ -0,0 +1,232 @@
+use std::fs::OpenOptions;
+use std::io::Write;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use crate::json::JsonObject;
+use crate::log::truncated_lossy;
+use crate::timestamp::{self, Rfc3339};
+use crate::{exit_signal, warn, CommandExt};
+
+// Read the first time an event could be written, unless a writer was set before.
+const EVENTS_ENV_VAR: &str = "CMD_UTILS_EVENTS";
+
+static EVENT_WRITER: Mutex<Option<Box<dyn Write + Send>>> = Mutex::new(None);
+static ENV_READ: AtomicBool = AtomicBool::new(false);
+static EVENT_WRITE_FAILED: AtomicBool = AtomicBool::new(false);
+static NEXT_ID: AtomicU64 = AtomicU64::new(1);
+
+/// Writes two JSON lines to `writer` for every command executed from now on, one when it starts
+/// and one when it ends. Instead of calling this, the `CMD_UTILS_EVENTS` environment variable can
+/// name a file to append the events to. Banners and `END OUTPUT` markers are still written unless
+/// turned off with `set_decorations_enabled(false)`.
+///
+/// Every event has `event` (`"start"` or `"end"`), `id` (the same for both events of a run, and
+/// unique within the process), and `timestamp` (RFC 3339, see `set_timestamps`). Start events
+/// also have `program`, `args`, `cwd`, and `envs` (the variables set on the command, mapped to
+/// null if removed), redacted as in command descriptions. End events also have `duration_ms`,
+/// `success`, `exit_code` and `signal` (either can be null, and both are if the process could
+/// not be spawned), and `stdout_bytes`, `stderr_bytes`, `stdout` and `stderr` (the first KiB,
+/// converted lossily), which are null for methods that don't capture output. Fields may be added
+/// in later versions, but not removed or changed.
+///
+/// Each event is written with a single call and flushed. A failure to write never fails the
+/// command; a warning is printed to stderr the first time it happens.
+pub fn set_event_writer<W: Write + Send + 'static>(writer: W) {
+    ENV_READ.store(true, Ordering::SeqCst);
+    *EVENT_WRITER.lock().unwrap() = Some(Box::new(writer));
+}
+
+pub fn clear_event_writer() {
+    ENV_READ.store(true, Ordering::SeqCst);
+    *EVENT_WRITER.lock().unwrap() = None;
+}
+
+pub(crate) fn next_id() -> u64 {
+    NEXT_ID.fetch_add(1, Ordering::Relaxed)
+}
+
+pub(crate) fn started(id: u64, cmd: &Command) {
+    emit(|| {
+        let snapshot = cmd.description().to_snapshot();
+        let mut envs = JsonObject::new();
+        for (key, value) in &snapshot.envs {
+            envs.opt_str(key, value.as_deref());
+        }
+        let mut event = header("start", id);
+        event
+            .str("program", &snapshot.program)
+            .str_array("args", snapshot.args.iter().map(|arg| &arg[..]))
+            .opt_str("cwd", snapshot.cwd.as_deref())
+            .raw("envs", &envs.finish());
+        event.finish()
+    });
+}
+
+pub(crate) fn finished(
+    id: u64,
+    duration: Duration,
+    status: Option<&ExitStatus>,
+    output: Option<(&[u8], &[u8])>,
+) {
+    emit(|| {
+        let mut event = header("end", id);
+        event
+            .num("duration_ms", duration.as_secs_f64() * 1000.0)
+            .bool("success", status.is_some_and(ExitStatus::success))
+            .opt_num("exit_code", status.and_then(ExitStatus::code))
+            .opt_num("signal", status.copied().and_then(exit_signal))
+            .opt_num("stdout_bytes", output.map(|(stdout, _)| stdout.len() as f64))
+            .opt_num("stderr_bytes", output.map(|(_, stderr)| stderr.len() as f64))
+            .opt_str("stdout", output.map(|(stdout, _)| truncated_lossy(stdout)).as_deref())
+            .opt_str("stderr", output.map(|(_, stderr)| truncated_lossy(stderr)).as_deref());
+        event.finish()
+    });
+}
+
+fn header(kind: &str, id: u64) -> JsonObject {
+    let now = Rfc3339::new(SystemTime::now(), timestamp::log_format());
+    let mut event = JsonObject::new();
+    event.str("event", kind).num("id", id as f64).str("timestamp", &now.to_string());
+    event
+}
+
+// Builds the event only if there is somewhere to write it.
+fn emit(event: impl FnOnce() -> String) {
+    let mut writer = EVENT_WRITER.lock().unwrap();
+    if !ENV_READ.swap(true, Ordering::SeqCst) {
+        *writer = open_env_file();
+    }
+    let Some(writer) = writer.as_mut() else {
+        return;
+    };
+    let mut line = event();
+    line.push('\n');
+    if let Err(e) = writer.write_all(line.as_bytes()).and_then(|()| writer.flush()) {
+        warn_once(&e);
+    }
+}
+
+fn open_env_file() -> Option<Box<dyn Write + Send>> {
+    let path = std::env::var_os(EVENTS_ENV_VAR).filter(|path| !path.is_empty())?;
+    match OpenOptions::new().create(true).append(true).open(&path) {
+        Ok(file) => Some(Box::new(file)),
+        Err(e) => {
+            warn_once(&format!("{:?}: {}", path, e));
+            None
+        }
+    }
+}
+
+fn warn_once(e: &dyn std::fmt::Display) {
+    if !EVENT_WRITE_FAILED.swap(true, Ordering::SeqCst) {
+        warn(format_args!("Failed to write to the command event stream: {}", e));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use serde_json::Value;
+
+    use super::*;
+    use crate::test_support::serial;
+    use crate::{cmd, shell};
+
+    #[derive(Clone, Default)]
+    struct Shared(Arc<Mutex<Vec<u8>>>);
+
+    impl Write for Shared {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            self.0.lock().unwrap().extend_from_slice(buf);
+            Ok(buf.len())
+        }
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // The events written while `f` runs, of the commands with `marker` among their arguments.
+    fn events(marker: &str, f: impl FnOnce()) -> Vec<(Value, Value)> {
+        let shared = Shared::default();
+        let _serial = serial();
+        set_event_writer(shared.clone());
+        f();
+        clear_event_writer();
+        let text = String::from_utf8(shared.0.lock().unwrap().clone()).unwrap();
+        let lines: Vec<Value> =
+            text.lines().map(|line| serde_json::from_str(line).unwrap()).collect();
+        let ours = |start: &Value| start["args"].as_array().unwrap().iter().any(|a| a == marker);
+        let starts = lines.iter().filter(|e| e["event"] == "start" && ours(e));
+        starts
+            .map(|start| {
+                let end = lines.iter().find(|e| e["event"] == "end" && e["id"] == start["id"]);
+                (start.clone(), end.unwrap_or_else(|| panic!("no end for {}", start)).clone())
+            })
+            .collect()
+    }
+
+    #[test]
+    fn every_run_has_a_start_and_an_end_event() {
+        let events = events("events-test", || {
+            let mut script = shell("printf out; printf error >&2; exit 3");
+            script.arg("events-test").env("CMD_UTILS_EVENTS_TEST", "1");
+            assert!(script.exec_stdout_string().is_err());
+            cmd!("true", "events-test").exec().unwrap();
+        });
+        assert_eq!(events.len(), 2, "{:?}", events);
+        let (start, end) = &events[0];
+        assert_eq!(start["program"], "sh");
+        assert_eq!(start["args"][2], "events-test");
+        assert_eq!(start["envs"]["CMD_UTILS_EVENTS_TEST"], "1");
+        assert!(start["cwd"].is_string() && start["timestamp"].is_string(), "{}", start);
+        assert_eq!(
+            (&end["success"], &end["exit_code"], &end["signal"]),
+            (&false.into(), &3.into(), &Value::Null)
+        );
+        assert!(end["duration_ms"].as_f64().unwrap() >= 0.0, "{}", end);
+        assert_eq!((&end["stdout_bytes"], &end["stderr_bytes"]), (&3.into(), &5.into()));
+        assert_eq!((&end["stdout"], &end["stderr"]), (&"out".into(), &"error".into()));
+        let (start, end) = &events[1];
+        assert_eq!(start["program"], "true");
+        assert_eq!((&end["success"], &end["exit_code"]), (&true.into(), &0.into()));
+        assert_eq!((&end["stdout_bytes"], &end["stdout"]), (&Value::Null, &Value::Null));
+        assert_ne!(events[0].0["id"], events[1].0["id"]);
+    }
+
+    #[test]
+    fn spawn_failures_end_without_a_status() {
+        let events = events("events-spawn", || {
+            cmd!("cmd-utils-no-such-program", "events-spawn").exec().unwrap_err();
+        });
+        let [(_, end)] = &events[..] else { panic!("{:?}", events) };
+        assert_eq!(
+            (&end["success"], &end["exit_code"], &end["signal"]),
+            (&false.into(), &Value::Null, &Value::Null)
+        );
+    }
+
+    #[test]
+    fn concurrent_events_are_whole_lines() {
+        let events = events("events-threads", || {
+            let threads: Vec<_> = (0..16)
+                .map(|i| {
+                    std::thread::spawn(move || {
+                        cmd!("echo", "events-threads", i.to_string()).exec_stdout_string()
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap().unwrap();
+            }
+        });
+        assert_eq!(events.len(), 16);
+        for (start, end) in &events {
+            let i = start["args"][1].as_str().unwrap();
+            assert_eq!(end["stdout"], format!("events-threads {}\n", i));
+        }
+    }
+}
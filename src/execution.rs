@@ -0,0 +1,280 @@
+use std::process::{Command, ExitStatus};
+use std::time::{Duration, Instant};
+
+#[cfg(feature = "replay")]
+use crate::replay;
+use crate::{events, heartbeat, hooks, interrupt, log, scope, slow, stats, ConfiguredCommand};
+
+// One run of a command, from just before it is spawned until its status is known. The log file
+// record, the hooks and, with the `tracing` feature, the `cmd` span and its events all come from
+// here, so every method reports its runs the same way.
+pub(crate) struct Execution {
+    // Taken once the end event is written, which dropping writes if finishing didn't.
+    id: Option<u64>,
+    start: Instant,
+    _watch: Option<slow::Watch>,
+    _heartbeat: Option<slow::Watch>,
+    #[cfg(feature = "tracing")]
+    _span: tracing::span::EnteredSpan,
+}
+
+// Runs the pre-exec hooks, so an error means the command must not be spawned.
+pub(crate) fn start(cmd: &ConfiguredCommand) -> anyhow::Result<Execution> {
+    hooks::run_pre_exec(cmd)?;
+    interrupt::started();
+    let id = events::next_id();
+    events::started(id, cmd);
+    Ok(Execution {
+        id: Some(id),
+        start: Instant::now(),
+        _watch: slow::watch(cmd),
+        _heartbeat: heartbeat::watch(cmd),
+        #[cfg(feature = "tracing")]
+        _span: trace::start(cmd).entered(),
+    })
+}
+
+impl Execution {
+    // `status` is `None` if the command could not be spawned or waited for, or was killed after
+    // timing out. `output` is the captured stdout and stderr, if any.
+    pub(crate) fn finish(
+        mut self,
+        cmd: &Command,
+        status: Option<&ExitStatus>,
+        output: Option<(&[u8], &[u8])>,
+    ) -> Duration {
+        let duration = self.start.elapsed();
+        finished(self.id.take(), cmd, duration, status, output);
+        duration
+    }
+}
+
+impl Drop for Execution {
+    fn drop(&mut self) {
+        interrupt::ended();
+        if let Some(id) = self.id.take() {
+            events::finished(id, self.start.elapsed(), None, None);
+        }
+    }
+}
+
+// An `Execution` that outlives the call that started it or crosses `.await`, such as in
+// `LineIter` or the `tokio` methods. The span can't stay entered that long, so it is only entered
+// while the future passed to `run` is polled and while finishing.
+pub(crate) struct DetachedExecution {
+    // Taken once the end event is written, which dropping writes if finishing didn't.
+    id: Option<u64>,
+    start: Instant,
+    _watch: Option<slow::Watch>,
+    _heartbeat: Option<slow::Watch>,
+    #[cfg(feature = "tracing")]
+    span: tracing::Span,
+}
+
+pub(crate) fn start_detached(cmd: &ConfiguredCommand) -> anyhow::Result<DetachedExecution> {
+    hooks::run_pre_exec(cmd)?;
+    interrupt::started();
+    let id = events::next_id();
+    events::started(id, cmd);
+    Ok(DetachedExecution {
+        id: Some(id),
+        start: Instant::now(),
+        _watch: slow::watch(cmd),
+        _heartbeat: heartbeat::watch(cmd),
+        #[cfg(feature = "tracing")]
+        span: trace::start(cmd),
+    })
+}
+
+impl Drop for DetachedExecution {
+    fn drop(&mut self) {
+        interrupt::ended();
+        if let Some(id) = self.id.take() {
+            events::finished(id, self.start.elapsed(), None, None);
+        }
+    }
+}
+
+impl DetachedExecution {
+    #[cfg(feature = "tokio")]
+    pub(crate) async fn run<F: std::future::Future>(&self, future: F) -> F::Output {
+        #[cfg(feature = "tracing")]
+        let future = tracing::Instrument::instrument(future, self.span.clone());
+        future.await
+    }
+
+    pub(crate) fn finish(
+        mut self,
+        cmd: &Command,
+        status: Option<&ExitStatus>,
+        output: Option<(&[u8], &[u8])>,
+    ) -> Duration {
+        #[cfg(feature = "tracing")]
+        let _entered = self.span.enter();
+        let duration = self.start.elapsed();
+        finished(self.id.take(), cmd, duration, status, output);
+        duration
+    }
+}
+
+// The execution of a child that runs in the background of the calling thread, as from
+// `exec_background`, `spawn_guarded` or `spawn_described`. Their handles are waited for in many
+// ways, or never, so it keeps a copy of the command to finish with, and finishing it again does
+// nothing.
+pub(crate) struct BackgroundExecution {
+    execution: Option<DetachedExecution>,
+    command: ConfiguredCommand,
+}
+
+pub(crate) fn start_background(cmd: &ConfiguredCommand) -> anyhow::Result<BackgroundExecution> {
+    Ok(BackgroundExecution { execution: Some(start_detached(cmd)?), command: cmd.copy() })
+}
+
+impl BackgroundExecution {
+    pub(crate) fn finish(
+        &mut self,
+        status: Option<&ExitStatus>,
+        output: Option<(&[u8], &[u8])>,
+    ) -> Option<Duration> {
+        let execution = self.execution.take()?;
+        Some(execution.finish(&self.command, status, output))
+    }
+}
+
+fn finished(
+    id: Option<u64>,
+    cmd: &Command,
+    duration: Duration,
+    status: Option<&ExitStatus>,
+    output: Option<(&[u8], &[u8])>,
+) {
+    log::log_execution(cmd, duration, status, output);
+    if let Some(id) = id {
+        events::finished(id, duration, status, output);
+    }
+    if let Some(status) = status {
+        hooks::run_post_exec(cmd, status, duration);
+    }
+    scope::record(status.is_some_and(ExitStatus::success));
+    stats::record(cmd, duration, status);
+    #[cfg(feature = "replay")]
+    replay::record(cmd, duration, status, output);
+    #[cfg(feature = "tracing")]
+    trace::finished(duration, status, output);
+}
+
+#[cfg(feature = "tracing")]
+mod trace {
+    use std::process::{Command, ExitStatus};
+    use std::time::Duration;
+
+    use crate::{quote, CommandExt};
+
+    // Creates the `cmd` span with the redacted program, arguments, and working directory, and
+    // records the spawn in it.
+    pub(super) fn start(cmd: &Command) -> tracing::Span {
+        let snapshot = cmd.description().to_snapshot();
+        let mut args = String::new();
+        for arg in &snapshot.args {
+            if !args.is_empty() {
+                args.push(' ');
+            }
+            let _ = quote::write_quoted(&mut args, arg.as_ref());
+        }
+        let span = tracing::info_span!(
+            target: "cmd_utils",
+            "cmd",
+            program = %snapshot.program,
+            args = %args,
+            cwd = snapshot.cwd.as_deref(),
+        );
+        span.in_scope(|| tracing::info!(target: "cmd_utils", description = %cmd.description(), "spawning command"));
+        span
+    }
+
+    pub(super) fn finished(
+        duration: Duration,
+        status: Option<&ExitStatus>,
+        output: Option<(&[u8], &[u8])>,
+    ) {
+        let exit_code = status.and_then(ExitStatus::code);
+        let duration_ms = duration.as_secs_f64() * 1000.0;
+        let stdout_len = output.map(|(stdout, _)| stdout.len() as u64);
+        let stderr_len = output.map(|(_, stderr)| stderr.len() as u64);
+        if status.is_some_and(ExitStatus::success) {
+            tracing::info!(target: "cmd_utils", exit_code, duration_ms, stdout_len, stderr_len, "command finished");
+        } else {
+            tracing::warn!(target: "cmd_utils", exit_code, duration_ms, stdout_len, stderr_len, "command failed");
+        }
+    }
+}
+
+#[cfg(all(test, feature = "tracing"))]
+mod tests {
+    use std::io;
+    use std::sync::{Arc, Mutex};
+
+    use crate::test_support::serial;
+    use crate::{cmd, CommandExt};
+    use crate::{set_decoration_writer, set_decorations_enabled, shell, take_decoration_writer};
+
+    #[derive(Clone, Default)]
+    struct Log(Arc<Mutex<Vec<u8>>>);
+
+    impl io::Write for Log {
+        fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+            self.0.lock().unwrap().write(buf)
+        }
+
+        fn flush(&mut self) -> io::Result<()> {
+            Ok(())
+        }
+    }
+
+    // What `f` logs through `tracing`, on this thread only.
+    fn traced(f: impl FnOnce()) -> String {
+        let log = Log::default();
+        let subscriber = tracing_subscriber::fmt()
+            .with_writer({
+                let log = log.clone();
+                move || log.clone()
+            })
+            .with_ansi(false)
+            .finish();
+        tracing::subscriber::with_default(subscriber, f);
+        let text = log.0.lock().unwrap().clone();
+        String::from_utf8(text).unwrap()
+    }
+
+    #[test]
+    fn spans_and_events_record_the_execution() {
+        let text = traced(|| {
+            cmd("echo").args_(["traced", "a b"]).exec_stdout_string().unwrap();
+            shell("exit 3").exec().unwrap_err();
+        });
+        let lines: Vec<&str> = text.lines().collect();
+        assert_eq!(lines.len(), 4, "{}", text);
+        let cwd = std::env::current_dir().unwrap();
+        let span = format!("cmd{{program=echo args=traced 'a b' cwd=\"{}\"}}", cwd.display());
+        assert!(lines[0].contains(&span) && lines[0].contains("spawning command"), "{}", text);
+        assert!(lines[0].contains(r#"description=program = "echo", args = ["traced", "a b"]"#));
+        assert!(lines[1].contains(" INFO ") && lines[1].contains("command finished"), "{}", text);
+        assert!(lines[1].contains("exit_code=0") && lines[1].contains("stdout_len=11"), "{}", text);
+        assert!(lines[1].ends_with(" stderr_len=0"), "{}", text);
+        assert!(lines[3].contains(" WARN ") && lines[3].contains("command failed"), "{}", text);
+        assert!(lines[3].contains("exit_code=3") && !lines[3].contains("stdout_len"), "{}", text);
+    }
+
+    #[test]
+    fn decorations_can_be_left_to_tracing() {
+        let _serial = serial();
+        set_decoration_writer(termcolor::Buffer::no_color());
+        set_decorations_enabled(false);
+        let text = traced(|| cmd("echo").arg("untraced-banner").exec().unwrap());
+        set_decorations_enabled(true);
+        let buffer = take_decoration_writer::<termcolor::Buffer>().unwrap();
+        let banners = String::from_utf8_lossy(buffer.as_slice()).into_owned();
+        assert!(!banners.contains("untraced-banner"), "{}", banners);
+        assert!(text.contains("args=untraced-banner"), "{}", text);
+    }
+}
@@ -0,0 +1,156 @@
+use std::process::ExitStatus;
+use std::sync::Mutex;
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::capture::{spawn_piped, Pipes};
+use crate::{
+    child, dry_run, exec_failed, execution, CommandExt, ConfiguredCommand, Embedded, StatusSummary,
+};
+
+/// The lines of `exec_filter_lines` that the predicate kept, without their line terminators and
+/// with invalid UTF-8 replaced, out of how many lines each stream had in total.
+#[derive(Debug, Clone)]
+pub struct FilteredOutput {
+    pub status: ExitStatus,
+    pub stdout_lines: Vec<String>,
+    pub stderr_lines: Vec<String>,
+    pub stdout_total_lines: usize,
+    pub stderr_total_lines: usize,
+    pub duration: Duration,
+}
+
+impl FilteredOutput {
+    /// Lines kept from both streams.
+    pub fn kept_lines(&self) -> usize {
+        self.stdout_lines.len() + self.stderr_lines.len()
+    }
+}
+
+#[derive(Default)]
+struct Kept {
+    lines: Vec<String>,
+    total: usize,
+}
+
+pub(crate) fn exec_filter_lines<P>(
+    cmd: &mut ConfiguredCommand,
+    pred: P,
+) -> anyhow::Result<FilteredOutput>
+where
+    P: FnMut(&str) -> bool + Send,
+{
+    if dry_run(cmd)? {
+        return Ok(FilteredOutput {
+            status: ExitStatus::default(),
+            stdout_lines: Vec::new(),
+            stderr_lines: Vec::new(),
+            stdout_total_lines: 0,
+            stderr_total_lines: 0,
+            duration: Duration::ZERO,
+        });
+    }
+    let execution = execution::start(cmd)?;
+    let mut child = spawn_piped(cmd, Pipes { stdout: true, stderr: true, ..Pipes::default() })?;
+    // The predicate is shared by the threads reading the two streams.
+    let pred = Mutex::new(pred);
+    let (mut stdout, mut stderr) = (Kept::default(), Kept::default());
+    let (stdout_pipe, stderr_pipe) = (child.take_stdout(), child.take_stderr());
+    std::thread::scope(|s| {
+        if let Some(pipe) = stdout_pipe {
+            s.spawn(|| child::for_each_line(pipe, keep(&pred, &mut stdout)));
+        }
+        if let Some(pipe) = stderr_pipe {
+            s.spawn(|| child::for_each_line(pipe, keep(&pred, &mut stderr)));
+        }
+    });
+    let status = child.wait();
+    let (kept_stdout, kept_stderr) = (joined(&stdout.lines), joined(&stderr.lines));
+    let duration = execution.finish(
+        cmd,
+        status.as_ref().ok(),
+        Some((kept_stdout.as_bytes(), kept_stderr.as_bytes())),
+    );
+    let status =
+        status.with_context(|| format!("Failed to wait for command ({})", cmd.description()))?;
+    if !status.success() {
+        return Err(exec_failed(
+            cmd,
+            status,
+            kept_stdout.as_bytes(),
+            kept_stderr.as_bytes(),
+            format!(
+                "Process did not exit successfully, {} ({}, kept {} of {} stdout lines = {}, kept {} of {} stderr lines = {})",
+                StatusSummary(status),
+                cmd.description(),
+                stdout.lines.len(),
+                stdout.total,
+                Embedded::new(kept_stdout.as_bytes()),
+                stderr.lines.len(),
+                stderr.total,
+                Embedded::new(kept_stderr.as_bytes()),
+            ),
+        ));
+    }
+    Ok(FilteredOutput {
+        status,
+        stdout_lines: stdout.lines,
+        stderr_lines: stderr.lines,
+        stdout_total_lines: stdout.total,
+        stderr_total_lines: stderr.total,
+        duration,
+    })
+}
+
+fn keep<'a, P: FnMut(&str) -> bool>(
+    pred: &'a Mutex<P>,
+    kept: &'a mut Kept,
+) -> impl FnMut(&str) + 'a {
+    move |line| {
+        kept.total += 1;
+        if (pred.lock().unwrap())(line) {
+            kept.lines.push(line.to_string());
+        }
+    }
+}
+
+fn joined(lines: &[String]) -> String {
+    lines.iter().map(|line| format!("{}\n", line)).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{shell, CmdError, CommandExt};
+
+    const GENERATOR: &str =
+        r#"seq 10000 | awk '{ if ($1 % 3333 == 0) print "warning: " $1; else print "noise " $1 }'"#;
+
+    #[test]
+    fn only_matching_lines_are_kept() {
+        let script = format!("{}; echo 'warning: on stderr' >&2; echo noise >&2", GENERATOR);
+        let out = shell(script).exec_filter_lines(|line| line.starts_with("warning:")).unwrap();
+        assert_eq!(out.stdout_lines, ["warning: 3333", "warning: 6666", "warning: 9999"]);
+        assert_eq!(out.stderr_lines, ["warning: on stderr"]);
+        assert_eq!((out.stdout_total_lines, out.stderr_total_lines), (10000, 2));
+        assert_eq!(out.kept_lines(), 4);
+    }
+
+    #[test]
+    fn failures_embed_the_kept_lines() {
+        let script = format!("{}; exit 2", GENERATOR);
+        let err = shell(script).exec_filter_lines(|line| line.contains("warning")).unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.contains("exit code 2"), "{}", message);
+        let kept =
+            r#"kept 3 of 10000 stdout lines = "warning: 3333\nwarning: 6666\nwarning: 9999\n""#;
+        assert!(message.contains(kept), "{}", message);
+        assert!(message.contains(r#"kept 0 of 0 stderr lines = """#), "{}", message);
+        match err.downcast_ref::<CmdError>() {
+            Some(CmdError::UnsuccessfulExit { stdout, .. }) => {
+                assert_eq!(stdout, b"warning: 3333\nwarning: 6666\nwarning: 9999\n")
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
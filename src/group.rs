@@ -0,0 +1,106 @@
+use std::process::Command;
+
+use crate::ConfiguredCommand;
+
+// Recorded as well as applied, so the kill paths know to signal the whole group.
+pub(crate) fn set(cmd: &mut ConfiguredCommand) {
+    if !is_set(cmd) {
+        apply(cmd);
+        cmd.settings_mut().process_group = true;
+    }
+}
+
+#[cfg(unix)]
+pub(crate) fn apply(cmd: &mut Command) {
+    std::os::unix::process::CommandExt::process_group(cmd, 0);
+}
+
+// Windows has no process groups to kill; a Job Object would be needed instead.
+#[cfg(not(unix))]
+pub(crate) fn apply(_cmd: &mut Command) {}
+
+pub(crate) fn is_set(cmd: &ConfiguredCommand) -> bool {
+    cfg!(unix) && cmd.settings().process_group
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::path::PathBuf;
+    use std::time::{Duration, Instant};
+
+    use crate::test_support::temp_path;
+    use crate::{cmd, shell, CommandExt};
+
+    // A script that starts a `sleep` in the background, with its output away from the pipes of
+    // the script, and writes the sleep's pid to the returned file before waiting for it.
+    fn with_grandchild(name: &str) -> (String, PathBuf) {
+        let pid_file = temp_path(name);
+        let _ = std::fs::remove_file(&pid_file);
+        let script = format!(
+            "sleep 30 >/dev/null 2>&1 & echo $! > '{}.tmp'; mv '{0}.tmp' '{0}'; wait",
+            pid_file.display()
+        );
+        (script, pid_file)
+    }
+
+    fn grandchild_pid(pid_file: &PathBuf) -> String {
+        let started = Instant::now();
+        while !pid_file.exists() {
+            assert!(started.elapsed() < Duration::from_secs(10), "no grandchild was started");
+            std::thread::sleep(Duration::from_millis(10));
+        }
+        std::fs::read_to_string(pid_file).unwrap().trim().to_owned()
+    }
+
+    // Whether `pid` is still running: zombies, left for whoever reaps orphans, don't count.
+    fn running(pid: &str) -> bool {
+        let out = cmd("ps").args_(["-o", "stat=", "-p", pid]).exec_stdout_string_allowing([1]);
+        let stat = out.unwrap().stdout;
+        !stat.trim().is_empty() && !stat.trim_start().starts_with('Z')
+    }
+
+    // Killing takes a moment to show in the process table.
+    fn gone(pid: &str) -> bool {
+        let started = Instant::now();
+        while running(pid) {
+            if started.elapsed() > Duration::from_secs(5) {
+                return false;
+            }
+            std::thread::sleep(Duration::from_millis(20));
+        }
+        true
+    }
+
+    #[test]
+    fn timeouts_kill_the_whole_group() {
+        let (script, pid_file) = with_grandchild("group-timeout");
+        let timeout = Duration::from_millis(300);
+        assert!(shell(&script).new_process_group().exec_with_timeout(timeout).is_err());
+        assert!(gone(&grandchild_pid(&pid_file)));
+    }
+
+    #[test]
+    fn guards_and_background_children_kill_the_whole_group() {
+        let (script, pid_file) = with_grandchild("group-guard");
+        let guard = shell(&script).new_process_group().spawn_guarded().unwrap();
+        let pid = grandchild_pid(&pid_file);
+        drop(guard.grace_period(Duration::from_secs(5)));
+        assert!(gone(&pid));
+
+        let (script, pid_file) = with_grandchild("group-background");
+        let mut child = shell(&script).new_process_group().exec_background().unwrap();
+        let pid = grandchild_pid(&pid_file);
+        child.kill().unwrap();
+        assert!(gone(&pid));
+    }
+
+    #[test]
+    fn without_a_group_the_grandchild_is_left_behind() {
+        let (script, pid_file) = with_grandchild("group-none");
+        let mut guard = shell(&script).spawn_guarded().unwrap();
+        let pid = grandchild_pid(&pid_file);
+        guard.terminate(Duration::from_secs(5)).unwrap();
+        assert!(running(&pid));
+        cmd("kill").args_(["-9", &pid]).exec_stdout_string().unwrap();
+    }
+}
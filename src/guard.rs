@@ -0,0 +1,188 @@
+use std::process::{Child, ExitStatus};
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::backend::Process;
+use crate::child::{self, TERMINATE_GRACE_PERIOD};
+use crate::execution::BackgroundExecution;
+
+/// A spawned process that is asked to exit when this is dropped: SIGTERM on Unix, then after the
+/// grace period (1 second by default) a kill, after which it is reaped. On Windows it is killed
+/// right away. Errors go with the description the guard was created with.
+pub struct ChildGuard {
+    child: Process,
+    description: String,
+    grace_period: Duration,
+    armed: bool,
+    // Only for a process from `spawn_guarded`.
+    execution: Option<BackgroundExecution>,
+}
+
+impl ChildGuard {
+    pub fn new(child: Child, description: impl Into<String>) -> Self {
+        ChildGuard::from_process(child.into(), description.into(), None)
+    }
+
+    pub(crate) fn from_process(
+        child: Process,
+        description: String,
+        execution: Option<BackgroundExecution>,
+    ) -> Self {
+        let grace_period = TERMINATE_GRACE_PERIOD;
+        ChildGuard { child, description, grace_period, armed: true, execution }
+    }
+
+    /// How long the process gets to exit after SIGTERM when the guard is dropped.
+    pub fn grace_period(mut self, grace_period: Duration) -> Self {
+        self.grace_period = grace_period;
+        self
+    }
+
+    pub fn id(&self) -> u32 {
+        self.child.id()
+    }
+
+    pub fn description(&self) -> &str {
+        &self.description
+    }
+
+    pub fn wait(&mut self) -> anyhow::Result<ExitStatus> {
+        let status = self.child.wait();
+        self.finish(status.as_ref().ok());
+        status.with_context(|| format!("Failed to wait for command ({})", self.description))
+    }
+
+    pub fn try_wait(&mut self) -> anyhow::Result<Option<ExitStatus>> {
+        let status = self.child.try_wait();
+        if let Ok(Some(status)) = &status {
+            self.finish(Some(status));
+        }
+        status.with_context(|| format!("Failed to wait for command ({})", self.description))
+    }
+
+    /// Does what dropping the guard does, with the given grace period, and returns the status the
+    /// process exited with. Returns right away if it has already exited.
+    pub fn terminate(&mut self, grace: Duration) -> anyhow::Result<ExitStatus> {
+        if let Some(status) = self.try_wait()? {
+            return Ok(status);
+        }
+        let status = child::terminate_within(&mut self.child, grace);
+        self.finish(status.as_ref().ok());
+        status.with_context(|| format!("Failed to terminate command ({})", self.description))
+    }
+
+    /// Lets the process outlive the guard.
+    pub fn detach(mut self) {
+        self.armed = false;
+    }
+
+    fn finish(&mut self, status: Option<&ExitStatus>) {
+        if let Some(execution) = &mut self.execution {
+            execution.finish(status, None);
+        }
+    }
+}
+
+impl Drop for ChildGuard {
+    fn drop(&mut self) {
+        if self.armed {
+            let status = match self.child.try_wait() {
+                Ok(None) => child::terminate_within(&mut self.child, self.grace_period).ok(),
+                status => status.ok().flatten(),
+            };
+            self.finish(status.as_ref());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use crate::test_support::record_exits;
+    use crate::{shell, CommandExt};
+
+    #[test]
+    fn wait_finishes_the_execution() {
+        let script = "exit 5 # guard wait";
+        let (_guard, exits) = record_exits(script);
+        let mut guard = shell(script).spawn_guarded().unwrap();
+        assert_eq!(guard.wait().unwrap().code(), Some(5));
+        drop(guard);
+        assert_eq!(*exits.lock().unwrap(), [Some(5)]);
+    }
+
+    #[test]
+    fn drop_terminates_and_finishes_the_execution() {
+        let script = "sleep 10 # guard drop";
+        let (_guard, exits) = record_exits(script);
+        drop(shell(script).spawn_guarded().unwrap().grace_period(Duration::from_secs(5)));
+        assert_eq!(*exits.lock().unwrap(), [None]);
+    }
+
+    // Waits for `path` to be written by a child, so that its traps are set.
+    #[cfg(unix)]
+    fn wait_for(path: &std::path::Path) {
+        let started = std::time::Instant::now();
+        while !path.exists() {
+            assert!(
+                started.elapsed() < Duration::from_secs(10),
+                "{} never appeared",
+                path.display()
+            );
+            std::thread::sleep(Duration::from_millis(10));
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn terminate_escalates_to_sigkill_when_term_is_trapped() {
+        let ready = crate::test_support::temp_path("guard-trap-ready");
+        let trapped = crate::test_support::temp_path("guard-trap-term");
+        let _ = std::fs::remove_file(&ready);
+        let _ = std::fs::remove_file(&trapped);
+        let script = format!(
+            "trap 'echo > \"{}\"' TERM; echo > \"{}\"; while :; do sleep 0.05; done",
+            trapped.display(),
+            ready.display()
+        );
+        let mut guard = shell(&script).spawn_guarded().unwrap();
+        wait_for(&ready);
+        let started = std::time::Instant::now();
+        let status = guard.terminate(Duration::from_millis(300)).unwrap();
+        assert!(started.elapsed() >= Duration::from_millis(300));
+        assert_eq!(crate::exit_signal(status), Some(9));
+        assert!(trapped.exists());
+        assert_eq!(guard.try_wait().unwrap(), Some(status));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn terminate_stops_at_sigterm_when_it_is_enough() {
+        let mut guard = shell("exec sleep 10").spawn_guarded().unwrap();
+        let started = std::time::Instant::now();
+        let status = guard.terminate(Duration::from_secs(5)).unwrap();
+        assert!(started.elapsed() < Duration::from_secs(5));
+        assert_eq!(crate::exit_signal(status), Some(15));
+    }
+
+    #[test]
+    fn terminate_returns_the_status_of_an_exited_process() {
+        let mut guard = crate::ChildGuard::new(shell("exit 3").spawn().unwrap(), "exit 3");
+        assert_eq!(guard.wait().unwrap().code(), Some(3));
+        assert_eq!(guard.terminate(Duration::ZERO).unwrap().code(), Some(3));
+        assert_eq!(guard.description(), "exit 3");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn detached_processes_outlive_the_guard() {
+        let guard = shell("exec sleep 10").spawn_guarded().unwrap();
+        let pid = guard.id().to_string();
+        guard.detach();
+        let alive = || crate::cmd("kill").args_(["-0", &pid]).status().unwrap().success();
+        assert!(alive());
+        crate::cmd("kill").args_(["-9", &pid]).status().unwrap();
+    }
+}
@@ -0,0 +1,123 @@
+use std::io::{IsTerminal, Write};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::slow::{self, Watch};
+use crate::{
+    decorate, decoration_stream, label, theme, CommandExt, ConfiguredCommand, HumanDuration,
+    TermColorStandardStreamExt,
+};
+
+const DEFAULT_TEMPLATE: &str = "… still running ({elapsed}): {command}";
+
+// `None` means the default template.
+static TEMPLATE: Mutex<Option<String>> = Mutex::new(None);
+static WHEN_NOT_TERMINAL: AtomicBool = AtomicBool::new(false);
+
+/// Sets the line written by the heartbeats of commands given `heartbeat`, where `{elapsed}` is
+/// replaced by how long the command has been running and `{command}` by the command. The default
+/// is `… still running ({elapsed}): {command}`.
+pub fn set_heartbeat_template(template: impl Into<String>) {
+    *TEMPLATE.lock().unwrap() = Some(template.into());
+}
+
+pub(crate) fn template_override() -> Option<String> {
+    TEMPLATE.lock().unwrap().clone()
+}
+
+pub(crate) fn set_template_override(template: Option<String>) {
+    *TEMPLATE.lock().unwrap() = template;
+}
+
+/// Writes heartbeats even when stderr is not a terminal, for CI systems that kill jobs whose
+/// log has been idle for too long. Off by default, since heartbeats would otherwise fill up
+/// logs.
+pub fn set_heartbeat_when_not_terminal(enabled: bool) {
+    WHEN_NOT_TERMINAL.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn when_not_terminal() -> bool {
+    WHEN_NOT_TERMINAL.load(Ordering::Relaxed)
+}
+
+pub(crate) fn set(cmd: &mut ConfiguredCommand, every: Duration) {
+    cmd.settings_mut().heartbeat.get_or_insert(every);
+}
+
+fn get(cmd: &ConfiguredCommand) -> Option<Duration> {
+    cmd.settings().heartbeat
+}
+
+pub(crate) fn watch(cmd: &ConfiguredCommand) -> Option<Watch> {
+    let every = get(cmd).filter(|every| !every.is_zero())?;
+    if !std::io::stderr().is_terminal() && !when_not_terminal() {
+        return None;
+    }
+    let label = label::get(cmd).map(str::to_owned);
+    let text = cmd.description().shell();
+    let template = template_override().unwrap_or_else(|| DEFAULT_TEMPLATE.to_string());
+    Some(slow::every(every, move |elapsed| {
+        let line = template
+            .replace("{elapsed}", &HumanDuration(elapsed).to_string())
+            .replace("{command}", &text);
+        write_heartbeat(label.as_deref(), &line);
+    }))
+}
+
+fn write_heartbeat(label: Option<&str>, line: &str) {
+    let mut stderr = decoration_stream();
+    let spec = theme::theme().heartbeat;
+    let _ = decorate(|| {
+        if let Some(label) = label {
+            label::write_tag(&mut stderr, label)?;
+        }
+        stderr.with_color(&spec, |s| write!(s, "{}", line))?;
+        writeln!(stderr)?;
+        stderr.flush()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{shell, test_support};
+
+    fn lines<'a>(text: &'a str, label: &str) -> Vec<&'a str> {
+        let tag = format!("[{}]", label);
+        text.lines().filter(|line| line.contains(&tag)).collect()
+    }
+
+    #[test]
+    fn heartbeats_stop_before_the_end_marker() {
+        let every = Duration::from_millis(250);
+        let text = test_support::decorations(|| {
+            set_heartbeat_when_not_terminal(true);
+            shell("sleep 0.6").label("beat-default").heartbeat(every).exec().unwrap();
+            set_heartbeat_template("{command} is busy ({elapsed})");
+            shell("sleep 0.35").label("beat-template").heartbeat(every).exec().unwrap();
+            set_template_override(None);
+            set_heartbeat_when_not_terminal(false);
+            if !std::io::stderr().is_terminal() {
+                shell("sleep 0.3").label("beat-off").heartbeat(every).exec().unwrap();
+            }
+        });
+        let beats = lines(&text, "beat-default");
+        assert_eq!(beats.len(), 4, "{:?}", beats);
+        for beat in &beats[1..3] {
+            let plain = crate::strip_ansi(beat);
+            assert!(plain.starts_with("[beat-default] … still running ("), "{:?}", plain);
+            assert!(plain.ends_with("ms): sh -c 'sleep 0.6'"), "{:?}", plain);
+            assert!(beat.contains("\x1b[2m"), "{:?}", beat);
+        }
+        assert!(beats[3].contains("END OUTPUT"), "{:?}", beats);
+        let beats = lines(&text, "beat-template");
+        assert_eq!(beats.len(), 3, "{:?}", beats);
+        assert!(
+            crate::strip_ansi(beats[1]).starts_with("[beat-template] sh -c 'sleep 0.35' is busy (")
+        );
+        if !std::io::stderr().is_terminal() {
+            assert_eq!(lines(&text, "beat-off").len(), 2, "{}", text);
+        }
+    }
+}
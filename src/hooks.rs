@@ -0,0 +1,202 @@
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+
+use crate::CommandExt;
+
+type PreExecHook = Arc<dyn Fn(&Command) -> anyhow::Result<()> + Send + Sync>;
+type PostExecHook = Arc<dyn Fn(&Command, &ExitStatus, Duration) + Send + Sync>;
+
+static PRE_EXEC_HOOKS: Mutex<Vec<(HookId, PreExecHook)>> = Mutex::new(Vec::new());
+static POST_EXEC_HOOKS: Mutex<Vec<(HookId, PostExecHook)>> = Mutex::new(Vec::new());
+static NEXT_HOOK_ID: AtomicU64 = AtomicU64::new(0);
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash)]
+pub struct HookId(u64);
+
+/// Runs `hook` before every command is spawned, in registration order. The first hook that
+/// returns an error stops the command from running, and the error is returned from the method
+/// that would have run it.
+///
+/// ```
+/// use cmd_utils::{add_pre_exec_hook, CommandExt};
+/// use std::process::Command;
+///
+/// add_pre_exec_hook(|cmd: &Command| {
+///     if cmd.get_program() == "rm" {
+///         anyhow::bail!("rm is not allowed here");
+///     }
+///     Ok(())
+/// });
+/// assert!(Command::new("rm").arg("-rf").arg("build").exec().is_err());
+/// ```
+pub fn add_pre_exec_hook<F>(hook: F) -> HookId
+where
+    F: Fn(&Command) -> anyhow::Result<()> + Send + Sync + 'static,
+{
+    let id = next_id();
+    PRE_EXEC_HOOKS.lock().unwrap().push((id, Arc::new(hook)));
+    id
+}
+
+/// Runs `hook` after every command has exited, successfully or not, in registration order. It
+/// isn't run for commands that could not be spawned or waited for.
+pub fn add_post_exec_hook<F>(hook: F) -> HookId
+where
+    F: Fn(&Command, &ExitStatus, Duration) + Send + Sync + 'static,
+{
+    let id = next_id();
+    POST_EXEC_HOOKS.lock().unwrap().push((id, Arc::new(hook)));
+    id
+}
+
+pub fn remove_hook(id: HookId) {
+    PRE_EXEC_HOOKS.lock().unwrap().retain(|(hook_id, _)| *hook_id != id);
+    POST_EXEC_HOOKS.lock().unwrap().retain(|(hook_id, _)| *hook_id != id);
+}
+
+/// Removes its hook when dropped.
+#[must_use = "the hook is removed as soon as the guard is dropped"]
+pub struct HookGuard(HookId);
+
+impl Drop for HookGuard {
+    fn drop(&mut self) {
+        remove_hook(self.0);
+    }
+}
+
+pub fn scoped_pre_exec_hook<F>(hook: F) -> HookGuard
+where
+    F: Fn(&Command) -> anyhow::Result<()> + Send + Sync + 'static,
+{
+    HookGuard(add_pre_exec_hook(hook))
+}
+
+pub fn scoped_post_exec_hook<F>(hook: F) -> HookGuard
+where
+    F: Fn(&Command, &ExitStatus, Duration) + Send + Sync + 'static,
+{
+    HookGuard(add_post_exec_hook(hook))
+}
+
+fn next_id() -> HookId {
+    HookId(NEXT_HOOK_ID.fetch_add(1, Ordering::SeqCst))
+}
+
+// The hooks are cloned out of the registry before they run, so a hook may add or remove hooks.
+pub(crate) fn run_pre_exec(cmd: &Command) -> anyhow::Result<()> {
+    let hooks: Vec<PreExecHook> =
+        PRE_EXEC_HOOKS.lock().unwrap().iter().map(|(_, hook)| Arc::clone(hook)).collect();
+    for hook in hooks {
+        hook(cmd).with_context(|| {
+            format!("Command rejected by a pre-exec hook ({})", cmd.description())
+        })?;
+    }
+    Ok(())
+}
+
+pub(crate) fn run_post_exec(cmd: &Command, status: &ExitStatus, duration: Duration) {
+    let hooks: Vec<PostExecHook> =
+        POST_EXEC_HOOKS.lock().unwrap().iter().map(|(_, hook)| Arc::clone(hook)).collect();
+    for hook in hooks {
+        hook(cmd, status, duration);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::thread;
+
+    use super::*;
+    use crate::shell;
+    use crate::test_support::temp_path;
+
+    // Hooks see the commands of every test, so they only act on the ones that mention `marker`.
+    fn mentions(cmd: &Command, marker: &str) -> bool {
+        cmd.get_args().any(|arg| arg.to_string_lossy().contains(marker))
+    }
+
+    #[test]
+    fn an_erroring_hook_stops_later_hooks_and_the_command() {
+        let touched = temp_path("hook-rejected");
+        let _ = std::fs::remove_file(&touched);
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let hook = |name: &'static str, fail: bool| {
+            let calls = Arc::clone(&calls);
+            scoped_pre_exec_hook(move |cmd: &Command| {
+                if mentions(cmd, "hook-rejected") {
+                    calls.lock().unwrap().push(name);
+                    anyhow::ensure!(!fail, "{} says no", name);
+                }
+                Ok(())
+            })
+        };
+        let _guards = [hook("first", false), hook("second", true), hook("third", false)];
+        let _post = scoped_post_exec_hook({
+            let calls = Arc::clone(&calls);
+            move |cmd: &Command, _: &ExitStatus, _: Duration| {
+                if mentions(cmd, "hook-rejected") {
+                    calls.lock().unwrap().push("post");
+                }
+            }
+        });
+        let script = format!("touch '{}'", touched.display());
+        let err = shell(&script).exec().unwrap_err();
+        assert!(format!("{:#}", err).contains("Command rejected by a pre-exec hook"), "{:#}", err);
+        assert_eq!(err.root_cause().to_string(), "second says no");
+        assert!(shell(&script).exec_stdout_string().is_err());
+        assert!(!touched.exists());
+        assert_eq!(*calls.lock().unwrap(), ["first", "second", "first", "second"]);
+    }
+
+    #[test]
+    fn post_hooks_run_in_order_for_every_method_and_thread() {
+        let calls = Arc::new(Mutex::new(Vec::new()));
+        let _guards = ["a", "b"].map(|name| {
+            let calls = Arc::clone(&calls);
+            scoped_post_exec_hook(move |cmd: &Command, status: &ExitStatus, _: Duration| {
+                if mentions(cmd, "hook-post") {
+                    calls.lock().unwrap().push((name, status.code()));
+                }
+            })
+        });
+        shell("exit 0 # hook-post").exec().unwrap();
+        assert!(shell("exit 1 # hook-post").exec_stdout_string().is_err());
+        let threads: Vec<_> =
+            (0..4).map(|_| thread::spawn(|| shell("true # hook-post").exec_status())).collect();
+        for thread in threads {
+            thread.join().unwrap().unwrap();
+        }
+        let calls = calls.lock().unwrap();
+        assert_eq!(calls[..4], [("a", Some(0)), ("b", Some(0)), ("a", Some(1)), ("b", Some(1))]);
+        assert_eq!(calls.len(), 12);
+        assert_eq!(calls.iter().filter(|(name, _)| *name == "a").count(), 6);
+    }
+
+    #[test]
+    fn dropping_the_guard_removes_the_hook() {
+        let guard = scoped_pre_exec_hook(|cmd: &Command| match mentions(cmd, "hook-dropped") {
+            true => anyhow::bail!("still here"),
+            false => Ok(()),
+        });
+        assert!(shell("true # hook-dropped").exec_stdout_string().is_err());
+        drop(guard);
+        shell("true # hook-dropped").exec_stdout_string().unwrap();
+    }
+
+    #[test]
+    fn a_rejected_command_leaves_its_stdout_file_alone() {
+        let path = temp_path("hook-rejected-file");
+        std::fs::write(&path, "kept\n").unwrap();
+        let _guard = scoped_pre_exec_hook(|cmd: &Command| {
+            anyhow::ensure!(!mentions(cmd, "hook-rejected-file"), "no");
+            Ok(())
+        });
+        assert!(shell("echo hook-rejected-file").exec_stdout_to_file(&path).is_err());
+        assert!(shell("echo hook-rejected-file").exec_stdout_to_file_append(&path).is_err());
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "kept\n");
+    }
+}
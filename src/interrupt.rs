@@ -0,0 +1,62 @@
+use std::process::ExitStatus;
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+
+use crate::exit_signal;
+
+/// Exit status for a program ended by Ctrl-C, by shell convention (128 + SIGINT).
+pub const INTERRUPTED_EXIT_CODE: i32 = 130;
+
+#[cfg(windows)]
+const STATUS_CONTROL_C_EXIT: i32 = 0xC000013A_u32 as i32;
+
+static PENDING: AtomicBool = AtomicBool::new(false);
+// Executions between `execution::start` and their end, on any thread.
+static RUNNING: AtomicUsize = AtomicUsize::new(0);
+
+/// Handles Ctrl-C (SIGINT on Unix, the console control handler on Windows) for the rest of the
+/// process, so that it reads as a cancellation instead of a failure.
+///
+/// While a command is running, the interrupt is noted and left to the child, which gets it too
+/// since it is in the same foreground process group or console. If the child then dies of it,
+/// the method running it fails with `CmdError::Interrupted` instead of `UnsuccessfulExit`, and
+/// the `END OUTPUT` marker uses `Theme::interrupted`. A second Ctrl-C during the same command,
+/// or one while no command is running, exits with code 130 right away.
+#[cfg(feature = "ctrlc")]
+pub fn install_interrupt_handler() -> anyhow::Result<()> {
+    use anyhow::Context;
+
+    ctrlc::set_handler(|| {
+        if RUNNING.load(Ordering::SeqCst) == 0 || PENDING.swap(true, Ordering::SeqCst) {
+            std::process::exit(INTERRUPTED_EXIT_CODE);
+        }
+    })
+    .context("Failed to install the interrupt handler")
+}
+
+// An interrupt stays pending until the next command starts with no other command running.
+pub(crate) fn started() {
+    if RUNNING.fetch_add(1, Ordering::SeqCst) == 0 {
+        PENDING.store(false, Ordering::SeqCst);
+    }
+}
+
+pub(crate) fn ended() {
+    RUNNING.fetch_sub(1, Ordering::SeqCst);
+}
+
+pub(crate) fn is_pending() -> bool {
+    PENDING.load(Ordering::SeqCst)
+}
+
+// Whether `status` is how a child ends on Ctrl-C, and a Ctrl-C was noted while it ran.
+pub(crate) fn interrupted(status: ExitStatus) -> bool {
+    if !is_pending() || status.success() {
+        return false;
+    }
+    #[cfg(windows)]
+    if status.code() == Some(STATUS_CONTROL_C_EXIT) {
+        return true;
+    }
+    // SIGINT
+    exit_signal(status) == Some(2) || status.code() == Some(INTERRUPTED_EXIT_CODE)
+}
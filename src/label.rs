@@ -0,0 +1,60 @@
+use std::io;
+
+use termcolor::{Color, ColorSpec, WriteColor};
+
+use crate::{ConfiguredCommand, TermColorStandardStreamExt};
+
+const PALETTE: [Color; 6] =
+    [Color::Cyan, Color::Magenta, Color::Yellow, Color::Green, Color::Blue, Color::Red];
+
+pub(crate) fn set(cmd: &mut ConfiguredCommand, label: &str) {
+    cmd.settings_mut().label.get_or_insert_with(|| label.to_owned());
+}
+
+pub(crate) fn get(cmd: &ConfiguredCommand) -> Option<&str> {
+    cmd.settings().label.as_deref()
+}
+
+// Writes "[label] " in a color picked from the label itself, so a label keeps its color across
+// runs.
+pub(crate) fn write_tag(out: &mut impl WriteColor, label: &str) -> io::Result<()> {
+    // FNV-1a
+    let hash =
+        label.bytes().fold(0x811c9dc5u32, |hash, b| (hash ^ u32::from(b)).wrapping_mul(0x01000193));
+    let mut spec = ColorSpec::new();
+    spec.set_fg(Some(PALETTE[hash as usize % PALETTE.len()]));
+    out.with_color(&spec, |out| write!(out, "[{}]", label))?;
+    write!(out, " ")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::Command;
+
+    use super::get;
+    use crate::{CommandExt, ConfiguredCommand};
+
+    #[test]
+    fn label_is_not_part_of_the_environment() {
+        let cmd = Command::new("true").env_("A", "1").label("build");
+        assert_eq!(cmd.get_envs().count(), 1);
+        assert_eq!(get(&cmd), Some("build"));
+    }
+
+    #[test]
+    fn label_survives_env_clear_and_copies() {
+        let cmd = Command::new("true").label("build").env_clear_().label("other");
+        assert_eq!(get(&cmd), Some("build"));
+        assert_eq!(get(&cmd.copy()), Some("build"));
+    }
+
+    #[test]
+    fn label_is_not_inherited_by_a_new_command() {
+        for label in ["first", "second", "third"] {
+            let cmd = Command::new("sh").label(label);
+            assert_eq!(get(&cmd), Some(label));
+        }
+        let cmd = ConfiguredCommand::from(Command::new("sh").args_(["-c", "true"]));
+        assert_eq!(get(&cmd), None);
+    }
+}
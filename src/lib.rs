@@ -1,28 +1,333 @@
 #![forbid(unsafe_code)]
 
+mod ansi;
+mod assert;
+mod backend;
+mod background;
+mod cache;
+mod capture;
+mod child;
+mod color;
+mod config;
+mod described;
+mod dir;
+mod duplex;
+#[cfg(feature = "encoding")]
+mod encoding;
+mod error;
+mod events;
+mod execution;
+mod filter;
+mod group;
+mod guard;
+mod heartbeat;
+mod hooks;
+mod interrupt;
+mod json;
+mod label;
+mod lines;
+mod log;
+#[cfg(feature = "serde")]
+mod manifest;
+mod merged;
+#[cfg(feature = "mock")]
+mod mock;
+mod parallel;
+mod parse;
+mod path_var;
+mod pipeline;
+mod quote;
+mod redact;
+mod replace;
+#[cfg(feature = "replay")]
+mod replay;
+mod retry;
+mod save;
+mod scope;
+mod scoped_env;
+mod sequence;
+mod settings;
+mod slow;
+mod snapshot;
+mod spec;
+mod split;
+mod stats;
+mod sudo;
+mod temp;
+mod template;
+#[cfg(test)]
+mod test_support;
+mod theme;
+mod timestamp;
+#[cfg(feature = "tokio")]
+mod tokio_ext;
+mod which;
+mod zone;
+
+use std::borrow::Cow;
 use std::ffi::OsStr;
 use std::fmt::Display;
-use std::io::Write;
-use std::process::{Command, ExitStatus};
+use std::io::{self, Read, Write};
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus, Stdio};
+use std::str::{FromStr, Utf8Error};
+use std::sync::atomic::{AtomicBool, AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
 
+pub use ansi::strip_ansi;
 use anyhow::Context;
+pub use background::BackgroundChild;
+pub use cache::CommandCache;
+use capture::{
+    capture, check_redirected, spawn_piped, CaptureOptions, Captured, Check, Forward, Info, Input,
+    Pipes,
+};
+use color::{decoration_stream, stderr_stream, stdout_stream, DecorationStream};
+pub use color::{
+    reset_color_choice, set_color_choice, set_decoration_writer, set_decorations_enabled,
+    take_decoration_writer,
+};
+pub use config::{Config, ConfigGuard};
+pub use described::DescribedChild;
+pub use dir::{pushd, DirGuard};
+pub use duplex::Duplex;
+#[cfg(feature = "encoding")]
+pub use encoding::Encoding;
+pub use error::{CmdError, Stream};
+pub use events::{clear_event_writer, set_event_writer};
+pub use filter::FilteredOutput;
+pub use guard::ChildGuard;
+pub use heartbeat::{set_heartbeat_template, set_heartbeat_when_not_terminal};
+pub use hooks::{
+    add_post_exec_hook, add_pre_exec_hook, remove_hook, scoped_post_exec_hook,
+    scoped_pre_exec_hook, HookGuard, HookId,
+};
+#[cfg(feature = "ctrlc")]
+pub use interrupt::install_interrupt_handler;
+pub use interrupt::INTERRUPTED_EXIT_CODE;
+pub use lines::LineIter;
+pub use log::{clear_log_file, set_log_file};
+#[cfg(feature = "serde")]
+pub use manifest::{Manifest, Step, StepReport};
+pub use merged::MergedOutput;
+#[cfg(feature = "mock")]
+pub use mock::{MockCommand, MockGuard, MockRunner};
+pub use parallel::{run_parallel, ParallelRunner};
+pub use pipeline::Pipeline;
+pub use quote::{shell_join, shell_quote, shell_quote_windows};
+pub use redact::{set_redacted_env_keys, set_redacted_values};
+pub use replace::exit_with_status;
+#[cfg(feature = "replay")]
+pub use replay::{record_to, RecordGuard, Replay};
+pub use retry::{Backoff, RetryPolicy};
+pub use scope::scope;
+pub use scoped_env::{scoped_env, set_show_scoped_env, EnvGuard};
+pub use sequence::{run_all, Sequence, SequenceFailed, SequenceFailure, SequenceMode};
+pub use settings::ConfiguredCommand;
+pub use slow::set_slow_command_warning;
+pub use snapshot::CommandSnapshot;
+pub use spec::CommandSpec;
+pub use split::{args_from_str, cmd_from_str};
+pub use stats::{reset_stats, set_stats_enabled, stats, CommandStat, Stats};
+pub use sudo::sudo;
+pub use temp::{TempDir, TempRun};
+pub use template::CommandTemplate;
 use termcolor::WriteColor;
+pub use theme::{set_theme, Theme};
+pub use timestamp::{set_timestamps, TimestampFormat};
+#[cfg(feature = "tokio")]
+pub use tokio_ext::AsyncCommandExt;
+use which::SpawnContext;
+pub use which::{validate_all, which, which_all};
 
 pub trait CommandExt {
     fn description(&self) -> CommandDescription<'_>;
+    /// Checks without spawning anything that the program exists and is executable, that the
+    /// directory exists, and that the names of the environment variables set on the command are
+    /// valid. A relative program path with a separator, such as `./build.sh`, is looked up in the
+    /// command's directory, as a child on Unix would.
+    fn validate(&self) -> anyhow::Result<()>;
 
     fn args_<I, S>(self, args: I) -> Self
     where
         I: IntoIterator<Item = S>,
         S: AsRef<OsStr>;
+    fn arg_<S: AsRef<OsStr>>(self, arg: S) -> Self;
+    /// The conditional forms of `arg_` and `args_`, to keep optional flags in one expression.
+    fn arg_if<S: AsRef<OsStr>>(self, cond: bool, arg: S) -> Self;
+    fn args_if<I, S>(self, cond: bool, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>;
+    fn arg_opt<S: AsRef<OsStr>>(self, arg: Option<S>) -> Self;
+    /// Appends `flag` and `value` as two arguments, such as `--output out.txt`, if `value` is
+    /// `Some`.
+    fn arg_key_val_opt<K, V>(self, flag: K, value: Option<V>) -> Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>;
+    /// Appends `arg` to the command line as is, without the quoting `arg` adds, for programs such
+    /// as `cmd /C` and `msiexec` that parse their command line their own way.
+    #[cfg(windows)]
+    fn raw_arg_<S: AsRef<OsStr>>(self, arg: S) -> Self;
+    fn env_<K, V>(self, key: K, val: V) -> Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>;
+    fn envs_<I, K, V>(self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>;
+    /// Puts `dir` first on the `PATH` the command runs with, which is the one set on the command
+    /// if there is one, otherwise the inherited one. Panics if `dir` contains the platform's
+    /// `PATH` separator.
+    fn prepend_path_<P: AsRef<Path>>(self, dir: P) -> Self;
+    /// Like `prepend_path_`, but puts `dir` last.
+    fn append_path_<P: AsRef<Path>>(self, dir: P) -> Self;
+    fn env_remove_<K: AsRef<OsStr>>(self, key: K) -> Self;
+    fn env_clear_(self) -> Self;
+    fn current_dir_<P: AsRef<Path>>(self, dir: P) -> Self;
+    /// The capturing methods (`exec_stdout_string` and friends) replace stdout and stderr with
+    /// pipes, and the methods that feed the child input replace stdin, so they fail instead of
+    /// running a command whose corresponding stream was set with these. Methods that inherit the
+    /// stream, such as `exec`, honor the setting. The settings are kept by the returned
+    /// `ConfiguredCommand`, so streams set with `Command::stdout` and the like can't be detected
+    /// and are replaced without an error.
+    fn stdin_<T: Into<Stdio>>(self, cfg: T) -> ConfiguredCommand;
+    fn stdout_<T: Into<Stdio>>(self, cfg: T) -> ConfiguredCommand;
+    fn stderr_<T: Into<Stdio>>(self, cfg: T) -> ConfiguredCommand;
+    /// Tags the banner, the `END OUTPUT` marker, and every line forwarded by `exec_tee` with
+    /// `[label]` in a color derived from the label. A command keeps the first label it is given.
+    fn label<S: AsRef<str>>(self, label: S) -> ConfiguredCommand;
+    /// Starts the command in a process group of its own, so that timeouts, `ChildGuard`,
+    /// `BackgroundChild::kill`, and the other kill paths signal the command and every process it
+    /// started, instead of leaving its descendants behind.
+    ///
+    /// The command is then no longer in the terminal's foreground process group: Ctrl-C at the
+    /// terminal doesn't reach it, and it is stopped if it reads from the terminal. On Windows
+    /// this does nothing.
+    fn new_process_group(self) -> ConfiguredCommand;
+    /// Warns about the command while it runs for longer than `after`, as
+    /// `set_slow_command_warning` does, which this overrides. A command keeps the first threshold
+    /// it is given.
+    fn warn_after(self, after: Duration) -> ConfiguredCommand;
+    /// Writes a heartbeat line (see `set_heartbeat_template`) each time another `every` passes
+    /// while the command runs, if stderr is a terminal or `set_heartbeat_when_not_terminal` is
+    /// on. A command keeps the first interval it is given.
+    fn heartbeat(self, every: Duration) -> ConfiguredCommand;
 
     fn exec(&mut self) -> anyhow::Result<()>;
+    fn exec_status(&mut self) -> anyhow::Result<ExitStatus>;
+    fn exec_timed(&mut self) -> anyhow::Result<ExecReport>;
+    fn exec_quiet(&mut self) -> anyhow::Result<()>;
+    /// Captures stdout and stderr and writes nothing at all if the command succeeds, like
+    /// `chronic` from moreutils. If it fails, the banner, the captured stdout, the captured stderr
+    /// in the failure color and the `END OUTPUT` marker are written only then.
+    fn exec_silent_unless_failure(&mut self) -> anyhow::Result<()>;
+    /// Runs the command attached to the user's terminal, for programs such as editors, `ssh`, or
+    /// password prompts.
+    ///
+    /// The banner is written and flushed, and the color of stderr is reset, before the child is
+    /// spawned, so a child that crashes cannot leave the terminal in a colored state.
+    fn exec_interactive(&mut self) -> anyhow::Result<()>;
+    fn exec_with_timeout(&mut self, timeout: Duration) -> anyhow::Result<()>;
+    fn exec_with_stdin<B: AsRef<[u8]>>(&mut self, input: B) -> anyhow::Result<()>;
+    fn exec_retry(&mut self, policy: RetryPolicy) -> anyhow::Result<()>;
+    /// Replaces the current process with the command, after printing the banner, and only
+    /// returns if that failed. On Windows, which can't do that, the command is run as a child
+    /// instead, and the current process then exits with its status as `exit_with_status` does.
+    /// Nothing is logged for the run, since it doesn't end while this process can see it.
+    fn exec_replace(&mut self) -> anyhow::Error;
+    fn exec_allowing<I: IntoIterator<Item = i32>>(
+        &mut self,
+        codes: I,
+    ) -> anyhow::Result<ExitStatus>;
     fn exec_args<I, S>(&mut self, args: I) -> anyhow::Result<()>
     where
         I: IntoIterator<Item = S>,
         S: AsRef<OsStr>;
+    fn exec_stdout_to_file<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()>;
+    fn exec_for_each_line<O, E>(
+        &mut self,
+        on_stdout: O,
+        on_stderr: E,
+    ) -> anyhow::Result<ExitStatus>
+    where
+        O: FnMut(&str) + Send,
+        E: FnMut(&str) + Send;
+    fn exec_stdout_to_file_append<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()>;
+    fn exec_capture_merged(&mut self) -> anyhow::Result<MergedOutput>;
+    /// Reads stdout and stderr line by line and keeps only the lines `pred` returns true for,
+    /// such as "warning:" lines of a compiler, so that the rest is never buffered. A failure
+    /// embeds the kept lines.
+    fn exec_filter_lines<P>(&mut self, pred: P) -> anyhow::Result<FilteredOutput>
+    where
+        P: FnMut(&str) -> bool + Send;
+    /// Spawns the command without waiting for it. The process is terminated when the guard is
+    /// dropped, unless it is detached or has exited.
+    fn spawn_guarded(&mut self) -> anyhow::Result<ChildGuard>;
+    /// Spawns the command without waiting for it, as `Command::spawn` does, after printing the
+    /// banner. The child keeps the command's description for its errors. Fails for a command
+    /// that a `MockRunner` would run, since there is no real child to return.
+    fn spawn_described(&mut self) -> anyhow::Result<DescribedChild>;
+    /// Spawns the command with stdin, stdout and stderr piped, to write lines to it while reading
+    /// the lines it writes.
+    fn spawn_duplex(&mut self) -> anyhow::Result<Duplex>;
 
     fn exec_stdout_string(self) -> anyhow::Result<Output>;
+    /// Like `exec_stdout_string`, but leaves the command to be run again. `Output::command` is a
+    /// copy of its program, arguments, environment and directory, for describing the run only:
+    /// stdio, `env_clear` and platform settings such as `process_group` are not copied.
+    fn exec_stdout_string_ref(&mut self) -> anyhow::Result<Output>;
+    /// Like `exec_stdout_string_ref`, but returns the output stored in `cache` for the same
+    /// command if there is any, without running it, and stores the output of a successful run
+    /// otherwise. Only for commands whose output depends on nothing but how they are run. A
+    /// cached `Output` has a zero duration.
+    fn exec_stdout_string_cached(&mut self, cache: &CommandCache) -> anyhow::Result<Output>;
+    /// Like `exec_stdout_string_ref`, in a new directory under the system's temporary directory,
+    /// which a copy of the command is run in, leaving its own `current_dir` alone. The directory
+    /// is removed when the returned `TempRun` is dropped, unless it is kept. If the command fails,
+    /// it is kept for inspection, and the error names it.
+    fn exec_in_temp_dir(&mut self) -> anyhow::Result<TempRun>;
+    fn exec_stdout_string_with_timeout(self, timeout: Duration) -> anyhow::Result<Output>;
+    /// Like `exec_stdout_string`, but keeps at most `max_bytes` of stdout and of stderr. The rest
+    /// is read and dropped, and `Output::truncation` records how much there was in total. A
+    /// multi-byte character cut by the limit is dropped too.
+    fn exec_stdout_string_limited(self, max_bytes: usize) -> anyhow::Result<Output>;
+    fn exec_stdout_string_with_stdin<B: AsRef<[u8]>>(self, input: B) -> anyhow::Result<Output>;
+    fn exec_stdin_reader<R: Read + Send>(self, reader: R) -> anyhow::Result<Output>;
+    /// Like `exec_stdout_string`, but also copies every chunk of stdout and stderr to the sinks as
+    /// soon as it is read. The sinks are flushed once the process has exited. A sink that fails
+    /// gets no more output, and the error is returned after the process has been waited for.
+    fn exec_stdout_string_tee(
+        self,
+        stdout_sink: &mut dyn Write,
+        stderr_sink: &mut dyn Write,
+    ) -> anyhow::Result<Output>;
+    fn exec_tee(self) -> anyhow::Result<Output>;
+    fn exec_background(self) -> anyhow::Result<BackgroundChild>;
+    fn exec_lines(self) -> anyhow::Result<LineIter>;
+    fn pipe(self, next: impl Into<ConfiguredCommand>) -> Pipeline;
+    fn exec_stdout_string_retry(self, policy: RetryPolicy) -> anyhow::Result<Output>;
+    fn exec_stdout_string_allowing<I: IntoIterator<Item = i32>>(
+        self,
+        codes: I,
+    ) -> anyhow::Result<Output>;
+    fn exec_stdout_lossy(self) -> anyhow::Result<Output>;
+    fn exec_capture(self, policy: Utf8Policy) -> anyhow::Result<Capture>;
+    fn exec_stdout_lines(self) -> anyhow::Result<Vec<String>>;
+    #[cfg(feature = "serde")]
+    fn exec_stdout_json<T: serde::de::DeserializeOwned>(self) -> anyhow::Result<T>;
+    /// Like `exec_stdout_string`, for commands that write text in another encoding, such as the
+    /// code page of a legacy tool. Stdout and stderr are decoded to UTF-8, and bytes of stdout
+    /// that aren't valid in the encoding are a `CmdError::Undecodable` naming the first one.
+    #[cfg(feature = "encoding")]
+    fn exec_stdout_encoded(self, encoding: Encoding) -> anyhow::Result<Output>;
+    fn exec_stdout_bytes(self) -> anyhow::Result<BytesOutput>;
+    fn exec_stderr_string(self) -> anyhow::Result<StderrOutput>;
+    fn exec_output(self) -> anyhow::Result<TextOutput>;
 }
 
 pub struct CommandDescription<'a> {
@@ -34,17 +339,98 @@ pub struct Output {
     pub status: ExitStatus,
     pub stdout: String,
     pub stderr: Vec<u8>,
+    pub stdout_was_lossy: bool,
+    pub duration: Duration,
+    /// Set when `exec_stdout_string_limited` dropped output past its limit.
+    pub truncation: Option<Truncation>,
+    /// Set when `normalize_newlines` changed stdout.
+    pub stdout_was_normalized: bool,
+}
+
+/// How many bytes a command wrote to each stream in total, of which `Output` only kept up to the
+/// capture limit.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct Truncation {
+    pub stdout_total: u64,
+    pub stderr_total: u64,
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct ExecReport {
+    pub status: ExitStatus,
+    pub duration: Duration,
 }
 
 pub struct OutputDescription<'a> {
     out: &'a Output,
 }
 
-impl CommandExt for Command {
+/// How `exec_capture` handles stdout that is not valid UTF-8.
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum Utf8Policy {
+    /// Fail, like `exec_stdout_string`.
+    Strict,
+    /// Replace invalid sequences with U+FFFD, like `exec_stdout_lossy`.
+    Lossy,
+    /// Keep stdout as raw bytes without checking it.
+    Bytes,
+}
+
+pub struct Capture {
+    pub command: Command,
+    pub status: ExitStatus,
+    stdout: CapturedStdout,
+    pub stderr: Vec<u8>,
+    pub duration: Duration,
+}
+
+enum CapturedStdout {
+    Text { text: String, lossy: bool },
+    Bytes(Vec<u8>),
+}
+
+pub struct BytesOutput {
+    pub command: Command,
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: Vec<u8>,
+}
+
+pub struct BytesOutputDescription<'a> {
+    out: &'a BytesOutput,
+}
+
+pub struct StderrOutput {
+    pub command: Command,
+    pub status: ExitStatus,
+    pub stdout: Vec<u8>,
+    pub stderr: String,
+}
+
+pub struct StderrOutputDescription<'a> {
+    out: &'a StderrOutput,
+}
+
+pub struct TextOutput {
+    pub command: Command,
+    pub status: ExitStatus,
+    pub stdout: String,
+    pub stderr: String,
+}
+
+pub struct TextOutputDescription<'a> {
+    out: &'a TextOutput,
+}
+
+impl CommandExt for ConfiguredCommand {
     fn description(&self) -> CommandDescription<'_> {
         CommandDescription { cmd: self }
     }
 
+    fn validate(&self) -> anyhow::Result<()> {
+        which::validate(self)
+    }
+
     fn args_<I, S>(self, args: I) -> Self
     where
         I: IntoIterator<Item = S>,
@@ -54,45 +440,405 @@ impl CommandExt for Command {
         self_.args(args);
         self_
     }
+    fn arg_<S: AsRef<OsStr>>(self, arg: S) -> Self {
+        let mut self_ = self;
+        self_.arg(arg);
+        self_
+    }
+    fn arg_if<S: AsRef<OsStr>>(self, cond: bool, arg: S) -> Self {
+        self.arg_opt(cond.then_some(arg))
+    }
+    fn args_if<I, S>(self, cond: bool, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let mut self_ = self;
+        if cond {
+            self_.args(args);
+        }
+        self_
+    }
+    fn arg_opt<S: AsRef<OsStr>>(self, arg: Option<S>) -> Self {
+        let mut self_ = self;
+        if let Some(arg) = arg {
+            self_.arg(arg);
+        }
+        self_
+    }
+    fn arg_key_val_opt<K, V>(self, flag: K, value: Option<V>) -> Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        let mut self_ = self;
+        if let Some(value) = value {
+            self_.arg(flag).arg(value);
+        }
+        self_
+    }
+    #[cfg(windows)]
+    fn raw_arg_<S: AsRef<OsStr>>(self, arg: S) -> Self {
+        use std::os::windows::process::CommandExt as _;
+        let mut self_ = self;
+        self_.raw_arg(arg);
+        self_
+    }
+    fn env_<K, V>(self, key: K, val: V) -> Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        let mut self_ = self;
+        self_.env(key, val);
+        self_
+    }
+    fn envs_<I, K, V>(self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        let mut self_ = self;
+        self_.envs(vars);
+        self_
+    }
+    fn prepend_path_<P: AsRef<Path>>(self, dir: P) -> Self {
+        let mut self_ = self;
+        path_var::add(&mut self_, dir.as_ref(), true);
+        self_
+    }
+    fn append_path_<P: AsRef<Path>>(self, dir: P) -> Self {
+        let mut self_ = self;
+        path_var::add(&mut self_, dir.as_ref(), false);
+        self_
+    }
+    fn env_remove_<K: AsRef<OsStr>>(self, key: K) -> Self {
+        let mut self_ = self;
+        self_.env_remove(key);
+        self_
+    }
+    fn env_clear_(self) -> Self {
+        let mut self_ = self;
+        self_.env_clear();
+        self_
+    }
+    fn current_dir_<P: AsRef<Path>>(self, dir: P) -> Self {
+        let mut self_ = self;
+        self_.current_dir(dir);
+        self_
+    }
+    fn stdin_<T: Into<Stdio>>(self, cfg: T) -> ConfiguredCommand {
+        let mut self_ = self;
+        self_.stdin(cfg);
+        self_.settings_mut().redirected.stdin = true;
+        self_
+    }
+    fn stdout_<T: Into<Stdio>>(self, cfg: T) -> ConfiguredCommand {
+        let mut self_ = self;
+        self_.stdout(cfg);
+        self_.settings_mut().redirected.stdout = true;
+        self_
+    }
+    fn stderr_<T: Into<Stdio>>(self, cfg: T) -> ConfiguredCommand {
+        let mut self_ = self;
+        self_.stderr(cfg);
+        self_.settings_mut().redirected.stderr = true;
+        self_
+    }
+    fn label<S: AsRef<str>>(self, label: S) -> ConfiguredCommand {
+        let mut self_ = self;
+        label::set(&mut self_, label.as_ref());
+        self_
+    }
+    fn new_process_group(self) -> ConfiguredCommand {
+        let mut self_ = self;
+        group::set(&mut self_);
+        self_
+    }
+    fn warn_after(self, after: Duration) -> ConfiguredCommand {
+        let mut self_ = self;
+        slow::set(&mut self_, after);
+        self_
+    }
+    fn heartbeat(self, every: Duration) -> ConfiguredCommand {
+        let mut self_ = self;
+        heartbeat::set(&mut self_, every);
+        self_
+    }
 
     fn exec(&mut self) -> anyhow::Result<()> {
-        use termcolor::{Color, ColorChoice, ColorSpec, StandardStream};
-
-        let mut stderr = StandardStream::stderr(ColorChoice::Auto);
-
-        let current_dir =
-            std::env::current_dir().with_context(|| "Failed to get current working directory")?;
-        let current_dir_color_spec = {
-            let mut spec = ColorSpec::new();
-            spec.set_bg(Some(Color::Cyan));
-            spec.set_fg(Some(Color::Black));
-            spec
-        };
-        stderr.with_color(&current_dir_color_spec, |s| {
-            write!(s, "{}", current_dir.display()).unwrap()
-        });
-        writeln!(stderr, " {:?}", self).unwrap();
-
-        let cmd_success = self.status().with_context(|| "Failed to execute command")?.success();
-
-        let eo_color_spec = {
-            let mut spec = ColorSpec::new();
-            if cmd_success {
-                spec.set_bg(Some(Color::Green));
-            } else {
-                spec.set_bg(Some(Color::Red));
-            }
-            spec.set_fg(Some(Color::Black));
-            spec
+        let status = self.exec_status()?;
+        if !status.success() {
+            return Err(exec_failed(
+                self,
+                status,
+                &[],
+                &[],
+                format!(
+                    "Process did not exit successfully, {} ({})",
+                    StatusSummary(status),
+                    self.description(),
+                ),
+            ));
+        }
+        Ok(())
+    }
+    fn exec_status(&mut self) -> anyhow::Result<ExitStatus> {
+        Ok(exec_report(self)?.status)
+    }
+    fn exec_timed(&mut self) -> anyhow::Result<ExecReport> {
+        let report = exec_report(self)?;
+        if !report.status.success() {
+            return Err(exec_failed(
+                self,
+                report.status,
+                &[],
+                &[],
+                format!(
+                    "Process did not exit successfully, {} ({})",
+                    StatusSummary(report.status),
+                    self.description(),
+                ),
+            ));
+        }
+        Ok(report)
+    }
+    fn exec_quiet(&mut self) -> anyhow::Result<()> {
+        if dry_run(self)? {
+            return Ok(());
+        }
+        let execution = execution::start(self)?;
+        let status = backend::status(self);
+        execution.finish(self, status.as_ref().ok(), None);
+        let status = status.spawn_context(self, || {
+            format!("Failed to execute command ({})", self.description())
+        })?;
+        if !status.success() {
+            let mut stderr = decoration_stream();
+            write_failure_summary(&mut stderr, self, status);
+            return Err(exec_failed(
+                self,
+                status,
+                &[],
+                &[],
+                format!(
+                    "Process did not exit successfully, {} ({})",
+                    StatusSummary(status),
+                    self.description(),
+                ),
+            ));
+        }
+        Ok(())
+    }
+    fn exec_silent_unless_failure(&mut self) -> anyhow::Result<()> {
+        if dry_run(self)? {
+            return Ok(());
+        }
+        let execution = execution::start(self)?;
+        let mut child =
+            spawn_piped(self, Pipes { stdout: true, stderr: true, ..Pipes::default() })?;
+        let readers = child::spawn_output_readers(&mut child);
+        let status = child.wait();
+        let (stdout, child_stderr) = readers.join();
+        let duration = execution.finish(self, status.as_ref().ok(), Some((&stdout, &child_stderr)));
+        let status = status
+            .with_context(|| format!("Failed to wait for command ({})", self.description()))?;
+        if status.success() {
+            return Ok(());
+        }
+        let mut stderr = decoration_stream();
+        write_banner(&mut stderr, self)?;
+        child::replay_output(label::get(self), &stdout, &child_stderr).with_context(|| {
+            format!("Failed to write the output of command ({})", self.description())
+        })?;
+        write_end_output_with_summary(
+            &mut stderr,
+            self,
+            false,
+            None,
+            duration,
+            Some((&stdout, &child_stderr)),
+        );
+        Err(exec_failed(
+            self,
+            status,
+            &stdout,
+            &child_stderr,
+            format!(
+                "Process did not exit successfully, {} ({})",
+                StatusSummary(status),
+                self.description(),
+            ),
+        ))
+    }
+    fn exec_interactive(&mut self) -> anyhow::Result<()> {
+        check_redirected(self, Pipes { stdin: true, stdout: true, stderr: true })?;
+        if dry_run(self)? {
+            return Ok(());
+        }
+        let mut stderr = decoration_stream();
+        write_banner(&mut stderr, self)?;
+        decorate(|| {
+            stderr.reset()?;
+            stderr.flush()
+        })
+        .with_context(|| format!("Failed to reset stderr ({})", self.description()))?;
+        let execution = execution::start(self)?;
+        self.stdin(Stdio::inherit()).stdout(Stdio::inherit()).stderr(Stdio::inherit());
+        let status = backend::status(self);
+        let duration = execution.finish(self, status.as_ref().ok(), None);
+        let status = status.spawn_context(self, || {
+            format!("Failed to execute command ({})", self.description())
+        })?;
+        write_end_output(&mut stderr, self, status.success(), None, duration);
+        if !status.success() {
+            return Err(exec_failed(
+                self,
+                status,
+                &[],
+                &[],
+                format!(
+                    "Process did not exit successfully, {} ({})",
+                    StatusSummary(status),
+                    self.description(),
+                ),
+            ));
+        }
+        Ok(())
+    }
+    fn exec_with_timeout(&mut self, timeout: Duration) -> anyhow::Result<()> {
+        if dry_run(self)? {
+            return Ok(());
+        }
+        let mut stderr = decoration_stream();
+        write_banner(&mut stderr, self)?;
+        let execution = execution::start(self)?;
+        let mut child = backend::spawn(self).spawn_context(self, || {
+            format!("Failed to execute command ({})", self.description())
+        })?;
+        let status = child::wait_timeout(&mut child, timeout);
+        if let Ok(None) = status {
+            child::terminate(&mut child);
+        }
+        let duration = execution.finish(self, status.as_ref().ok().and_then(Option::as_ref), None);
+        let status = status
+            .with_context(|| format!("Failed to wait for command ({})", self.description()))?;
+        let Some(status) = status else {
+            write_end_output(&mut stderr, self, false, Some("TIMED OUT"), duration);
+            return Err(timed_out(
+                self,
+                timeout,
+                format!("Process timed out after {:?} ({})", duration, self.description()),
+            ));
         };
-        stderr.with_color(&eo_color_spec, |s| write!(s, " END OUTPUT ").unwrap());
-        writeln!(stderr).unwrap();
-
-        if !cmd_success {
-            anyhow::bail!("Process did not exit successfully");
+        write_end_output(&mut stderr, self, status.success(), None, duration);
+        if !status.success() {
+            return Err(exec_failed(
+                self,
+                status,
+                &[],
+                &[],
+                format!(
+                    "Process did not exit successfully, {} ({})",
+                    StatusSummary(status),
+                    self.description(),
+                ),
+            ));
         }
         Ok(())
     }
+    fn exec_with_stdin<B: AsRef<[u8]>>(&mut self, input: B) -> anyhow::Result<()> {
+        let options = CaptureOptions {
+            stdout: false,
+            stderr: false,
+            input: Some(Input::Bytes(input.as_ref())),
+            decorated: true,
+            ..CaptureOptions::default()
+        };
+        capture(self, options).map(|_| ())
+    }
+    fn exec_retry(&mut self, policy: RetryPolicy) -> anyhow::Result<()> {
+        if dry_run(self)? {
+            return Ok(());
+        }
+        let mut stderr = decoration_stream();
+        let max_attempts = policy.max_attempts();
+        let reads_stderr = policy.reads_stderr();
+        let mut attempt = 1;
+        loop {
+            let annotation = format!("attempt {}/{}", attempt, max_attempts);
+            write_banner_annotated(&mut stderr, self, Some(&annotation))?;
+            // Stderr is only piped for the attempt, so the command is left as it was.
+            let options = CaptureOptions {
+                stdout: false,
+                stderr: reads_stderr,
+                check: Check::Unchecked,
+                ..CaptureOptions::default()
+            };
+            let captured = capture(self, options)?;
+            let status = captured.status;
+            let retried = !status.success()
+                && attempt < max_attempts
+                && policy.is_retryable(&status, &captured.stderr);
+            if reads_stderr {
+                let spec = if retried {
+                    theme::theme().retried_output
+                } else {
+                    termcolor::ColorSpec::new()
+                };
+                let mut out = color::stderr_stream();
+                let _ = out.with_color(&spec, |out| out.write_all(&captured.stderr));
+                let _ = out.flush();
+            }
+            write_end_output(&mut stderr, self, status.success(), None, captured.duration);
+            if status.success() {
+                return Ok(());
+            }
+            if !retried {
+                let after = format!(" after {} attempt(s)", attempt);
+                return Err(captured.failure(self, &after));
+            }
+            std::thread::sleep(policy.delay(attempt));
+            attempt += 1;
+        }
+    }
+    fn exec_replace(&mut self) -> anyhow::Error {
+        replace::exec_replace(self)
+    }
+    fn exec_allowing<I: IntoIterator<Item = i32>>(
+        &mut self,
+        codes: I,
+    ) -> anyhow::Result<ExitStatus> {
+        if dry_run(self)? {
+            return Ok(ExitStatus::default());
+        }
+        let codes: Vec<i32> = codes.into_iter().collect();
+        let mut stderr = decoration_stream();
+        write_banner(&mut stderr, self)?;
+        let execution = execution::start(self)?;
+        let status = backend::status(self);
+        let duration = execution.finish(self, status.as_ref().ok(), None);
+        let status = status.spawn_context(self, || {
+            format!("Failed to execute command ({})", self.description())
+        })?;
+        let allowed = is_allowed(status, &codes);
+        write_end_output(&mut stderr, self, allowed, None, duration);
+        if !allowed {
+            return Err(exec_failed(
+                self,
+                status,
+                &[],
+                &[],
+                format!(
+                    "Process did not exit successfully, {} ({})",
+                    StatusSummary(status),
+                    self.description(),
+                ),
+            ));
+        }
+        Ok(status)
+    }
     fn exec_args<I, S>(&mut self, args: I) -> anyhow::Result<()>
     where
         I: IntoIterator<Item = S>,
@@ -102,111 +848,2638 @@ impl CommandExt for Command {
         self.exec()
     }
 
-    fn exec_stdout_string(self) -> anyhow::Result<Output> {
-        use std::process::{Output as StdOutput, Stdio};
-        let mut self_ = self;
-        let StdOutput { status, stdout, stderr } = self_
-            .stdout(Stdio::piped())
-            .stderr(Stdio::piped())
-            .output()
-            .with_context(|| format!("Failed to execute command ({})", self_.description()))?;
-        if !status.success() {
+    fn exec_stdout_to_file<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        exec_stdout_to_file(self, path.as_ref(), false)
+    }
+    fn exec_stdout_to_file_append<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        exec_stdout_to_file(self, path.as_ref(), true)
+    }
+    fn exec_for_each_line<O, E>(&mut self, on_stdout: O, on_stderr: E) -> anyhow::Result<ExitStatus>
+    where
+        O: FnMut(&str) + Send,
+        E: FnMut(&str) + Send,
+    {
+        if dry_run(self)? {
+            return Ok(ExitStatus::default());
+        }
+        let execution = execution::start(self)?;
+        let mut child =
+            spawn_piped(self, Pipes { stdout: true, stderr: true, ..Pipes::default() })?;
+        let stdout = child.take_stdout();
+        let stderr = child.take_stderr();
+        std::thread::scope(|s| {
+            if let Some(stdout) = stdout {
+                s.spawn(|| child::for_each_line(stdout, on_stdout));
+            }
+            if let Some(stderr) = stderr {
+                s.spawn(|| child::for_each_line(stderr, on_stderr));
+            }
+        });
+        let status = child.wait();
+        execution.finish(self, status.as_ref().ok(), None);
+        status.with_context(|| format!("Failed to wait for command ({})", self.description()))
+    }
+
+    fn exec_capture_merged(&mut self) -> anyhow::Result<MergedOutput> {
+        if dry_run(self)? {
+            let status = ExitStatus::default();
+            return Ok(MergedOutput { status, chunks: Vec::new(), duration: Duration::ZERO });
+        }
+        let execution = execution::start(self)?;
+        let mut child =
+            spawn_piped(self, Pipes { stdout: true, stderr: true, ..Pipes::default() })?;
+        let chunks = Arc::default();
+        let readers = child::spawn_merged_readers(&mut child, &chunks);
+        let status = child.wait();
+        readers.join();
+        let chunks = std::mem::take(&mut *chunks.lock().unwrap());
+        let mut out =
+            MergedOutput { status: ExitStatus::default(), chunks, duration: Duration::ZERO };
+        let (stdout, stderr) = (out.stdout(), out.stderr());
+        out.duration = execution.finish(self, status.as_ref().ok(), Some((&stdout, &stderr)));
+        out.status = status
+            .with_context(|| format!("Failed to wait for command ({})", self.description()))?;
+        if !out.status.success() {
+            return Err(exec_failed(
+                self,
+                out.status,
+                &stdout,
+                &stderr,
+                format!(
+                    "Process did not exit successfully ({}, output = {})",
+                    self.description(),
+                    Embedded::new(&out.transcript()),
+                ),
+            ));
+        }
+        Ok(out)
+    }
+
+    fn exec_filter_lines<P>(&mut self, pred: P) -> anyhow::Result<FilteredOutput>
+    where
+        P: FnMut(&str) -> bool + Send,
+    {
+        filter::exec_filter_lines(self, pred)
+    }
+
+    fn spawn_guarded(&mut self) -> anyhow::Result<ChildGuard> {
+        if dry_run(self)? {
             anyhow::bail!(
-                "Process did not exit successfully ({})",
-                cmd_info_with_output(&self_, &stdout, &stderr),
+                "Cannot start a guarded process in dry-run mode ({})",
+                self.description()
             );
         }
-        let stdout = String::from_utf8(stdout).map_err(|e| {
-            let context = format!(
-                "Process stdout is not UTF-8 ({})",
-                cmd_info_with_output(&self_, e.as_bytes(), &stderr),
-            );
-            anyhow::Error::new(e).context(context)
+        let mut stderr = decoration_stream();
+        write_banner_annotated(&mut stderr, self, Some("BACKGROUND"))?;
+        let execution = execution::start_background(self)?;
+        let child = backend::spawn(self).spawn_context(self, || {
+            format!("Failed to execute command ({})", self.description())
         })?;
-        Ok(Output { command: self_, status, stdout, stderr })
+        Ok(ChildGuard::from_process(child, self.description().to_string(), Some(execution)))
     }
-}
 
-impl Output {
-    pub fn description(&self) -> OutputDescription<'_> {
-        OutputDescription { out: self }
+    fn spawn_described(&mut self) -> anyhow::Result<DescribedChild> {
+        if dry_run(self)? {
+            anyhow::bail!("Cannot spawn a process in dry-run mode ({})", self.description());
+        }
+        let mut stderr = decoration_stream();
+        write_banner(&mut stderr, self)?;
+        let execution = execution::start_background(self)?;
+        let child = backend::spawn(self).spawn_context(self, || {
+            format!("Failed to execute command ({})", self.description())
+        })?;
+        let Some((child, group)) = child.into_child() else {
+            anyhow::bail!(
+                "Cannot spawn a mocked command as a std::process::Child ({})",
+                self.description()
+            );
+        };
+        let description = self.description();
+        let snapshot = description.to_snapshot();
+        Ok(DescribedChild::new(child, description.to_string(), snapshot, group, execution))
+    }
+    fn exec_stdout_string(self) -> anyhow::Result<Output> {
+        self.exec_capture(Utf8Policy::Strict).map(Capture::into_output)
+    }
+    fn spawn_duplex(&mut self) -> anyhow::Result<Duplex> {
+        if dry_run(self)? {
+            anyhow::bail!(
+                "Cannot hold a dialog with a command in dry-run mode ({})",
+                self.description()
+            );
+        }
+        let execution = execution::start_detached(self)?;
+        let child = spawn_piped(self, Pipes { stdin: true, stdout: true, stderr: true })?;
+        Ok(Duplex::new(copy_command(self), child, execution))
     }
-}
 
-impl Display for CommandDescription<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "program = {:?}, args = {:?}, envs = {:?}, current_dir = {:?}",
-            self.cmd.get_program(),
-            self.cmd.get_args(),
-            self.cmd.get_envs(),
-            self.cmd.get_current_dir(),
-        )
+    fn exec_stdout_string_ref(&mut self) -> anyhow::Result<Output> {
+        capture(self, CaptureOptions { info: Info::Pretty, ..CaptureOptions::default() })?
+            .into_output(copy_command(self))
     }
-}
 
-impl Display for OutputDescription<'_> {
-    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(
-            f,
-            "{}, stdout = {:?}, stderr = {:?}",
-            self.out.command.description(),
-            self.out.stdout,
-            String::from_utf8_lossy(&self.out.stderr),
-        )
+    fn exec_stdout_string_cached(&mut self, cache: &CommandCache) -> anyhow::Result<Output> {
+        cache.run(self)
     }
-}
 
-pub fn cmd(program: impl AsRef<OsStr>) -> Command {
-    Command::new(program)
-}
+    fn exec_in_temp_dir(&mut self) -> anyhow::Result<TempRun> {
+        temp::exec_in_temp_dir(self)
+    }
 
-pub trait TermColorStandardStreamExt {
-    fn with_color<F, T>(&mut self, spec: &termcolor::ColorSpec, f: F) -> T
-    where
-        F: FnOnce(&mut Self) -> T;
-}
+    fn exec_stdout_string_tee(
+        self,
+        stdout_sink: &mut dyn Write,
+        stderr_sink: &mut dyn Write,
+    ) -> anyhow::Result<Output> {
+        let mut self_ = self;
+        let forward = Forward::Sinks(stdout_sink, stderr_sink);
+        capture(&mut self_, CaptureOptions { forward, ..CaptureOptions::default() })?
+            .into_output(self_)
+    }
+    fn exec_stdout_string_limited(self, max_bytes: usize) -> anyhow::Result<Output> {
+        let mut self_ = self;
+        capture(&mut self_, CaptureOptions { limit: max_bytes, ..CaptureOptions::default() })?
+            .into_output(self_)
+    }
+    fn exec_stdout_string_with_timeout(self, timeout: Duration) -> anyhow::Result<Output> {
+        let mut self_ = self;
+        let timeout = Some(timeout);
+        capture(&mut self_, CaptureOptions { timeout, ..CaptureOptions::default() })?
+            .into_output(self_)
+    }
 
-impl TermColorStandardStreamExt for termcolor::StandardStream {
-    fn with_color<F, T>(&mut self, spec: &termcolor::ColorSpec, f: F) -> T
-    where
-        F: FnOnce(&mut Self) -> T,
-    {
-        self.set_color(spec).unwrap();
-        let v = f(self);
-        self.reset().unwrap();
-        v
+    fn exec_stdout_string_with_stdin<B: AsRef<[u8]>>(self, input: B) -> anyhow::Result<Output> {
+        let mut self_ = self;
+        let input = Some(Input::Bytes(input.as_ref()));
+        capture(&mut self_, CaptureOptions { input, ..CaptureOptions::default() })?
+            .into_output(self_)
     }
-}
 
-pub fn stdout_with_color<F, T>(spec: &termcolor::ColorSpec, f: F) -> T
-where
-    F: FnOnce(&mut termcolor::StandardStream) -> T,
-{
-    let color_choice = match atty::is(atty::Stream::Stdout) {
-        true => termcolor::ColorChoice::Auto,
-        false => termcolor::ColorChoice::Never,
-    };
-    termcolor::StandardStream::stdout(color_choice).with_color(spec, f)
-}
+    fn exec_stdin_reader<R: Read + Send>(self, reader: R) -> anyhow::Result<Output> {
+        let mut self_ = self;
+        let input = Some(Input::Reader(Box::new(reader)));
+        capture(&mut self_, CaptureOptions { input, ..CaptureOptions::default() })?
+            .into_output(self_)
+    }
 
-pub fn stderr_with_color<F, T>(spec: &termcolor::ColorSpec, f: F) -> T
-where
-    F: FnOnce(&mut termcolor::StandardStream) -> T,
-{
-    let color_choice = match atty::is(atty::Stream::Stderr) {
-        true => termcolor::ColorChoice::Auto,
-        false => termcolor::ColorChoice::Never,
+    fn exec_tee(self) -> anyhow::Result<Output> {
+        let mut self_ = self;
+        let options = CaptureOptions {
+            forward: Forward::Terminal,
+            decorated: true,
+            ..CaptureOptions::default()
+        };
+        capture(&mut self_, options)?.into_output(self_)
+    }
+
+    fn exec_stdout_string_retry(self, policy: RetryPolicy) -> anyhow::Result<Output> {
+        let mut self_ = self;
+        let start = Instant::now();
+        let max_attempts = policy.max_attempts();
+        let mut attempt = 1;
+        let mut captured = loop {
+            let captured = capture(
+                &mut self_,
+                CaptureOptions { check: Check::Unchecked, ..CaptureOptions::default() },
+            )?;
+            if captured.status.success() {
+                break captured;
+            }
+            if attempt >= max_attempts || !policy.is_retryable(&captured.status, &captured.stderr) {
+                let after = format!(" after {} attempt(s)", attempt);
+                return Err(captured.failure(&self_, &after));
+            }
+            std::thread::sleep(policy.delay(attempt));
+            attempt += 1;
+        };
+        captured.duration = start.elapsed();
+        captured.into_output(self_)
+    }
+
+    fn exec_stdout_string_allowing<I: IntoIterator<Item = i32>>(
+        self,
+        codes: I,
+    ) -> anyhow::Result<Output> {
+        let codes: Vec<i32> = codes.into_iter().collect();
+        let mut self_ = self;
+        let check = Check::Allowing(&codes);
+        let captured = capture(&mut self_, CaptureOptions { check, ..CaptureOptions::default() })?;
+        captured.into_output(self_)
+    }
+    fn exec_background(self) -> anyhow::Result<BackgroundChild> {
+        let mut self_ = self;
+        if dry_run(&self_)? {
+            anyhow::bail!(
+                "Cannot start a background process in dry-run mode ({})",
+                self_.description(),
+            );
+        }
+        let mut stderr = decoration_stream();
+        write_banner_annotated(&mut stderr, &self_, Some("BACKGROUND"))?;
+        let execution = execution::start_background(&self_)?;
+        let child = backend::spawn(&mut self_).spawn_context(&self_, || {
+            format!("Failed to execute command ({})", self_.description())
+        })?;
+        Ok(BackgroundChild::new(child, self_.into_command(), execution))
+    }
+
+    fn exec_lines(self) -> anyhow::Result<LineIter> {
+        let mut self_ = self;
+        if dry_run(&self_)? {
+            anyhow::bail!(
+                "Cannot read the output of a command in dry-run mode ({})",
+                self_.description()
+            );
+        }
+        let execution = execution::start_detached(&self_)?;
+        let child =
+            spawn_piped(&mut self_, Pipes { stdout: true, stderr: true, ..Pipes::default() })?;
+        Ok(LineIter::new(self_.into_command(), child, execution))
+    }
+
+    fn pipe(self, next: impl Into<ConfiguredCommand>) -> Pipeline {
+        Pipeline::new(self).pipe(next)
+    }
+
+    fn exec_stdout_lossy(self) -> anyhow::Result<Output> {
+        self.exec_capture(Utf8Policy::Lossy).map(Capture::into_output)
+    }
+
+    fn exec_capture(self, policy: Utf8Policy) -> anyhow::Result<Capture> {
+        let mut self_ = self;
+        let info = match policy {
+            Utf8Policy::Bytes => Info::Bytes,
+            Utf8Policy::Strict | Utf8Policy::Lossy => Info::Pretty,
+        };
+        let Captured { status, stdout, stderr, duration, .. } =
+            capture(&mut self_, CaptureOptions { info, ..CaptureOptions::default() })?;
+        let stdout = match policy {
+            Utf8Policy::Strict => {
+                let text = stdout_to_string(&self_, stdout, &stderr)?;
+                CapturedStdout::Text { text, lossy: false }
+            }
+            Utf8Policy::Lossy => match String::from_utf8(stdout) {
+                Ok(text) => CapturedStdout::Text { text, lossy: false },
+                Err(e) => CapturedStdout::Text {
+                    text: String::from_utf8_lossy(e.as_bytes()).into_owned(),
+                    lossy: true,
+                },
+            },
+            Utf8Policy::Bytes => CapturedStdout::Bytes(stdout),
+        };
+        Ok(Capture { command: self_.into_command(), status, stdout, stderr, duration })
+    }
+
+    fn exec_stdout_lines(self) -> anyhow::Result<Vec<String>> {
+        let out = self.exec_stdout_string()?;
+        Ok(out.lines().map(str::to_owned).collect())
+    }
+
+    #[cfg(feature = "serde")]
+    fn exec_stdout_json<T: serde::de::DeserializeOwned>(self) -> anyhow::Result<T> {
+        let out = self.exec_stdout_string()?;
+        serde_json::from_str(&out.stdout).map_err(|e| {
+            let context = format!(
+                "Failed to parse process stdout as JSON ({}, stdout = {:?})",
+                out.command.description(),
+                truncate_str(&out.stdout, OUTPUT_SNIPPET_LIMIT),
+            );
+            anyhow::Error::new(e).context(context)
+        })
+    }
+
+    #[cfg(feature = "encoding")]
+    fn exec_stdout_encoded(self, encoding: Encoding) -> anyhow::Result<Output> {
+        encoding::exec_stdout_encoded(self, encoding)
+    }
+
+    fn exec_stdout_bytes(self) -> anyhow::Result<BytesOutput> {
+        let mut self_ = self;
+        let Captured { status, stdout, stderr, .. } =
+            capture(&mut self_, CaptureOptions { info: Info::Bytes, ..CaptureOptions::default() })?;
+        Ok(BytesOutput { command: self_.into_command(), status, stdout, stderr })
+    }
+
+    fn exec_stderr_string(self) -> anyhow::Result<StderrOutput> {
+        let mut self_ = self;
+        let Captured { status, stdout, stderr, .. } =
+            capture(&mut self_, CaptureOptions::default())?;
+        let stderr = stderr_to_string(&self_, &stdout, stderr)?;
+        Ok(StderrOutput { command: self_.into_command(), status, stdout, stderr })
+    }
+
+    fn exec_output(self) -> anyhow::Result<TextOutput> {
+        let mut self_ = self;
+        let Captured { status, stdout, stderr, .. } =
+            capture(&mut self_, CaptureOptions::default())?;
+        let stdout = stdout_to_string(&self_, stdout, &stderr)?;
+        let stderr = stderr_to_string(&self_, stdout.as_bytes(), stderr)?;
+        Ok(TextOutput { command: self_.into_command(), status, stdout, stderr })
+    }
+}
+
+// The methods of `Command` run it as a `ConfiguredCommand` without settings.
+impl CommandExt for Command {
+    fn description(&self) -> CommandDescription<'_> {
+        CommandDescription { cmd: self }
+    }
+
+    fn validate(&self) -> anyhow::Result<()> {
+        which::validate(self)
+    }
+
+    fn args_<I, S>(self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        ConfiguredCommand::from(self).args_(args).into_command()
+    }
+    fn arg_<S: AsRef<OsStr>>(self, arg: S) -> Self {
+        ConfiguredCommand::from(self).arg_(arg).into_command()
+    }
+    fn arg_if<S: AsRef<OsStr>>(self, cond: bool, arg: S) -> Self {
+        ConfiguredCommand::from(self).arg_if(cond, arg).into_command()
+    }
+    fn args_if<I, S>(self, cond: bool, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        ConfiguredCommand::from(self).args_if(cond, args).into_command()
+    }
+    fn arg_opt<S: AsRef<OsStr>>(self, arg: Option<S>) -> Self {
+        ConfiguredCommand::from(self).arg_opt(arg).into_command()
+    }
+    fn arg_key_val_opt<K, V>(self, flag: K, value: Option<V>) -> Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        ConfiguredCommand::from(self).arg_key_val_opt(flag, value).into_command()
+    }
+    #[cfg(windows)]
+    fn raw_arg_<S: AsRef<OsStr>>(self, arg: S) -> Self {
+        ConfiguredCommand::from(self).raw_arg_(arg).into_command()
+    }
+    fn env_<K, V>(self, key: K, val: V) -> Self
+    where
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        ConfiguredCommand::from(self).env_(key, val).into_command()
+    }
+    fn envs_<I, K, V>(self, vars: I) -> Self
+    where
+        I: IntoIterator<Item = (K, V)>,
+        K: AsRef<OsStr>,
+        V: AsRef<OsStr>,
+    {
+        ConfiguredCommand::from(self).envs_(vars).into_command()
+    }
+    fn prepend_path_<P: AsRef<Path>>(self, dir: P) -> Self {
+        ConfiguredCommand::from(self).prepend_path_(dir).into_command()
+    }
+    fn append_path_<P: AsRef<Path>>(self, dir: P) -> Self {
+        ConfiguredCommand::from(self).append_path_(dir).into_command()
+    }
+    fn env_remove_<K: AsRef<OsStr>>(self, key: K) -> Self {
+        ConfiguredCommand::from(self).env_remove_(key).into_command()
+    }
+    fn env_clear_(self) -> Self {
+        ConfiguredCommand::from(self).env_clear_().into_command()
+    }
+    fn current_dir_<P: AsRef<Path>>(self, dir: P) -> Self {
+        ConfiguredCommand::from(self).current_dir_(dir).into_command()
+    }
+    fn stdin_<T: Into<Stdio>>(self, cfg: T) -> ConfiguredCommand {
+        ConfiguredCommand::from(self).stdin_(cfg)
+    }
+    fn stdout_<T: Into<Stdio>>(self, cfg: T) -> ConfiguredCommand {
+        ConfiguredCommand::from(self).stdout_(cfg)
+    }
+    fn stderr_<T: Into<Stdio>>(self, cfg: T) -> ConfiguredCommand {
+        ConfiguredCommand::from(self).stderr_(cfg)
+    }
+    fn label<S: AsRef<str>>(self, label: S) -> ConfiguredCommand {
+        ConfiguredCommand::from(self).label(label)
+    }
+    fn new_process_group(self) -> ConfiguredCommand {
+        ConfiguredCommand::from(self).new_process_group()
+    }
+    fn warn_after(self, after: Duration) -> ConfiguredCommand {
+        ConfiguredCommand::from(self).warn_after(after)
+    }
+    fn heartbeat(self, every: Duration) -> ConfiguredCommand {
+        ConfiguredCommand::from(self).heartbeat(every)
+    }
+
+    fn exec(&mut self) -> anyhow::Result<()> {
+        settings::configured(self, |cmd| cmd.exec())
+    }
+    fn exec_status(&mut self) -> anyhow::Result<ExitStatus> {
+        settings::configured(self, |cmd| cmd.exec_status())
+    }
+    fn exec_timed(&mut self) -> anyhow::Result<ExecReport> {
+        settings::configured(self, |cmd| cmd.exec_timed())
+    }
+    fn exec_quiet(&mut self) -> anyhow::Result<()> {
+        settings::configured(self, |cmd| cmd.exec_quiet())
+    }
+    fn exec_silent_unless_failure(&mut self) -> anyhow::Result<()> {
+        settings::configured(self, |cmd| cmd.exec_silent_unless_failure())
+    }
+    fn exec_interactive(&mut self) -> anyhow::Result<()> {
+        settings::configured(self, |cmd| cmd.exec_interactive())
+    }
+    fn exec_with_timeout(&mut self, timeout: Duration) -> anyhow::Result<()> {
+        settings::configured(self, |cmd| cmd.exec_with_timeout(timeout))
+    }
+    fn exec_with_stdin<B: AsRef<[u8]>>(&mut self, input: B) -> anyhow::Result<()> {
+        settings::configured(self, |cmd| cmd.exec_with_stdin(input))
+    }
+    fn exec_retry(&mut self, policy: RetryPolicy) -> anyhow::Result<()> {
+        settings::configured(self, |cmd| cmd.exec_retry(policy))
+    }
+    fn exec_replace(&mut self) -> anyhow::Error {
+        settings::configured(self, |cmd| cmd.exec_replace())
+    }
+    fn exec_allowing<I: IntoIterator<Item = i32>>(
+        &mut self,
+        codes: I,
+    ) -> anyhow::Result<ExitStatus> {
+        settings::configured(self, |cmd| cmd.exec_allowing(codes))
+    }
+    fn exec_args<I, S>(&mut self, args: I) -> anyhow::Result<()>
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        settings::configured(self, |cmd| cmd.exec_args(args))
+    }
+    fn exec_stdout_to_file<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        settings::configured(self, |cmd| cmd.exec_stdout_to_file(path))
+    }
+    fn exec_stdout_to_file_append<P: AsRef<Path>>(&mut self, path: P) -> anyhow::Result<()> {
+        settings::configured(self, |cmd| cmd.exec_stdout_to_file_append(path))
+    }
+    fn exec_for_each_line<O, E>(&mut self, on_stdout: O, on_stderr: E) -> anyhow::Result<ExitStatus>
+    where
+        O: FnMut(&str) + Send,
+        E: FnMut(&str) + Send,
+    {
+        settings::configured(self, |cmd| cmd.exec_for_each_line(on_stdout, on_stderr))
+    }
+    fn exec_capture_merged(&mut self) -> anyhow::Result<MergedOutput> {
+        settings::configured(self, |cmd| cmd.exec_capture_merged())
+    }
+    fn exec_filter_lines<P>(&mut self, pred: P) -> anyhow::Result<FilteredOutput>
+    where
+        P: FnMut(&str) -> bool + Send,
+    {
+        settings::configured(self, |cmd| cmd.exec_filter_lines(pred))
+    }
+    fn spawn_guarded(&mut self) -> anyhow::Result<ChildGuard> {
+        settings::configured(self, |cmd| cmd.spawn_guarded())
+    }
+    fn spawn_described(&mut self) -> anyhow::Result<DescribedChild> {
+        settings::configured(self, |cmd| cmd.spawn_described())
+    }
+    fn spawn_duplex(&mut self) -> anyhow::Result<Duplex> {
+        settings::configured(self, |cmd| cmd.spawn_duplex())
+    }
+
+    fn exec_stdout_string(self) -> anyhow::Result<Output> {
+        ConfiguredCommand::from(self).exec_stdout_string()
+    }
+    fn exec_stdout_string_ref(&mut self) -> anyhow::Result<Output> {
+        settings::configured(self, |cmd| cmd.exec_stdout_string_ref())
+    }
+    fn exec_stdout_string_cached(&mut self, cache: &CommandCache) -> anyhow::Result<Output> {
+        settings::configured(self, |cmd| cmd.exec_stdout_string_cached(cache))
+    }
+    fn exec_in_temp_dir(&mut self) -> anyhow::Result<TempRun> {
+        settings::configured(self, |cmd| cmd.exec_in_temp_dir())
+    }
+    fn exec_stdout_string_with_timeout(self, timeout: Duration) -> anyhow::Result<Output> {
+        ConfiguredCommand::from(self).exec_stdout_string_with_timeout(timeout)
+    }
+    fn exec_stdout_string_limited(self, max_bytes: usize) -> anyhow::Result<Output> {
+        ConfiguredCommand::from(self).exec_stdout_string_limited(max_bytes)
+    }
+    fn exec_stdout_string_with_stdin<B: AsRef<[u8]>>(self, input: B) -> anyhow::Result<Output> {
+        ConfiguredCommand::from(self).exec_stdout_string_with_stdin(input)
+    }
+    fn exec_stdin_reader<R: Read + Send>(self, reader: R) -> anyhow::Result<Output> {
+        ConfiguredCommand::from(self).exec_stdin_reader(reader)
+    }
+    fn exec_stdout_string_tee(
+        self,
+        stdout_sink: &mut dyn Write,
+        stderr_sink: &mut dyn Write,
+    ) -> anyhow::Result<Output> {
+        ConfiguredCommand::from(self).exec_stdout_string_tee(stdout_sink, stderr_sink)
+    }
+    fn exec_tee(self) -> anyhow::Result<Output> {
+        ConfiguredCommand::from(self).exec_tee()
+    }
+    fn exec_background(self) -> anyhow::Result<BackgroundChild> {
+        ConfiguredCommand::from(self).exec_background()
+    }
+    fn exec_lines(self) -> anyhow::Result<LineIter> {
+        ConfiguredCommand::from(self).exec_lines()
+    }
+    fn pipe(self, next: impl Into<ConfiguredCommand>) -> Pipeline {
+        ConfiguredCommand::from(self).pipe(next)
+    }
+    fn exec_stdout_string_retry(self, policy: RetryPolicy) -> anyhow::Result<Output> {
+        ConfiguredCommand::from(self).exec_stdout_string_retry(policy)
+    }
+    fn exec_stdout_string_allowing<I: IntoIterator<Item = i32>>(
+        self,
+        codes: I,
+    ) -> anyhow::Result<Output> {
+        ConfiguredCommand::from(self).exec_stdout_string_allowing(codes)
+    }
+    fn exec_stdout_lossy(self) -> anyhow::Result<Output> {
+        ConfiguredCommand::from(self).exec_stdout_lossy()
+    }
+    fn exec_capture(self, policy: Utf8Policy) -> anyhow::Result<Capture> {
+        ConfiguredCommand::from(self).exec_capture(policy)
+    }
+    fn exec_stdout_lines(self) -> anyhow::Result<Vec<String>> {
+        ConfiguredCommand::from(self).exec_stdout_lines()
+    }
+    #[cfg(feature = "serde")]
+    fn exec_stdout_json<T: serde::de::DeserializeOwned>(self) -> anyhow::Result<T> {
+        ConfiguredCommand::from(self).exec_stdout_json()
+    }
+    #[cfg(feature = "encoding")]
+    fn exec_stdout_encoded(self, encoding: Encoding) -> anyhow::Result<Output> {
+        ConfiguredCommand::from(self).exec_stdout_encoded(encoding)
+    }
+    fn exec_stdout_bytes(self) -> anyhow::Result<BytesOutput> {
+        ConfiguredCommand::from(self).exec_stdout_bytes()
+    }
+    fn exec_stderr_string(self) -> anyhow::Result<StderrOutput> {
+        ConfiguredCommand::from(self).exec_stderr_string()
+    }
+    fn exec_output(self) -> anyhow::Result<TextOutput> {
+        ConfiguredCommand::from(self).exec_output()
+    }
+}
+
+impl Output {
+    pub(crate) fn new(
+        command: Command,
+        status: ExitStatus,
+        stdout: String,
+        stderr: Vec<u8>,
+        duration: Duration,
+    ) -> Self {
+        Output {
+            command,
+            status,
+            stdout,
+            stderr,
+            stdout_was_lossy: false,
+            duration,
+            truncation: None,
+            stdout_was_normalized: false,
+        }
+    }
+
+    pub fn description(&self) -> OutputDescription<'_> {
+        OutputDescription { out: self }
+    }
+
+    /// Whether `exec_stdout_string_limited` dropped any of stdout or stderr.
+    pub fn is_truncated(&self) -> bool {
+        self.truncation.is_some_and(|truncation| {
+            truncation.stdout_total > self.stdout.len() as u64
+                || truncation.stderr_total > self.stderr.len() as u64
+        })
+    }
+
+    fn embedded(&self) -> (Embedded<'_>, Embedded<'_>) {
+        let mut stdout = Embedded::new(self.stdout.as_bytes());
+        let mut stderr = Embedded::new(&self.stderr);
+        if let Some(truncation) = self.truncation {
+            stdout.total = truncation.stdout_total;
+            stderr.total = truncation.stderr_total;
+        }
+        (stdout, stderr)
+    }
+
+    pub fn exit_code(&self) -> Option<i32> {
+        self.status.code()
+    }
+
+    /// The signal that killed the process. Always `None` outside of Unix.
+    pub fn signal(&self) -> Option<i32> {
+        exit_signal(self.status)
+    }
+
+    pub fn stderr_str(&self) -> anyhow::Result<&str> {
+        std::str::from_utf8(&self.stderr).map_err(|e| {
+            let context = format!(
+                "Process stderr is not UTF-8 ({})",
+                cmd_info_with_output(&self.command, self.stdout.as_bytes(), &self.stderr),
+            );
+            invalid_utf8(&self.command, Stream::Stderr, e, context)
+        })
+    }
+
+    pub fn stderr_lossy(&self) -> Cow<'_, str> {
+        String::from_utf8_lossy(&self.stderr)
+    }
+
+    pub fn stdout_bytes(&self) -> usize {
+        self.stdout.len()
+    }
+
+    pub fn stderr_bytes(&self) -> usize {
+        self.stderr.len()
+    }
+
+    /// Lines of stdout, counting a last line without a newline.
+    pub fn stdout_line_count(&self) -> usize {
+        line_count(self.stdout.as_bytes())
+    }
+
+    pub fn stderr_line_count(&self) -> usize {
+        line_count(&self.stderr)
+    }
+
+    /// Stdout and stderr without ANSI escape sequences, as with `strip_ansi`, for output of
+    /// programs that color it even when it is not written to a terminal.
+    pub fn stdout_plain(&self) -> Cow<'_, str> {
+        strip_ansi(&self.stdout)
+    }
+
+    pub fn stderr_plain(&self) -> Cow<'_, str> {
+        ansi::strip_ansi_cow(self.stderr_lossy())
+    }
+
+    /// Like `exec_stdout_lines`, but for stderr and converted lossily.
+    pub fn stderr_lines(&self) -> Vec<String> {
+        self.stderr_lossy().lines().map(str::to_owned).collect()
+    }
+
+    /// Stdout with `\r\n` line endings turned into `\n` and without a leading UTF-8 byte order
+    /// mark. A lone `\r` is kept.
+    pub fn stdout_unix_newlines(&self) -> Cow<'_, str> {
+        unix_newlines(&self.stdout)
+    }
+
+    /// Replaces stdout with `stdout_unix_newlines`, and sets `stdout_was_normalized` if that
+    /// changed it.
+    pub fn normalize_newlines(&mut self) -> &mut Self {
+        if let Cow::Owned(stdout) = unix_newlines(&self.stdout) {
+            self.stdout = stdout;
+            self.stdout_was_normalized = true;
+        }
+        self
+    }
+
+    /// The lines of stdout, ending in `\n` or `\r\n`, without a leading byte order mark.
+    pub fn lines(&self) -> std::str::Lines<'_> {
+        self.stdout.strip_prefix(BYTE_ORDER_MARK).unwrap_or(&self.stdout).lines()
+    }
+
+    pub fn stdout_trimmed(&self) -> &str {
+        self.stdout.trim_end()
+    }
+
+    pub fn first_line(&self) -> Option<&str> {
+        self.lines().next()
+    }
+
+    /// The only non-empty line of stdout, without surrounding whitespace. Fails if there is no such
+    /// line or more than one, so a value spliced into a path or another command is never silently
+    /// truncated or multi-line.
+    pub fn single_line(&self) -> anyhow::Result<&str> {
+        let mut lines = self.stdout.lines().map(str::trim).filter(|line| !line.is_empty());
+        match (lines.next(), lines.next()) {
+            (Some(line), None) => Ok(line),
+            (None, _) => anyhow::bail!(
+                "Expected a single line of output but got none ({})",
+                self.command.description(),
+            ),
+            (Some(_), Some(_)) => anyhow::bail!(
+                "Expected a single line of output but got {} ({}, stdout = {:?})",
+                2 + lines.count(),
+                self.command.description(),
+                truncate_str(&self.stdout, OUTPUT_SNIPPET_LIMIT),
+            ),
+        }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.stdout.trim().is_empty()
+    }
+
+    pub fn parse<T>(&self) -> anyhow::Result<T>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        let text = self.stdout.trim();
+        text.parse().with_context(|| {
+            format!(
+                "Failed to parse process stdout {:?} ({})",
+                truncate_str(text, OUTPUT_SNIPPET_LIMIT),
+                self.command.description(),
+            )
+        })
+    }
+
+    /// Parses every non-empty line of stdout, ignoring surrounding whitespace. The error names the
+    /// 1-based line number of the first line that fails to parse.
+    pub fn parse_lines<T>(&self) -> anyhow::Result<Vec<T>>
+    where
+        T: FromStr,
+        T::Err: std::error::Error + Send + Sync + 'static,
+    {
+        self.stdout
+            .lines()
+            .enumerate()
+            .map(|(i, line)| (i, line.trim()))
+            .filter(|(_, line)| !line.is_empty())
+            .map(|(i, line)| {
+                line.parse().with_context(|| {
+                    format!(
+                        "Failed to parse line {} of process stdout {:?} ({})",
+                        i + 1,
+                        truncate_str(line, OUTPUT_SNIPPET_LIMIT),
+                        self.command.description(),
+                    )
+                })
+            })
+            .collect()
+    }
+}
+
+impl Capture {
+    /// The text of stdout, or `None` if it was captured with `Utf8Policy::Bytes`.
+    pub fn stdout_str(&self) -> Option<&str> {
+        match &self.stdout {
+            CapturedStdout::Text { text, .. } => Some(text),
+            CapturedStdout::Bytes(_) => None,
+        }
+    }
+
+    /// The bytes of stdout. With `Utf8Policy::Lossy` these are the bytes of the converted text.
+    pub fn stdout_bytes(&self) -> &[u8] {
+        match &self.stdout {
+            CapturedStdout::Text { text, .. } => text.as_bytes(),
+            CapturedStdout::Bytes(bytes) => bytes,
+        }
+    }
+
+    /// Whether invalid UTF-8 in stdout was replaced under `Utf8Policy::Lossy`.
+    pub fn stdout_was_lossy(&self) -> bool {
+        matches!(self.stdout, CapturedStdout::Text { lossy: true, .. })
+    }
+
+    /// Bytes captured with `Utf8Policy::Bytes` are converted lossily.
+    pub fn into_output(self) -> Output {
+        let (stdout, stdout_was_lossy) = match self.stdout {
+            CapturedStdout::Text { text, lossy } => (text, lossy),
+            CapturedStdout::Bytes(bytes) => match String::from_utf8(bytes) {
+                Ok(text) => (text, false),
+                Err(e) => (String::from_utf8_lossy(e.as_bytes()).into_owned(), true),
+            },
+        };
+        Output {
+            command: self.command,
+            status: self.status,
+            stdout,
+            stderr: self.stderr,
+            stdout_was_lossy,
+            duration: self.duration,
+            truncation: None,
+            stdout_was_normalized: false,
+        }
+    }
+}
+
+impl BytesOutput {
+    pub fn description(&self) -> BytesOutputDescription<'_> {
+        BytesOutputDescription { out: self }
+    }
+}
+
+impl StderrOutput {
+    pub fn description(&self) -> StderrOutputDescription<'_> {
+        StderrOutputDescription { out: self }
+    }
+}
+
+impl TextOutput {
+    pub fn description(&self) -> TextOutputDescription<'_> {
+        TextOutputDescription { out: self }
+    }
+}
+
+impl CommandDescription<'_> {
+    /// The command as a single line that can be pasted into a shell, such as
+    /// `cd /src && RUST_LOG=debug cargo test --features 'a b'`. Same as formatting with `{:#}`.
+    pub fn shell(&self) -> String {
+        format!("{:#}", self)
+    }
+
+    /// The command spread over several lines: the program, one argument per indented line, the
+    /// environment overrides, and the working directory. Meant for long commands in error output.
+    pub fn pretty(&self) -> String {
+        let mut pretty = String::new();
+        write_pretty_command(&mut pretty, self.cmd).unwrap();
+        pretty
+    }
+}
+
+impl Display for CommandDescription<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if f.alternate() {
+            if quote::write_shell_line(f, self.cmd)? {
+                f.write_str(" # (non-UTF-8 parts shown lossily)")?;
+            }
+            return Ok(());
+        }
+        if let Some(script) = shell_script(self.cmd) {
+            write!(f, "script = {:?}, ", redact::scrub(script))?;
+        }
+        if let Some(inner) = sudo::inner_line(self.cmd) {
+            write!(f, "sudo = {:?}, ", inner)?;
+        }
+        let args: Vec<_> = self.cmd.get_args().map(redact::scrub).collect();
+        let envs: Vec<_> = self
+            .cmd
+            .get_envs()
+            .map(|(key, value)| {
+                (key, value.map(|value| path_var::elided(key, redact::env_value(key, value))))
+            })
+            .collect();
+        write!(
+            f,
+            "program = {:?}, args = {:?}, envs = {:?}, current_dir = {:?}",
+            redact::scrub(self.cmd.get_program()),
+            args,
+            envs,
+            self.cmd.get_current_dir().map(|dir| redact::scrub(dir.as_os_str())),
+        )?;
+        if self.cmd.get_current_dir().is_none() {
+            if let Ok(inherited) = std::env::current_dir() {
+                write!(f, " (inherited {:?})", inherited)?;
+            }
+        }
+        Ok(())
+    }
+}
+
+// The alternate form (`{:#}`) uses `CommandDescription::pretty` followed by stdout and stderr in
+// fenced blocks.
+impl Display for OutputDescription<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (stdout, stderr) = self.out.embedded();
+        if f.alternate() {
+            write_pretty_command(f, &self.out.command)?;
+            writeln!(f, "duration = {}", HumanDuration(self.out.duration))?;
+            write_fenced(f, "stdout", stdout)?;
+            return write_fenced(f, "stderr", stderr);
+        }
+        write!(
+            f,
+            "{}, duration = {}, stdout = {}, stderr = {}",
+            self.out.command.description(),
+            HumanDuration(self.out.duration),
+            stdout,
+            stderr,
+        )
+    }
+}
+
+impl Display for BytesOutputDescription<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, stdout = {}, stderr = {}",
+            self.out.command.description(),
+            EscapedBytes(&self.out.stdout),
+            EscapedBytes(&self.out.stderr),
+        )
+    }
+}
+
+impl Display for StderrOutputDescription<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, stdout = {:?}, stderr = {:?}",
+            self.out.command.description(),
+            String::from_utf8_lossy(&self.out.stdout),
+            self.out.stderr,
+        )
+    }
+}
+
+impl Display for TextOutputDescription<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "{}, stdout = {:?}, stderr = {:?}",
+            self.out.command.description(),
+            self.out.stdout,
+            self.out.stderr,
+        )
+    }
+}
+
+const ESCAPED_BYTES_LIMIT: usize = 256;
+
+struct EscapedBytes<'a>(&'a [u8]);
+
+impl Display for EscapedBytes<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let bytes = self.0;
+        if bytes.len() <= ESCAPED_BYTES_LIMIT {
+            write!(f, "b\"{}\"", bytes.escape_ascii())
+        } else {
+            write!(
+                f,
+                "b\"{}\"... ({} bytes total)",
+                bytes[..ESCAPED_BYTES_LIMIT].escape_ascii(),
+                bytes.len(),
+            )
+        }
+    }
+}
+
+// Output embedded in an error message or a description is cut down to this many bytes per
+// stream by default, whatever was captured.
+const DEFAULT_EMBEDDED_OUTPUT_LIMIT: usize = 4 * 1024;
+
+static EMBEDDED_OUTPUT_LIMIT: AtomicUsize = AtomicUsize::new(DEFAULT_EMBEDDED_OUTPUT_LIMIT);
+
+/// Sets how many bytes of each stream error messages and descriptions of output show, 4 KiB by
+/// default. Longer output is shown as its first and last halves of that, with the number of bytes
+/// left out in between, since a failure usually shows at the end. Only the messages are cut down:
+/// `CmdError` and `Output` keep everything that was captured.
+pub fn set_embedded_output_limit(bytes: usize) {
+    EMBEDDED_OUTPUT_LIMIT.store(bytes, Ordering::Relaxed);
+}
+
+const BYTE_ORDER_MARK: char = '\u{feff}';
+
+fn unix_newlines(text: &str) -> Cow<'_, str> {
+    let text = text
+        .strip_prefix(BYTE_ORDER_MARK)
+        .map_or(Cow::Borrowed(text), |rest| Cow::Owned(rest.to_owned()));
+    match text.contains("\r\n") {
+        true => Cow::Owned(text.replace("\r\n", "\n")),
+        false => text,
+    }
+}
+
+// Captured output as embedded in messages: at most `EMBEDDED_OUTPUT_LIMIT` bytes of it, without
+// escape sequences. `total` is how much the command wrote, which is more than was captured if a
+// capture limit was hit. Output that was captured in full is cut in the middle, and otherwise
+// the captured head is shown with a marker of the total size.
+#[derive(Clone, Copy)]
+struct Embedded<'a> {
+    bytes: &'a [u8],
+    total: u64,
+}
+
+impl<'a> Embedded<'a> {
+    fn new(bytes: &'a [u8]) -> Self {
+        Embedded { bytes, total: bytes.len() as u64 }
+    }
+
+    fn is_complete(&self) -> bool {
+        self.bytes.len() as u64 == self.total
+    }
+
+    // The text to show and, if the middle is left out, the number of bytes left out and the tail.
+    fn parts(&self) -> (Cow<'a, str>, Option<(usize, Cow<'a, str>)>) {
+        let limit = EMBEDDED_OUTPUT_LIMIT.load(Ordering::Relaxed);
+        let bytes = self.bytes;
+        if bytes.len() <= limit {
+            return (embedded_text(bytes), None);
+        }
+        if !self.is_complete() {
+            return (embedded_text(&bytes[..char_start(bytes, limit)]), None);
+        }
+        let head_end = char_start(bytes, limit / 2);
+        let tail_start = char_start(bytes, bytes.len() - (limit - limit / 2));
+        let tail = embedded_text(&bytes[tail_start..]);
+        (embedded_text(&bytes[..head_end]), Some((tail_start - head_end, tail)))
+    }
+
+    fn marker(&self) -> TruncationMarker {
+        let limit = EMBEDDED_OUTPUT_LIMIT.load(Ordering::Relaxed);
+        let shown = match self.is_complete() {
+            true => self.total,
+            false => self.bytes.len().min(limit) as u64,
+        };
+        TruncationMarker { shown, total: self.total }
+    }
+}
+
+// Without escape sequences, so that colored output stays readable in logs.
+fn embedded_text(bytes: &[u8]) -> Cow<'_, str> {
+    ansi::strip_ansi_cow(String::from_utf8_lossy(bytes))
+}
+
+// `index`, moved back to the start of the UTF-8 character it is in, if any.
+fn char_start(bytes: &[u8], index: usize) -> usize {
+    let mut start = index;
+    while start > 0 && index - start < 3 && bytes.get(start).is_some_and(|b| b & 0xc0 == 0x80) {
+        start -= 1;
+    }
+    start
+}
+
+// `text` with control characters other than newlines and tabs escaped as in `{:?}`, so that
+// output shown as is can't mess up a terminal or a log.
+fn escape_controls(text: &str) -> Cow<'_, str> {
+    let is_escaped = |(i, c): (usize, char)| {
+        c.is_control() && c != '\n' && c != '\t' && !(c == '\r' && text[i + 1..].starts_with('\n'))
+    };
+    if !text.char_indices().any(is_escaped) {
+        return Cow::Borrowed(text);
+    }
+    let mut escaped = String::with_capacity(text.len());
+    for (i, c) in text.char_indices() {
+        match is_escaped((i, c)) {
+            true => escaped.extend(c.escape_debug()),
+            false => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+// " … (truncated, 6.2 GiB total)" if anything is left out, and nothing otherwise.
+struct TruncationMarker {
+    shown: u64,
+    total: u64,
+}
+
+impl Display for TruncationMarker {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if self.shown < self.total {
+            write!(f, " … (truncated, {} total)", HumanSize(self.total))?;
+        }
+        Ok(())
+    }
+}
+
+// As a quoted string, or two around the marker of what is left out:
+// `"head" … 12345 bytes omitted … "tail"`.
+impl Display for Embedded<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let (text, tail) = self.parts();
+        write!(f, "{:?}", text)?;
+        if let Some((omitted, tail)) = tail {
+            write!(f, " … {} bytes omitted … {:?}", omitted, tail)?;
+        }
+        write!(f, "{}", self.marker())
+    }
+}
+
+// Bytes in binary units with one decimal, such as "812 B" or "6.2 GiB".
+struct HumanSize(u64);
+
+/// Formats a number of bytes the way output sizes are shown: bytes under 1 KiB, and otherwise
+/// binary units with one decimal, such as "812 B", "1.0 KiB" or "6.2 GiB".
+pub fn human_size(bytes: u64) -> String {
+    HumanSize(bytes).to_string()
+}
+
+static OUTPUT_SUMMARY: AtomicBool = AtomicBool::new(false);
+
+/// Makes the `END OUTPUT` marker of methods that capture output sum it up, as in
+/// `END OUTPUT (1.52s, stdout 14 lines / 1.2 KiB, stderr empty)`. Off by default.
+pub fn set_output_summary(enabled: bool) {
+    OUTPUT_SUMMARY.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn output_summary_enabled() -> bool {
+    OUTPUT_SUMMARY.load(Ordering::Relaxed)
+}
+
+// "stdout 14 lines / 1.2 KiB, stderr empty"
+struct OutputSummary<'a> {
+    stdout: &'a [u8],
+    stderr: &'a [u8],
+}
+
+impl Display for OutputSummary<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for (i, (name, bytes)) in
+            [("stdout", self.stdout), ("stderr", self.stderr)].iter().enumerate()
+        {
+            if i > 0 {
+                f.write_str(", ")?;
+            }
+            match line_count(bytes) {
+                0 => write!(f, "{} empty", name)?,
+                1 => write!(f, "{} 1 line / {}", name, HumanSize(bytes.len() as u64))?,
+                lines => write!(f, "{} {} lines / {}", name, lines, HumanSize(bytes.len() as u64))?,
+            }
+        }
+        Ok(())
+    }
+}
+
+// Newlines, plus one for a last line without one.
+fn line_count(bytes: &[u8]) -> usize {
+    let newlines = bytes.iter().filter(|&&b| b == b'\n').count();
+    newlines + usize::from(!bytes.is_empty() && !bytes.ends_with(b"\n"))
+}
+
+impl Display for HumanSize {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        const UNITS: [&str; 4] = ["KiB", "MiB", "GiB", "TiB"];
+        if self.0 < 1024 {
+            return write!(f, "{} B", self.0);
+        }
+        let mut size = self.0 as f64 / 1024.0;
+        let mut unit = 0;
+        while size >= 1024.0 && unit < UNITS.len() - 1 {
+            size /= 1024.0;
+            unit += 1;
+        }
+        write!(f, "{:.1} {}", size, UNITS[unit])
+    }
+}
+
+// Milliseconds under a second, seconds with two decimals under two minutes, and minutes and
+// seconds beyond that.
+struct HumanDuration(Duration);
+
+impl Display for HumanDuration {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let secs = self.0.as_secs_f64();
+        if secs < 1.0 {
+            write!(f, "{}ms", self.0.as_millis())
+        } else if secs < 120.0 {
+            write!(f, "{:.2}s", secs)
+        } else {
+            write!(f, "{}m {:02}s", self.0.as_secs() / 60, self.0.as_secs() % 60)
+        }
+    }
+}
+
+pub fn cmd(program: impl AsRef<OsStr>) -> Command {
+    Command::new(program)
+}
+
+static DRY_RUN: AtomicBool = AtomicBool::new(false);
+
+/// Enables or disables dry-run mode for the whole process.
+///
+/// While enabled, every `CommandExt` execution method prints the usual cwd banner followed by a
+/// `DRY RUN` marker instead of spawning anything. Methods returning `()` return `Ok(())`, and
+/// methods capturing output pretend the command succeeded: the status is `ExitStatus::default()`
+/// (exit code 0) and stdout and stderr are empty. `exec_background` has no process to hand out
+/// and fails instead.
+pub fn set_dry_run(enabled: bool) {
+    DRY_RUN.store(enabled, Ordering::SeqCst);
+}
+
+pub fn is_dry_run() -> bool {
+    DRY_RUN.load(Ordering::SeqCst)
+}
+
+/// Builds a `Command` from a program and arguments, each passed through `AsRef<OsStr>`.
+/// `...(iter)` splats an iterable of arguments, and a leading `in dir =>` sets `current_dir`.
+///
+/// ```
+/// use cmd_utils::cmd;
+/// use std::path::Path;
+///
+/// let files = ["a.txt", "b.txt"];
+/// let tar = cmd!(in "/tmp" => "tar", "-cf", "out.tar", ...(files));
+/// assert_eq!(tar.get_program(), "tar");
+/// assert_eq!(tar.get_args().collect::<Vec<_>>(), ["-cf", "out.tar", "a.txt", "b.txt"]);
+/// assert_eq!(tar.get_current_dir(), Some(Path::new("/tmp")));
+/// ```
+#[macro_export]
+macro_rules! cmd {
+    (in $dir:expr => $program:expr $(, $($rest:tt)*)?) => {{
+        let mut cmd = $crate::cmd!($program $(, $($rest)*)?);
+        cmd.current_dir($dir);
+        cmd
+    }};
+    ($program:expr $(, $($rest:tt)*)?) => {{
+        #[allow(unused_mut)]
+        let mut cmd = $crate::cmd($program);
+        $crate::__cmd_args!(cmd; $($($rest)*)?);
+        cmd
+    }};
+}
+
+#[doc(hidden)]
+#[macro_export]
+macro_rules! __cmd_args {
+    ($cmd:ident;) => {};
+    ($cmd:ident; ...($args:expr) $(, $($rest:tt)*)?) => {
+        $cmd.args($args);
+        $crate::__cmd_args!($cmd; $($($rest)*)?);
+    };
+    ($cmd:ident; $arg:expr $(, $($rest:tt)*)?) => {
+        $cmd.arg($arg);
+        $crate::__cmd_args!($cmd; $($($rest)*)?);
+    };
+}
+
+const POWERSHELL_FLAGS: [&str; 3] = ["-NoProfile", "-NonInteractive", "-Command"];
+
+/// Runs `script` through the platform shell: `sh -c` on Unix, `cmd /C` on Windows (see `cmd_exe`).
+pub fn shell(script: impl AsRef<str>) -> Command {
+    #[cfg(windows)]
+    {
+        cmd_exe(script)
+    }
+    #[cfg(not(windows))]
+    {
+        let mut cmd = cmd("sh");
+        cmd.arg("-c").arg(script.as_ref());
+        cmd
+    }
+}
+
+/// Runs `script` with `cmd /C`. The script is passed with `raw_arg` so it reaches `cmd` exactly as
+/// written. Only available on Windows.
+#[cfg(windows)]
+pub fn cmd_exe(script: impl AsRef<str>) -> Command {
+    use std::os::windows::process::CommandExt as _;
+    let mut cmd = cmd("cmd");
+    cmd.arg("/C").raw_arg(script.as_ref());
+    cmd
+}
+
+/// Runs `script` with `pwsh -NoProfile -NonInteractive -Command`, or with `powershell.exe` when
+/// `pwsh` isn't on `PATH`. The script is passed as is (with `raw_arg` on Windows), so it is
+/// written in PowerShell syntax and quoting.
+///
+/// This is available on every platform, since `pwsh` is too. Where neither program is
+/// installed, as on most Unix systems, running the command fails with a "not found" error.
+///
+/// PowerShell exits with 1 when the last statement of the script failed, not with the exit code
+/// of a native program that failed: end the script with `exit $LASTEXITCODE` to pass that on.
+pub fn powershell(script: impl AsRef<str>) -> Command {
+    let program = if which("pwsh").is_ok() { "pwsh" } else { "powershell.exe" };
+    let mut cmd = cmd(program);
+    cmd.args(POWERSHELL_FLAGS);
+    #[cfg(windows)]
+    {
+        use std::os::windows::process::CommandExt as _;
+        cmd.raw_arg(script.as_ref());
+    }
+    #[cfg(not(windows))]
+    cmd.arg(script.as_ref());
+    cmd
+}
+
+// The script of a command built by `shell`, `cmd_exe` or `powershell`, if it looks like one.
+fn shell_script(cmd: &Command) -> Option<&OsStr> {
+    let (program, flag) = if cfg!(windows) { ("cmd", "/C") } else { ("sh", "-c") };
+    let args: Vec<&OsStr> = cmd.get_args().collect();
+    match args[..] {
+        [f, script] if cmd.get_program() == program && f == flag => Some(script),
+        [ref flags @ .., script]
+            if ["pwsh", "powershell.exe"].iter().any(|p| cmd.get_program() == *p)
+                && flags == POWERSHELL_FLAGS =>
+        {
+            Some(script)
+        }
+        _ => None,
+    }
+}
+
+pub fn cmd_in(program: impl AsRef<OsStr>, dir: impl AsRef<Path>) -> Command {
+    let mut cmd = cmd(program);
+    cmd.current_dir(dir);
+    cmd
+}
+
+pub trait TermColorStandardStreamExt {
+    fn with_color<F, T>(&mut self, spec: &termcolor::ColorSpec, f: F) -> T
+    where
+        F: FnOnce(&mut Self) -> T;
+}
+
+impl<W: WriteColor> TermColorStandardStreamExt for W {
+    fn with_color<F, T>(&mut self, spec: &termcolor::ColorSpec, f: F) -> T
+    where
+        F: FnOnce(&mut Self) -> T,
+    {
+        // An empty spec would only produce escape sequences that change nothing.
+        if spec.is_none() {
+            return f(self);
+        }
+        // Colors are best-effort: a stream that cannot take them still gets the text.
+        let _ = self.set_color(spec);
+        let v = f(self);
+        let _ = self.reset();
+        v
+    }
+}
+
+pub fn stdout_with_color<F, T>(spec: &termcolor::ColorSpec, f: F) -> T
+where
+    F: FnOnce(&mut termcolor::StandardStream) -> T,
+{
+    stdout_stream().with_color(spec, f)
+}
+
+pub fn stderr_with_color<F, T>(spec: &termcolor::ColorSpec, f: F) -> T
+where
+    F: FnOnce(&mut termcolor::StandardStream) -> T,
+{
+    stderr_stream().with_color(spec, f)
+}
+
+static DECORATIONS_BROKEN: AtomicBool = AtomicBool::new(false);
+
+// Runs a decorative write. Once the stream reports a broken pipe (its reader is gone, as in
+// `tool | head`), decorations are skipped for the rest of the process while commands keep
+// running.
+fn decorate(f: impl FnOnce() -> io::Result<()>) -> io::Result<()> {
+    if DECORATIONS_BROKEN.load(Ordering::SeqCst) {
+        return Ok(());
+    }
+    let _lock = color::DECORATION_LOCK.lock().unwrap();
+    match f() {
+        Err(e) if e.kind() == io::ErrorKind::BrokenPipe => {
+            DECORATIONS_BROKEN.store(true, Ordering::SeqCst);
+            Ok(())
+        }
+        result => result,
+    }
+}
+
+// Writes a problem that can't be returned as an error, such as one met while dropping a guard,
+// with the decorations, so that it goes where banners go and is indented for the scopes.
+fn warn(message: impl Display) {
+    let mut stderr = decoration_stream();
+    let _ = decorate(|| {
+        writeln!(stderr, "cmd_utils: warning: {}", message)?;
+        stderr.flush()
+    });
+}
+
+fn write_banner(stderr: &mut DecorationStream, cmd: &ConfiguredCommand) -> anyhow::Result<()> {
+    write_banner_annotated(stderr, cmd, None)
+}
+
+fn write_banner_annotated(
+    stderr: &mut DecorationStream,
+    cmd: &ConfiguredCommand,
+    annotation: Option<&str>,
+) -> anyhow::Result<()> {
+    decorate(|| {
+        write_timestamp(stderr)?;
+        if let Some(label) = label::get(cmd) {
+            label::write_tag(stderr, label)?;
+        }
+        write_current_dir(stderr, cmd)?;
+        write!(stderr, " ")?;
+        write_command_text(stderr, &cmd.description().shell())?;
+        scoped_env::write_active(stderr)?;
+        match annotation {
+            Some(annotation) => writeln!(stderr, " ({})", annotation)?,
+            None => writeln!(stderr)?,
+        }
+        // Before the command is spawned, so nothing it writes can come first.
+        stderr.flush()
+    })
+    .with_context(|| format!("Failed to write the command banner ({})", cmd.description()))
+}
+
+fn write_timestamp(stderr: &mut DecorationStream) -> io::Result<()> {
+    match timestamp::now() {
+        Some(now) => write!(stderr, "[{}] ", now),
+        None => Ok(()),
+    }
+}
+
+fn write_command_text(stderr: &mut DecorationStream, text: &str) -> io::Result<()> {
+    stderr.with_color(&theme::theme().command, |s| write!(s, "{}", text))
+}
+
+fn write_current_dir(stderr: &mut DecorationStream, cmd: &Command) -> io::Result<()> {
+    // A directory configured on the command is marked with an arrow and a different color, so it
+    // can't be mistaken for the inherited working directory of this process.
+    let (current_dir, explicit) = match cmd.get_current_dir() {
+        Some(_) => (effective_dir(cmd).unwrap_or_default(), true),
+        None => (std::env::current_dir()?, false),
     };
-    termcolor::StandardStream::stderr(color_choice).with_color(spec, f)
+    let theme = theme::theme();
+    let current_dir_color_spec = if explicit { theme.explicit_cwd } else { theme.cwd };
+    stderr.with_color(&current_dir_color_spec, |s| match explicit {
+        true => write!(s, "→ {}", current_dir.display()),
+        false => write!(s, "{}", current_dir.display()),
+    })
 }
 
-fn cmd_info_with_output(cmd: &Command, stdout: &[u8], stderr: &[u8]) -> String {
-    format!(
-        "{}, stdout = {:?}, stderr = {:?}",
-        cmd.description(),
-        String::from_utf8_lossy(stdout),
-        String::from_utf8_lossy(stderr),
+// The directory the command runs in: its own, joined onto the working directory of this process
+// if it is relative, otherwise that working directory.
+pub(crate) fn effective_dir(cmd: &Command) -> Option<PathBuf> {
+    let cwd = std::env::current_dir();
+    match cmd.get_current_dir() {
+        Some(dir) if dir.is_absolute() => Some(dir.to_path_buf()),
+        Some(dir) => Some(cwd.map_or_else(|_| dir.to_path_buf(), |cwd| cwd.join(dir))),
+        None => cwd.ok(),
+    }
+}
+
+// Written after the command has finished, so a failure to write is ignored rather than hiding
+// the command's own result.
+fn write_end_output(
+    stderr: &mut DecorationStream,
+    cmd: &ConfiguredCommand,
+    success: bool,
+    annotation: Option<&str>,
+    duration: Duration,
+) {
+    write_end_output_with_summary(stderr, cmd, success, annotation, duration, None);
+}
+
+// `write_end_output` for a command whose stdout and stderr were captured, which are summed up
+// after the duration with `set_output_summary`.
+fn write_end_output_with_summary(
+    stderr: &mut DecorationStream,
+    cmd: &ConfiguredCommand,
+    success: bool,
+    annotation: Option<&str>,
+    duration: Duration,
+    output: Option<(&[u8], &[u8])>,
+) {
+    let output = output.filter(|_| OUTPUT_SUMMARY.load(Ordering::Relaxed));
+    let theme = theme::theme();
+    let eo_color_spec = match (success, interrupt::is_pending()) {
+        (true, _) => theme.success,
+        (false, true) => theme.interrupted,
+        (false, false) => theme.failure,
+    };
+    // The duration of a slow command keeps the background of the marker.
+    let mut duration_color_spec = eo_color_spec.clone();
+    if slow::exceeded(cmd, duration) && !theme.slow.is_none() {
+        duration_color_spec
+            .set_fg(theme.slow.fg().copied())
+            .set_bold(theme.slow.bold())
+            .set_intense(theme.slow.intense());
+    }
+    let label = label::get(cmd);
+    let _ = decorate(|| {
+        if let Some(label) = &label {
+            label::write_tag(stderr, label)?;
+        }
+        stderr.with_color(&eo_color_spec, |s| {
+            write!(s, " END OUTPUT (")?;
+            if let Some(annotation) = annotation {
+                write!(s, "{}, ", annotation)?;
+            }
+            Ok::<_, io::Error>(())
+        })?;
+        stderr.with_color(&duration_color_spec, |s| write!(s, "{}", HumanDuration(duration)))?;
+        stderr.with_color(&eo_color_spec, |s| {
+            if let Some((stdout, stderr)) = output {
+                write!(s, ", {}", OutputSummary { stdout, stderr })?;
+            }
+            if let Some(now) = timestamp::now() {
+                write!(s, ", finished {}", now)?;
+            }
+            write!(s, ") ")
+        })?;
+        writeln!(stderr)
+    });
+}
+
+fn exec_report(cmd: &mut ConfiguredCommand) -> anyhow::Result<ExecReport> {
+    if dry_run(cmd)? {
+        return Ok(ExecReport { status: ExitStatus::default(), duration: Duration::ZERO });
+    }
+    let mut stderr = decoration_stream();
+    write_banner(&mut stderr, cmd)?;
+    let execution = execution::start(cmd)?;
+    let status = backend::status(cmd);
+    let duration = execution.finish(cmd, status.as_ref().ok(), None);
+    let status = status
+        .spawn_context(cmd, || format!("Failed to execute command ({})", cmd.description()))?;
+    write_end_output(&mut stderr, cmd, status.success(), None, duration);
+    Ok(ExecReport { status, duration })
+}
+
+// Prints the banner with a DRY RUN marker and returns true if the command should not be spawned.
+fn dry_run(cmd: &ConfiguredCommand) -> anyhow::Result<bool> {
+    if !is_dry_run() {
+        return Ok(false);
+    }
+    let mut stderr = decoration_stream();
+    write_banner(&mut stderr, cmd)?;
+    write_dry_run_marker(&mut stderr);
+    Ok(true)
+}
+
+fn write_dry_run_marker(stderr: &mut DecorationStream) {
+    let dry_run_color_spec = theme::theme().dry_run;
+    let _ = decorate(|| {
+        stderr.with_color(&dry_run_color_spec, |s| write!(s, " DRY RUN "))?;
+        writeln!(stderr)
+    });
+}
+
+fn dry_run_output(cmd: Command) -> Output {
+    Output::new(cmd, ExitStatus::default(), String::new(), Vec::new(), Duration::ZERO)
+}
+
+fn write_failure_summary(stderr: &mut DecorationStream, cmd: &Command, status: ExitStatus) {
+    let failure_color_spec = theme::theme().failure_text;
+    let _ = decorate(|| {
+        stderr.with_color(&failure_color_spec, |s| {
+            write!(s, "Command failed ({})", StatusSummary(status))
+        })?;
+        writeln!(stderr, ": {}", cmd.description())
+    });
+}
+
+// "exit code 137" or "killed by signal 9 (SIGKILL)".
+struct StatusSummary(ExitStatus);
+
+impl Display for StatusSummary {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        if let Some(code) = self.0.code() {
+            return write!(f, "exit code {}", code);
+        }
+        match exit_signal(self.0) {
+            Some(signal) => match signal_name(signal) {
+                Some(name) => write!(f, "killed by signal {} ({})", signal, name),
+                None => write!(f, "killed by signal {}", signal),
+            },
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
+
+#[cfg(unix)]
+fn exit_signal(status: ExitStatus) -> Option<i32> {
+    std::os::unix::process::ExitStatusExt::signal(&status)
+}
+
+#[cfg(not(unix))]
+fn exit_signal(_status: ExitStatus) -> Option<i32> {
+    None
+}
+
+fn signal_name(signal: i32) -> Option<&'static str> {
+    Some(match signal {
+        1 => "SIGHUP",
+        2 => "SIGINT",
+        3 => "SIGQUIT",
+        4 => "SIGILL",
+        6 => "SIGABRT",
+        9 => "SIGKILL",
+        11 => "SIGSEGV",
+        13 => "SIGPIPE",
+        14 => "SIGALRM",
+        15 => "SIGTERM",
+        _ => return None,
+    })
+}
+
+// Processes killed by a signal have no exit code and are therefore never allowed.
+fn is_allowed(status: ExitStatus, codes: &[i32]) -> bool {
+    status.success() || status.code().is_some_and(|code| codes.contains(&code))
+}
+
+fn exec_stdout_to_file(
+    cmd: &mut ConfiguredCommand,
+    path: &Path,
+    append: bool,
+) -> anyhow::Result<()> {
+    use std::process::Stdio;
+    check_redirected(cmd, Pipes { stdout: true, stderr: true, ..Pipes::default() })?;
+    if dry_run(cmd)? {
+        return Ok(());
+    }
+    let execution = execution::start(cmd)?;
+    // Opened only once the pre-exec hooks let the command run, so that a refused command
+    // leaves the file alone.
+    let file = std::fs::OpenOptions::new()
+        .write(true)
+        .create(true)
+        .append(append)
+        .truncate(!append)
+        .open(path)
+        .with_context(|| {
+            format!("Failed to open {:?} for command stdout ({})", path, cmd.description())
+        })?;
+    let out = backend::output_to_file(cmd, file);
+    cmd.stdout(Stdio::inherit()).stderr(Stdio::inherit());
+    execution.finish(
+        cmd,
+        out.as_ref().ok().map(|out| &out.status),
+        out.as_ref().ok().map(|out| (&[][..], &out.stderr[..])),
+    );
+    let std::process::Output { status, stderr, .. } = out.spawn_context(cmd, || {
+        format!(
+            "Failed to execute command ({}, stdout file = {:?}, which may have been left \
+                 empty or partially written)",
+            cmd.description(),
+            path,
+        )
+    })?;
+    if !status.success() {
+        return Err(exec_failed(
+            cmd,
+            status,
+            &[],
+            &stderr,
+            format!(
+                "Process did not exit successfully ({}, stderr = {:?}, stdout file = {:?}, which may \
+                 have been left partially written)",
+                cmd.description(),
+                String::from_utf8_lossy(&stderr),
+                path,
+            ),
+        ));
+    }
+    Ok(())
+}
+
+fn exec_failed(
+    cmd: &Command,
+    status: ExitStatus,
+    stdout: &[u8],
+    stderr: &[u8],
+    message: String,
+) -> anyhow::Error {
+    let description = cmd.description();
+    exec_failed_with(
+        &description.to_string(),
+        description.to_snapshot(),
+        status,
+        stdout,
+        stderr,
+        message,
     )
 }
+
+// `exec_failed` for a command that is only known by its description.
+fn exec_failed_with(
+    text: &str,
+    description: CommandSnapshot,
+    status: ExitStatus,
+    stdout: &[u8],
+    stderr: &[u8],
+    message: String,
+) -> anyhow::Error {
+    if interrupt::interrupted(status) {
+        let message = format!("Process was interrupted ({})", text);
+        let interrupted = CmdError::Interrupted { status, description };
+        return anyhow::Error::new(interrupted).context(message);
+    }
+    let failed = CmdError::UnsuccessfulExit {
+        status,
+        description,
+        stdout: stdout.to_vec(),
+        stderr: stderr.to_vec(),
+    };
+    anyhow::Error::new(failed).context(message)
+}
+
+fn timed_out(cmd: &Command, timeout: Duration, message: String) -> anyhow::Error {
+    let timed_out = CmdError::TimedOut { timeout, description: cmd.description().to_snapshot() };
+    anyhow::Error::new(timed_out).context(message)
+}
+
+fn invalid_utf8(
+    cmd: &Command,
+    stream: Stream,
+    source: Utf8Error,
+    message: String,
+) -> anyhow::Error {
+    let invalid =
+        CmdError::InvalidUtf8 { stream, description: cmd.description().to_snapshot(), source };
+    anyhow::Error::new(invalid).context(message)
+}
+
+// A command with the same program, arguments, environment changes and directory as `cmd`.
+fn copy_command(cmd: &Command) -> Command {
+    let mut copy = crate::cmd(cmd.get_program());
+    copy.args(cmd.get_args());
+    for (key, value) in cmd.get_envs() {
+        match value {
+            Some(value) => copy.env(key, value),
+            None => copy.env_remove(key),
+        };
+    }
+    if let Some(dir) = cmd.get_current_dir() {
+        copy.current_dir(dir);
+    }
+    copy
+}
+
+// A child closing its stdin early is not an error by itself; if it also exits unsuccessfully the
+// caller reports that instead, along with how much input was written.
+fn check_stdin_written(
+    cmd: &Command,
+    input_len: Option<usize>,
+    written: u64,
+    result: std::io::Result<()>,
+) -> anyhow::Result<()> {
+    match result {
+        Ok(()) => Ok(()),
+        Err(e) if e.kind() == std::io::ErrorKind::BrokenPipe => Ok(()),
+        Err(e) => {
+            let context = match input_len {
+                Some(input_len) => format!(
+                    "Failed to write to process stdin ({} of {} bytes written) ({})",
+                    written,
+                    input_len,
+                    cmd.description(),
+                ),
+                None => format!(
+                    "Failed to write to process stdin ({} bytes written) ({})",
+                    written,
+                    cmd.description(),
+                ),
+            };
+            Err(anyhow::Error::new(e).context(context))
+        }
+    }
+}
+
+fn stdout_to_string(cmd: &Command, stdout: Vec<u8>, stderr: &[u8]) -> anyhow::Result<String> {
+    String::from_utf8(stdout).map_err(|e| {
+        let context = format!(
+            "Process stdout is not UTF-8 ({})",
+            cmd_info_with_output(cmd, e.as_bytes(), stderr),
+        );
+        invalid_utf8(cmd, Stream::Stdout, e.utf8_error(), context)
+    })
+}
+
+// Drops an incomplete UTF-8 sequence at the end of `bytes`, left by cutting them off.
+fn trim_partial_char(bytes: &mut Vec<u8>) {
+    if let Err(e) = std::str::from_utf8(bytes) {
+        if e.error_len().is_none() {
+            bytes.truncate(e.valid_up_to());
+        }
+    }
+}
+
+fn stderr_to_string(cmd: &Command, stdout: &[u8], stderr: Vec<u8>) -> anyhow::Result<String> {
+    String::from_utf8(stderr).map_err(|e| {
+        let context = format!(
+            "Process stderr is not UTF-8 ({})",
+            cmd_info_with_output(cmd, stdout, e.as_bytes()),
+        );
+        invalid_utf8(cmd, Stream::Stderr, e.utf8_error(), context)
+    })
+}
+
+fn write_pretty_command(f: &mut impl std::fmt::Write, cmd: &Command) -> std::fmt::Result {
+    writeln!(f, "program = {:?}", redact::scrub(cmd.get_program()))?;
+    for arg in cmd.get_args() {
+        writeln!(f, "    {:?}", redact::scrub(arg))?;
+    }
+    if cmd.get_envs().next().is_some() {
+        writeln!(f, "envs:")?;
+    }
+    for (key, value) in cmd.get_envs() {
+        match value {
+            Some(value) => writeln!(
+                f,
+                "    {:?} = {:?}",
+                key,
+                path_var::elided(key, redact::env_value(key, value)),
+            )?,
+            None => writeln!(f, "    {:?} (removed)", key)?,
+        }
+    }
+    match cmd.get_current_dir() {
+        Some(dir) => writeln!(f, "current_dir = {:?}", redact::scrub(dir.as_os_str())),
+        None => match std::env::current_dir() {
+            Ok(inherited) => writeln!(f, "current_dir = {:?} (inherited)", inherited),
+            Err(_) => writeln!(f, "current_dir = None"),
+        },
+    }
+}
+
+fn write_fenced(f: &mut impl std::fmt::Write, name: &str, output: Embedded) -> std::fmt::Result {
+    let (text, tail) = output.parts();
+    writeln!(f, "{} ({} bytes):", name, output.total)?;
+    writeln!(f, "```")?;
+    write_fenced_text(f, &text)?;
+    if let Some((omitted, tail)) = tail {
+        writeln!(f, "… {} bytes omitted …", omitted)?;
+        write_fenced_text(f, &tail)?;
+    }
+    writeln!(f, "```{}", output.marker())
+}
+
+fn write_fenced_text(f: &mut impl std::fmt::Write, text: &str) -> std::fmt::Result {
+    if !text.is_empty() {
+        write!(f, "{}", escape_controls(text))?;
+        if !text.ends_with('\n') {
+            writeln!(f)?;
+        }
+    }
+    Ok(())
+}
+
+fn cmd_info_with_output(cmd: &Command, stdout: &[u8], stderr: &[u8]) -> String {
+    cmd_info_embedded(cmd, Embedded::new(stdout), Embedded::new(stderr))
+}
+
+fn cmd_info_embedded(cmd: &Command, stdout: Embedded, stderr: Embedded) -> String {
+    format!("{}, stdout = {}, stderr = {}", cmd.description(), stdout, stderr)
+}
+
+const OUTPUT_SNIPPET_LIMIT: usize = 1024;
+
+fn truncate_str(s: &str, max_len: usize) -> &str {
+    if s.len() <= max_len {
+        return s;
+    }
+    let mut end = max_len;
+    while !s.is_char_boundary(end) {
+        end -= 1;
+    }
+    &s[..end]
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn cmd_error(err: &anyhow::Error) -> &CmdError {
+        err.downcast_ref::<CmdError>().unwrap_or_else(|| panic!("no CmdError in {:#}", err))
+    }
+
+    #[test]
+    fn exec_stdout_bytes_keeps_invalid_utf8() {
+        let out = shell(r"printf 'a\377\376b'").exec_stdout_bytes().unwrap();
+        assert_eq!(out.stdout, b"a\xff\xfeb");
+        assert!(out.status.success());
+    }
+
+    #[test]
+    fn exec_stdout_bytes_fails_on_nonzero_exit_with_escaped_bytes() {
+        let err = shell(r"printf 'a\377'; exit 3").exec_stdout_bytes().err().unwrap();
+        match cmd_error(&err) {
+            CmdError::UnsuccessfulExit { status, stdout, .. } => {
+                assert_eq!(status.code(), Some(3));
+                assert_eq!(stdout, b"a\xff");
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let message = format!("{:#}", err);
+        assert!(message.contains(r"\xff"), "{}", message);
+        assert!(!message.contains('\u{FFFD}'), "{}", message);
+    }
+
+    #[test]
+    fn exec_output_validates_both_streams() {
+        let out = shell("echo out; echo err >&2").exec_output().unwrap();
+        assert_eq!((out.stdout.as_str(), out.stderr.as_str()), ("out\n", "err\n"));
+        let err = shell(r"printf '\377' >&2").exec_output().err().unwrap();
+        assert!(matches!(cmd_error(&err), CmdError::InvalidUtf8 { stream: Stream::Stderr, .. }));
+        assert!(format!("{:#}", err).contains("Process stderr is not UTF-8"), "{:#}", err);
+    }
+
+    #[test]
+    fn exec_output_failure_shows_both_streams() {
+        let err = shell("echo out; echo err >&2; exit 1").exec_output().err().unwrap();
+        let message = format!("{:#}", err);
+        assert!(message.contains(r#"stdout = "out\n""#), "{}", message);
+        assert!(message.contains(r#"stderr = "err\n""#), "{}", message);
+    }
+
+    // The lines of `text` tagged with `label`.
+    fn labeled<'a>(text: &'a str, label: &str) -> Vec<&'a str> {
+        let tag = format!("[{}]", label);
+        text.lines().filter(|line| line.contains(&tag)).collect()
+    }
+
+    #[test]
+    fn exec_status_returns_failures_and_colors_the_marker() {
+        let mut statuses = Vec::new();
+        let text = test_support::decorations(|| {
+            for (label, script) in [("status-ok", "true"), ("status-fail", "exit 1")] {
+                statuses.push(shell(script).label(label).exec_status().unwrap().code());
+            }
+        });
+        assert_eq!(statuses, [Some(0), Some(1)]);
+        let ok = labeled(&text, "status-ok");
+        let fail = labeled(&text, "status-fail");
+        assert_eq!((ok.len(), fail.len()), (2, 2), "{}", text);
+        assert!(ok[0].contains("sh -c true") && fail[0].contains("sh -c 'exit 1'"), "{}", text);
+        assert!(ok[1].contains("\x1b[42m") && ok[1].contains("END OUTPUT"), "{:?}", ok[1]);
+        assert!(fail[1].contains("\x1b[41m") && fail[1].contains("END OUTPUT"), "{:?}", fail[1]);
+    }
+
+    #[test]
+    fn exec_stdout_lines_drops_one_trailing_empty_line() {
+        let cases: [(&str, &[&str]); 5] = [
+            ("true", &[]),
+            ("printf 'a\\nb'", &["a", "b"]),
+            ("printf 'a\\nb\\n'", &["a", "b"]),
+            ("printf 'a\\r\\nb\\r\\n'", &["a", "b"]),
+            ("printf 'a\\n\\n'", &["a", ""]),
+        ];
+        for (script, expected) in cases {
+            assert_eq!(shell(script).exec_stdout_lines().unwrap(), expected, "{}", script);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn exec_stdout_json_parses_and_reports_malformed_output() {
+        let value: serde_json::Value = shell(r#"echo '{"a": [1, 2]}'"#).exec_stdout_json().unwrap();
+        assert_eq!(value["a"][1], 2);
+        let script = format!("echo '{{not json'; printf '%{}s' x", 4000);
+        let err = shell(&script).exec_stdout_json::<serde_json::Value>().err().unwrap();
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains(r#"Failed to parse process stdout as JSON (script = "echo"#),
+            "{}",
+            message
+        );
+        assert!(message.contains(r#"program = "sh""#), "{}", message);
+        assert!(message.contains(r#"stdout = "{not json\n"#), "{}", message);
+        assert!(message.len() < 2000, "{}", message);
+    }
+
+    #[test]
+    fn exec_stdout_lossy_replaces_invalid_bytes() {
+        let out = shell(r"printf 'caf\351'").exec_stdout_lossy().unwrap();
+        assert_eq!(out.stdout, "caf\u{FFFD}");
+        assert!(out.stdout_was_lossy);
+        let out = shell("printf café").exec_stdout_lossy().unwrap();
+        assert_eq!((out.stdout.as_str(), out.stdout_was_lossy), ("café", false));
+        let err = shell(r"printf '\377'; exit 2").exec_stdout_lossy().err().unwrap();
+        assert!(matches!(cmd_error(&err), CmdError::UnsuccessfulExit { .. }), "{:#}", err);
+    }
+
+    #[test]
+    fn exec_stderr_string_swaps_the_roles_of_the_streams() {
+        let out = shell(r"printf '\377'; echo diag >&2").exec_stderr_string().unwrap();
+        assert_eq!((out.stdout.as_slice(), out.stderr.as_str()), (&b"\xff"[..], "diag\n"));
+        let err = shell(r"printf '\377' >&2").exec_stderr_string().err().unwrap();
+        assert!(matches!(cmd_error(&err), CmdError::InvalidUtf8 { stream: Stream::Stderr, .. }));
+        let message = format!("{:#}", err);
+        assert!(message.contains("Process stderr is not UTF-8 (script = "), "{}", message);
+        let err = shell("echo out; echo err >&2; exit 1").exec_stderr_string().err().unwrap();
+        let message = format!("{:#}", err);
+        assert!(message.contains(r#"stdout = "out\n""#), "{}", message);
+        assert!(message.contains(r#"stderr = "err\n""#), "{}", message);
+    }
+
+    #[test]
+    fn exec_with_timeout_kills_a_hung_command() {
+        let timeout = Duration::from_millis(100);
+        let start = Instant::now();
+        let mut result = None;
+        let text = test_support::decorations(|| {
+            result = Some(shell("exec sleep 60").label("timeout-hung").exec_with_timeout(timeout));
+        });
+        assert!(start.elapsed() < Duration::from_secs(10), "{:?}", start.elapsed());
+        let err = result.unwrap().err().unwrap();
+        assert!(matches!(cmd_error(&err), CmdError::TimedOut { timeout: t, .. } if *t == timeout));
+        assert!(format!("{:#}", err).contains("Process timed out after"), "{:#}", err);
+        let end = labeled(&text, "timeout-hung");
+        assert!(end.last().is_some_and(|line| line.contains("TIMED OUT")), "{}", text);
+        shell("true").exec_with_timeout(Duration::from_secs(10)).unwrap();
+    }
+
+    #[test]
+    fn exec_stdout_string_with_timeout_keeps_output_of_fast_commands() {
+        let out = shell("echo quick").exec_stdout_string_with_timeout(Duration::from_secs(10));
+        assert_eq!(out.unwrap().stdout, "quick\n");
+        let err = shell("echo started; exec sleep 60")
+            .exec_stdout_string_with_timeout(Duration::from_millis(100))
+            .err()
+            .unwrap();
+        assert!(matches!(cmd_error(&err), CmdError::TimedOut { .. }), "{:#}", err);
+    }
+
+    #[test]
+    fn exec_with_stdin_feeds_the_input() {
+        let out = shell("tr a-z A-Z").exec_stdout_string_with_stdin("hello").unwrap();
+        assert_eq!(out.stdout, "HELLO");
+        // Much more than a pipe holds, and not read at all.
+        shell("true").exec_with_stdin(vec![b'x'; 1 << 20]).unwrap();
+        let err = shell("head -c 3 >/dev/null; exit 1").exec_with_stdin(vec![b'x'; 1 << 20]);
+        let message = format!("{:#}", err.err().unwrap());
+        assert!(message.contains("Process did not exit successfully"), "{}", message);
+        assert!(message.contains("bytes of input written"), "{}", message);
+    }
+
+    // Yields `data` and then fails.
+    struct FailingReader(&'static [u8]);
+
+    impl Read for FailingReader {
+        fn read(&mut self, buf: &mut [u8]) -> std::io::Result<usize> {
+            if self.0.is_empty() {
+                return Err(std::io::Error::other("source went away"));
+            }
+            let n = self.0.len().min(buf.len());
+            buf[..n].copy_from_slice(&self.0[..n]);
+            self.0 = &self.0[n..];
+            Ok(n)
+        }
+    }
+
+    #[test]
+    fn exec_stdin_reader_streams_large_input_and_output() {
+        // Far more than a pipe holds in either direction.
+        let input = std::io::repeat(b'y').take(4 << 20);
+        let out = shell("cat").exec_stdin_reader(input).unwrap();
+        assert_eq!(out.stdout.len(), 4 << 20);
+        let err = shell("cat >/dev/null").exec_stdin_reader(FailingReader(b"hello")).err().unwrap();
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains("Failed to write to process stdin (5 bytes written)"),
+            "{}",
+            message
+        );
+        assert!(message.contains("source went away"), "{}", message);
+    }
+
+    #[test]
+    fn exec_tee_captures_what_it_forwards() {
+        let out = shell("echo one; echo two >&2; echo three").exec_tee().unwrap();
+        assert_eq!((out.stdout.as_str(), out.stderr.as_slice()), ("one\nthree\n", &b"two\n"[..]));
+        let err = shell("echo built; echo broke >&2; exit 2").exec_tee().err().unwrap();
+        assert!(matches!(cmd_error(&err), CmdError::UnsuccessfulExit { .. }), "{:#}", err);
+        let message = format!("{:#}", err);
+        assert!(message.contains(r#"stdout = "built\n""#), "{}", message);
+        assert!(message.contains(r#"stderr = "broke\n""#), "{}", message);
+    }
+
+    #[test]
+    fn exec_quiet_only_writes_a_summary_of_failures() {
+        let mut results = Vec::new();
+        let text = test_support::decorations(|| {
+            results.push(shell("true # quiet-ok").exec_quiet().is_ok());
+            results.push(shell("exit 3 # quiet-fail").exec_quiet().is_ok());
+        });
+        assert_eq!(results, [true, false]);
+        assert!(!text.contains("quiet-ok"), "{}", text);
+        let failed: Vec<_> = text.lines().filter(|line| line.contains("quiet-fail")).collect();
+        assert_eq!(failed.len(), 1, "{}", text);
+        assert!(failed[0].contains("Command failed (exit code 3)"), "{:?}", failed[0]);
+        assert!(!failed[0].contains("END OUTPUT"), "{:?}", failed[0]);
+    }
+
+    #[test]
+    fn exec_allowing_treats_listed_codes_as_success() {
+        let (mut allowed, mut failed) = (None, None);
+        let text = test_support::decorations(|| {
+            allowed = Some(shell("exit 1").label("allow-1").exec_allowing([1]));
+            failed = Some(shell("exit 2").label("allow-2").exec_allowing([1]));
+        });
+        assert_eq!(allowed.unwrap().unwrap().code(), Some(1));
+        let err = failed.unwrap().err().unwrap();
+        assert!(matches!(cmd_error(&err), CmdError::UnsuccessfulExit { .. }), "{:#}", err);
+        assert!(labeled(&text, "allow-1")[1].contains("\x1b[42m"), "{}", text);
+        assert!(labeled(&text, "allow-2")[1].contains("\x1b[41m"), "{}", text);
+        let out = shell("echo no match; exit 1").exec_stdout_string_allowing([1]).unwrap();
+        assert_eq!((out.stdout.as_str(), out.status.code()), ("no match\n", Some(1)));
+        assert!(shell("exit 2").exec_stdout_string_allowing([1]).is_err());
+    }
+
+    #[test]
+    fn exec_stdout_to_file_writes_stdout_and_reports_stderr() {
+        let path = test_support::temp_path("stdout-to-file");
+        shell("echo written; echo noise >&2").exec_stdout_to_file(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "written\n");
+        let err = shell("echo partial; echo broken >&2; exit 1").exec_stdout_to_file(&path);
+        let contents = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(contents, "partial\n");
+        let message = format!("{:#}", err.err().unwrap());
+        assert!(message.contains(r#"stderr = "broken\n""#), "{}", message);
+        assert!(message.contains(&format!("stdout file = {:?}", path)), "{}", message);
+        let missing = Path::new("/nonexistent/dir/out.txt");
+        let message = format!("{:#}", shell("true").exec_stdout_to_file(missing).err().unwrap());
+        assert!(message.starts_with("Failed to open \"/nonexistent/dir/out.txt\""), "{}", message);
+    }
+
+    #[test]
+    fn exec_for_each_line_calls_back_as_lines_arrive() {
+        let (mut out, mut err) = (Vec::new(), Vec::new());
+        let status = shell(r"printf 'one\r\n\377two\n'; echo oops >&2; printf tail; exit 4")
+            .exec_for_each_line(|line| out.push(line.to_owned()), |line| err.push(line.to_owned()))
+            .unwrap();
+        assert_eq!(status.code(), Some(4));
+        assert_eq!(out, ["one", "\u{FFFD}two", "tail"]);
+        assert_eq!(err, ["oops"]);
+        let mut arrivals = Vec::new();
+        shell("echo first; sleep 0.3; echo second")
+            .exec_for_each_line(|_| arrivals.push(Instant::now()), |_| {})
+            .unwrap();
+        assert!(arrivals[1] - arrivals[0] >= Duration::from_millis(200), "{:?}", arrivals);
+    }
+
+    #[test]
+    fn exec_interactive_keeps_the_banner_and_the_failure_context() {
+        let mut result = None;
+        let text = test_support::decorations(|| {
+            result = Some(shell("exit 5 # interactive").label("interactive").exec_interactive());
+        });
+        let lines = labeled(&text, "interactive");
+        assert_eq!(lines.len(), 2, "{}", text);
+        assert!(lines[1].contains("\x1b[41m") && lines[1].contains("END OUTPUT"), "{}", text);
+        let err = result.unwrap().err().unwrap();
+        assert!(matches!(cmd_error(&err), CmdError::UnsuccessfulExit { .. }), "{:#}", err);
+        assert!(format!("{:#}", err).contains("exit code 5"), "{:#}", err);
+        let mut result = None;
+        let text = test_support::decorations(|| {
+            let mut redirected = shell("true").stdout_(Stdio::null()).label("redirected");
+            result = Some(redirected.exec_interactive());
+        });
+        assert!(labeled(&text, "redirected").is_empty(), "{}", text);
+        let err = result.unwrap().err().unwrap();
+        assert!(format!("{:#}", err).contains("was set with `stdout_`"), "{:#}", err);
+    }
+
+    #[test]
+    fn cmd_in_runs_in_and_shows_its_directory() {
+        let dir = std::env::temp_dir();
+        let mut explicit = cmd_in("pwd", &dir).label("cwd-explicit");
+        let mut inherited = cmd("pwd").label("cwd-inherited");
+        let explicit_text = explicit.description().to_string();
+        assert!(
+            explicit_text.contains(&format!("current_dir = Some({:?})", dir)),
+            "{}",
+            explicit_text
+        );
+        assert!(!explicit_text.contains("inherited"), "{}", explicit_text);
+        assert!(inherited.description().to_string().contains("current_dir = None (inherited"));
+        let mut out = None;
+        let text = test_support::decorations(|| {
+            explicit.exec().unwrap();
+            inherited.exec().unwrap();
+            out = Some(cmd_in("pwd", &dir).exec_stdout_string().unwrap());
+        });
+        let canonical = dir.canonicalize().unwrap();
+        assert_eq!(out.unwrap().stdout.trim_end(), canonical.to_str().unwrap());
+        let explicit = labeled(&text, "cwd-explicit")[0];
+        let inherited = labeled(&text, "cwd-inherited")[0];
+        assert!(explicit.contains("\x1b[45m") && explicit.contains("→ "), "{:?}", explicit);
+        assert!(inherited.contains("\x1b[46m") && !inherited.contains('→'), "{:?}", inherited);
+    }
+
+    #[test]
+    fn a_failed_capture_keeps_its_output_in_the_error() {
+        let err = shell("echo partial; echo 'fatal: no remote' >&2; exit 128")
+            .exec_stdout_string()
+            .err()
+            .unwrap();
+        match cmd_error(&err) {
+            CmdError::UnsuccessfulExit { status, stdout, stderr, .. } => {
+                assert_eq!(status.code(), Some(128));
+                assert_eq!(
+                    (&stdout[..], &stderr[..]),
+                    (&b"partial\n"[..], &b"fatal: no remote\n"[..])
+                );
+            }
+            other => panic!("unexpected {:?}", other),
+        }
+        let message = format!("{:#}", err);
+        assert!(message.contains("stderr (17 bytes):\n```\nfatal: no remote\n```"), "{}", message);
+    }
+
+    #[test]
+    fn shell_runs_a_script_and_describes_it_by_its_script() {
+        let out = shell("echo hi | tr a-z A-Z").exec_stdout_string().unwrap();
+        assert_eq!(out.stdout, "HI\n");
+        let text = shell("ls *.rs && true").description().to_string();
+        assert!(text.starts_with(r#"script = "ls *.rs && true", program = "sh""#), "{}", text);
+        assert!(!cmd("sh").arg("-x").description().to_string().contains("script ="));
+    }
+
+    #[test]
+    fn cmd_macro_mixes_literals_expressions_and_splats() {
+        let msg = String::from("fix: it");
+        let files = vec![Path::new("a.txt"), Path::new("b c.txt")];
+        let snapshot =
+            crate::cmd!("git", "commit", "-m", &msg, ...(&files)).description().to_snapshot();
+        assert_eq!(snapshot.program, "git");
+        assert_eq!(snapshot.args, ["commit", "-m", "fix: it", "a.txt", "b c.txt"]);
+        let in_dir = crate::cmd!(in "/tmp" => "ls", "-l");
+        assert_eq!(in_dir.get_current_dir(), Some(Path::new("/tmp")));
+        assert_eq!(in_dir.description().to_snapshot().args, ["-l"]);
+        assert_eq!(crate::cmd!("true").get_args().count(), 0);
+    }
+
+    #[test]
+    fn consuming_builders_chain_like_their_std_counterparts() {
+        let cmd = cmd("env")
+            .arg_("-0")
+            .args_(["a", "b"])
+            .env_("KEEP", "1")
+            .envs_([("DROP", "2"), ("OTHER", "3")])
+            .env_remove_("DROP");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), ["-0", "a", "b"]);
+        let envs: Vec<_> = cmd.get_envs().collect();
+        let expected = [("DROP", None), ("KEEP", Some("1")), ("OTHER", Some("3"))]
+            .map(|(key, value)| (OsStr::new(key), value.map(OsStr::new)));
+        assert_eq!(envs, expected);
+        let cleared = cmd.env_clear_().env_("ONLY", "x");
+        assert_eq!(
+            cleared.get_envs().collect::<Vec<_>>(),
+            [(OsStr::new("ONLY"), Some(OsStr::new("x")))]
+        );
+    }
+
+    #[test]
+    fn alternate_descriptions_spread_over_lines() {
+        let cmd = cmd("printf").args_(["%s|", "a b", "c"]).env_("LANG", "C").current_dir_("/");
+        let pretty = cmd.description().pretty();
+        let expected =
+            "program = \"printf\"\n    \"%s|\"\n    \"a b\"\n    \"c\"\nenvs:\n    \"LANG\" = \"C\"\n\
+             current_dir = \"/\"\n";
+        assert_eq!(pretty, expected);
+        assert!(!cmd.description().to_string().contains('\n'));
+        let out = cmd.exec_stdout_string().unwrap();
+        let text = format!("{:#}", out.description());
+        assert!(text.starts_with(expected), "{}", text);
+        assert!(text.contains("stdout (6 bytes):\n```\na b|c|\n```"), "{}", text);
+        assert!(text.ends_with("stderr (0 bytes):\n```\n```\n"), "{}", text);
+    }
+
+    #[test]
+    fn human_durations() {
+        let cases = [
+            (0.0, "0ms"),
+            (0.2345, "234ms"),
+            (3.419, "3.42s"),
+            (119.994, "119.99s"),
+            (125.0, "2m 05s"),
+        ];
+        for (secs, expected) in cases {
+            assert_eq!(HumanDuration(Duration::from_secs_f64(secs)).to_string(), expected);
+        }
+    }
+
+    #[test]
+    fn durations_are_measured_and_shown() {
+        let mut report = None;
+        let text = test_support::decorations(|| {
+            report = Some(shell("sleep 0.2").label("timed").exec_timed().unwrap());
+        });
+        let report = report.unwrap();
+        assert!(report.duration >= Duration::from_millis(200), "{:?}", report.duration);
+        let end = labeled(&text, "timed")[1];
+        assert!(end.contains(&format!("{}ms", report.duration.as_millis())), "{:?}", end);
+        let out = shell("sleep 0.2").exec_stdout_string().unwrap();
+        assert!(out.duration >= Duration::from_millis(200), "{:?}", out.duration);
+    }
+
+    fn output(stdout: &str) -> Output {
+        Output::new(
+            cmd("tool"),
+            ExitStatus::default(),
+            stdout.to_owned(),
+            Vec::new(),
+            Duration::ZERO,
+        )
+    }
+
+    #[test]
+    fn output_accessors() {
+        let out = output("  abc123 \n\n");
+        assert_eq!(out.stdout_trimmed(), "  abc123");
+        assert_eq!(out.first_line(), Some("  abc123 "));
+        assert_eq!(out.single_line().unwrap(), "abc123");
+        assert!(!out.is_empty());
+        assert!(output(" \n\t\n").is_empty());
+        assert_eq!(output("").first_line(), None);
+    }
+
+    #[test]
+    fn single_line_fails_on_none_or_several() {
+        let none = output("\n  \n").single_line().err().unwrap().to_string();
+        assert!(
+            none.starts_with("Expected a single line of output but got none (program = \"tool\"")
+        );
+        let several = output("main\n\nfeature\nfix\n").single_line().err().unwrap().to_string();
+        assert!(several.starts_with("Expected a single line of output but got 3 ("), "{}", several);
+        assert!(several.ends_with(r#"stdout = "main\n\nfeature\nfix\n")"#), "{}", several);
+    }
+
+    #[test]
+    fn parse_reads_trimmed_stdout() {
+        assert_eq!(output(" 8\n").parse::<u32>().unwrap(), 8);
+        assert_eq!(output("0.25\n").parse::<f64>().unwrap(), 0.25);
+        let err = output("eight\n").parse::<u32>().err().unwrap();
+        let message = format!("{:#}", err);
+        assert!(message.starts_with(r#"Failed to parse process stdout "eight" (program = "tool""#));
+        assert!(message.ends_with("invalid digit found in string"), "{}", message);
+    }
+
+    #[test]
+    fn parse_lines_names_the_first_bad_line() {
+        assert_eq!(output("1\n\n 2\n3").parse_lines::<i64>().unwrap(), [1, 2, 3]);
+        let err = output("1\n2\nthree\nfour\n").parse_lines::<i64>().err().unwrap();
+        assert!(err.to_string().starts_with(r#"Failed to parse line 3 of process stdout "three""#));
+    }
+
+    #[test]
+    fn stderr_as_text() {
+        let mut out = output("");
+        out.stderr = b"warning: a\r\nwarning: b\n".to_vec();
+        assert_eq!(out.stderr_str().unwrap(), "warning: a\r\nwarning: b\n");
+        assert_eq!(out.stderr_lines(), ["warning: a", "warning: b"]);
+        out.stderr = b"bad \xff byte".to_vec();
+        assert_eq!(out.stderr_lossy(), "bad \u{FFFD} byte");
+        let err = out.stderr_str().err().unwrap();
+        assert!(matches!(cmd_error(&err), CmdError::InvalidUtf8 { stream: Stream::Stderr, .. }));
+        assert!(err.to_string().starts_with("Process stderr is not UTF-8 (program = \"tool\""));
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn killed_commands_report_the_signal() {
+        let mut child = cmd("sleep").arg("10").spawn().unwrap();
+        child.kill().unwrap();
+        let mut out = output("");
+        out.status = child.wait().unwrap();
+        assert_eq!((out.exit_code(), out.signal()), (None, Some(9)));
+        assert_eq!(StatusSummary(out.status).to_string(), "killed by signal 9 (SIGKILL)");
+        let err = shell("kill -TERM $$").exec().err().unwrap();
+        assert!(err.to_string().contains("killed by signal 15 (SIGTERM)"), "{:#}", err);
+        let err = shell("exit 137").exec_stdout_string().err().unwrap();
+        assert!(err.to_string().contains("exit code 137"), "{:#}", err);
+        assert_eq!(shell("exit 24").exec_status().unwrap().code(), Some(24));
+        let names: Vec<_> = [1, 2, 3, 9, 11, 15, 64].map(signal_name).into();
+        let expected = [Some("SIGHUP"), Some("SIGINT"), Some("SIGQUIT"), Some("SIGKILL")];
+        assert_eq!(names[..4], expected);
+        assert_eq!(names[4..], [Some("SIGSEGV"), Some("SIGTERM"), None]);
+    }
+
+    #[test]
+    fn exec_capture_policies() {
+        let invalid = || shell("printf 'a\\377b'");
+        let err = invalid().exec_capture(Utf8Policy::Strict).err().unwrap();
+        assert!(matches!(cmd_error(&err), CmdError::InvalidUtf8 { stream: Stream::Stdout, .. }));
+        let lossy = invalid().exec_capture(Utf8Policy::Lossy).unwrap();
+        assert_eq!(lossy.stdout_str(), Some("a\u{FFFD}b"));
+        assert!(lossy.stdout_was_lossy());
+        let bytes = invalid().exec_capture(Utf8Policy::Bytes).unwrap();
+        assert_eq!((bytes.stdout_str(), bytes.stdout_bytes()), (None, &b"a\xffb"[..]));
+        assert!(!bytes.stdout_was_lossy());
+        let output = bytes.into_output();
+        assert!(output.stdout_was_lossy && output.stdout == "a\u{FFFD}b");
+        let strict = shell("printf 'ok'").exec_capture(Utf8Policy::Strict).unwrap();
+        assert_eq!((strict.stdout_str(), strict.stdout_was_lossy()), (Some("ok"), false));
+        let clean = shell("printf 'ok'").exec_capture(Utf8Policy::Lossy).unwrap();
+        assert!(!clean.stdout_was_lossy());
+    }
+
+    #[test]
+    fn exec_stdout_string_limited_keeps_draining() {
+        let out = shell("yes | head -c 3000000; echo err >&2").exec_stdout_string_limited(1000);
+        let out = out.unwrap();
+        assert_eq!((out.stdout.len(), &out.stdout[..4]), (1000, "y\ny\n"));
+        let truncation = Truncation { stdout_total: 3_000_000, stderr_total: 4 };
+        assert_eq!(out.truncation, Some(truncation));
+        assert!(out.is_truncated());
+        let description = out.description().to_string();
+        assert!(description.contains("\" … (truncated, 2.9 MiB total), stderr = \"err\\n\""));
+
+        let out = shell("printf 'a\\303\\251'").exec_stdout_string_limited(2).unwrap();
+        assert_eq!(out.stdout, "a");
+        let out = shell("printf abc").exec_stdout_string_limited(3).unwrap();
+        assert!(!out.is_truncated());
+        assert!(!shell("printf abc").exec_stdout_string().unwrap().is_truncated());
+
+        let err = shell("head -c 2000000 /dev/zero | tr '\\0' a; exit 1")
+            .exec_stdout_string_limited(100)
+            .err()
+            .unwrap();
+        let message = err.to_string();
+        let stdout = format!("stdout = \"{}\" … (truncated, 1.9 MiB total)", "a".repeat(100));
+        assert!(message.contains(&stdout), "{}", message);
+    }
+
+    #[test]
+    fn embedded_output_is_cut_in_the_middle() {
+        let err = shell("head -c 10000 /dev/zero | tr '\\0' x; exit 1").exec_stdout_string();
+        let message = err.err().unwrap().to_string();
+        let (head, tail) = ("x".repeat(2048), "x".repeat(2048));
+        let embedded = format!("```\n{}\n… 5904 bytes omitted …\n{}\n```\n", head, tail);
+        assert!(message.contains(&embedded), "{}", message);
+        let err = shell("head -c 10000 /dev/zero; exit 1").exec_stdout_string().err().unwrap();
+        match cmd_error(&err) {
+            CmdError::UnsuccessfulExit { stdout, .. } => assert_eq!(stdout.len(), 10000),
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn powershell_scripts_are_passed_as_is() {
+        let script = r#"Write-Output "a b"; exit $LASTEXITCODE"#;
+        let ps = powershell(script);
+        assert!(["pwsh", "powershell.exe"].iter().any(|p| ps.get_program() == *p));
+        let args: Vec<&OsStr> = ps.get_args().collect();
+        assert_eq!(args, ["-NoProfile", "-NonInteractive", "-Command", script]);
+        let description = ps.description().to_string();
+        assert!(description.starts_with(&format!("script = {:?}, ", script)), "{}", description);
+        if which("pwsh").is_ok() {
+            let out = powershell("Write-Output hi").exec_stdout_string().unwrap();
+            assert_eq!(out.stdout.trim_end(), "hi");
+            let status = powershell("sh -c 'exit 7'; exit $LASTEXITCODE").exec_status().unwrap();
+            assert_eq!(status.code(), Some(7));
+        } else {
+            let err = powershell("Write-Output hi").exec_stdout_string().err().unwrap();
+            assert!(matches!(cmd_error(&err), CmdError::SpawnFailed { .. }), "{:#}", err);
+        }
+    }
+
+    #[test]
+    fn exec_stdout_string_ref_runs_the_same_command_again() {
+        let counter = test_support::temp_path("ref-counter");
+        let _ = std::fs::remove_file(&counter);
+        let mut poll =
+            shell(format!("echo x >> '{}'; wc -l < '{}'", counter.display(), counter.display()));
+        let first = poll.exec_stdout_string_ref().unwrap();
+        let second = poll.exec_stdout_string_ref().unwrap();
+        assert_eq!((first.stdout.trim(), second.stdout.trim()), ("1", "2"));
+        assert_eq!(second.command.get_program(), poll.get_program());
+        assert_eq!(
+            second.command.get_args().collect::<Vec<_>>(),
+            poll.get_args().collect::<Vec<_>>()
+        );
+        std::fs::remove_file(&counter).unwrap();
+
+        let mut failing = shell("echo once; exit 3");
+        for _ in 0..2 {
+            let err = failing.exec_stdout_string_ref().err().unwrap();
+            assert_eq!(cmd_error(&err).exit_code(), Some(3));
+            assert!(format!("{:#}", err).contains("once"), "{:#}", err);
+        }
+    }
+
+    #[test]
+    fn relative_directories_are_shown_joined_onto_the_working_directory() {
+        let mut relative = cmd("true").current_dir_("src").label("cwd-relative");
+        let text = test_support::decorations(|| relative.exec().unwrap());
+        let banner = labeled(&text, "cwd-relative")[0];
+        let absolute = std::env::current_dir().unwrap().join("src");
+        assert!(banner.contains(&format!("→ {}", absolute.display())), "{:?}", banner);
+        assert_eq!(effective_dir(&relative), Some(absolute));
+        assert_eq!(effective_dir(&cmd("true").current_dir_("/")), Some(PathBuf::from("/")));
+        assert_eq!(effective_dir(&cmd("true")), std::env::current_dir().ok());
+    }
+
+    #[test]
+    fn concurrent_banners_stay_whole() {
+        let text = test_support::decorations(|| {
+            let threads: Vec<_> = (0..16)
+                .map(|i| {
+                    std::thread::spawn(move || {
+                        cmd!("echo", format!("stress-{}", i)).label(format!("stress-{}", i)).exec()
+                    })
+                })
+                .collect();
+            for thread in threads {
+                thread.join().unwrap().unwrap();
+            }
+        });
+        for line in text.lines().filter(|line| line.contains("[stress-")) {
+            assert_eq!(line.matches("[stress-").count(), 1, "{:?}", line);
+        }
+        let cwd = std::env::current_dir().unwrap();
+        for i in 0..16 {
+            let lines = labeled(&text, &format!("stress-{}", i));
+            assert_eq!(lines.len(), 2, "{:?}", lines);
+            let banner = format!("{}\x1b[0m echo stress-{}", cwd.display(), i);
+            assert!(lines[0].ends_with(&banner), "{:?}", lines[0]);
+            assert!(
+                lines[1].contains(" END OUTPUT (") && lines[1].ends_with(") \x1b[0m"),
+                "{:?}",
+                lines[1]
+            );
+        }
+    }
+
+    #[test]
+    fn conditional_args() {
+        fn args(cmd: &Command) -> Vec<&str> {
+            cmd.get_args().map(|arg| arg.to_str().unwrap()).collect()
+        }
+        let build = |verbose: bool, target: Option<&str>, jobs: Option<u32>| {
+            cmd("cargo")
+                .arg_("build")
+                .arg_if(verbose, "--verbose")
+                .args_if(!verbose, ["--quiet", "--color=never"])
+                .arg_opt(target)
+                .arg_key_val_opt("--jobs", jobs.map(|jobs| jobs.to_string()))
+                .args_(["--", "x"])
+        };
+        assert_eq!(args(&build(true, None, None)), ["build", "--verbose", "--", "x"]);
+        assert_eq!(
+            args(&build(false, Some("app"), Some(4))),
+            ["build", "--quiet", "--color=never", "app", "--jobs", "4", "--", "x"]
+        );
+        assert_eq!(args(&build(true, Some("app"), None)), ["build", "--verbose", "app", "--", "x"]);
+        assert_eq!(
+            args(&build(true, None, Some(1))),
+            ["build", "--verbose", "--jobs", "1", "--", "x"]
+        );
+    }
+
+    #[test]
+    fn newline_normalization() {
+        let mut out = shell(r"printf '\357\273\277one\r\ntwo\nthree\rstill three\r\nfour'")
+            .exec_stdout_string()
+            .unwrap();
+        assert!(out.stdout.starts_with(BYTE_ORDER_MARK) && !out.stdout_was_normalized);
+        assert_eq!(out.stdout_unix_newlines(), "one\ntwo\nthree\rstill three\nfour");
+        let lines: Vec<_> = out.lines().collect();
+        assert_eq!(lines, ["one", "two", "three\rstill three", "four"]);
+        out.normalize_newlines();
+        assert!(out.stdout_was_normalized);
+        assert_eq!(out.stdout, "one\ntwo\nthree\rstill three\nfour");
+
+        let mut unix = shell(r"printf 'a\rb\n'").exec_stdout_string().unwrap();
+        assert!(matches!(unix.stdout_unix_newlines(), Cow::Borrowed("a\rb\n")));
+        unix.normalize_newlines();
+        assert!(!unix.stdout_was_normalized);
+        assert_eq!(unix_newlines("\u{feff}only a mark"), "only a mark");
+        let lines = shell(r"printf '\357\273\277a\r\nb\r\n'").exec_stdout_lines().unwrap();
+        assert_eq!(lines, ["a", "b"]);
+    }
+
+    #[test]
+    fn embedded_output_is_bounded_and_escaped() {
+        let script =
+            "head -c 3000000 /dev/zero | tr '\\0' y; echo; echo 'the real error' >&2; exit 1";
+        let err = shell(script).exec_stdout_string().err().unwrap();
+        let message = format!("{:#}", err);
+        assert!(message.len() < 3 * DEFAULT_EMBEDDED_OUTPUT_LIMIT, "{}", message.len());
+        assert!(message.contains("… 2995905 bytes omitted …"), "{}", &message[..200]);
+        assert!(message.contains("the real error"), "{}", message);
+        match cmd_error(&err) {
+            CmdError::UnsuccessfulExit { stdout, .. } => assert_eq!(stdout.len(), 3000001),
+            other => panic!("{:?}", other),
+        }
+
+        let err = shell(r"printf 'a\000b\033c\033[31md\r\ne\tf\rg\n'; exit 1").exec_stdout_string();
+        let message = format!("{:#}", err.err().unwrap());
+        assert!(message.contains("a\\0bd\r\ne\tf\\rg\n"), "{:?}", message);
+        assert!(!message.contains('\0') && !message.contains('\x1b'), "{:?}", message);
+        assert_eq!(escape_controls("bell\x07 nul\0"), "bell\\u{7} nul\\0");
+        assert!(matches!(escape_controls("line\r\nnext\ttab"), Cow::Borrowed(_)));
+    }
+
+    #[test]
+    fn human_sizes() {
+        assert_eq!(human_size(0), "0 B");
+        assert_eq!(human_size(1023), "1023 B");
+        assert_eq!(human_size(1024), "1.0 KiB");
+        assert_eq!(human_size(1536), "1.5 KiB");
+        assert_eq!(human_size(1024 * 1024 - 1), "1024.0 KiB");
+        assert_eq!(human_size(1024 * 1024), "1.0 MiB");
+        assert_eq!(human_size(5 * 1024 * 1024 * 1024), "5.0 GiB");
+        assert_eq!(human_size(u64::MAX), "16777216.0 TiB");
+    }
+
+    #[test]
+    fn line_counts() {
+        for (stdout, lines) in [("", 0), ("\n", 1), ("a", 1), ("a\n", 1), ("a\nb", 2), ("a\n\n", 2)]
+        {
+            assert_eq!(output(stdout).stdout_line_count(), lines, "{:?}", stdout);
+        }
+        let out = shell("printf '12345'; printf 'x\\ny\\n' >&2").exec_stdout_string().unwrap();
+        assert_eq!((out.stdout_bytes(), out.stderr_bytes()), (5, 4));
+        assert_eq!((out.stdout_line_count(), out.stderr_line_count()), (1, 2));
+    }
+
+    #[test]
+    fn end_output_can_sum_up_the_captured_output() {
+        let text = test_support::decorations(|| {
+            set_output_summary(true);
+            let script = "seq 14; head -c 1200 /dev/zero | tr '\\0' x";
+            shell(script).label("summary-on").exec_tee().unwrap();
+            shell("printf x").label("summary-one").exec_tee().unwrap();
+            shell("true").label("summary-exec").exec().unwrap();
+            set_output_summary(false);
+            shell("true").label("summary-off").exec_tee().unwrap();
+        });
+        let end = |label| strip_ansi(labeled(&text, label)[1]).into_owned();
+        assert!(end("summary-on").ends_with("s, stdout 15 lines / 1.2 KiB, stderr empty) "));
+        assert!(end("summary-one").ends_with("s, stdout 1 line / 1 B, stderr empty) "));
+        assert!(!end("summary-exec").contains("stdout"), "{}", end("summary-exec"));
+        assert!(!end("summary-off").contains("stdout"), "{}", end("summary-off"));
+    }
+}
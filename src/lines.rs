@@ -0,0 +1,206 @@
+use std::io::{self, BufRead, BufReader, Read};
+use std::process::{Command, ExitStatus};
+
+use anyhow::Context;
+
+use crate::backend::Process;
+use crate::child::{self, OutputReaders};
+use crate::execution::DetachedExecution;
+use crate::{exec_failed, invalid_utf8, CommandExt, Embedded, StatusSummary, Stream};
+
+/// The lines of stdout of a running command, returned by `exec_lines`, without their line
+/// terminators. Stderr is collected for the error of an unsuccessful exit, which is the last item.
+/// A line that is not UTF-8 is an error of its own, and the lines after it are still read.
+///
+/// Dropping the iterator before the end kills the command and waits for it.
+pub struct LineIter {
+    command: Command,
+    child: Process,
+    stdout: Option<BufReader<Box<dyn Read + Send>>>,
+    stderr: Option<OutputReaders>,
+    execution: Option<DetachedExecution>,
+    status: Option<ExitStatus>,
+    line_number: usize,
+}
+
+impl LineIter {
+    pub(crate) fn new(command: Command, mut child: Process, execution: DetachedExecution) -> Self {
+        let stdout = child.take_stdout().map(BufReader::new);
+        // Only stderr is left for the readers.
+        let stderr = child::spawn_output_readers(&mut child);
+        LineIter {
+            command,
+            child,
+            stdout,
+            stderr: Some(stderr),
+            execution: Some(execution),
+            status: None,
+            line_number: 0,
+        }
+    }
+
+    pub fn command(&self) -> &Command {
+        &self.command
+    }
+
+    /// Reads and drops the rest of stdout, and returns the exit status whether or not it is
+    /// successful.
+    pub fn finish(mut self) -> anyhow::Result<ExitStatus> {
+        if let Some(mut stdout) = self.stdout.take() {
+            let _ = io::copy(&mut stdout, &mut io::sink());
+        }
+        self.wait().map(|(status, _)| status)
+    }
+
+    // Waits for the command once stdout is done, returning its status and stderr.
+    fn wait(&mut self) -> anyhow::Result<(ExitStatus, Vec<u8>)> {
+        let status = self.child.wait();
+        let (_, stderr) = self.stderr.take().map(OutputReaders::join).unwrap_or_default();
+        if let Some(execution) = self.execution.take() {
+            execution.finish(&self.command, status.as_ref().ok(), Some((&[], &stderr)));
+        }
+        let status = status.with_context(|| {
+            format!("Failed to wait for command ({})", self.command.description())
+        })?;
+        self.status = Some(status);
+        Ok((status, stderr))
+    }
+
+    fn read_line(&mut self) -> Option<io::Result<Vec<u8>>> {
+        let stdout = self.stdout.as_mut()?;
+        let mut line = Vec::new();
+        loop {
+            return match stdout.read_until(b'\n', &mut line) {
+                Ok(0) => None,
+                Ok(_) => {
+                    if line.ends_with(b"\n") {
+                        line.pop();
+                        if line.ends_with(b"\r") {
+                            line.pop();
+                        }
+                    }
+                    Some(Ok(line))
+                }
+                Err(e) if e.kind() == io::ErrorKind::Interrupted => continue,
+                Err(e) => Some(Err(e)),
+            };
+        }
+    }
+}
+
+impl Iterator for LineIter {
+    type Item = anyhow::Result<String>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        match self.read_line() {
+            Some(Ok(line)) => {
+                self.line_number += 1;
+                Some(String::from_utf8(line).map_err(|e| {
+                    let context = format!(
+                        "Line {} of process stdout is not UTF-8 ({}, line = {:?})",
+                        self.line_number,
+                        self.command.description(),
+                        String::from_utf8_lossy(e.as_bytes()),
+                    );
+                    invalid_utf8(&self.command, Stream::Stdout, e.utf8_error(), context)
+                }))
+            }
+            Some(Err(e)) => {
+                self.stdout = None;
+                Some(Err(anyhow::Error::new(e).context(format!(
+                    "Failed to read process stdout ({})",
+                    self.command.description(),
+                ))))
+            }
+            None => {
+                self.stdout.take()?;
+                match self.wait() {
+                    Ok((status, _)) if status.success() => None,
+                    Ok((status, stderr)) => Some(Err(exec_failed(
+                        &self.command,
+                        status,
+                        &[],
+                        &stderr,
+                        format!(
+                            "Process did not exit successfully, {} ({}, stderr = {})",
+                            StatusSummary(status),
+                            self.command.description(),
+                            Embedded::new(&stderr),
+                        ),
+                    ))),
+                    Err(e) => Some(Err(e)),
+                }
+            }
+        }
+    }
+}
+
+impl Drop for LineIter {
+    fn drop(&mut self) {
+        if self.status.is_some() {
+            return;
+        }
+        if let Ok(None) = self.child.try_wait() {
+            let _ = self.child.kill();
+        }
+        let _ = self.child.wait();
+        drop(self.stdout.take());
+        if let Some(stderr) = self.stderr.take() {
+            stderr.join_timeout(child::ORPHANED_PIPE_TIMEOUT);
+        }
+        if let Some(execution) = self.execution.take() {
+            execution.finish(&self.command, None, None);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use crate::{shell, CmdError, CommandExt};
+
+    #[test]
+    fn lines_then_the_failure() {
+        let script = "printf 'one\\r\\ntwo\\n\\377\\nfour'; echo oops >&2; exit 2";
+        let mut lines = shell(script).exec_lines().unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "one");
+        assert_eq!(lines.next().unwrap().unwrap(), "two");
+        let err = lines.next().unwrap().unwrap_err();
+        assert!(err.to_string().starts_with("Line 3 of process stdout is not UTF-8"), "{}", err);
+        assert_eq!(lines.next().unwrap().unwrap(), "four");
+        let err = lines.next().unwrap().unwrap_err();
+        match err.downcast_ref::<CmdError>() {
+            Some(CmdError::UnsuccessfulExit { status, stderr, .. }) => {
+                assert_eq!((status.code(), &stderr[..]), (Some(2), &b"oops\n"[..]));
+            }
+            other => panic!("{:?}", other),
+        }
+        assert!(lines.next().is_none());
+    }
+
+    #[test]
+    fn lines_arrive_as_they_are_written() {
+        let mut lines = shell("echo first; exec sleep 10").exec_lines().unwrap();
+        let started = Instant::now();
+        assert_eq!(lines.next().unwrap().unwrap(), "first");
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn dropping_early_kills_and_reaps_the_command() {
+        let mut lines = crate::cmd("yes").exec_lines().unwrap();
+        assert_eq!(lines.next().unwrap().unwrap(), "y");
+        let pid = lines.child.id().to_string();
+        drop(lines);
+        // Not even a zombie is left.
+        let ps = crate::cmd("ps").args_(["-o", "stat=", "-p", &pid]);
+        assert!(ps.exec_stdout_string_allowing([1]).unwrap().stdout.trim().is_empty());
+    }
+
+    #[test]
+    fn finish_drains_stdout() {
+        let lines = shell("seq 100000; exit 3").exec_lines().unwrap();
+        assert_eq!(lines.finish().unwrap().code(), Some(3));
+    }
+}
@@ -0,0 +1,119 @@
+use std::fs::{File, OpenOptions};
+use std::io::Write;
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::{Duration, SystemTime};
+
+use anyhow::Context;
+
+use crate::json::JsonObject;
+use crate::timestamp::{self, Rfc3339};
+use crate::{effective_dir, redact, warn};
+
+const LOGGED_OUTPUT_LIMIT: usize = 1024;
+
+static LOG_FILE: Mutex<Option<File>> = Mutex::new(None);
+static LOG_WRITE_FAILED: AtomicBool = AtomicBool::new(false);
+
+/// Appends a JSON object to `path` for every command executed from now on.
+///
+/// Each line has the fields `program`, `args`, `cwd`, `started_at`, `finished_at` (RFC 3339, see
+/// `set_timestamps`), `duration_ms`, `success`, `exit_code` (null if the process was killed by a
+/// signal or could not be spawned), and, for capturing methods,
+/// `stdout` and `stderr` truncated to the first KiB. Failing to write a record never fails the
+/// command itself; a warning is printed to stderr the first time it happens.
+pub fn set_log_file(path: impl AsRef<Path>) -> anyhow::Result<()> {
+    let path = path.as_ref();
+    let file = OpenOptions::new()
+        .create(true)
+        .append(true)
+        .open(path)
+        .with_context(|| format!("Failed to open log file {:?}", path))?;
+    *LOG_FILE.lock().unwrap() = Some(file);
+    Ok(())
+}
+
+pub fn clear_log_file() {
+    *LOG_FILE.lock().unwrap() = None;
+}
+
+pub(crate) fn log_execution(
+    cmd: &Command,
+    duration: Duration,
+    status: Option<&ExitStatus>,
+    output: Option<(&[u8], &[u8])>,
+) {
+    let mut log_file = LOG_FILE.lock().unwrap();
+    let Some(file) = log_file.as_mut() else {
+        return;
+    };
+    let args: Vec<_> =
+        cmd.get_args().map(|arg| redact::scrub(arg).to_string_lossy().into_owned()).collect();
+    let cwd = effective_dir(cmd);
+    let cwd = cwd.as_ref().map(|cwd| redact::scrub(cwd.as_os_str()).to_string_lossy().into_owned());
+    let finished_at = SystemTime::now();
+    let started_at = finished_at.checked_sub(duration).unwrap_or(finished_at);
+    let format = timestamp::log_format();
+    let mut record = JsonObject::new();
+    record
+        .str("program", &redact::scrub(cmd.get_program()).to_string_lossy())
+        .str_array("args", args.iter().map(|arg| &arg[..]))
+        .opt_str("cwd", cwd.as_deref())
+        .str("started_at", &Rfc3339::new(started_at, format).to_string())
+        .str("finished_at", &Rfc3339::new(finished_at, format).to_string())
+        .num("duration_ms", duration.as_secs_f64() * 1000.0)
+        .bool("success", status.is_some_and(ExitStatus::success))
+        .opt_num("exit_code", status.and_then(ExitStatus::code));
+    if let Some((stdout, stderr)) = output {
+        record.str("stdout", &truncated_lossy(stdout)).str("stderr", &truncated_lossy(stderr));
+    }
+    let mut line = record.finish();
+    line.push('\n');
+    if let Err(e) = file.write_all(line.as_bytes()) {
+        if !LOG_WRITE_FAILED.swap(true, Ordering::SeqCst) {
+            warn(format_args!("Failed to write to the command log file: {}", e));
+        }
+    }
+}
+
+pub(crate) fn truncated_lossy(bytes: &[u8]) -> String {
+    String::from_utf8_lossy(&bytes[..bytes.len().min(LOGGED_OUTPUT_LIMIT)]).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{serial, temp_path};
+    use crate::{shell, CommandExt};
+
+    #[test]
+    fn every_run_appends_one_record() {
+        let path = temp_path("command-log");
+        let _serial = serial();
+        set_log_file(&path).unwrap();
+        std::thread::scope(|s| {
+            for i in 0..8 {
+                s.spawn(move || shell(format!("exit {} # logged", i % 2)).exec_status().unwrap());
+            }
+        });
+        shell("echo captured # logged").exec_stdout_string().unwrap();
+        clear_log_file();
+        let log = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        let records: Vec<_> = log.lines().filter(|line| line.contains("# logged")).collect();
+        assert_eq!(records.len(), 9, "{}", log);
+        assert!(records.iter().all(|r| r.starts_with(r#"{"program":"sh""#) && r.ends_with('}')));
+        let failed = records.iter().filter(|r| r.contains(r#""exit_code":1"#)).count();
+        assert_eq!(failed, 4, "{}", log);
+        let captured = records.iter().find(|r| r.contains("echo captured")).unwrap();
+        assert!(captured.contains(r#""stdout":"captured\n""#), "{}", captured);
+    }
+
+    #[test]
+    fn output_is_cut_to_the_first_kib() {
+        assert_eq!(truncated_lossy(&[b'x'; 3000]).len(), LOGGED_OUTPUT_LIMIT);
+        assert_eq!(truncated_lossy(b"short"), "short");
+    }
+}
@@ -0,0 +1,193 @@
+use std::collections::BTreeMap;
+use std::path::Path;
+use std::process::ExitStatus;
+use std::time::{Duration, Instant};
+
+use anyhow::Context;
+use serde::de::IgnoredAny;
+use serde::Deserialize;
+
+use crate::{CmdError, CommandExt, CommandSpec};
+
+const STEP_FIELDS: &[&str] =
+    &["name", "program", "args", "cwd", "env", "allow_failure", "timeout_secs"];
+
+/// Named steps to run in order, read with serde from any format, such as JSON with `from_json` or
+/// TOML with the `toml` crate:
+///
+/// ```toml
+/// [[steps]]
+/// name = "build"
+/// program = "cargo"
+/// args = ["build", "--release"]
+/// cwd = "tools"
+/// env = { RUSTFLAGS = "-Dwarnings" }
+/// allow_failure = false
+/// timeout_secs = 600
+/// ```
+///
+/// Only `name` and `program` are required. Unknown fields are an error naming the step.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Manifest {
+    pub steps: Vec<Step>,
+}
+
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(try_from = "StepFields")]
+pub struct Step {
+    pub name: String,
+    pub command: CommandSpec,
+    /// A failure of this step is reported, and the next steps are still run.
+    pub allow_failure: bool,
+    pub timeout: Option<Duration>,
+}
+
+#[derive(Deserialize)]
+struct StepFields {
+    name: String,
+    program: String,
+    #[serde(default)]
+    args: Vec<String>,
+    cwd: Option<String>,
+    #[serde(default)]
+    env: BTreeMap<String, String>,
+    #[serde(default)]
+    allow_failure: bool,
+    timeout_secs: Option<f64>,
+    #[serde(flatten)]
+    unknown: BTreeMap<String, IgnoredAny>,
+}
+
+impl TryFrom<StepFields> for Step {
+    type Error = String;
+
+    fn try_from(fields: StepFields) -> Result<Self, String> {
+        if let Some(field) = fields.unknown.keys().next() {
+            return Err(format!(
+                "unknown field `{}` in step {:?}, expected one of {}",
+                field,
+                fields.name,
+                STEP_FIELDS
+                    .iter()
+                    .map(|field| format!("`{}`", field))
+                    .collect::<Vec<_>>()
+                    .join(", "),
+            ));
+        }
+        let timeout =
+            match fields.timeout_secs {
+                Some(secs) => Some(Duration::try_from_secs_f64(secs).map_err(|e| {
+                    format!("invalid `timeout_secs` in step {:?}: {}", fields.name, e)
+                })?),
+                None => None,
+            };
+        let mut command = CommandSpec::new(fields.program).args(fields.args);
+        command.cwd = fields.cwd;
+        command.envs = fields.env.into_iter().map(|(key, value)| (key, Some(value))).collect();
+        Ok(Step { name: fields.name, command, allow_failure: fields.allow_failure, timeout })
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct StepReport {
+    pub name: String,
+    /// `None` if the step could not be spawned or timed out.
+    pub status: Option<ExitStatus>,
+    pub duration: Duration,
+    pub success: bool,
+}
+
+impl Manifest {
+    pub fn from_json(json: &str) -> anyhow::Result<Self> {
+        serde_json::from_str(json).context("Invalid command manifest")
+    }
+
+    pub fn from_json_file(path: impl AsRef<Path>) -> anyhow::Result<Self> {
+        let path = path.as_ref();
+        let json = std::fs::read_to_string(path)
+            .with_context(|| format!("Failed to read command manifest {}", path.display()))?;
+        serde_json::from_str(&json)
+            .with_context(|| format!("Invalid command manifest {}", path.display()))
+    }
+
+    /// Runs the steps in order through `exec`, or `exec_with_timeout` for a step with a timeout,
+    /// and returns a report for each. A failing step stops the run with its error, unless it
+    /// allows failure.
+    pub fn run(&self) -> anyhow::Result<Vec<StepReport>> {
+        let total = self.steps.len();
+        let mut reports = Vec::with_capacity(total);
+        for (index, step) in self.steps.iter().enumerate() {
+            let mut cmd = step.command.to_command();
+            let start = Instant::now();
+            let result = match step.timeout {
+                Some(timeout) => cmd.exec_with_timeout(timeout),
+                None => cmd.exec(),
+            };
+            let duration = start.elapsed();
+            let status = match &result {
+                Ok(()) => Some(ExitStatus::default()),
+                Err(e) => e.downcast_ref::<CmdError>().and_then(CmdError::status),
+            };
+            let success = result.is_ok();
+            reports.push(StepReport { name: step.name.clone(), status, duration, success });
+            if let Err(e) = result {
+                if !step.allow_failure {
+                    return Err(e.context(format!(
+                        "Step {:?} ({} of {}) failed",
+                        step.name,
+                        index + 1,
+                        total
+                    )));
+                }
+            }
+        }
+        Ok(reports)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fixture() -> std::path::PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("tests/fixtures/manifest.json")
+    }
+
+    #[test]
+    fn steps_run_in_order_until_one_fails() {
+        let manifest = Manifest::from_json_file(fixture()).unwrap();
+        assert_eq!(manifest.steps[0].command.envs["GREETING"].as_deref(), Some("hello"));
+        assert_eq!(manifest.steps[3].timeout, Some(Duration::from_millis(100)));
+        let err = manifest.run().unwrap_err();
+        let message = format!("{:#}", err);
+        assert!(message.starts_with(r#"Step "broken" (5 of 6) failed: "#), "{}", message);
+        assert_eq!(err.downcast_ref::<CmdError>().unwrap().exit_code(), Some(4));
+
+        let mut manifest = manifest;
+        manifest.steps[4].allow_failure = true;
+        let reports = manifest.run().unwrap();
+        let names: Vec<_> = reports.iter().map(|report| &report.name[..]).collect();
+        assert_eq!(names, ["greet", "in root", "flaky", "slow", "broken", "never"]);
+        let successes: Vec<_> = reports.iter().map(|report| report.success).collect();
+        assert_eq!(successes, [true, true, false, false, false, true]);
+        let codes: Vec<_> =
+            reports.iter().map(|report| report.status.and_then(|s| s.code())).collect();
+        assert_eq!(codes, [Some(0), Some(0), Some(3), None, Some(4), Some(0)]);
+        assert!(reports[3].duration < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn unknown_fields_name_their_step() {
+        let json = r#"{"steps": [{"name": "build", "program": "make", "arg": ["all"]}]}"#;
+        let message = format!("{:#}", Manifest::from_json(json).unwrap_err());
+        assert!(
+            message.contains(r#"unknown field `arg` in step "build", expected one of `name`, "#)
+        );
+        let json = r#"{"steps": [], "extra": 1}"#;
+        assert!(format!("{:#}", Manifest::from_json(json).unwrap_err()).contains("`extra`"));
+        let json = r#"{"steps": [{"name": "wait", "program": "sleep", "timeout_secs": -1}]}"#;
+        let message = format!("{:#}", Manifest::from_json(json).unwrap_err());
+        assert!(message.contains(r#"invalid `timeout_secs` in step "wait""#), "{}", message);
+    }
+}
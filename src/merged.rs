@@ -0,0 +1,101 @@
+use std::process::ExitStatus;
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use crate::Stream;
+
+/// Stdout and stderr of `exec_capture_merged` as one transcript. Chunks are in the order their
+/// reader threads got to them, which is close to but not exactly the order the command wrote
+/// them in: writes to the two pipes that are very close together can come out swapped, and a
+/// single write may be split over several chunks. Consecutive chunks of the same stream are
+/// joined.
+#[derive(Debug, Clone)]
+pub struct MergedOutput {
+    pub status: ExitStatus,
+    pub chunks: Vec<(Stream, Vec<u8>)>,
+    pub duration: Duration,
+}
+
+impl MergedOutput {
+    /// Everything the command wrote, interleaved, with invalid UTF-8 replaced.
+    pub fn transcript_lossy(&self) -> String {
+        String::from_utf8_lossy(&self.transcript()).into_owned()
+    }
+
+    pub fn stdout(&self) -> Vec<u8> {
+        self.stream(Stream::Stdout)
+    }
+
+    pub fn stderr(&self) -> Vec<u8> {
+        self.stream(Stream::Stderr)
+    }
+
+    pub(crate) fn transcript(&self) -> Vec<u8> {
+        self.chunks.iter().flat_map(|(_, chunk)| chunk.iter().copied()).collect()
+    }
+
+    fn stream(&self, stream: Stream) -> Vec<u8> {
+        self.chunks
+            .iter()
+            .filter(|(tag, _)| *tag == stream)
+            .flat_map(|(_, chunk)| chunk.iter().copied())
+            .collect()
+    }
+}
+
+pub(crate) type SharedChunks = Arc<Mutex<Vec<(Stream, Vec<u8>)>>>;
+
+pub(crate) fn push_chunk(chunks: &SharedChunks, stream: Stream, chunk: &[u8]) {
+    let mut chunks = chunks.lock().unwrap();
+    match chunks.last_mut() {
+        Some((tag, last)) if *tag == stream => last.extend_from_slice(chunk),
+        _ => chunks.push((stream, chunk.to_vec())),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{shell, CmdError, CommandExt};
+
+    // Pauses between the writes keep the reader threads from swapping them.
+    const ALTERNATING: &str = "for i in 1 2 3; do echo out$i; sleep 0.05; echo err$i >&2; \
+                               sleep 0.05; done";
+
+    #[test]
+    fn chunks_alternate_in_arrival_order() {
+        let out = shell(ALTERNATING).exec_capture_merged().unwrap();
+        let tags: Vec<Stream> = out.chunks.iter().map(|(tag, _)| *tag).collect();
+        assert_eq!(tags, [Stream::Stdout, Stream::Stderr].repeat(3));
+        assert_eq!(out.transcript_lossy(), "out1\nerr1\nout2\nerr2\nout3\nerr3\n");
+        assert_eq!(out.stdout(), b"out1\nout2\nout3\n");
+        assert_eq!(out.stderr(), b"err1\nerr2\nerr3\n");
+    }
+
+    #[test]
+    fn consecutive_chunks_of_a_stream_are_joined() {
+        let chunks = SharedChunks::default();
+        push_chunk(&chunks, Stream::Stdout, b"a");
+        push_chunk(&chunks, Stream::Stdout, b"b");
+        push_chunk(&chunks, Stream::Stderr, b"c");
+        let chunks = chunks.lock().unwrap();
+        assert_eq!(*chunks, [(Stream::Stdout, b"ab".to_vec()), (Stream::Stderr, b"c".to_vec())]);
+    }
+
+    #[test]
+    fn failures_embed_the_transcript() {
+        let err = shell(format!("{}; exit 1", ALTERNATING)).exec_capture_merged().unwrap_err();
+        let message = err.to_string();
+        let transcript = r#"output = "out1\nerr1\nout2\nerr2\nout3\nerr3\n")"#;
+        assert!(message.ends_with(transcript), "{}", message);
+        match err.downcast_ref::<CmdError>() {
+            Some(CmdError::UnsuccessfulExit { stdout, stderr, .. }) => {
+                assert_eq!(
+                    (&stdout[..], &stderr[..]),
+                    (&b"out1\nout2\nout3\n"[..], &b"err1\nerr2\nerr3\n"[..])
+                );
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,372 @@
+use std::cell::RefCell;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicU32, Ordering};
+use std::sync::{Arc, Mutex};
+use std::time::{Duration, Instant};
+
+use crate::CommandExt;
+
+type ArgsMatcher = Box<dyn Fn(&[&OsStr]) -> bool + Send + Sync>;
+type UnmatchedMessage = Box<dyn Fn(&Command) -> String + Send>;
+pub(crate) type SharedState = Arc<Mutex<MockState>>;
+
+thread_local! {
+    static CURRENT: RefCell<Option<SharedState>> = const { RefCell::new(None) };
+}
+
+// Fake process ids, well above what real ones usually reach.
+static NEXT_ID: AtomicU32 = AtomicU32::new(4_000_000);
+
+/// A scripted command for `MockRunner`: which invocations it matches and what they return.
+pub struct MockCommand {
+    program: OsString,
+    args: Option<ArgsMatcher>,
+    times: Option<usize>,
+    required: bool,
+    exit_code: i32,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+    delay: Duration,
+}
+
+impl MockCommand {
+    /// Matches commands whose program is `program`, or a path whose file name is `program`.
+    pub fn new(program: impl AsRef<OsStr>) -> Self {
+        MockCommand {
+            program: program.as_ref().to_os_string(),
+            args: None,
+            times: None,
+            required: true,
+            exit_code: 0,
+            stdout: Vec::new(),
+            stderr: Vec::new(),
+            delay: Duration::ZERO,
+        }
+    }
+
+    /// Only matches exactly these arguments; by default any arguments match.
+    pub fn args<I, S>(self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        let expected: Vec<OsString> = args.into_iter().map(|a| a.as_ref().to_os_string()).collect();
+        self.args_matching(move |args| {
+            args.iter().copied().eq(expected.iter().map(OsString::as_os_str))
+        })
+    }
+
+    pub fn args_matching<F>(self, f: F) -> Self
+    where
+        F: Fn(&[&OsStr]) -> bool + Send + Sync + 'static,
+    {
+        MockCommand { args: Some(Box::new(f)), ..self }
+    }
+
+    /// Expects exactly `times` matching invocations. By default any number is accepted, as long
+    /// as there is at least one.
+    pub fn times(self, times: usize) -> Self {
+        MockCommand { times: Some(times), ..self }
+    }
+
+    pub fn exit_code(self, exit_code: i32) -> Self {
+        MockCommand { exit_code, ..self }
+    }
+
+    pub fn stdout(self, stdout: impl Into<Vec<u8>>) -> Self {
+        MockCommand { stdout: stdout.into(), ..self }
+    }
+
+    pub fn stderr(self, stderr: impl Into<Vec<u8>>) -> Self {
+        MockCommand { stderr: stderr.into(), ..self }
+    }
+
+    /// How long the mocked process runs before exiting, for exercising timeouts.
+    pub fn delay(self, delay: Duration) -> Self {
+        MockCommand { delay, ..self }
+    }
+
+    fn matches(&self, cmd: &Command, calls: usize) -> bool {
+        let program = cmd.get_program();
+        let program_matches =
+            program == self.program || Path::new(program).file_name() == Some(&self.program);
+        let args: Vec<&OsStr> = cmd.get_args().collect();
+        program_matches
+            && self.times.is_none_or(|times| calls < times)
+            && self.args.as_ref().is_none_or(|f| f(&args))
+    }
+
+    // Lets the guard be dropped without this command having been called.
+    pub(crate) fn optional(self) -> Self {
+        MockCommand { required: false, ..self }
+    }
+
+    fn is_satisfied(&self, calls: usize) -> bool {
+        if !self.required {
+            return true;
+        }
+        match self.times {
+            Some(times) => calls == times,
+            None => calls > 0,
+        }
+    }
+}
+
+/// Stands in for real processes while the guard returned by `install` is alive: every
+/// `CommandExt` method, `Pipeline`, and `ParallelRunner` started from the installing thread
+/// gets the result of the first matching `MockCommand` instead of spawning anything. The async
+/// methods of the `tokio` feature are not covered.
+///
+/// A command that matches nothing panics with its description, and dropping the guard panics if
+/// an expectation was not met.
+#[derive(Default)]
+pub struct MockRunner {
+    commands: Vec<MockCommand>,
+}
+
+impl MockRunner {
+    pub fn new() -> Self {
+        MockRunner::default()
+    }
+
+    pub fn expect(self, command: MockCommand) -> Self {
+        let mut commands = self.commands;
+        commands.push(command);
+        MockRunner { commands }
+    }
+
+    pub fn install(self) -> MockGuard {
+        self.install_with(None)
+    }
+
+    // `unmatched_message` replaces the description in the panic for a command that matches
+    // nothing.
+    pub(crate) fn install_with(self, unmatched_message: Option<UnmatchedMessage>) -> MockGuard {
+        let commands = self.commands.into_iter().map(|c| (c, 0)).collect();
+        let state = MockState { commands, unmatched_message };
+        let state = Arc::new(Mutex::new(state));
+        let previous = CURRENT.with(|current| current.replace(Some(Arc::clone(&state))));
+        MockGuard { state, previous }
+    }
+}
+
+#[must_use = "the mock is uninstalled as soon as the guard is dropped"]
+pub struct MockGuard {
+    state: SharedState,
+    previous: Option<SharedState>,
+}
+
+impl Drop for MockGuard {
+    fn drop(&mut self) {
+        CURRENT.with(|current| *current.borrow_mut() = self.previous.take());
+        if std::thread::panicking() {
+            return;
+        }
+        let state = self.state.lock().unwrap();
+        let unmet: Vec<String> = state
+            .commands
+            .iter()
+            .filter(|(command, calls)| !command.is_satisfied(*calls))
+            .map(|(command, calls)| match command.times {
+                Some(times) => {
+                    format!("{:?} expected {} time(s), called {}", command.program, times, calls)
+                }
+                None => format!("{:?} never called", command.program),
+            })
+            .collect();
+        if !unmet.is_empty() {
+            panic!("unmet mock expectations: {}", unmet.join("; "));
+        }
+    }
+}
+
+pub(crate) struct MockState {
+    commands: Vec<(MockCommand, usize)>,
+    unmatched_message: Option<UnmatchedMessage>,
+}
+
+pub(crate) fn current() -> Option<SharedState> {
+    CURRENT.with(|current| current.borrow().clone())
+}
+
+// Makes `state` current on this thread until the returned value is dropped.
+pub(crate) fn enter(state: Option<SharedState>) -> impl Drop {
+    struct Restore(Option<SharedState>);
+    impl Drop for Restore {
+        fn drop(&mut self) {
+            CURRENT.with(|current| *current.borrow_mut() = self.0.take());
+        }
+    }
+    Restore(CURRENT.with(|current| current.replace(state)))
+}
+
+// The scripted process for `cmd` if a mock is installed on this thread. Panics if none of its
+// commands match.
+pub(crate) fn spawn(cmd: &Command) -> Option<MockProcess> {
+    let state = current()?;
+    let mut state = state.lock().unwrap();
+    let Some((command, calls)) =
+        state.commands.iter_mut().find(|(command, calls)| command.matches(cmd, *calls))
+    else {
+        let message = match &state.unmatched_message {
+            Some(message) => message(cmd),
+            None => format!("unexpected command: {}", cmd.description()),
+        };
+        drop(state);
+        panic!("{}", message);
+    };
+    *calls += 1;
+    Some(MockProcess {
+        id: NEXT_ID.fetch_add(1, Ordering::SeqCst),
+        status: exit_status(command.exit_code),
+        stdout: Some(command.stdout.clone()),
+        stderr: Some(command.stderr.clone()),
+        exits_at: Instant::now() + command.delay,
+        killed: false,
+    })
+}
+
+pub(crate) struct MockProcess {
+    id: u32,
+    status: ExitStatus,
+    stdout: Option<Vec<u8>>,
+    stderr: Option<Vec<u8>>,
+    exits_at: Instant,
+    killed: bool,
+}
+
+impl MockProcess {
+    pub(crate) fn id(&self) -> u32 {
+        self.id
+    }
+
+    // Input is accepted and discarded.
+    pub(crate) fn take_stdin(&mut self) -> Option<Box<dyn Write + Send>> {
+        Some(Box::new(io::sink()))
+    }
+
+    pub(crate) fn take_stdout(&mut self) -> Option<Box<dyn Read + Send>> {
+        self.stdout.take().map(|bytes| Box::new(io::Cursor::new(bytes)) as _)
+    }
+
+    pub(crate) fn take_stderr(&mut self) -> Option<Box<dyn Read + Send>> {
+        self.stderr.take().map(|bytes| Box::new(io::Cursor::new(bytes)) as _)
+    }
+
+    pub(crate) fn wait(&mut self) -> ExitStatus {
+        if !self.killed {
+            std::thread::sleep(self.exits_at.saturating_duration_since(Instant::now()));
+        }
+        self.status
+    }
+
+    pub(crate) fn try_wait(&mut self) -> Option<ExitStatus> {
+        (self.killed || Instant::now() >= self.exits_at).then_some(self.status)
+    }
+
+    pub(crate) fn kill(&mut self) {
+        if !self.killed && Instant::now() < self.exits_at {
+            self.killed = true;
+            self.status = killed_status();
+        }
+    }
+}
+
+#[cfg(unix)]
+fn exit_status(code: i32) -> ExitStatus {
+    std::os::unix::process::ExitStatusExt::from_raw((code & 0xff) << 8)
+}
+
+#[cfg(windows)]
+fn exit_status(code: i32) -> ExitStatus {
+    std::os::windows::process::ExitStatusExt::from_raw(code as u32)
+}
+
+#[cfg(unix)]
+fn killed_status() -> ExitStatus {
+    // SIGKILL
+    std::os::unix::process::ExitStatusExt::from_raw(9)
+}
+
+#[cfg(windows)]
+fn killed_status() -> ExitStatus {
+    std::os::windows::process::ExitStatusExt::from_raw(1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cmd, CmdError};
+
+    #[test]
+    fn mocked_output_looks_real() {
+        let _mock = MockRunner::new()
+            .expect(MockCommand::new("git").args(["rev-parse", "HEAD"]).stdout("abc123\n"))
+            .expect(MockCommand::new("git").stderr("fatal: nope\n").exit_code(128))
+            .install();
+        let out = cmd("/usr/bin/git").args_(["rev-parse", "HEAD"]).exec_stdout_string().unwrap();
+        assert_eq!((out.stdout.as_str(), out.status.code()), ("abc123\n", Some(0)));
+        assert_eq!(out.command.get_program(), "/usr/bin/git");
+        let err = cmd("git").arg_("push").exec_stdout_string().err().unwrap();
+        match err.downcast_ref::<CmdError>() {
+            Some(CmdError::UnsuccessfulExit { status, stderr, .. }) => {
+                assert_eq!((status.code(), &stderr[..]), (Some(128), &b"fatal: nope\n"[..]));
+            }
+            other => panic!("{:?}", other),
+        }
+    }
+
+    #[test]
+    fn delays_run_into_timeouts() {
+        let _mock = MockRunner::new()
+            .expect(MockCommand::new("slow").delay(Duration::from_secs(10)).times(1))
+            .install();
+        let started = Instant::now();
+        let err = cmd("slow").exec_with_timeout(Duration::from_millis(50)).unwrap_err();
+        assert!(matches!(err.downcast_ref::<CmdError>(), Some(CmdError::TimedOut { .. })));
+        assert!(started.elapsed() < Duration::from_secs(5));
+    }
+
+    #[test]
+    fn mocks_are_per_thread() {
+        let _mock = MockRunner::new().expect(MockCommand::new("true").exit_code(1)).install();
+        assert!(cmd("true").exec_status().unwrap().code() == Some(1));
+        let real = std::thread::spawn(|| cmd("true").exec_status().unwrap().code());
+        assert_eq!(real.join().unwrap(), Some(0));
+    }
+
+    #[test]
+    #[should_panic(expected = r#"unexpected command: program = "cargo", args = ["build"]"#)]
+    fn unexpected_commands_panic_with_their_description() {
+        let _mock = MockRunner::new().expect(MockCommand::new("git").optional()).install();
+        let _ = cmd("cargo").arg_("build").exec_stdout_string();
+    }
+
+    #[test]
+    #[should_panic(expected = "unmet mock expectations: \"git\" expected 2 time(s), called 1")]
+    fn unmet_expectations_panic_on_drop() {
+        let _mock = MockRunner::new().expect(MockCommand::new("git").times(2)).install();
+        let _ = cmd("git").exec_stdout_string();
+    }
+
+    #[test]
+    fn mocked_stdout_goes_to_the_file() {
+        let _mock = MockRunner::new()
+            .expect(MockCommand::new("git").args(["log"]).stdout("abc123\n"))
+            .expect(MockCommand::new("git").args(["show"]).stdout("def456\n"))
+            .expect(MockCommand::new("git").args(["push"]).stderr("fatal: nope\n").exit_code(128))
+            .install();
+        let path = crate::test_support::temp_path("mocked-stdout");
+        cmd("git").arg_("log").exec_stdout_to_file(&path).unwrap();
+        cmd("git").arg_("show").exec_stdout_to_file_append(&path).unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "abc123\ndef456\n");
+        let err = cmd("git").arg_("push").exec_stdout_to_file(&path).unwrap_err();
+        match err.downcast_ref::<CmdError>() {
+            Some(CmdError::UnsuccessfulExit { stderr, .. }) => assert_eq!(stderr, b"fatal: nope\n"),
+            other => panic!("{:?}", other),
+        }
+    }
+}
@@ -0,0 +1,142 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Instant;
+
+use crate::{
+    backend, decoration_stream, scope, write_banner_annotated, write_end_output_with_summary,
+    CommandExt, ConfiguredCommand, Output,
+};
+
+pub fn run_parallel<I>(commands: I, max_concurrency: usize) -> Vec<anyhow::Result<Output>>
+where
+    I: IntoIterator,
+    I::Item: Into<ConfiguredCommand>,
+{
+    ParallelRunner::new(max_concurrency).run(commands)
+}
+
+/// Runs independent commands on a small pool of threads, capturing each one's output like
+/// `exec_stdout_string`. Results are returned in input order. The commands can also be given as
+/// `CommandSpec`s or references to them.
+pub struct ParallelRunner {
+    max_concurrency: usize,
+    fail_fast: bool,
+}
+
+impl ParallelRunner {
+    pub fn new(max_concurrency: usize) -> Self {
+        ParallelRunner { max_concurrency: max_concurrency.max(1), fail_fast: false }
+    }
+
+    /// Stops launching new commands after the first failure. Commands already running are
+    /// allowed to finish; the ones never started report an error.
+    pub fn fail_fast(self, fail_fast: bool) -> Self {
+        ParallelRunner { fail_fast, ..self }
+    }
+
+    pub fn run<I>(&self, commands: I) -> Vec<anyhow::Result<Output>>
+    where
+        I: IntoIterator,
+        I::Item: Into<ConfiguredCommand>,
+    {
+        let commands: Vec<ConfiguredCommand> = commands.into_iter().map(Into::into).collect();
+        let total = commands.len();
+        let queue = Mutex::new(commands.into_iter().enumerate());
+        let results: Mutex<Vec<Option<anyhow::Result<Output>>>> =
+            Mutex::new((0..total).map(|_| None).collect());
+        let failed = AtomicBool::new(false);
+        let backend = backend::context();
+        let scopes = scope::current();
+        std::thread::scope(|s| {
+            for _ in 0..self.max_concurrency.min(total) {
+                s.spawn(|| {
+                    let _entered = scopes.clone().enter();
+                    backend.run(|| loop {
+                        let Some((i, cmd)) = queue.lock().unwrap().next() else {
+                            break;
+                        };
+                        let result = if self.fail_fast && failed.load(Ordering::SeqCst) {
+                            Err(anyhow::anyhow!(
+                                "Command not started because an earlier command failed ({})",
+                                cmd.description(),
+                            ))
+                        } else {
+                            let result = run_one(cmd, i, total);
+                            if result.is_err() {
+                                failed.store(true, Ordering::SeqCst);
+                            }
+                            result
+                        };
+                        results.lock().unwrap()[i] = Some(result);
+                    })
+                });
+            }
+        });
+        results.into_inner().unwrap().into_iter().map(Option::unwrap).collect()
+    }
+}
+
+fn run_one(cmd: ConfiguredCommand, i: usize, total: usize) -> anyhow::Result<Output> {
+    let annotation = format!("{}/{}", i + 1, total);
+    // The command is moved into its `Output`, so the marker is written for a copy.
+    let copy = cmd.copy();
+    let mut stderr = decoration_stream();
+    write_banner_annotated(&mut stderr, &cmd, Some(&annotation))?;
+    let start = Instant::now();
+    let result = cmd.exec_stdout_string();
+    let output = result.as_ref().ok().map(|out| (out.stdout.as_bytes(), &out.stderr[..]));
+    write_end_output_with_summary(
+        &mut stderr,
+        &copy,
+        result.is_ok(),
+        Some(&annotation),
+        start.elapsed(),
+        output,
+    );
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::{Duration, Instant};
+
+    use super::*;
+    use crate::shell;
+    use crate::test_support::decorations;
+
+    #[test]
+    fn results_keep_the_input_order() {
+        let commands = ["sleep 0.2; echo 0", "echo 1", "sleep 0.1; echo 2"].map(shell);
+        let stdouts: Vec<_> =
+            run_parallel(commands, 3).into_iter().map(|out| out.unwrap().stdout).collect();
+        assert_eq!(stdouts, ["0\n", "1\n", "2\n"]);
+    }
+
+    #[test]
+    fn at_most_max_concurrency_commands_run_at_once() {
+        let start = Instant::now();
+        let results = run_parallel((0..4).map(|_| shell("sleep 0.2")), 2);
+        assert!(results.iter().all(Result::is_ok));
+        assert!(start.elapsed() >= Duration::from_millis(400), "{:?}", start.elapsed());
+    }
+
+    #[test]
+    fn fail_fast_skips_the_commands_not_yet_started() {
+        let commands = [shell("exit 1"), shell("true # never started")];
+        let results = ParallelRunner::new(1).fail_fast(true).run(commands);
+        let skipped = format!("{:#}", results[1].as_ref().err().unwrap());
+        assert!(results[0].is_err());
+        assert!(skipped.starts_with("Command not started because an earlier command failed"));
+    }
+
+    #[test]
+    fn banners_of_concurrent_commands_stay_on_their_own_lines() {
+        let text = decorations(|| {
+            run_parallel((0..8).map(|i| shell(format!("echo {}", i)).label("parallel")), 8);
+        });
+        let lines: Vec<_> = text.lines().filter(|line| line.contains("[parallel]")).collect();
+        assert_eq!(lines.len(), 16, "{}", text);
+        assert!(lines.iter().all(|line| line.matches("[parallel]").count() == 1), "{}", text);
+        assert_eq!(lines.iter().filter(|line| line.contains("END OUTPUT")).count(), 8);
+    }
+}
@@ -0,0 +1,220 @@
+use crate::{CommandExt, Output};
+
+// Parsing the common plain-text formats of stdout. Lines are those of `Output::lines`, and blank
+// lines are skipped.
+impl Output {
+    /// The `key<sep>value` pairs of stdout in order, as written by `git config --list`,
+    /// `printenv` or `systemctl show`. A value is the rest of its line, unless it is in double
+    /// quotes, as with `lsblk -P`: then `\"`, `\\` and `\xHH` are unescaped, and more pairs can
+    /// follow on the same line after whitespace.
+    pub fn parse_kv(&self, sep: char) -> anyhow::Result<Vec<(String, String)>> {
+        let mut pairs = Vec::new();
+        for (i, line) in self.lines().enumerate() {
+            if line.trim().is_empty() {
+                continue;
+            }
+            if let Err(problem) = parse_kv_line(line, sep, &mut pairs) {
+                anyhow::bail!(
+                    "Line {} of process stdout {} ({}, line = {:?})",
+                    i + 1,
+                    problem,
+                    self.command.description(),
+                    line,
+                );
+            }
+        }
+        Ok(pairs)
+    }
+
+    /// The rows of whitespace-aligned tables such as those of `docker ps` or `kubectl get`, the
+    /// header included, with cells delimited by runs of at least `min_spaces` spaces, or of
+    /// blanks with a tab in them.
+    /// An empty cell can't be told apart from the space around it, so rows with empty cells are
+    /// shorter.
+    pub fn parse_columns(&self, min_spaces: usize) -> Vec<Vec<String>> {
+        let min_spaces = min_spaces.max(1);
+        self.lines()
+            .filter(|line| !line.trim().is_empty())
+            .map(|line| split_columns(line.trim(), min_spaces))
+            .collect()
+    }
+}
+
+fn parse_kv_line(
+    line: &str,
+    sep: char,
+    pairs: &mut Vec<(String, String)>,
+) -> Result<(), &'static str> {
+    let mut rest = line.trim_start();
+    while !rest.is_empty() {
+        let (key, after) = rest.split_once(sep).ok_or("is not a key-value pair")?;
+        if key.trim().is_empty() {
+            return Err("has an empty key");
+        }
+        let Some(quoted) = after.strip_prefix('"') else {
+            pairs.push((key.trim().to_string(), after.to_string()));
+            return Ok(());
+        };
+        let (value, after) = unquote(quoted)?;
+        pairs.push((key.trim().to_string(), value));
+        if !after.is_empty() && !after.starts_with(char::is_whitespace) {
+            return Err("has text right after a quoted value");
+        }
+        rest = after.trim_start();
+    }
+    Ok(())
+}
+
+// The value of a quoted string whose opening quote is already stripped, and what follows the
+// closing quote.
+fn unquote(s: &str) -> Result<(String, &str), &'static str> {
+    let mut value = Vec::new();
+    let mut chars = s.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            '"' => return Ok((String::from_utf8_lossy(&value).into_owned(), &s[i + 1..])),
+            '\\' => match chars.next() {
+                Some((j, 'x')) => {
+                    let byte = s
+                        .get(j + 1..j + 3)
+                        .and_then(|hex| u8::from_str_radix(hex, 16).ok())
+                        .ok_or("has an invalid \\x escape")?;
+                    value.push(byte);
+                    chars.nth(1);
+                }
+                Some((_, c)) => value.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+                None => return Err("has an unterminated quoted value"),
+            },
+            c => value.extend_from_slice(c.encode_utf8(&mut [0; 4]).as_bytes()),
+        }
+    }
+    Err("has an unterminated quoted value")
+}
+
+fn split_columns(line: &str, min_spaces: usize) -> Vec<String> {
+    let mut cells = Vec::new();
+    let mut cell_start = 0;
+    let mut blank_start = None;
+    for (i, c) in line.char_indices() {
+        if c == ' ' || c == '\t' {
+            blank_start.get_or_insert(i);
+            continue;
+        }
+        if let Some(start) = blank_start.take() {
+            let blank = &line[start..i];
+            if blank.len() >= min_spaces || blank.contains('\t') {
+                cells.push(line[cell_start..start].to_string());
+                cell_start = i;
+            }
+        }
+    }
+    cells.push(line[cell_start..].to_string());
+    cells
+}
+
+#[cfg(test)]
+mod tests {
+    use std::process::ExitStatus;
+    use std::time::Duration;
+
+    use crate::{cmd, Output};
+
+    fn output(stdout: &str) -> Output {
+        Output::new(cmd("tool"), ExitStatus::default(), stdout.into(), Vec::new(), Duration::ZERO)
+    }
+
+    fn pairs(pairs: &[(&str, &str)]) -> Vec<(String, String)> {
+        pairs.iter().map(|&(key, value)| (key.to_owned(), value.to_owned())).collect()
+    }
+
+    #[test]
+    fn git_config_list() {
+        let sample = "\
+user.name=Jane Doe
+user.email=jane@example.com
+core.autocrlf=input
+alias.lg=log --graph --format=%h=%s
+remote.origin.url=git@github.com:example/repo.git
+remote.origin.fetch=+refs/heads/*:refs/remotes/origin/*
+";
+        let expected = pairs(&[
+            ("user.name", "Jane Doe"),
+            ("user.email", "jane@example.com"),
+            ("core.autocrlf", "input"),
+            ("alias.lg", "log --graph --format=%h=%s"),
+            ("remote.origin.url", "git@github.com:example/repo.git"),
+            ("remote.origin.fetch", "+refs/heads/*:refs/remotes/origin/*"),
+        ]);
+        assert_eq!(output(sample).parse_kv('=').unwrap(), expected);
+        assert_eq!(
+            output("a=1\r\n\r\nb=\r\n").parse_kv('=').unwrap(),
+            pairs(&[("a", "1"), ("b", "")])
+        );
+    }
+
+    #[test]
+    fn lsblk_pairs() {
+        let sample = r#"NAME="sda" SIZE="931.5G" MOUNTPOINT=""
+NAME="sda1" SIZE="512M" MOUNTPOINT="/boot/efi"
+NAME="sdb1" LABEL="My\x20Disk" MODEL="say \"hi\"\\"
+"#;
+        let expected = pairs(&[
+            ("NAME", "sda"),
+            ("SIZE", "931.5G"),
+            ("MOUNTPOINT", ""),
+            ("NAME", "sda1"),
+            ("SIZE", "512M"),
+            ("MOUNTPOINT", "/boot/efi"),
+            ("NAME", "sdb1"),
+            ("LABEL", "My Disk"),
+            ("MODEL", r#"say "hi"\"#),
+        ]);
+        assert_eq!(output(sample).parse_kv('=').unwrap(), expected);
+    }
+
+    #[test]
+    fn malformed_lines_are_named() {
+        let cases = [
+            ("a=1\nnot a pair\n", "Line 2 of process stdout is not a key-value pair ("),
+            ("\n=value\n", "Line 2 of process stdout has an empty key ("),
+            ("A=\"open\n", "Line 1 of process stdout has an unterminated quoted value ("),
+            ("A=\"x\"B=\"y\"\n", "Line 1 of process stdout has text right after a quoted value ("),
+            ("A=\"\\xZZ\"\n", "Line 1 of process stdout has an invalid \\x escape ("),
+        ];
+        for (stdout, expected) in cases {
+            let message = output(stdout).parse_kv('=').unwrap_err().to_string();
+            assert!(message.starts_with(expected), "{}", message);
+        }
+        let message = output("a=1\nnot a pair\n").parse_kv('=').unwrap_err().to_string();
+        assert!(message.ends_with(r#", line = "not a pair")"#), "{}", message);
+    }
+
+    #[test]
+    fn docker_ps_columns() {
+        let sample = "\
+CONTAINER ID   IMAGE          COMMAND                  CREATED        STATUS                  PORTS                    NAMES
+4c01db0b339c   nginx:1.25     \"/docker-entrypoint.…\"   2 hours ago    Up 2 hours              0.0.0.0:8080->80/tcp     web
+d7886598dbe2   postgres:16    \"docker-entrypoint.s…\"   3 days ago     Up 3 days (healthy)     5432/tcp                 db
+";
+        let rows = output(sample).parse_columns(2);
+        assert_eq!(
+            rows[0],
+            ["CONTAINER ID", "IMAGE", "COMMAND", "CREATED", "STATUS", "PORTS", "NAMES"]
+        );
+        assert_eq!(
+            rows[2],
+            [
+                "d7886598dbe2",
+                "postgres:16",
+                "\"docker-entrypoint.s…\"",
+                "3 days ago",
+                "Up 3 days (healthy)",
+                "5432/tcp",
+                "db"
+            ]
+        );
+        assert_eq!(rows.len(), 3);
+        assert_eq!(output("a b\tc\n\n").parse_columns(2), [["a b", "c"]]);
+        assert_eq!(output("a b  c\n").parse_columns(0), [["a", "b", "c"]]);
+    }
+}
@@ -0,0 +1,117 @@
+use std::borrow::Cow;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+// Shown in place of the unchanged process `PATH` within a modified one.
+const INHERITED: &str = "$PATH";
+
+fn is_path_key(key: &OsStr) -> bool {
+    match cfg!(windows) {
+        true => key.eq_ignore_ascii_case("PATH"),
+        false => key == "PATH",
+    }
+}
+
+// Adds `dir` to the front or the back of the `PATH` the command will run with: the one set on it
+// if any, otherwise the inherited one.
+pub(crate) fn add(cmd: &mut Command, dir: &Path, front: bool) {
+    let configured = cmd.get_envs().find(|(key, _)| is_path_key(key));
+    let key = configured.map_or_else(|| OsString::from("PATH"), |(key, _)| key.to_os_string());
+    let current = match configured {
+        Some((_, value)) => value.map(OsStr::to_os_string),
+        None => env::var_os("PATH"),
+    };
+    let mut dirs: Vec<PathBuf> = match current.as_deref() {
+        // Splitting an empty `PATH` gives one empty entry, which would mean the working directory.
+        None => Vec::new(),
+        Some(current) if current.is_empty() => Vec::new(),
+        Some(current) => env::split_paths(current).collect(),
+    };
+    match front {
+        true => dirs.insert(0, dir.to_path_buf()),
+        false => dirs.push(dir.to_path_buf()),
+    }
+    let joined = env::join_paths(dirs).unwrap_or_else(|e| {
+        panic!("Can't add {} to PATH: {}", dir.display(), e);
+    });
+    cmd.env(key, joined);
+}
+
+// A `PATH` value that contains the process `PATH`, with that part shown as `$PATH`.
+pub(crate) fn elided<'a>(key: &OsStr, value: Cow<'a, OsStr>) -> Cow<'a, OsStr> {
+    let inherited = env::var_os("PATH").filter(|inherited| !inherited.is_empty());
+    let (Some(inherited), Some(text)) = (inherited, value.to_str()) else {
+        return value;
+    };
+    let (true, Some(inherited)) = (is_path_key(key), inherited.to_str()) else {
+        return value;
+    };
+    let separator = if cfg!(windows) { ';' } else { ':' };
+    // Matched as whole entries, so that directories are added before, after or around it.
+    let padded = format!("{0}{1}{0}", separator, text);
+    let needle = format!("{0}{1}{0}", separator, inherited);
+    if !padded.contains(&needle) {
+        return value;
+    }
+    let shown = padded.replacen(&needle, &format!("{0}{1}{0}", separator, INHERITED), 1);
+    Cow::Owned(OsString::from(&shown[1..shown.len() - 1]))
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::ffi::OsStrExt;
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::test_support::temp_path;
+    use crate::{cmd, CommandExt};
+
+    // A directory with an `ls` that only says where it is from.
+    fn tools(name: &str) -> PathBuf {
+        let dir = temp_path(name);
+        std::fs::create_dir_all(&dir).unwrap();
+        let ls = dir.join("ls");
+        std::fs::write(&ls, "#!/bin/sh\necho prepended ls\n").unwrap();
+        std::fs::set_permissions(&ls, std::fs::Permissions::from_mode(0o755)).unwrap();
+        dir
+    }
+
+    fn path_of(cmd: &Command) -> Option<&OsStr> {
+        cmd.get_envs().find(|(key, _)| *key == "PATH").and_then(|(_, value)| value)
+    }
+
+    #[test]
+    fn the_child_resolves_programs_from_the_prepended_directory() {
+        let dir = tools("path-prepend");
+        let out = cmd("ls").prepend_path_(&dir).exec_stdout_string().unwrap();
+        assert_eq!(out.stdout, "prepended ls\n");
+        let out = cmd("ls").args_(["-d", "/"]).append_path_(&dir).exec_stdout_string().unwrap();
+        assert_eq!(out.stdout, "/\n");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn an_existing_override_is_extended() {
+        let ls = cmd("ls").env_("PATH", "/a:/b").prepend_path_("/front").append_path_("/back");
+        assert_eq!(path_of(&ls), Some(OsStr::new("/front:/a:/b:/back")));
+        let ls = cmd("ls").env_("PATH", "").prepend_path_("/only");
+        assert_eq!(path_of(&ls), Some(OsStr::new("/only")));
+        let odd = OsStr::from_bytes(b"/odd\xff");
+        let ls = cmd("ls").env_("PATH", "/a").append_path_(odd);
+        assert_eq!(path_of(&ls).unwrap().as_bytes(), b"/a:/odd\xff");
+    }
+
+    #[test]
+    fn descriptions_elide_the_inherited_path() {
+        let inherited = env::var_os("PATH").unwrap();
+        let ls = cmd("ls").prepend_path_("/front").append_path_("/back");
+        let shown = elided(OsStr::new("PATH"), Cow::Borrowed(path_of(&ls).unwrap()));
+        assert_eq!(shown, OsStr::new("/front:$PATH:/back"));
+        let description = ls.description().to_string();
+        assert!(description.contains(r#"("PATH", Some("/front:$PATH:/back"))"#), "{}", description);
+        let other = elided(OsStr::new("OTHER"), Cow::Borrowed(inherited.as_os_str()));
+        assert_eq!(other, inherited.as_os_str());
+    }
+}
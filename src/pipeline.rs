@@ -0,0 +1,269 @@
+use std::io::Write;
+use std::process::{ExitStatus, Stdio};
+use std::time::Instant;
+
+use anyhow::Context;
+
+use crate::backend::{self, Process};
+use crate::capture::{check_redirected, Captured, Pipes};
+use crate::child::{self, OutputReaders};
+use crate::which::SpawnContext;
+use crate::{
+    decorate, decoration_stream, exec_failed, execution, exit_signal, is_dry_run, label,
+    write_command_text, write_current_dir, write_dry_run_marker, write_end_output, write_timestamp,
+    CommandExt, ConfiguredCommand, DecorationStream, Embedded, Output, StatusSummary,
+};
+
+const SIGPIPE: i32 = 13;
+
+/// Commands connected like a shell pipe, each stage's stdout feeding the next stage's stdin.
+///
+/// The pipeline fails if any stage fails, even when the last one exits successfully (like
+/// `set -o pipefail`), and the error names the first failing stage along with its stderr. A stage
+/// killed by SIGPIPE because a later one stopped reading, as `yes | head -n 1` does, hasn't failed.
+pub struct Pipeline {
+    stages: Vec<ConfiguredCommand>,
+}
+
+impl Pipeline {
+    pub fn new(first: impl Into<ConfiguredCommand>) -> Self {
+        Pipeline { stages: vec![first.into()] }
+    }
+
+    pub fn pipe(self, next: impl Into<ConfiguredCommand>) -> Self {
+        let mut stages = self.stages;
+        stages.push(next.into());
+        Pipeline { stages }
+    }
+
+    pub fn stages(&self) -> &[ConfiguredCommand] {
+        &self.stages
+    }
+
+    pub fn exec(&mut self) -> anyhow::Result<()> {
+        let mut stderr = decoration_stream();
+        self.write_banner(&mut stderr)?;
+        if is_dry_run() {
+            write_dry_run_marker(&mut stderr);
+            return Ok(());
+        }
+        let start = Instant::now();
+        let finished = self.run(false)?;
+        let failure = self.check(&finished);
+        write_end_output(&mut stderr, &self.stages[0], failure.is_ok(), None, start.elapsed());
+        failure
+    }
+
+    pub fn exec_stdout_string(self) -> anyhow::Result<Output> {
+        let mut self_ = self;
+        if is_dry_run() {
+            let mut stderr = decoration_stream();
+            self_.write_banner(&mut stderr)?;
+            write_dry_run_marker(&mut stderr);
+            return Ok(crate::dry_run_output(self_.stages.pop().unwrap().into_command()));
+        }
+        let start = Instant::now();
+        let mut finished = self_.run(true)?;
+        self_.check(&finished)?;
+        let command = self_.stages.pop().unwrap();
+        let Finished { status, stdout, stderr } = finished.pop().unwrap();
+        Captured::new(status.unwrap(), stdout, stderr, start.elapsed()).into_output(command)
+    }
+
+    fn write_banner(&self, stderr: &mut DecorationStream) -> anyhow::Result<()> {
+        let stages: Vec<_> = self.stages.iter().map(|stage| stage.description().shell()).collect();
+        let banner = stages.join(" | ");
+        decorate(|| {
+            write_timestamp(stderr)?;
+            if let Some(label) = label::get(&self.stages[0]) {
+                label::write_tag(stderr, label)?;
+            }
+            write_current_dir(stderr, &self.stages[0])?;
+            write!(stderr, " ")?;
+            write_command_text(stderr, &banner)?;
+            writeln!(stderr)?;
+            stderr.flush()
+        })
+        .with_context(|| format!("Failed to write the pipeline banner ({})", banner))
+    }
+
+    // Runs every stage as a command of its own, with its own execution record, and waits for
+    // all of them. The stderr of a stage is forwarded as it is written and also kept for the
+    // error, except that of the last stage when its output is captured, which is only kept.
+    fn run(&mut self, capture_last: bool) -> anyhow::Result<Vec<Finished>> {
+        let count = self.stages.len();
+        for (i, stage) in self.stages.iter().enumerate() {
+            let stdout = i + 1 < count || capture_last;
+            check_redirected(stage, Pipes { stdin: i > 0, stdout, stderr: true })?;
+        }
+        let mut executions = Vec::with_capacity(count);
+        for stage in &self.stages {
+            executions.push(execution::start(stage)?);
+        }
+        let mut children: Vec<Process> = Vec::with_capacity(count);
+        for (i, stage) in self.stages.iter_mut().enumerate() {
+            let last = i + 1 == count;
+            let prev_stdout = children.last_mut().and_then(Process::take_stdout_for_pipe);
+            let piped_stdin = prev_stdout.is_some();
+            if let Some(prev_stdout) = prev_stdout {
+                stage.stdin(prev_stdout);
+            }
+            let piped_stdout = !last || capture_last;
+            if piped_stdout {
+                stage.stdout(Stdio::piped());
+            }
+            stage.stderr(Stdio::piped());
+            let spawned = backend::spawn(stage);
+            // The stage holds on to the read end of the previous stage's stdout until its stdin is
+            // replaced, and the previous stage would never see the pipe close if this one exited
+            // early, as `head` does.
+            if piped_stdin {
+                stage.stdin(Stdio::inherit());
+            }
+            if piped_stdout {
+                stage.stdout(Stdio::inherit());
+            }
+            stage.stderr(Stdio::inherit());
+            let spawned = spawned.spawn_context(stage, || {
+                format!(
+                    "Failed to execute pipeline stage {} of {} ({})",
+                    i + 1,
+                    count,
+                    stage.description()
+                )
+            });
+            match spawned {
+                Ok(child) => children.push(child),
+                Err(e) => {
+                    for mut child in children {
+                        let _ = child.kill();
+                        let _ = child.wait();
+                    }
+                    return Err(e);
+                }
+            }
+        }
+        let readers: Vec<OutputReaders> = children
+            .iter_mut()
+            .zip(&self.stages)
+            .enumerate()
+            .map(|(i, (child, stage))| match i + 1 == count {
+                true if capture_last => child::spawn_output_readers(child),
+                last => {
+                    // What a mocked stage writes to stdout goes nowhere unless it is the last.
+                    if !last {
+                        drop(child.take_stdout());
+                    }
+                    child::spawn_tee_readers(child, label::get(stage))
+                }
+            })
+            .collect();
+        let statuses: Vec<_> = children.iter_mut().map(Process::wait).collect();
+        let finished: Vec<Finished> = statuses
+            .into_iter()
+            .zip(readers)
+            .map(|(status, readers)| {
+                let (stdout, stderr) = readers.join();
+                Finished { status, stdout: if capture_last { stdout } else { Vec::new() }, stderr }
+            })
+            .collect();
+        for ((execution, stage), finished) in
+            executions.into_iter().zip(&self.stages).zip(&finished)
+        {
+            let output = Some((&finished.stdout[..], &finished.stderr[..]));
+            execution.finish(stage, finished.status.as_ref().ok(), output);
+        }
+        Ok(finished)
+    }
+
+    fn check(&self, finished: &[Finished]) -> anyhow::Result<()> {
+        let count = self.stages.len();
+        for (i, (stage, finished)) in self.stages.iter().zip(finished).enumerate() {
+            let status = match &finished.status {
+                Ok(status) => *status,
+                Err(e) => {
+                    anyhow::bail!(
+                        "Failed to wait for pipeline stage {} of {} ({}): {}",
+                        i + 1,
+                        count,
+                        stage.description(),
+                        e,
+                    );
+                }
+            };
+            let broken_pipe = i + 1 < count && exit_signal(status) == Some(SIGPIPE);
+            if !status.success() && !broken_pipe {
+                return Err(exec_failed(
+                    stage,
+                    status,
+                    &finished.stdout,
+                    &finished.stderr,
+                    format!(
+                        "Pipeline stage {} of {} did not exit successfully, {} ({}, stderr = {})",
+                        i + 1,
+                        count,
+                        StatusSummary(status),
+                        stage.description(),
+                        Embedded::new(&finished.stderr),
+                    ),
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// A stage that has exited, with its stderr, and its stdout if it was captured.
+struct Finished {
+    status: std::io::Result<ExitStatus>,
+    stdout: Vec<u8>,
+    stderr: Vec<u8>,
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{cmd, shell, CmdError, CommandExt};
+
+    #[test]
+    fn three_stages() {
+        let out = shell("printf 'a\\nb\\nc\\n'")
+            .pipe(cmd("grep").arg_("-v").arg_("b"))
+            .pipe(cmd("wc").arg_("-l"))
+            .exec_stdout_string()
+            .unwrap();
+        assert_eq!(out.stdout.trim(), "2");
+    }
+
+    #[test]
+    fn failing_stage_is_named_with_its_stderr() {
+        let err = shell("echo a")
+            .pipe(shell("echo broken >&2; exit 3"))
+            .pipe(cmd("cat"))
+            .exec_stdout_string()
+            .err()
+            .unwrap();
+        assert!(err.to_string().starts_with("Pipeline stage 2 of 3 did not exit successfully"));
+        let Some(CmdError::UnsuccessfulExit { status, description, stderr, .. }) =
+            err.downcast_ref::<CmdError>()
+        else {
+            panic!("not an unsuccessful exit: {:#}", err);
+        };
+        assert_eq!(status.code(), Some(3));
+        assert_eq!(description.args[1], "echo broken >&2; exit 3");
+        assert_eq!(stderr, b"broken\n");
+    }
+
+    #[test]
+    fn early_exit_of_a_later_stage_ends_the_pipeline() {
+        let out = cmd("yes").pipe(cmd("head").arg_("-n1")).exec_stdout_string().unwrap();
+        assert_eq!(out.stdout, "y\n");
+        cmd("yes").pipe(cmd("head").arg_("-n1")).exec().unwrap();
+    }
+
+    #[test]
+    fn a_failing_middle_stage_fails_the_pipeline() {
+        let mut pipeline = shell("echo a").pipe(shell("cat; exit 2")).pipe(cmd("cat"));
+        let err = pipeline.exec().err().unwrap();
+        assert!(err.to_string().starts_with("Pipeline stage 2 of 3"), "{:#}", err);
+    }
+}
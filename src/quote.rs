@@ -0,0 +1,297 @@
+use std::borrow::Cow;
+use std::ffi::OsStr;
+use std::fmt::{self, Write};
+use std::process::Command;
+
+use crate::redact;
+
+// Renders `cmd` as a single line that can be pasted into a shell (POSIX `sh` on Unix, `cmd.exe`
+// on Windows) to run it again. Returns whether any part was not UTF-8 and was rendered lossily.
+pub(crate) fn write_shell_line(f: &mut impl Write, cmd: &Command) -> Result<bool, fmt::Error> {
+    let mut lossy = false;
+    if let Some(dir) = cmd.get_current_dir() {
+        f.write_str(if cfg!(windows) { "cd /d " } else { "cd " })?;
+        lossy |= write_quoted(f, &redact::scrub(dir.as_os_str()))?;
+        f.write_str(" && ")?;
+    }
+    let removed: Vec<&OsStr> =
+        cmd.get_envs().filter(|(_, val)| val.is_none()).map(|(key, _)| key).collect();
+    if !removed.is_empty() && !cfg!(windows) {
+        f.write_str("env")?;
+        for key in removed {
+            f.write_str(" -u ")?;
+            lossy |= write_quoted(f, key)?;
+        }
+        f.write_char(' ')?;
+    }
+    for (key, val) in cmd.get_envs() {
+        let Some(val) = val else { continue };
+        if cfg!(windows) {
+            let mut assignment = key.to_os_string();
+            assignment.push("=");
+            assignment.push(redact::env_value(key, val));
+            f.write_str("set ")?;
+            lossy |= write_quoted(f, &assignment)?;
+            f.write_str(" && ")?;
+        } else {
+            // The name is left unquoted, since a quoted name is no longer an assignment.
+            lossy |= key.to_str().is_none();
+            write!(f, "{}=", key.to_string_lossy())?;
+            lossy |= write_quoted(f, &redact::env_value(key, val))?;
+            f.write_char(' ')?;
+        }
+    }
+    lossy |= write_quoted(f, &redact::scrub(cmd.get_program()))?;
+    for arg in cmd.get_args() {
+        f.write_char(' ')?;
+        lossy |= write_quoted(f, &redact::scrub(arg))?;
+    }
+    Ok(lossy)
+}
+
+/// Quotes `s` as a single word for a POSIX shell: a word made only of ASCII letters, digits and
+/// `_-+@:,./%` is returned as it is, and anything else is put in single quotes, with each `'`
+/// written as `'\''`. A word with `=` is quoted too, since as the first word of a line it would
+/// be taken for a variable assignment. Input that is not UTF-8 is converted lossily, each invalid sequence
+/// becoming `U+FFFD` (`�`), so the result names the original but can't reproduce it exactly.
+pub fn shell_quote(s: &OsStr) -> Cow<'_, str> {
+    let s = s.to_string_lossy();
+    if !s.is_empty() && s.chars().all(|c| is_safe(c, false)) {
+        return s;
+    }
+    let mut quoted = String::with_capacity(s.len() + 2);
+    quoted.push('\'');
+    for c in s.chars() {
+        match c {
+            '\'' => quoted.push_str("'\\''"),
+            c => quoted.push(c),
+        }
+    }
+    quoted.push('\'');
+    Cow::Owned(quoted)
+}
+
+/// Quotes `s` as a single argument for programs that split their command line with the rules of
+/// the Microsoft C runtime and `CommandLineToArgvW`: an argument without spaces, tabs, newlines
+/// or double quotes is returned as it is, and anything else is put in double quotes, with each
+/// `"` escaped as `\"` and backslashes doubled where they precede one. `cmd.exe` has rules of
+/// its own, which this doesn't account for. Input that is not UTF-8 is converted lossily as by
+/// `shell_quote`.
+pub fn shell_quote_windows(s: &OsStr) -> String {
+    let s = s.to_string_lossy();
+    if !s.is_empty() && !s.contains([' ', '\t', '\n', '\x0b', '"']) {
+        return s.into_owned();
+    }
+    let mut quoted = String::with_capacity(s.len() + 2);
+    let _ = write_windows_quoted(&mut quoted, &s);
+    quoted
+}
+
+/// Joins `args` into one POSIX shell line, each quoted with `shell_quote`.
+pub fn shell_join<I, S>(args: I) -> String
+where
+    I: IntoIterator<Item = S>,
+    S: AsRef<OsStr>,
+{
+    let mut line = String::new();
+    for arg in args {
+        if !line.is_empty() {
+            line.push(' ');
+        }
+        line.push_str(&shell_quote(arg.as_ref()));
+    }
+    line
+}
+
+// Writes `s` quoted for the platform shell, leaving it bare when that is unambiguous.
+pub(crate) fn write_quoted(f: &mut impl Write, s: &OsStr) -> Result<bool, fmt::Error> {
+    let lossy = s.to_str().is_none();
+    if !cfg!(windows) {
+        f.write_str(&shell_quote(s))?;
+        return Ok(lossy);
+    }
+    let s = s.to_string_lossy();
+    if !s.is_empty() && s.chars().all(|c| is_safe(c, true)) {
+        f.write_str(&s)?;
+    } else {
+        write_cmd_quoted(f, &s)?;
+    }
+    Ok(lossy)
+}
+
+fn is_safe(c: char, cmd_exe: bool) -> bool {
+    // `%` starts a variable expansion in `cmd.exe`, and `=` makes a leading word an assignment
+    // in `sh`.
+    c.is_ascii_alphanumeric() || "_-+@:,./".contains(c) || (c == '%' && !cmd_exe)
+}
+
+// Quotes `s` for the program's argument parser, then escapes everything `cmd.exe` would act on
+// with `^`, so that `cmd.exe` passes it on unchanged. The quotes are escaped too, since `cmd.exe`
+// still expands `%` between them.
+fn write_cmd_quoted(f: &mut impl Write, s: &str) -> fmt::Result {
+    let mut quoted = String::new();
+    write_windows_quoted(&mut quoted, s)?;
+    for c in quoted.chars() {
+        if "^&|<>()%!\"".contains(c) {
+            f.write_char('^')?;
+        }
+        f.write_char(c)?;
+    }
+    Ok(())
+}
+
+// Quotes following the rules of `CommandLineToArgvW`, where backslashes are only special when
+// they precede a double quote.
+fn write_windows_quoted(f: &mut impl Write, s: &str) -> fmt::Result {
+    let write_backslashes =
+        |f: &mut dyn Write, n: usize| (0..n).try_for_each(|_| f.write_char('\\'));
+    f.write_char('"')?;
+    let mut backslashes = 0;
+    for c in s.chars() {
+        match c {
+            '\\' => backslashes += 1,
+            '"' => {
+                write_backslashes(f, backslashes * 2 + 1)?;
+                f.write_char(c)?;
+                backslashes = 0;
+            }
+            c => {
+                write_backslashes(f, backslashes)?;
+                f.write_char(c)?;
+                backslashes = 0;
+            }
+        }
+    }
+    write_backslashes(f, backslashes * 2)?;
+    f.write_char('"')
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ffi::OsStr;
+
+    #[cfg(unix)]
+    use std::process::Command;
+
+    #[cfg(unix)]
+    use super::write_shell_line;
+    use super::{shell_join, shell_quote, shell_quote_windows, write_cmd_quoted};
+
+    #[test]
+    fn posix_quoting() {
+        let cases = [
+            ("", "''"),
+            ("plain-1.0/x_y+z@host:a,b", "plain-1.0/x_y+z@host:a,b"),
+            ("a b", "'a b'"),
+            ("it's", r"'it'\''s'"),
+            (r#"say "hi""#, r#"'say "hi"'"#),
+            (r"dir\", r"'dir\'"),
+            ("100%", "100%"),
+            ("a\nb", "'a\nb'"),
+            ("héllo", "'héllo'"),
+            ("a=b", "'a=b'"),
+            ("--out=x", "'--out=x'"),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(shell_quote(OsStr::new(input)), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn posix_join() {
+        let cases: [(&[&str], &str); 4] = [
+            (&[], ""),
+            (&["echo", "a b", "it's", ""], r"echo 'a b' 'it'\''s' ''"),
+            (&["FOO=bar", "env"], "'FOO=bar' env"),
+            (&["printf", "%s\n", "ü"], "printf '%s\n' 'ü'"),
+        ];
+        for (args, expected) in cases {
+            assert_eq!(shell_join(args), expected, "{:?}", args);
+        }
+    }
+
+    #[test]
+    fn windows_quoting() {
+        let cases = [
+            ("", r#""""#),
+            ("plain", "plain"),
+            ("a b", r#""a b""#),
+            ("it's", "it's"),
+            (r#"say "hi""#, r#""say \"hi\"""#),
+            (r"C:\dir\", r"C:\dir\"),
+            (r"C:\my dir\", r#""C:\my dir\\""#),
+            (r#"a\"b"#, r#""a\\\"b""#),
+            ("100%", "100%"),
+            ("a\nb", "\"a\nb\""),
+            ("héllo wörld", r#""héllo wörld""#),
+        ];
+        for (input, expected) in cases {
+            assert_eq!(shell_quote_windows(OsStr::new(input)), expected, "{:?}", input);
+        }
+    }
+
+    #[test]
+    fn cmd_exe_quoting() {
+        let cases = [
+            ("100%", r#"^"100^%^""#),
+            ("a & b", r#"^"a ^& b^""#),
+            (r#"say "hi""#, r#"^"say \^"hi\^"^""#),
+            (r"dir\", r#"^"dir\\^""#),
+        ];
+        for (input, expected) in cases {
+            let mut quoted = String::new();
+            write_cmd_quoted(&mut quoted, input).unwrap();
+            assert_eq!(quoted, expected, "{:?}", input);
+        }
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_line_prefixes_the_directory_and_environment() {
+        let mut cmd = Command::new("git");
+        cmd.args(["commit", "-m", "it's $HOME", "a\nb", ""]);
+        cmd.current_dir("/tmp/my dir").env("FOO", "a b").env_remove("BAR");
+        let mut line = String::new();
+        assert!(!write_shell_line(&mut line, &cmd).unwrap());
+        let expected =
+            "cd '/tmp/my dir' && env -u BAR FOO='a b' git commit -m 'it'\\''s $HOME' 'a\nb' ''";
+        assert_eq!(line, expected);
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn shell_line_reproduces_the_arguments() {
+        let args = ["a b", "it's", r#"say "hi""#, "$HOME", "`x`", r"dir\", "", "a\nb", "*", "a=b"];
+        let mut cmd = Command::new("printf");
+        cmd.arg("[%s]\n").args(args);
+        let mut line = String::new();
+        write_shell_line(&mut line, &cmd).unwrap();
+        let direct = cmd.output().unwrap().stdout;
+        let through_shell = Command::new("sh").arg("-c").arg(&line).output().unwrap().stdout;
+        assert_eq!(String::from_utf8(through_shell).unwrap(), String::from_utf8(direct).unwrap());
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_parts_are_marked() {
+        use std::os::unix::ffi::OsStrExt;
+
+        use crate::CommandExt;
+
+        let mut cmd = Command::new("ls");
+        cmd.arg(OsStr::from_bytes(b"caf\xe9"));
+        let line = format!("{:#}", cmd.description());
+        assert_eq!(line, "ls 'caf\u{FFFD}' # (non-UTF-8 parts shown lossily)");
+    }
+
+    #[cfg(unix)]
+    #[test]
+    fn non_utf8_input_is_converted_lossily() {
+        use std::os::unix::ffi::OsStrExt;
+
+        let name = OsStr::from_bytes(b"caf\xe9");
+        assert_eq!(shell_quote(name), "'caf\u{fffd}'");
+        assert_eq!(shell_quote_windows(name), "caf\u{fffd}");
+        assert_eq!(shell_join([name, OsStr::new("a b")]), "'caf\u{fffd}' 'a b'");
+    }
+}
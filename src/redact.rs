@@ -0,0 +1,119 @@
+use std::borrow::Cow;
+use std::ffi::{OsStr, OsString};
+use std::sync::Mutex;
+
+pub(crate) const REDACTED: &str = "***";
+const DEFAULT_REDACTED_ENV_KEYS: &[&str] = &["TOKEN", "SECRET", "PASSWORD", "KEY"];
+
+// `None` means the default keys.
+static REDACTED_ENV_KEYS: Mutex<Option<Vec<String>>> = Mutex::new(None);
+static REDACTED_VALUES: Mutex<Vec<String>> = Mutex::new(Vec::new());
+
+/// Sets the patterns of environment variable names whose values are shown as `***` in command
+/// descriptions, banners, and error messages. A variable is redacted if its name contains any of
+/// the patterns, ignoring case. The default patterns are `TOKEN`, `SECRET`, `PASSWORD`, and
+/// `KEY`; pass an empty iterator to show every value.
+pub fn set_redacted_env_keys<I, S>(keys: I)
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let keys = keys.into_iter().map(|key| key.into().to_uppercase()).collect();
+    *REDACTED_ENV_KEYS.lock().unwrap() = Some(keys);
+}
+
+/// Registers secrets that are replaced by `***` wherever they occur in the program, arguments,
+/// environment values, or working directory of a rendered command, and in the command log file.
+/// Nothing is scrubbed by value until this is called.
+pub fn set_redacted_values<I, S>(values: I)
+where
+    I: IntoIterator<Item = S>,
+    S: Into<String>,
+{
+    let values = values.into_iter().map(Into::into).filter(|value| !value.is_empty()).collect();
+    *REDACTED_VALUES.lock().unwrap() = values;
+}
+
+// The raw settings, to be restored by a `ConfigGuard`.
+pub(crate) fn settings() -> (Option<Vec<String>>, Vec<String>) {
+    (REDACTED_ENV_KEYS.lock().unwrap().clone(), REDACTED_VALUES.lock().unwrap().clone())
+}
+
+pub(crate) fn restore(env_keys: Option<Vec<String>>, values: Vec<String>) {
+    *REDACTED_ENV_KEYS.lock().unwrap() = env_keys;
+    *REDACTED_VALUES.lock().unwrap() = values;
+}
+
+pub(crate) fn is_redacted_key(key: &OsStr) -> bool {
+    let key = key.to_string_lossy().to_uppercase();
+    match &*REDACTED_ENV_KEYS.lock().unwrap() {
+        Some(keys) => keys.iter().any(|pattern| key.contains(&pattern[..])),
+        None => DEFAULT_REDACTED_ENV_KEYS.iter().any(|pattern| key.contains(pattern)),
+    }
+}
+
+// The value of the environment variable `key` as it may be shown.
+pub(crate) fn env_value<'a>(key: &OsStr, value: &'a OsStr) -> Cow<'a, OsStr> {
+    match is_redacted_key(key) {
+        true => Cow::Borrowed(OsStr::new(REDACTED)),
+        false => scrub(value),
+    }
+}
+
+// Replaces every registered secret in `s`. Strings that contain none are returned unchanged,
+// even if they are not UTF-8.
+pub(crate) fn scrub(s: &OsStr) -> Cow<'_, OsStr> {
+    let values = REDACTED_VALUES.lock().unwrap();
+    let lossy = s.to_string_lossy();
+    if !values.iter().any(|value| lossy.contains(&value[..])) {
+        return Cow::Borrowed(s);
+    }
+    let mut scrubbed = lossy.into_owned();
+    for value in values.iter() {
+        scrubbed = scrubbed.replace(&value[..], REDACTED);
+    }
+    Cow::Owned(OsString::from(scrubbed))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{decorations, serial};
+    use crate::{shell, CommandExt};
+
+    #[test]
+    fn secret_env_values_never_show_in_errors_or_banners() {
+        let secret = "ghp_env-secret-1234";
+        let cmd = || shell("exit 1").env_("GITHUB_TOKEN", secret).env_("MODE", "ci");
+        let err = cmd().exec_stdout_string().err().unwrap();
+        let text = decorations(|| assert!(cmd().exec().is_err()));
+        for rendered in [format!("{}", err), format!("{:#}", err), format!("{:?}", err), text] {
+            assert!(!rendered.contains(secret), "{}", rendered);
+            assert!(rendered.contains("***"), "{}", rendered);
+        }
+    }
+
+    #[test]
+    fn registered_values_are_scrubbed_from_arguments() {
+        let _serial = serial();
+        set_redacted_values(["hunter2-arg-secret"]);
+        let err = shell("echo --password=hunter2-arg-secret >/dev/null; exit 1").exec().err();
+        set_redacted_values(Vec::<String>::new());
+        let message = format!("{:#}", err.unwrap());
+        assert!(!message.contains("hunter2"), "{}", message);
+        assert!(message.contains("--password=***"), "{}", message);
+    }
+
+    #[test]
+    fn key_patterns_ignore_case_and_can_be_replaced() {
+        let _serial = serial();
+        assert!(is_redacted_key(OsStr::new("aws_secret_access_key")));
+        assert!(!is_redacted_key(OsStr::new("HOME")));
+        let defaults = settings();
+        set_redacted_env_keys(["pass"]);
+        assert!(is_redacted_key(OsStr::new("DB_PASSWD")));
+        assert!(!is_redacted_key(OsStr::new("API_TOKEN")));
+        restore(defaults.0, defaults.1);
+        assert!(is_redacted_key(OsStr::new("API_TOKEN")));
+    }
+}
@@ -0,0 +1,61 @@
+use std::io::Write;
+use std::process::{Command, ExitStatus};
+
+#[cfg(windows)]
+use crate::{backend, SpawnContext};
+use crate::{
+    decoration_stream, exit_signal, hooks, is_dry_run, write_banner, CommandExt, ConfiguredCommand,
+};
+
+/// Exits the current process the way the status says the child exited: with its exit code, or on
+/// Unix with 128 plus the number of the signal that killed it, as shells report it.
+pub fn exit_with_status(status: ExitStatus) -> ! {
+    let code = match (status.code(), exit_signal(status)) {
+        (Some(code), _) => code,
+        (None, Some(signal)) => 128 + signal,
+        (None, None) => 1,
+    };
+    let _ = std::io::stdout().flush();
+    let _ = std::io::stderr().flush();
+    std::process::exit(code)
+}
+
+pub(crate) fn exec_replace(cmd: &mut ConfiguredCommand) -> anyhow::Error {
+    match replace(cmd) {
+        Ok(never) => match never {},
+        Err(e) => e,
+    }
+}
+
+fn replace(cmd: &mut ConfiguredCommand) -> anyhow::Result<std::convert::Infallible> {
+    if is_dry_run() {
+        anyhow::bail!("Cannot replace the process in dry-run mode ({})", cmd.description());
+    }
+    #[cfg(feature = "mock")]
+    if crate::mock::current().is_some() {
+        anyhow::bail!(
+            "Cannot replace the process while a MockRunner is installed ({})",
+            cmd.description()
+        );
+    }
+    let mut stderr = decoration_stream();
+    write_banner(&mut stderr, cmd)?;
+    hooks::run_pre_exec(cmd)?;
+    let _ = std::io::stdout().flush();
+    run(cmd)
+}
+
+#[cfg(unix)]
+fn run(cmd: &mut Command) -> anyhow::Result<std::convert::Infallible> {
+    use crate::SpawnContext;
+
+    Err(std::os::unix::process::CommandExt::exec(cmd))
+        .spawn_context(cmd, || format!("Failed to execute command ({})", cmd.description()))
+}
+
+#[cfg(windows)]
+fn run(cmd: &mut Command) -> anyhow::Result<std::convert::Infallible> {
+    let status = backend::status(cmd)
+        .spawn_context(cmd, || format!("Failed to execute command ({})", cmd.description()))?;
+    exit_with_status(status)
+}
@@ -0,0 +1,287 @@
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, ExitStatus};
+use std::sync::{Arc, Mutex};
+use std::time::Duration;
+
+use anyhow::Context;
+use serde::{Deserialize, Serialize};
+
+use crate::{redact, warn, CommandExt, MockCommand, MockGuard, MockRunner};
+
+type ArgFilter = Arc<dyn Fn(&str) -> bool + Send + Sync>;
+
+// The commands recorded so far, while a `RecordGuard` is alive.
+static RECORDING: Mutex<Option<Vec<Recording>>> = Mutex::new(None);
+
+// The fixture file: `{"commands": [...]}`, one entry per run in the order the runs finished.
+#[derive(Serialize, Deserialize)]
+struct Fixture {
+    commands: Vec<Recording>,
+}
+
+#[derive(Clone, Serialize, Deserialize)]
+struct Recording {
+    description: String,
+    program: String,
+    args: Vec<String>,
+    exit_code: i32,
+    stdout: String,
+    stderr: String,
+    duration_ms: u64,
+}
+
+/// Records every command run from any thread until the returned guard is dropped or finished,
+/// then writes them to `path` as pretty-printed JSON, to be replayed with [`Replay`]. Each
+/// recording holds the command's description, program, arguments, exit code, duration, and its
+/// stdout and stderr converted lossily to UTF-8, with secrets redacted as in command
+/// descriptions. Output is only recorded by the methods that capture it; the others record it as
+/// empty. Runs without an exit code (spawn failures, timeouts, signals) are not recorded.
+pub fn record_to(path: impl AsRef<Path>) -> RecordGuard {
+    *RECORDING.lock().unwrap() = Some(Vec::new());
+    RecordGuard { path: Some(path.as_ref().to_path_buf()) }
+}
+
+#[must_use = "the recordings are written as soon as the guard is dropped"]
+pub struct RecordGuard {
+    path: Option<PathBuf>,
+}
+
+impl RecordGuard {
+    /// Stops recording and writes the fixture, unlike dropping the guard, which only prints a
+    /// warning if the fixture can't be written.
+    pub fn finish(mut self) -> anyhow::Result<()> {
+        let path = self.path.take().unwrap();
+        write_fixture(&path)
+    }
+}
+
+impl Drop for RecordGuard {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            if let Err(e) = write_fixture(&path) {
+                warn(format_args!("{:#}", e));
+            }
+        }
+    }
+}
+
+fn write_fixture(path: &Path) -> anyhow::Result<()> {
+    let commands = RECORDING.lock().unwrap().take().unwrap_or_default();
+    let mut json = serde_json::to_string_pretty(&Fixture { commands })?;
+    json.push('\n');
+    std::fs::write(path, json)
+        .with_context(|| format!("Failed to write command recordings to {}", path.display()))
+}
+
+pub(crate) fn record(
+    cmd: &Command,
+    duration: Duration,
+    status: Option<&ExitStatus>,
+    output: Option<(&[u8], &[u8])>,
+) {
+    let mut recording = RECORDING.lock().unwrap();
+    let (Some(commands), Some(exit_code)) = (&mut *recording, status.and_then(ExitStatus::code))
+    else {
+        return;
+    };
+    let snapshot = cmd.description().to_snapshot();
+    let (stdout, stderr) = output.unwrap_or_default();
+    commands.push(Recording {
+        description: cmd.description().to_string(),
+        program: snapshot.program,
+        args: snapshot.args,
+        exit_code,
+        stdout: String::from_utf8_lossy(stdout).into_owned(),
+        stderr: String::from_utf8_lossy(stderr).into_owned(),
+        duration_ms: duration.as_millis() as u64,
+    });
+}
+
+/// Replays a fixture written by [`record_to`] on the installing thread, as a [`MockRunner`]
+/// would: each command gets the exit code and output of a recording with the same program and
+/// arguments, without anything being spawned. Each recording is used once, in order, and
+/// recordings that are never used are not an error.
+///
+/// A command with no matching recording panics with the closest recording of the same program
+/// and a diff of their arguments.
+pub struct Replay {
+    path: PathBuf,
+    ignored: Vec<ArgFilter>,
+}
+
+impl Replay {
+    pub fn new(path: impl AsRef<Path>) -> Self {
+        Replay { path: path.as_ref().to_path_buf(), ignored: Vec::new() }
+    }
+
+    /// Ignores arguments that change from run to run, such as timestamps or temporary paths: an
+    /// argument matches whatever is at the same position in a recording if either of them
+    /// satisfies `filter`.
+    pub fn ignore_args_matching<F>(self, filter: F) -> Self
+    where
+        F: Fn(&str) -> bool + Send + Sync + 'static,
+    {
+        let mut ignored = self.ignored;
+        ignored.push(Arc::new(filter));
+        Replay { ignored, ..self }
+    }
+
+    pub fn install(self) -> anyhow::Result<MockGuard> {
+        let path = &self.path;
+        let json = std::fs::read_to_string(path).with_context(|| {
+            format!("Failed to read command recordings from {}", path.display())
+        })?;
+        let fixture: Fixture = serde_json::from_str(&json)
+            .with_context(|| format!("Invalid command recordings in {}", path.display()))?;
+        let ignored: Arc<[ArgFilter]> = self.ignored.into();
+
+        let mut runner = MockRunner::new();
+        for recording in &fixture.commands {
+            let expected = recording.args.clone();
+            let ignored = Arc::clone(&ignored);
+            let command = MockCommand::new(&recording.program)
+                .args_matching(move |args| {
+                    let args: Vec<String> = args.iter().map(|arg| scrubbed(arg)).collect();
+                    differences(&expected, &args, &ignored) == 0
+                })
+                .times(1)
+                .optional()
+                .exit_code(recording.exit_code)
+                .stdout(recording.stdout.as_bytes())
+                .stderr(recording.stderr.as_bytes());
+            runner = runner.expect(command);
+        }
+
+        let path = self.path.display().to_string();
+        let recordings = fixture.commands;
+        Ok(runner.install_with(Some(Box::new(move |cmd: &Command| {
+            unmatched_message(&path, &recordings, &ignored, cmd)
+        }))))
+    }
+}
+
+fn scrubbed(arg: &OsStr) -> String {
+    redact::scrub(arg).to_string_lossy().into_owned()
+}
+
+fn is_ignored(ignored: &[ArgFilter], recorded: &str, actual: &str) -> bool {
+    ignored.iter().any(|filter| filter(recorded) || filter(actual))
+}
+
+fn differences(recorded: &[String], actual: &[String], ignored: &[ArgFilter]) -> usize {
+    let differing = recorded
+        .iter()
+        .zip(actual)
+        .filter(|(recorded, actual)| recorded != actual && !is_ignored(ignored, recorded, actual))
+        .count();
+    differing + recorded.len().abs_diff(actual.len())
+}
+
+fn unmatched_message(
+    path: &str,
+    recordings: &[Recording],
+    ignored: &[ArgFilter],
+    cmd: &Command,
+) -> String {
+    let program = scrubbed(cmd.get_program());
+    let args: Vec<String> = cmd.get_args().map(scrubbed).collect();
+    let mut message = format!("no recording in {} matches {}", path, cmd.description());
+    let same_program = recordings.iter().filter(|recording| {
+        recording.program == program
+            || Path::new(&program).file_name() == Some(recording.program.as_ref())
+    });
+    let Some(closest) =
+        same_program.min_by_key(|recording| differences(&recording.args, &args, ignored))
+    else {
+        message.push_str(&format!("\nnothing was recorded for program {:?}", program));
+        return message;
+    };
+    if differences(&closest.args, &args, ignored) == 0 {
+        message.push_str("\nevery recording of this command has already been replayed");
+        return message;
+    }
+    message.push_str(&format!(
+        "\nclosest recording: {}\narguments (- recorded, + actual):",
+        closest.description
+    ));
+    for i in 0..closest.args.len().max(args.len()) {
+        match (closest.args.get(i), args.get(i)) {
+            (Some(recorded), Some(actual))
+                if recorded == actual || is_ignored(ignored, recorded, actual) =>
+            {
+                message.push_str(&format!("\n    {:?}", actual))
+            }
+            (recorded, actual) => {
+                if let Some(recorded) = recorded {
+                    message.push_str(&format!("\n  - {:?}", recorded));
+                }
+                if let Some(actual) = actual {
+                    message.push_str(&format!("\n  + {:?}", actual));
+                }
+            }
+        }
+    }
+    message
+}
+
+#[cfg(test)]
+mod tests {
+    use std::panic::{self, AssertUnwindSafe};
+
+    use super::*;
+    use crate::test_support::{serial, temp_path};
+    use crate::{cmd, shell};
+
+    // Records a run of `echo replay-<arg>` and a failing script, and keeps only those in the
+    // fixture, since the commands of other tests running at the same time are recorded too.
+    fn recorded(name: &str) -> PathBuf {
+        let _serial = serial();
+        let path = temp_path(name);
+        let guard = record_to(&path);
+        cmd("echo").args_(["replay-test", "2024-01-01"]).exec_stdout_string().unwrap();
+        assert!(shell("echo out; echo err >&2; exit 4 # replay-test")
+            .exec_stdout_string()
+            .is_err());
+        guard.finish().unwrap();
+        let json = std::fs::read_to_string(&path).unwrap();
+        let mut fixture: Fixture = serde_json::from_str(&json).unwrap();
+        fixture.commands.retain(|c| c.args.iter().any(|arg| arg.contains("replay-test")));
+        std::fs::write(&path, serde_json::to_string_pretty(&fixture).unwrap()).unwrap();
+        path
+    }
+
+    #[test]
+    fn recordings_replay_without_spawning() {
+        let path = recorded("replay-fixture.json");
+        let json = std::fs::read_to_string(&path).unwrap();
+        assert!(json.contains("\n      \"exit_code\": 4,\n"), "{}", json);
+        assert!(json.contains("\"stderr\": \"err\\n\""), "{}", json);
+        let is_date = |arg: &str| arg.len() == 10 && arg.as_bytes()[4] == b'-';
+        let _replay = Replay::new(&path).ignore_args_matching(is_date).install().unwrap();
+        let out = cmd("echo").args_(["replay-test", "2025-06-30"]).exec_stdout_string().unwrap();
+        assert_eq!(out.stdout, "replay-test 2024-01-01\n");
+        let err = shell("echo out; echo err >&2; exit 4 # replay-test").exec_stdout_string();
+        let err = err.err().unwrap();
+        assert_eq!(err.downcast_ref::<crate::CmdError>().unwrap().exit_code(), Some(4));
+    }
+
+    #[test]
+    fn drifted_arguments_panic_with_a_diff() {
+        let path = recorded("replay-drift.json");
+        let _replay = Replay::new(&path).install().unwrap();
+        let panic = panic::catch_unwind(AssertUnwindSafe(|| {
+            let _ = cmd("echo").args_(["replay-test", "2025-06-30"]).exec_stdout_string();
+        }));
+        let message = panic.unwrap_err().downcast::<String>().unwrap();
+        let diff =
+            "arguments (- recorded, + actual):\n    \"replay-test\"\n  - \"2024-01-01\"\n  + \
+                    \"2025-06-30\"";
+        assert!(message.starts_with("no recording in ") && message.ends_with(diff), "{}", message);
+        let panic = panic::catch_unwind(|| {
+            let _ = cmd("cmd-utils-never-recorded").exec_stdout_string();
+        });
+        let message = panic.unwrap_err().downcast::<String>().unwrap();
+        assert!(message.ends_with("nothing was recorded for program \"cmd-utils-never-recorded\""));
+    }
+}
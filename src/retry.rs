@@ -0,0 +1,195 @@
+use std::collections::hash_map::RandomState;
+use std::hash::{BuildHasher, Hasher};
+use std::process::ExitStatus;
+use std::time::Duration;
+
+type StatusPredicate = Box<dyn Fn(&ExitStatus) -> bool + Send + Sync>;
+type StderrPredicate = Box<dyn Fn(&ExitStatus, &[u8]) -> bool + Send + Sync>;
+
+pub enum Backoff {
+    Fixed(Duration),
+    Exponential { initial: Duration, max: Duration },
+}
+
+pub struct RetryPolicy {
+    max_attempts: u32,
+    backoff: Backoff,
+    jitter: bool,
+    retryable: Option<StatusPredicate>,
+    retryable_stderr: Option<StderrPredicate>,
+}
+
+impl RetryPolicy {
+    pub fn new(max_attempts: u32) -> Self {
+        RetryPolicy {
+            max_attempts: max_attempts.max(1),
+            backoff: Backoff::Fixed(Duration::from_secs(1)),
+            jitter: false,
+            retryable: None,
+            retryable_stderr: None,
+        }
+    }
+
+    pub fn backoff(self, backoff: Backoff) -> Self {
+        RetryPolicy { backoff, ..self }
+    }
+
+    /// Randomizes each delay to somewhere between half and the full computed value.
+    pub fn jitter(self, jitter: bool) -> Self {
+        RetryPolicy { jitter, ..self }
+    }
+
+    /// Only failures for which `f` returns true are retried; by default every failure is.
+    pub fn retry_if<F>(self, f: F) -> Self
+    where
+        F: Fn(&ExitStatus) -> bool + Send + Sync + 'static,
+    {
+        RetryPolicy { retryable: Some(Box::new(f)), ..self }
+    }
+
+    /// Only failures for which `f` returns true given the attempt's stderr are retried, which
+    /// makes `exec_retry` capture stderr and write it after each attempt. Attempts that are
+    /// retried have theirs written with `Theme::retried_output`. Applies on top of `retry_if`.
+    pub fn retry_if_stderr<F>(self, f: F) -> Self
+    where
+        F: Fn(&ExitStatus, &[u8]) -> bool + Send + Sync + 'static,
+    {
+        RetryPolicy { retryable_stderr: Some(Box::new(f)), ..self }
+    }
+
+    /// Only retries failures whose stderr contains one of `patterns`, as `retry_if_stderr`.
+    pub fn on_stderr_containing(self, patterns: &[&str]) -> Self {
+        let patterns: Vec<Vec<u8>> = patterns.iter().map(|p| p.as_bytes().to_vec()).collect();
+        self.retry_if_stderr(move |_, stderr| {
+            patterns.iter().any(|p| p.is_empty() || stderr.windows(p.len()).any(|w| w == &p[..]))
+        })
+    }
+
+    pub fn max_attempts(&self) -> u32 {
+        self.max_attempts
+    }
+
+    pub(crate) fn reads_stderr(&self) -> bool {
+        self.retryable_stderr.is_some()
+    }
+
+    pub(crate) fn is_retryable(&self, status: &ExitStatus, stderr: &[u8]) -> bool {
+        self.retryable.as_ref().is_none_or(|f| f(status))
+            && self.retryable_stderr.as_ref().is_none_or(|f| f(status, stderr))
+    }
+
+    // Delay to wait after the given (1-based) failed attempt.
+    pub(crate) fn delay(&self, attempt: u32) -> Duration {
+        let delay = match self.backoff {
+            Backoff::Fixed(delay) => delay,
+            Backoff::Exponential { initial, max } => {
+                let factor = 2u32.saturating_pow(attempt.saturating_sub(1));
+                initial.saturating_mul(factor).min(max)
+            }
+        };
+        if self.jitter {
+            let random = RandomState::new().build_hasher().finish();
+            delay / 2 + delay.mul_f64((random % 1000) as f64 / 2000.0)
+        } else {
+            delay
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::{decorations, record_exits, temp_path};
+    use crate::{shell, CommandExt};
+
+    fn status(code: i32) -> ExitStatus {
+        shell(format!("exit {}", code)).status().unwrap()
+    }
+
+    #[test]
+    fn exponential_backoff_doubles_up_to_the_maximum() {
+        let backoff =
+            Backoff::Exponential { initial: Duration::from_secs(1), max: Duration::from_secs(5) };
+        let policy = RetryPolicy::new(10).backoff(backoff);
+        let delays: Vec<u64> = (1..=5).map(|attempt| policy.delay(attempt).as_secs()).collect();
+        assert_eq!(delays, [1, 2, 4, 5, 5]);
+        assert_eq!(RetryPolicy::new(0).max_attempts(), 1);
+    }
+
+    #[test]
+    fn jitter_stays_between_half_and_the_full_delay() {
+        let policy =
+            RetryPolicy::new(3).backoff(Backoff::Fixed(Duration::from_secs(2))).jitter(true);
+        for _ in 0..100 {
+            let delay = policy.delay(1);
+            assert!(
+                delay >= Duration::from_secs(1) && delay <= Duration::from_secs(2),
+                "{:?}",
+                delay
+            );
+        }
+    }
+
+    #[test]
+    fn predicates_decide_what_is_retried() {
+        let policy = RetryPolicy::new(3)
+            .retry_if(|status| status.code() == Some(75))
+            .on_stderr_containing(&["Connection reset"]);
+        assert!(policy.is_retryable(&status(75), b"curl: Connection reset by peer"));
+        assert!(!policy.is_retryable(&status(75), b"404 Not Found"));
+        assert!(!policy.is_retryable(&status(1), b"Connection reset"));
+        assert!(RetryPolicy::new(3).is_retryable(&status(1), b""));
+    }
+
+    #[test]
+    fn exec_retry_stops_at_the_first_success() {
+        let counter = temp_path("retry-counter");
+        let script = format!(
+            "n=$(($(cat '{0}' 2>/dev/null || echo 0) + 1)); echo $n > '{0}'; [ $n -ge 3 ]",
+            counter.display()
+        );
+        let policy = RetryPolicy::new(5).backoff(Backoff::Fixed(Duration::ZERO));
+        let text = decorations(|| shell(&script).label("retry-flaky").exec_retry(policy).unwrap());
+        std::fs::remove_file(&counter).unwrap();
+        let banners: Vec<_> =
+            text.lines().filter(|l| l.contains("[retry-flaky]") && l.contains("attempt")).collect();
+        assert_eq!(banners.len(), 3, "{}", text);
+        assert!(banners[2].contains("attempt 3/5"), "{}", text);
+    }
+
+    #[test]
+    fn exec_retry_gives_up_on_failures_that_are_not_retryable() {
+        let script = "exit 2 # retry fatal";
+        let (_guard, exits) = record_exits(script);
+        let policy = RetryPolicy::new(5)
+            .backoff(Backoff::Fixed(Duration::ZERO))
+            .retry_if(|status| status.code() == Some(75));
+        assert!(shell(script).exec_retry(policy).is_err());
+        assert_eq!(*exits.lock().unwrap(), [Some(2)]);
+    }
+
+    // Fails with "429" on the first two attempts and "401" on the third, counting in `counter`.
+    fn rate_limited(counter: &std::path::Path) -> String {
+        format!(
+            "n=$(($(cat '{0}' 2>/dev/null || echo 0) + 1)); echo $n > '{0}'; \
+             if [ $n -lt 3 ]; then echo \"429 Too Many Requests ($n)\" >&2; \
+             else echo '401 Unauthorized' >&2; fi; exit 1",
+            counter.display()
+        )
+    }
+
+    #[test]
+    fn exec_retry_decides_on_stderr_and_reports_the_last_attempt() {
+        let counter = temp_path("retry-stderr");
+        let _ = std::fs::remove_file(&counter);
+        let policy = RetryPolicy::new(5)
+            .backoff(Backoff::Fixed(Duration::ZERO))
+            .on_stderr_containing(&["timed out", "429"]);
+        let err = shell(rate_limited(&counter)).exec_retry(policy).unwrap_err();
+        assert_eq!(std::fs::read_to_string(&counter).unwrap(), "3\n");
+        std::fs::remove_file(&counter).unwrap();
+        let message = format!("{:#}", err);
+        assert!(message.contains(" after 3 attempt(s)"), "{}", message);
+        assert!(message.contains(r#"stderr = "401 Unauthorized\n")"#), "{}", message);
+    }
+}
@@ -0,0 +1,96 @@
+use std::path::{Path, PathBuf};
+
+use anyhow::Context;
+
+use crate::{CommandExt, Output, StatusSummary};
+
+// Writing captured output to files, such as build logs for CI to keep as artifacts. Stdout and
+// stderr are written as they were captured, and errors name the file and the command.
+impl Output {
+    pub fn save_stdout(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        self.save(path.as_ref(), self.stdout.as_bytes(), "stdout")
+    }
+
+    pub fn save_stderr(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        self.save(path.as_ref(), &self.stderr, "stderr")
+    }
+
+    /// Writes the command as a shell line and how it exited, such as:
+    ///
+    /// ```text
+    /// $ cd /src && cargo build --release
+    /// exit code 101
+    /// ```
+    pub fn save_command(&self, path: impl AsRef<Path>) -> anyhow::Result<()> {
+        let text = format!("$ {:#}\n{}\n", self.command.description(), StatusSummary(self.status));
+        self.save(path.as_ref(), text.as_bytes(), "command")
+    }
+
+    /// Writes `stdout.txt` and `stderr.txt` into `dir`, creating it if needed, and returns their
+    /// paths.
+    pub fn save_to_dir(&self, dir: impl AsRef<Path>) -> anyhow::Result<(PathBuf, PathBuf)> {
+        let dir = dir.as_ref();
+        std::fs::create_dir_all(dir).with_context(|| {
+            format!(
+                "Failed to create directory {} for process output ({})",
+                dir.display(),
+                self.command.description()
+            )
+        })?;
+        let stdout = dir.join("stdout.txt");
+        let stderr = dir.join("stderr.txt");
+        self.save_stdout(&stdout)?;
+        self.save_stderr(&stderr)?;
+        Ok((stdout, stderr))
+    }
+
+    fn save(&self, path: &Path, contents: &[u8], what: &str) -> anyhow::Result<()> {
+        std::fs::write(path, contents).with_context(|| {
+            format!(
+                "Failed to write process {} to {} ({})",
+                what,
+                path.display(),
+                self.command.description()
+            )
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::test_support::temp_path;
+    use crate::{shell, CommandExt};
+
+    #[test]
+    fn output_round_trips_through_a_new_directory() {
+        let out = shell(r"printf 'out\n'; printf 'err\377\n' >&2").exec_stdout_string().unwrap();
+        let dir = temp_path("save").join("nested").join("artifacts");
+        let (stdout, stderr) = out.save_to_dir(&dir).unwrap();
+        assert_eq!(
+            (stdout.file_name().unwrap(), stderr.file_name().unwrap()),
+            ("stdout.txt".as_ref(), "stderr.txt".as_ref())
+        );
+        assert_eq!(std::fs::read(&stdout).unwrap(), b"out\n");
+        assert_eq!(std::fs::read(&stderr).unwrap(), b"err\xff\n");
+        out.save_command(dir.join("command.txt")).unwrap();
+        let command = std::fs::read_to_string(dir.join("command.txt")).unwrap();
+        assert!(command.starts_with("$ ") && command.contains("sh -c "), "{}", command);
+        assert!(command.ends_with("\nexit code 0\n"), "{:?}", command);
+        std::fs::remove_dir_all(temp_path("save")).unwrap();
+    }
+
+    #[test]
+    fn errors_name_the_file_and_the_command() {
+        let out = shell("echo saved").exec_stdout_string().unwrap();
+        let file = temp_path("save-file");
+        out.save_stdout(&file).unwrap();
+        let message = format!("{:#}", out.save_to_dir(&file).unwrap_err());
+        assert!(message.starts_with("Failed to create directory "), "{}", message);
+        assert!(message.contains(r#"script = "echo saved""#), "{}", message);
+        let inside = file.join("stderr.txt");
+        let message = format!("{:#}", out.save_stderr(&inside).unwrap_err());
+        let expected = format!("Failed to write process stderr to {} (", inside.display());
+        assert!(message.starts_with(&expected), "{}", message);
+        std::fs::remove_file(&file).unwrap();
+    }
+}
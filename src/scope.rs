@@ -0,0 +1,186 @@
+use std::cell::RefCell;
+use std::io::Write;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use crate::{decorate, decoration_stream, theme, HumanDuration, TermColorStandardStreamExt};
+
+// Spaces of indentation per level of scopes.
+const INDENT_WIDTH: usize = 2;
+
+thread_local! {
+    // The scopes the thread is in, innermost last.
+    static SCOPES: RefCell<Scopes> = const { RefCell::new(Scopes(Vec::new())) };
+}
+
+// The commands run in a scope, including in the scopes nested in it.
+#[derive(Default)]
+struct Counts {
+    commands: AtomicUsize,
+    failed: AtomicUsize,
+}
+
+// The scopes of a thread, to be lent to the threads it runs commands or writes decorations from,
+// such as the workers of `run_parallel`.
+#[derive(Clone)]
+pub(crate) struct Scopes(Vec<Arc<Counts>>);
+
+pub(crate) fn current() -> Scopes {
+    SCOPES.with(|scopes| scopes.borrow().clone())
+}
+
+impl Scopes {
+    // Puts the thread in these scopes until the guard is dropped.
+    pub(crate) fn enter(self) -> Entered {
+        let previous = SCOPES.with(|scopes| scopes.replace(self));
+        Entered { previous: Some(previous) }
+    }
+}
+
+pub(crate) struct Entered {
+    previous: Option<Scopes>,
+}
+
+impl Drop for Entered {
+    fn drop(&mut self) {
+        if let Some(previous) = self.previous.take() {
+            SCOPES.with(|scopes| *scopes.borrow_mut() = previous);
+        }
+    }
+}
+
+pub(crate) fn indent_width() -> usize {
+    SCOPES.with(|scopes| scopes.borrow().0.len()) * INDENT_WIDTH
+}
+
+// Counts a finished command in every scope the thread is in.
+pub(crate) fn record(success: bool) {
+    SCOPES.with(|scopes| {
+        for counts in &scopes.borrow().0 {
+            counts.commands.fetch_add(1, Ordering::Relaxed);
+            if !success {
+                counts.failed.fetch_add(1, Ordering::Relaxed);
+            }
+        }
+    });
+}
+
+/// Runs `f` as a named step of a larger task: a ` SCOPE name ` header is written first, every
+/// banner, `END OUTPUT` marker, warning and labeled line of output written while `f` runs is
+/// indented by two more spaces, and a footer with how long `f` took and how many commands it
+/// ran, colored by whether it returned `Ok`, is written last. A scope ends when `f` returns,
+/// including early with `?`, and scopes nest.
+///
+/// Scopes are per thread: commands that `f` runs on threads of its own are neither indented nor
+/// counted, except for those of `run_parallel` and `ParallelRunner`, which run in the scopes of
+/// the thread that called them.
+pub fn scope<T, E>(name: &str, f: impl FnOnce() -> Result<T, E>) -> Result<T, E> {
+    write_header(name);
+    let counts = Arc::new(Counts::default());
+    let start = Instant::now();
+    let mut scopes = current();
+    scopes.0.push(Arc::clone(&counts));
+    let result = {
+        let _entered = scopes.enter();
+        f()
+    };
+    write_footer(name, result.is_ok(), start.elapsed(), &counts);
+    result
+}
+
+fn write_header(name: &str) {
+    let mut stderr = decoration_stream();
+    let spec = theme::theme().scope;
+    let _ = decorate(|| {
+        stderr.with_color(&spec, |s| write!(s, " SCOPE {} ", name))?;
+        writeln!(stderr)?;
+        stderr.flush()
+    });
+}
+
+// " END SCOPE build (2.31s, 3 commands) ", or " SCOPE FAILED build (2.31s, 3 commands, 1 failed) "
+fn write_footer(name: &str, success: bool, duration: Duration, counts: &Counts) {
+    let mut stderr = decoration_stream();
+    let theme = theme::theme();
+    let (spec, marker) = match success {
+        true => (theme.success, "END SCOPE"),
+        false => (theme.failure, "SCOPE FAILED"),
+    };
+    let commands = counts.commands.load(Ordering::Relaxed);
+    let failed = counts.failed.load(Ordering::Relaxed);
+    let _ = decorate(|| {
+        stderr.with_color(&spec, |s| {
+            write!(s, " {} {} ({}, ", marker, name, HumanDuration(duration))?;
+            match commands {
+                1 => write!(s, "1 command")?,
+                commands => write!(s, "{} commands", commands)?,
+            }
+            if failed > 0 {
+                write!(s, ", {} failed", failed)?;
+            }
+            write!(s, ") ")
+        })?;
+        writeln!(stderr)?;
+        stderr.flush()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cmd, shell, strip_ansi, test_support, CommandExt};
+
+    #[test]
+    fn nested_scopes() {
+        let text = test_support::decorations(|| {
+            let result: anyhow::Result<()> = scope("scope-outer", || {
+                cmd!("echo", "scope-1").exec()?;
+                scope("scope-inner", || {
+                    cmd!("echo", "scope-2").label("scope-label").exec()?;
+                    shell("exit 1; scope-3").exec()
+                })
+                .unwrap_err();
+                scope("scope-early", || {
+                    cmd!("echo", "scope-4").exec()?;
+                    shell("exit 2; scope-5").exec()?;
+                    cmd!("echo", "scope-never").exec()
+                })
+            });
+            assert!(result.is_err());
+        });
+        let cwd = std::env::current_dir().unwrap();
+        let expected = [
+            " SCOPE scope-outer ",
+            "  {cwd} echo scope-1",
+            "   END OUTPUT (T) ",
+            "   SCOPE scope-inner ",
+            "    [scope-label] {cwd} echo scope-2",
+            "    [scope-label]  END OUTPUT (T) ",
+            "    {cwd} sh -c 'exit 1; scope-3'",
+            "     END OUTPUT (T) ",
+            "   SCOPE FAILED scope-inner (T, 2 commands, 1 failed) ",
+            "   SCOPE scope-early ",
+            "    {cwd} echo scope-4",
+            "     END OUTPUT (T) ",
+            "    {cwd} sh -c 'exit 2; scope-5'",
+            "     END OUTPUT (T) ",
+            "   SCOPE FAILED scope-early (T, 2 commands, 1 failed) ",
+            " SCOPE FAILED scope-outer (T, 5 commands, 2 failed) ",
+        ];
+        let expected = expected.map(|line| line.replace("{cwd}", &cwd.display().to_string()));
+        // Lines of other tests are neither indented nor in a scope.
+        let plain = strip_ansi(&text);
+        let ours = plain.lines().filter(|line| line.starts_with("  ") || line.contains(" SCOPE "));
+        assert_eq!(ours.map(without_durations).collect::<Vec<_>>(), expected, "{}", plain);
+    }
+
+    // `line` with the duration after the first parenthesis, if any, replaced by `T`.
+    fn without_durations(line: &str) -> String {
+        let Some(start) = line.find('(') else {
+            return line.to_owned();
+        };
+        let end = start + line[start..].find([',', ')']).unwrap();
+        format!("{}(T{}", &line[..start], &line[end..])
+    }
+}
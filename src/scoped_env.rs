@@ -0,0 +1,115 @@
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::io::{self, Write};
+use std::marker::PhantomData;
+use std::sync::atomic::{AtomicBool, AtomicU64, Ordering};
+use std::sync::Mutex;
+
+use crate::{redact, shell_quote, DecorationStream};
+
+// The settings of the guards that are alive, in the order they were made, with the id of their
+// guard.
+static ACTIVE: Mutex<Vec<(u64, OsString, Option<OsString>)>> = Mutex::new(Vec::new());
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+static SHOW_IN_BANNER: AtomicBool = AtomicBool::new(false);
+
+/// Sets (`Some`) or removes (`None`) environment variables of the process until the returned
+/// guard is dropped, so that every command run in the meantime inherits them. Dropping the guard
+/// restores each variable to what it was, in the reverse order of the settings.
+///
+/// The environment is shared by every thread of the process, and changing it while another
+/// thread reads it is a data race on some platforms: C libraries read it without the lock that
+/// `std::env` takes, which is why `std::env::set_var` is `unsafe` as of Rust 2024. Only use this
+/// while no other thread can be reading the environment, such as before spawning any, which is
+/// also why `EnvGuard` isn't `Send`. A guard puts back the values it saw when it was created, so
+/// when two guards set the same variable, dropping the older one first leaves the variable with
+/// the value from before the newer one instead of the original.
+pub fn scoped_env<I>(vars: I) -> EnvGuard
+where
+    I: IntoIterator<Item = (OsString, Option<OsString>)>,
+{
+    let id = NEXT_ID.fetch_add(1, Ordering::Relaxed);
+    let mut previous = Vec::new();
+    let mut active = ACTIVE.lock().unwrap();
+    for (key, value) in vars {
+        previous.push((key.clone(), env::var_os(&key)));
+        match &value {
+            Some(value) => env::set_var(&key, value),
+            None => env::remove_var(&key),
+        }
+        active.push((id, key, value));
+    }
+    EnvGuard { id, previous, _not_send: PhantomData }
+}
+
+/// Shows the variables set or removed by live `scoped_env` guards in every banner, as in
+/// `/src cargo build [scoped env: RUSTFLAGS='-D warnings', CARGO_HOME unset]`, with secrets
+/// redacted as in command descriptions. Off by default.
+pub fn set_show_scoped_env(enabled: bool) {
+    SHOW_IN_BANNER.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn show_scoped_env() -> bool {
+    SHOW_IN_BANNER.load(Ordering::Relaxed)
+}
+
+#[must_use = "the previous environment is restored as soon as the guard is dropped"]
+pub struct EnvGuard {
+    id: u64,
+    // The value of each variable before it was set, in the order of the settings.
+    previous: Vec<(OsString, Option<OsString>)>,
+    _not_send: PhantomData<*const ()>,
+}
+
+impl EnvGuard {
+    /// The variables the guard restores, with their previous values.
+    pub fn previous(&self) -> impl Iterator<Item = (&OsStr, Option<&OsStr>)> {
+        self.previous.iter().map(|(key, value)| (key.as_os_str(), value.as_deref()))
+    }
+}
+
+impl Drop for EnvGuard {
+    fn drop(&mut self) {
+        ACTIVE.lock().unwrap().retain(|(id, ..)| *id != self.id);
+        for (key, value) in self.previous.drain(..).rev() {
+            match value {
+                Some(value) => env::set_var(&key, value),
+                None => env::remove_var(&key),
+            }
+        }
+    }
+}
+
+// " [scoped env: KEY=value, OTHER unset]" after the command of a banner, with the latest setting
+// of each variable.
+pub(crate) fn write_active(stderr: &mut DecorationStream) -> io::Result<()> {
+    if !show_scoped_env() {
+        return Ok(());
+    }
+    let mut settings: Vec<(OsString, Option<OsString>)> = Vec::new();
+    for (_, key, value) in ACTIVE.lock().unwrap().iter() {
+        match settings.iter_mut().find(|(k, _)| k == key) {
+            Some(setting) => setting.1 = value.clone(),
+            None => settings.push((key.clone(), value.clone())),
+        }
+    }
+    if settings.is_empty() {
+        return Ok(());
+    }
+    write!(stderr, " [scoped env: ")?;
+    for (i, (key, value)) in settings.iter().enumerate() {
+        if i > 0 {
+            write!(stderr, ", ")?;
+        }
+        match value {
+            Some(value) => write!(
+                stderr,
+                "{}={}",
+                key.to_string_lossy(),
+                shell_quote(&redact::env_value(key, value))
+            )?,
+            None => write!(stderr, "{} unset", key.to_string_lossy())?,
+        }
+    }
+    write!(stderr, "]")
+}
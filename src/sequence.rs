@@ -0,0 +1,172 @@
+use std::fmt::Display;
+use std::process::ExitStatus;
+
+use crate::{
+    decorate, decoration_stream, theme, CmdError, CommandExt, ConfiguredCommand, StatusSummary,
+    TermColorStandardStreamExt,
+};
+
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub enum SequenceMode {
+    /// Stop at the first failing command and return its error.
+    FailFast,
+    /// Run every command and report all failures together at the end.
+    ContinueOnError,
+}
+
+pub fn run_all<I>(commands: I, mode: SequenceMode) -> anyhow::Result<()>
+where
+    I: IntoIterator,
+    I::Item: Into<ConfiguredCommand>,
+{
+    Sequence::new(commands, mode).run()
+}
+
+/// Runs commands one after another through `exec`, printing a summary line when done.
+pub struct Sequence {
+    commands: Vec<ConfiguredCommand>,
+    mode: SequenceMode,
+}
+
+#[derive(Debug)]
+pub struct SequenceFailure {
+    pub index: usize,
+    pub description: String,
+    /// `None` if the command could not be spawned at all.
+    pub status: Option<ExitStatus>,
+    pub error: String,
+}
+
+/// Returned in `SequenceMode::ContinueOnError` when at least one command failed.
+#[derive(Debug)]
+pub struct SequenceFailed {
+    pub failures: Vec<SequenceFailure>,
+    pub total: usize,
+}
+
+impl Sequence {
+    pub fn new<I>(commands: I, mode: SequenceMode) -> Self
+    where
+        I: IntoIterator,
+        I::Item: Into<ConfiguredCommand>,
+    {
+        Sequence { commands: commands.into_iter().map(Into::into).collect(), mode }
+    }
+
+    pub fn commands(&self) -> &[ConfiguredCommand] {
+        &self.commands
+    }
+
+    /// `validate_all` for the commands, to find problems before any of them is run.
+    pub fn validate(&self) -> anyhow::Result<()> {
+        crate::validate_all(&self.commands)
+    }
+
+    pub fn run(&mut self) -> anyhow::Result<()> {
+        let total = self.commands.len();
+        let mut succeeded = 0;
+        let mut failures = Vec::new();
+        for (index, cmd) in self.commands.iter_mut().enumerate() {
+            let error = match cmd.exec() {
+                Ok(()) => {
+                    succeeded += 1;
+                    continue;
+                }
+                Err(e) => e,
+            };
+            if self.mode == SequenceMode::FailFast {
+                print_summary(succeeded, 1, total - succeeded - 1);
+                return Err(error.context(format!(
+                    "Command {} of {} failed ({})",
+                    index + 1,
+                    total,
+                    cmd.description(),
+                )));
+            }
+            failures.push(SequenceFailure {
+                index,
+                description: cmd.description().to_string(),
+                status: error.downcast_ref::<CmdError>().and_then(CmdError::status),
+                error: format!("{:#}", error),
+            });
+        }
+        print_summary(succeeded, failures.len(), 0);
+        if !failures.is_empty() {
+            return Err(SequenceFailed { failures, total }.into());
+        }
+        Ok(())
+    }
+}
+
+fn print_summary(succeeded: usize, failed: usize, not_run: usize) {
+    use std::io::Write;
+
+    let theme = theme::theme();
+    let mut spec = if failed == 0 { theme.success_text } else { theme.failure_text };
+    spec.set_bold(true);
+    let mut stderr = decoration_stream();
+    let _ = decorate(|| {
+        stderr.with_color(&spec, |s| {
+            write!(s, "{} succeeded, {} failed", succeeded, failed)?;
+            if not_run > 0 {
+                write!(s, ", {} not run", not_run)?;
+            }
+            Ok::<(), std::io::Error>(())
+        })?;
+        writeln!(stderr)
+    });
+}
+
+impl Display for SequenceFailed {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "{} of {} commands failed:", self.failures.len(), self.total)?;
+        for failure in &self.failures {
+            write!(f, "\n  [{}] ", failure.index + 1)?;
+            match failure.status {
+                Some(status) => write!(f, "{}", StatusSummary(status))?,
+                None => write!(f, "{}", failure.error)?,
+            }
+            write!(f, ": {}", failure.description)?;
+        }
+        Ok(())
+    }
+}
+
+impl std::error::Error for SequenceFailed {}
+
+#[cfg(test)]
+mod tests {
+    use super::{Sequence, SequenceFailed, SequenceMode};
+    use crate::test_support::decorations;
+    use crate::{shell, CmdError};
+
+    #[test]
+    fn failures_show_the_status_summary() {
+        let commands = vec![shell("exit 3"), shell("true"), shell("kill -9 $$")];
+        let mut result = None;
+        decorations(|| {
+            result = Some(Sequence::new(commands, SequenceMode::ContinueOnError).run());
+        });
+        let err = result.unwrap().err().unwrap();
+        let failed = err.downcast_ref::<SequenceFailed>().unwrap().to_string();
+        assert!(failed.starts_with("2 of 3 commands failed:"), "{}", failed);
+        assert!(failed.contains("[1] exit code 3: "), "{}", failed);
+        assert!(failed.contains("[3] killed by signal 9 (SIGKILL): "), "{}", failed);
+    }
+
+    #[test]
+    fn fail_fast_stops_at_the_first_failure() {
+        let commands =
+            ["true", "exit 4", "true"].map(|s| shell(format!("{} # sequence fail-fast", s)));
+        let mut result = None;
+        let text = decorations(|| {
+            result = Some(Sequence::new(commands, SequenceMode::FailFast).run());
+        });
+        let err = result.unwrap().err().unwrap();
+        assert!(err.to_string().starts_with("Command 2 of 3 failed"), "{:#}", err);
+        assert_eq!(err.downcast_ref::<CmdError>().and_then(CmdError::exit_code), Some(4));
+        let run = text.lines().filter(|line| line.contains("# sequence fail-fast")).count();
+        assert_eq!(run, 2, "{}", text);
+        assert!(text.contains("1 succeeded, 1 failed, 1 not run"), "{}", text);
+    }
+}
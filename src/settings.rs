@@ -0,0 +1,115 @@
+use std::borrow::Borrow;
+use std::fmt;
+use std::ops::{Deref, DerefMut};
+use std::process::Command;
+use std::time::Duration;
+
+use crate::capture::Pipes;
+use crate::{copy_command, group};
+
+// What `label`, `new_process_group`, `warn_after`, and `heartbeat` set on a command, and which
+// of its streams were set with `stdin_`, `stdout_` or `stderr_`.
+#[derive(Clone, Default, PartialEq)]
+pub(crate) struct Settings {
+    pub(crate) label: Option<String>,
+    pub(crate) process_group: bool,
+    pub(crate) warn_after: Option<Duration>,
+    pub(crate) heartbeat: Option<Duration>,
+    pub(crate) redirected: Pipes,
+}
+
+/// A `Command` along with the settings of `CommandExt` that `Command` has no room for: the
+/// label, the process group, the `warn_after` threshold, the heartbeat interval, and which
+/// streams were set with `stdin_`, `stdout_` or `stderr_`. The methods making those settings
+/// return one, and it has all the methods of `CommandExt` as well as, through `Deref`, those of
+/// `Command`.
+///
+/// ```no_run
+/// use cmd_utils::{cmd, CommandExt};
+///
+/// let mut build = cmd("cargo").arg_("build").label("build");
+/// build.env("CARGO_TERM_COLOR", "always");
+/// build.exec()?;
+/// # anyhow::Ok(())
+/// ```
+pub struct ConfiguredCommand {
+    command: Command,
+    settings: Settings,
+}
+
+impl ConfiguredCommand {
+    /// The command, without the settings.
+    pub fn into_command(self) -> Command {
+        self.command
+    }
+
+    pub(crate) fn settings(&self) -> &Settings {
+        &self.settings
+    }
+
+    pub(crate) fn settings_mut(&mut self) -> &mut Settings {
+        &mut self.settings
+    }
+
+    // `command`, rebuilt from this one, with the same settings. Its streams are inherited, so
+    // none of them count as set with `stdin_` and the like.
+    pub(crate) fn rebuilt(&self, mut command: Command) -> ConfiguredCommand {
+        if self.settings.process_group {
+            group::apply(&mut command);
+        }
+        let settings = Settings { redirected: Pipes::default(), ..self.settings.clone() };
+        ConfiguredCommand { command, settings }
+    }
+
+    // A copy as made by `copy_command`, with the same settings.
+    pub(crate) fn copy(&self) -> ConfiguredCommand {
+        self.rebuilt(copy_command(&self.command))
+    }
+}
+
+impl From<Command> for ConfiguredCommand {
+    fn from(command: Command) -> Self {
+        ConfiguredCommand { command, settings: Settings::default() }
+    }
+}
+
+impl From<ConfiguredCommand> for Command {
+    fn from(cmd: ConfiguredCommand) -> Self {
+        cmd.command
+    }
+}
+
+impl Borrow<Command> for ConfiguredCommand {
+    fn borrow(&self) -> &Command {
+        &self.command
+    }
+}
+
+impl Deref for ConfiguredCommand {
+    type Target = Command;
+
+    fn deref(&self) -> &Command {
+        &self.command
+    }
+}
+
+impl DerefMut for ConfiguredCommand {
+    fn deref_mut(&mut self) -> &mut Command {
+        &mut self.command
+    }
+}
+
+impl fmt::Debug for ConfiguredCommand {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        self.command.fmt(f)
+    }
+}
+
+// Runs `f` on `cmd` as a `ConfiguredCommand` without settings, for the methods of `CommandExt`
+// that `Command` takes by reference, and puts `cmd` back afterwards.
+pub(crate) fn configured<T>(cmd: &mut Command, f: impl FnOnce(&mut ConfiguredCommand) -> T) -> T {
+    let mut configured = ConfiguredCommand::from(std::mem::replace(cmd, Command::new("")));
+    let result = f(&mut configured);
+    *cmd = configured.command;
+    result
+}
@@ -0,0 +1,141 @@
+use std::io::Write;
+use std::sync::mpsc::{self, RecvTimeoutError, Sender};
+use std::sync::Mutex;
+use std::thread::{self, JoinHandle};
+use std::time::{Duration, Instant};
+
+use crate::{
+    decorate, decoration_stream, label, scope, theme, CommandExt, ConfiguredCommand, HumanDuration,
+    TermColorStandardStreamExt,
+};
+
+static DEFAULT_THRESHOLD: Mutex<Option<Duration>> = Mutex::new(None);
+
+/// Warns about every command that runs for longer than `after`, unless it was given a threshold
+/// of its own with `warn_after`. `None`, the default, turns the warnings off.
+///
+/// While such a command runs, a "still running after ..." line is written with the decorations
+/// each time another `after` has passed, and its `END OUTPUT` marker shows the duration with
+/// `Theme::slow`.
+pub fn set_slow_command_warning(after: Option<Duration>) {
+    *DEFAULT_THRESHOLD.lock().unwrap() = after;
+}
+
+pub(crate) fn default_threshold() -> Option<Duration> {
+    *DEFAULT_THRESHOLD.lock().unwrap()
+}
+
+pub(crate) fn set(cmd: &mut ConfiguredCommand, after: Duration) {
+    cmd.settings_mut().warn_after.get_or_insert(after);
+}
+
+fn get(cmd: &ConfiguredCommand) -> Option<Duration> {
+    cmd.settings().warn_after
+}
+
+pub(crate) fn threshold(cmd: &ConfiguredCommand) -> Option<Duration> {
+    get(cmd).or_else(default_threshold).filter(|after| !after.is_zero())
+}
+
+pub(crate) fn exceeded(cmd: &ConfiguredCommand, duration: Duration) -> bool {
+    threshold(cmd).is_some_and(|after| duration >= after)
+}
+
+// Writes the warnings (or, from `heartbeat.rs`, the heartbeats) from a thread of its own until
+// it is dropped, which waits for the thread so that nothing comes after the `END OUTPUT` marker.
+pub(crate) struct Watch {
+    stop: Option<Sender<()>>,
+    thread: Option<JoinHandle<()>>,
+}
+
+pub(crate) fn watch(cmd: &ConfiguredCommand) -> Option<Watch> {
+    let after = threshold(cmd)?;
+    let label = label::get(cmd).map(str::to_owned);
+    let text = cmd.description().shell();
+    Some(every(after, move |elapsed| write_warning(label.as_deref(), &text, elapsed)))
+}
+
+// Calls `tick` with the time since the start each time another `interval` has passed.
+pub(crate) fn every(interval: Duration, mut tick: impl FnMut(Duration) + Send + 'static) -> Watch {
+    let (stop, stopped) = mpsc::channel::<()>();
+    let start = Instant::now();
+    let scopes = scope::current();
+    let thread = thread::spawn(move || {
+        let _entered = scopes.enter();
+        let mut next = interval;
+        loop {
+            match stopped.recv_timeout(next.saturating_sub(start.elapsed())) {
+                Err(RecvTimeoutError::Timeout) => {}
+                _ => return,
+            }
+            tick(start.elapsed());
+            next += interval;
+        }
+    });
+    Watch { stop: Some(stop), thread: Some(thread) }
+}
+
+impl Drop for Watch {
+    fn drop(&mut self) {
+        drop(self.stop.take());
+        if let Some(thread) = self.thread.take() {
+            let _ = thread.join();
+        }
+    }
+}
+
+fn write_warning(label: Option<&str>, text: &str, elapsed: Duration) {
+    let mut stderr = decoration_stream();
+    let spec = theme::theme().slow;
+    let _ = decorate(|| {
+        if let Some(label) = label {
+            label::write_tag(&mut stderr, label)?;
+        }
+        stderr.with_color(&spec, |s| {
+            write!(s, "still running after {}: {}", HumanDuration(elapsed), text)
+        })?;
+        writeln!(stderr)?;
+        stderr.flush()
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cmd, shell, test_support};
+
+    fn lines<'a>(text: &'a str, label: &str) -> Vec<&'a str> {
+        let tag = format!("[{}]", label);
+        text.lines().filter(|line| line.contains(&tag)).collect()
+    }
+
+    #[test]
+    fn slow_commands_are_warned_about_once_per_threshold() {
+        let text = test_support::decorations(|| {
+            let after = Duration::from_millis(300);
+            shell("sleep 0.5").label("slow-once").warn_after(after).exec().unwrap();
+            shell("true").label("slow-fast").warn_after(after).exec().unwrap();
+        });
+        let slow = lines(&text, "slow-once");
+        let warnings: Vec<_> = slow.iter().filter(|line| line.contains("still running")).collect();
+        assert_eq!(warnings.len(), 1, "{:?}", slow);
+        assert!(warnings[0].contains("\x1b[33mstill running after 3"), "{:?}", warnings[0]);
+        assert!(warnings[0].ends_with("ms: sh -c 'sleep 0.5'\x1b[0m"), "{:?}", warnings[0]);
+        assert!(slow.last().unwrap().contains("\x1b[33m\x1b[42m"), "{:?}", slow);
+        let fast = lines(&text, "slow-fast");
+        assert_eq!(fast.len(), 2, "{:?}", fast);
+        assert!(!fast[1].contains("\x1b[33m"), "{:?}", fast);
+    }
+
+    #[test]
+    fn thresholds() {
+        let mut own = cmd("true").warn_after(Duration::from_secs(1));
+        assert_eq!(threshold(&own), Some(Duration::from_secs(1)));
+        own = own.warn_after(Duration::from_secs(2));
+        assert_eq!(threshold(&own), Some(Duration::from_secs(1)));
+        assert!(exceeded(&own, Duration::from_secs(1)));
+        assert!(!exceeded(&own, Duration::from_millis(999)));
+        let off = cmd("true").warn_after(Duration::ZERO);
+        assert_eq!(threshold(&off), None);
+    }
+}
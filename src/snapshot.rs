@@ -0,0 +1,137 @@
+use std::collections::BTreeMap;
+
+use crate::{effective_dir, redact, CommandDescription};
+
+/// An owned copy of what a `Command` was configured with, with paths and arguments converted
+/// lossily to strings and secrets redacted as in `CommandDescription`. An environment variable
+/// mapped to `None` was removed from the child's environment.
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize))]
+pub struct CommandSnapshot {
+    pub program: String,
+    pub args: Vec<String>,
+    pub envs: BTreeMap<String, Option<String>>,
+    pub cwd: Option<String>,
+}
+
+impl CommandDescription<'_> {
+    pub fn to_snapshot(&self) -> CommandSnapshot {
+        let cmd = self.cmd;
+        let cwd = effective_dir(cmd);
+        CommandSnapshot {
+            program: redact::scrub(cmd.get_program()).to_string_lossy().into_owned(),
+            args: cmd
+                .get_args()
+                .map(|arg| redact::scrub(arg).to_string_lossy().into_owned())
+                .collect(),
+            envs: cmd
+                .get_envs()
+                .map(|(key, value)| {
+                    let value = value
+                        .map(|value| redact::env_value(key, value).to_string_lossy().into_owned());
+                    (key.to_string_lossy().into_owned(), value)
+                })
+                .collect(),
+            cwd: cwd.map(|cwd| redact::scrub(cwd.as_os_str()).to_string_lossy().into_owned()),
+        }
+    }
+}
+
+// Serializes as `command` (a `CommandSnapshot`), `exit_code` (null if killed by a signal),
+// `success`, `stdout`, and `stderr` converted lossily. `stderr_base64` holds the exact bytes of
+// stderr when it is not UTF-8, and is null otherwise.
+#[cfg(feature = "serde")]
+impl serde::Serialize for crate::Output {
+    fn serialize<S: serde::Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        use serde::ser::SerializeStruct;
+
+        use crate::CommandExt;
+        let stderr_base64 = match std::str::from_utf8(&self.stderr) {
+            Ok(_) => None,
+            Err(_) => Some(base64(&self.stderr)),
+        };
+        let mut s = serializer.serialize_struct("Output", 6)?;
+        s.serialize_field("command", &self.command.description().to_snapshot())?;
+        s.serialize_field("exit_code", &self.status.code())?;
+        s.serialize_field("success", &self.status.success())?;
+        s.serialize_field("stdout", &self.stdout)?;
+        s.serialize_field("stderr", &String::from_utf8_lossy(&self.stderr))?;
+        s.serialize_field("stderr_base64", &stderr_base64)?;
+        s.end()
+    }
+}
+
+#[cfg(feature = "serde")]
+fn base64(bytes: &[u8]) -> String {
+    const ALPHABET: &[u8; 64] = b"ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz0123456789+/";
+    let mut encoded = String::with_capacity(bytes.len().div_ceil(3) * 4);
+    for chunk in bytes.chunks(3) {
+        let n = chunk.iter().enumerate().fold(0u32, |n, (i, &b)| n | (b as u32) << (16 - 8 * i));
+        for i in 0..4 {
+            match i <= chunk.len() {
+                true => encoded.push(ALPHABET[(n >> (18 - 6 * i) & 0x3f) as usize] as char),
+                false => encoded.push('='),
+            }
+        }
+    }
+    encoded
+}
+
+impl std::fmt::Display for CommandSnapshot {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(
+            f,
+            "program = {:?}, args = {:?}, envs = {:?}, cwd = {:?}",
+            self.program, self.args, self.envs, self.cwd,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{cmd, CommandExt};
+
+    #[test]
+    fn snapshot_copies_the_configuration() {
+        let cmd = cmd("git").args_(["log", "-1"]).env_("PAGER", "cat").env_remove_("GIT_DIR");
+        let snapshot = cmd.current_dir_("/").description().to_snapshot();
+        let envs = BTreeMap::from([
+            ("GIT_DIR".to_owned(), None),
+            ("PAGER".to_owned(), Some("cat".to_owned())),
+        ]);
+        let expected = CommandSnapshot {
+            program: "git".to_owned(),
+            args: vec!["log".to_owned(), "-1".to_owned()],
+            envs,
+            cwd: Some("/".to_owned()),
+        };
+        assert_eq!(snapshot, expected);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn base64_pads_partial_chunks() {
+        let cases: [(&[u8], &str); 5] =
+            [(b"", ""), (b"f", "Zg=="), (b"fo", "Zm8="), (b"foo", "Zm9v"), (b"\xff\xfe", "//4=")];
+        for (bytes, expected) in cases {
+            assert_eq!(base64(bytes), expected, "{:?}", bytes);
+        }
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn output_serializes_as_a_json_object() {
+        let out = crate::shell(r"echo out; printf 'err\377' >&2").exec_stdout_string().unwrap();
+        let json = serde_json::to_value(&out).unwrap();
+        assert_eq!(json["command"]["program"], "sh");
+        assert_eq!(json["command"]["args"][1], r"echo out; printf 'err\377' >&2");
+        assert_eq!(json["command"]["envs"], serde_json::json!({}));
+        assert_eq!((&json["exit_code"], &json["success"]), (&0.into(), &true.into()));
+        assert_eq!(json["stdout"], "out\n");
+        assert_eq!(json["stderr"], "err\u{FFFD}");
+        assert_eq!(json["stderr_base64"], "ZXJy/w==");
+        let clean = crate::shell("true").exec_stdout_string().unwrap();
+        assert!(serde_json::to_value(&clean).unwrap()["stderr_base64"].is_null());
+    }
+}
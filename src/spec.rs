@@ -0,0 +1,194 @@
+use std::collections::BTreeMap;
+use std::process::Command;
+
+use crate::{CommandExt, ConfiguredCommand, Output, RetryPolicy};
+
+/// A command to run, as plain data: unlike a `Command`, it can be cloned, compared, stored in
+/// configuration and, with the `serde` feature, read from it. Every run builds a new `Command`
+/// from it. An environment variable mapped to `None` is removed from the child's environment, and
+/// with `env_clear` the child only gets the variables in `envs`.
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[cfg_attr(feature = "serde", serde(default))]
+pub struct CommandSpec {
+    pub program: String,
+    pub args: Vec<String>,
+    pub envs: BTreeMap<String, Option<String>>,
+    pub env_clear: bool,
+    pub cwd: Option<String>,
+}
+
+impl CommandSpec {
+    pub fn new(program: impl Into<String>) -> Self {
+        CommandSpec { program: program.into(), ..CommandSpec::default() }
+    }
+
+    pub fn arg(self, arg: impl Into<String>) -> Self {
+        let mut args = self.args;
+        args.push(arg.into());
+        CommandSpec { args, ..self }
+    }
+
+    pub fn args<I, S>(self, args: I) -> Self
+    where
+        I: IntoIterator<Item = S>,
+        S: Into<String>,
+    {
+        let mut all = self.args;
+        all.extend(args.into_iter().map(Into::into));
+        CommandSpec { args: all, ..self }
+    }
+
+    pub fn env(self, key: impl Into<String>, value: impl Into<String>) -> Self {
+        let mut envs = self.envs;
+        envs.insert(key.into(), Some(value.into()));
+        CommandSpec { envs, ..self }
+    }
+
+    pub fn env_remove(self, key: impl Into<String>) -> Self {
+        let mut envs = self.envs;
+        envs.insert(key.into(), None);
+        CommandSpec { envs, ..self }
+    }
+
+    pub fn env_clear(self) -> Self {
+        CommandSpec { env_clear: true, envs: BTreeMap::new(), ..self }
+    }
+
+    pub fn current_dir(self, dir: impl Into<String>) -> Self {
+        CommandSpec { cwd: Some(dir.into()), ..self }
+    }
+
+    pub fn to_command(&self) -> Command {
+        let mut cmd = crate::cmd(&self.program);
+        cmd.args(&self.args);
+        if self.env_clear {
+            cmd.env_clear();
+        }
+        for (key, value) in &self.envs {
+            match value {
+                Some(value) => cmd.env(key, value),
+                None => cmd.env_remove(key),
+            };
+        }
+        if let Some(dir) = &self.cwd {
+            cmd.current_dir(dir);
+        }
+        cmd
+    }
+
+    pub fn exec(&self) -> anyhow::Result<()> {
+        self.to_command().exec()
+    }
+
+    pub fn exec_stdout_string(&self) -> anyhow::Result<Output> {
+        self.to_command().exec_stdout_string()
+    }
+
+    pub fn exec_retry(&self, policy: RetryPolicy) -> anyhow::Result<()> {
+        self.to_command().exec_retry(policy)
+    }
+
+    pub fn exec_stdout_string_retry(&self, policy: RetryPolicy) -> anyhow::Result<Output> {
+        self.to_command().exec_stdout_string_retry(policy)
+    }
+}
+
+// Takes what the getters of `Command` show, converted lossily to strings. `env_clear` can't be
+// read back and is always false, and settings such as `label` are not kept.
+impl From<&Command> for CommandSpec {
+    fn from(cmd: &Command) -> Self {
+        let lossy = |s: &std::ffi::OsStr| s.to_string_lossy().into_owned();
+        CommandSpec {
+            program: lossy(cmd.get_program()),
+            args: cmd.get_args().map(lossy).collect(),
+            envs: cmd.get_envs().map(|(key, value)| (lossy(key), value.map(lossy))).collect(),
+            env_clear: false,
+            cwd: cmd.get_current_dir().map(|dir| lossy(dir.as_os_str())),
+        }
+    }
+}
+
+impl From<&CommandSpec> for Command {
+    fn from(spec: &CommandSpec) -> Self {
+        spec.to_command()
+    }
+}
+
+impl From<CommandSpec> for Command {
+    fn from(spec: CommandSpec) -> Self {
+        spec.to_command()
+    }
+}
+
+impl From<&CommandSpec> for ConfiguredCommand {
+    fn from(spec: &CommandSpec) -> Self {
+        spec.to_command().into()
+    }
+}
+
+impl From<CommandSpec> for ConfiguredCommand {
+    fn from(spec: CommandSpec) -> Self {
+        spec.to_command().into()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn spec() -> CommandSpec {
+        CommandSpec::new("sh")
+            .args(["-c", "echo $SPEC_VALUE; pwd"])
+            .env("SPEC_VALUE", "from spec")
+            .env_remove("SPEC_REMOVED")
+            .current_dir("/")
+    }
+
+    #[test]
+    fn specs_round_trip_through_commands() {
+        let spec = spec();
+        let cmd = spec.to_command();
+        let snapshot = cmd.description().to_snapshot();
+        assert_eq!((snapshot.program.as_str(), &snapshot.args), ("sh", &spec.args));
+        assert_eq!(snapshot.envs["SPEC_VALUE"].as_deref(), Some("from spec"));
+        assert_eq!(snapshot.envs["SPEC_REMOVED"], None);
+        assert_eq!(snapshot.cwd.as_deref(), Some("/"));
+        assert_eq!(CommandSpec::from(&cmd), spec);
+        assert_eq!(spec.exec_stdout_string().unwrap().stdout, "from spec\n/\n");
+        // Each run gets a command of its own.
+        assert_eq!(spec.clone().exec_stdout_string().unwrap().stdout, "from spec\n/\n");
+    }
+
+    #[test]
+    fn env_clear_leaves_only_the_given_variables() {
+        let spec = CommandSpec::new("/usr/bin/env").env("OTHER", "x").env_clear().env("ONLY", "1");
+        assert_eq!(spec.envs.len(), 1);
+        assert_eq!(spec.exec_stdout_string().unwrap().stdout, "ONLY=1\n");
+        let mut cmd = crate::cmd("/usr/bin/env");
+        cmd.env_clear();
+        assert!(!CommandSpec::from(&cmd).env_clear);
+    }
+
+    #[test]
+    fn specs_fan_out_over_inputs() {
+        let base = CommandSpec::new("echo").arg("spec-input");
+        let specs: Vec<_> = (0..4).map(|i| base.clone().arg(i.to_string())).collect();
+        let outputs = crate::run_parallel(&specs, 2);
+        let stdouts: Vec<_> = outputs.into_iter().map(|out| out.unwrap().stdout).collect();
+        assert_eq!(
+            stdouts,
+            ["spec-input 0\n", "spec-input 1\n", "spec-input 2\n", "spec-input 3\n"]
+        );
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn specs_are_read_with_defaults() {
+        let read: CommandSpec =
+            serde_json::from_str(r#"{"program": "ls", "args": ["-l"]}"#).unwrap();
+        assert_eq!(read, CommandSpec::new("ls").arg("-l"));
+        let json = serde_json::to_string(&spec()).unwrap();
+        assert_eq!(serde_json::from_str::<CommandSpec>(&json).unwrap(), spec());
+    }
+}
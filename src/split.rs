@@ -0,0 +1,151 @@
+use std::process::Command;
+
+/// Builds a command from a single command line, using the first token as the program. See
+/// `args_from_str` for the quoting rules.
+pub fn cmd_from_str(line: &str) -> anyhow::Result<Command> {
+    let args = args_from_str(line)?;
+    let Some((program, args)) = args.split_first() else {
+        anyhow::bail!("Command line is empty ({:?})", line);
+    };
+    let mut cmd = crate::cmd(program);
+    cmd.args(args);
+    Ok(cmd)
+}
+
+/// Splits a command line into arguments with POSIX-like quoting: single quotes preserve
+/// everything literally, double quotes allow `\` to escape `"`, `\`, `$`, `` ` `` and newline,
+/// and an unquoted `\` escapes any character. Nothing else is interpreted: there is no variable
+/// expansion, globbing, or operator handling, and no shell is invoked.
+pub fn args_from_str(line: &str) -> anyhow::Result<Vec<String>> {
+    let mut args = Vec::new();
+    let mut current: Option<String> = None;
+    let mut chars = line.char_indices();
+    while let Some((i, c)) = chars.next() {
+        match c {
+            c if c.is_whitespace() => {
+                args.extend(current.take());
+            }
+            '\'' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some((_, '\'')) => break,
+                        Some((_, c)) => arg.push(c),
+                        None => anyhow::bail!(
+                            "Unterminated single quote at byte {} in command line {:?}",
+                            i,
+                            line,
+                        ),
+                    }
+                }
+            }
+            '"' => {
+                let arg = current.get_or_insert_with(String::new);
+                loop {
+                    match chars.next() {
+                        Some((_, '"')) => break,
+                        Some((j, '\\')) => match chars.next() {
+                            Some((_, c @ ('"' | '\\' | '$' | '`'))) => arg.push(c),
+                            Some((_, '\n')) => {}
+                            Some((_, c)) => {
+                                arg.push('\\');
+                                arg.push(c);
+                            }
+                            None => anyhow::bail!(
+                                "Trailing backslash at byte {} in command line {:?}",
+                                j,
+                                line,
+                            ),
+                        },
+                        Some((_, c)) => arg.push(c),
+                        None => anyhow::bail!(
+                            "Unterminated double quote at byte {} in command line {:?}",
+                            i,
+                            line,
+                        ),
+                    }
+                }
+            }
+            '\\' => match chars.next() {
+                Some((_, '\n')) => {}
+                Some((_, c)) => current.get_or_insert_with(String::new).push(c),
+                None => {
+                    anyhow::bail!("Trailing backslash at byte {} in command line {:?}", i, line)
+                }
+            },
+            c => current.get_or_insert_with(String::new).push(c),
+        }
+    }
+    args.extend(current);
+    Ok(args)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn split(line: &str) -> Vec<String> {
+        args_from_str(line).unwrap()
+    }
+
+    fn error(line: &str) -> String {
+        args_from_str(line).err().unwrap().to_string()
+    }
+
+    #[test]
+    fn quotes_group_words() {
+        assert_eq!(
+            split("cargo test --features 'foo bar'"),
+            ["cargo", "test", "--features", "foo bar"]
+        );
+        assert_eq!(split(r#"a"b c"d  'e'f"#), ["ab cd", "ef"]);
+        assert_eq!(split("  \t "), Vec::<String>::new());
+    }
+
+    #[test]
+    fn nested_quotes_are_literal() {
+        assert_eq!(split(r#"echo "it's" '"quoted"'"#), ["echo", "it's", r#""quoted""#]);
+        assert_eq!(split(r#"sh -c "echo \"\$HOME\" \`x\`""#), ["sh", "-c", r#"echo "$HOME" `x`"#]);
+        assert_eq!(split(r#""a\b" 'a\b'"#), [r"a\b", r"a\b"]);
+    }
+
+    #[test]
+    fn empty_quotes_are_empty_args() {
+        assert_eq!(split("git commit -m ''"), ["git", "commit", "-m", ""]);
+        assert_eq!(split(r#""" ''"#), ["", ""]);
+    }
+
+    #[test]
+    fn backslashes_escape_outside_quotes() {
+        assert_eq!(split(r"a\ b c\'d"), ["a b", "c'd"]);
+        assert_eq!(split("a \\\nb"), ["a", "b"]);
+    }
+
+    #[test]
+    fn nothing_is_expanded() {
+        assert_eq!(split("echo $HOME *.rs ~ a|b"), ["echo", "$HOME", "*.rs", "~", "a|b"]);
+    }
+
+    #[test]
+    fn errors_give_the_byte_offset() {
+        assert_eq!(
+            error("echo 'unterminated"),
+            r#"Unterminated single quote at byte 5 in command line "echo 'unterminated""#
+        );
+        assert!(error(r#"a "b"#).starts_with("Unterminated double quote at byte 2"));
+        assert!(error(r"echo a\").starts_with("Trailing backslash at byte 6"));
+        assert!(error(r#"echo "a\"#).starts_with("Trailing backslash at byte 7"));
+    }
+
+    #[test]
+    fn cmd_from_str_takes_the_program_first() {
+        let cmd = cmd_from_str("grep -r 'fn main' src").unwrap();
+        assert_eq!(cmd.get_program(), "grep");
+        assert_eq!(cmd.get_args().collect::<Vec<_>>(), ["-r", "fn main", "src"]);
+        assert!(cmd_from_str("   ")
+            .err()
+            .unwrap()
+            .to_string()
+            .starts_with("Command line is empty"));
+    }
+}
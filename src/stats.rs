@@ -0,0 +1,189 @@
+use std::collections::VecDeque;
+use std::io::Write;
+use std::process::{Command, ExitStatus};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Mutex;
+use std::time::Duration;
+
+use crate::{
+    decorate, decoration_stream, theme, CommandExt, CommandSnapshot, HumanDuration, StatusSummary,
+    TermColorStandardStreamExt,
+};
+
+// How many of the slowest commands, and of the most recent failures, are kept.
+const SLOWEST_KEPT: usize = 10;
+const FAILURES_KEPT: usize = 100;
+
+static ENABLED: AtomicBool = AtomicBool::new(false);
+static STATS: Mutex<Stats> = Mutex::new(Stats::new());
+
+/// Starts or stops collecting statistics about every command run from any thread, as returned
+/// by [`stats`]. Off by default. Besides the totals, only the slowest commands and the most
+/// recent failures are kept, so collecting costs the same however long the process runs.
+pub fn set_stats_enabled(enabled: bool) {
+    ENABLED.store(enabled, Ordering::Relaxed);
+}
+
+pub(crate) fn stats_enabled() -> bool {
+    ENABLED.load(Ordering::Relaxed)
+}
+
+/// The statistics collected since they were enabled or last reset.
+pub fn stats() -> Stats {
+    STATS.lock().unwrap().clone()
+}
+
+/// Clears the collected statistics, for example between the phases of a script.
+pub fn reset_stats() {
+    *STATS.lock().unwrap() = Stats::new();
+}
+
+/// A command that failed or was among the slowest. `exit_code` is `None` if the command could not
+/// be spawned or waited for, or was killed.
+#[derive(Debug, Clone)]
+pub struct CommandStat {
+    pub command: CommandSnapshot,
+    /// The command as shown in banners.
+    pub description: String,
+    pub exit_code: Option<i32>,
+    pub duration: Duration,
+    status: Option<ExitStatus>,
+}
+
+#[derive(Debug, Clone)]
+pub struct Stats {
+    pub commands: u64,
+    pub failed: u64,
+    /// The sum of the durations of the commands, which overlap for commands run in parallel.
+    pub total_duration: Duration,
+    /// The most recent failures, oldest first. There were more if `failed` is larger.
+    pub failures: VecDeque<CommandStat>,
+    /// The slowest commands, slowest first.
+    pub slowest: Vec<CommandStat>,
+}
+
+impl Stats {
+    const fn new() -> Self {
+        Stats {
+            commands: 0,
+            failed: 0,
+            total_duration: Duration::ZERO,
+            failures: VecDeque::new(),
+            slowest: Vec::new(),
+        }
+    }
+
+    /// Writes "ran 47 commands, 3 failed, total child time 6m 12s", the slowest commands and the
+    /// recent failures, with the decorations.
+    pub fn print_summary(&self) {
+        let theme = theme::theme();
+        let mut stderr = decoration_stream();
+        let header_spec = match self.failed {
+            0 => &theme.success_text,
+            _ => &theme.failure_text,
+        };
+        let _ = decorate(|| {
+            stderr.with_color(header_spec, |s| {
+                match self.commands {
+                    1 => write!(s, "ran 1 command")?,
+                    commands => write!(s, "ran {} commands", commands)?,
+                }
+                write!(
+                    s,
+                    ", {} failed, total child time {}",
+                    self.failed,
+                    HumanDuration(self.total_duration),
+                )
+            })?;
+            writeln!(stderr)?;
+            if !self.slowest.is_empty() {
+                writeln!(stderr, "slowest:")?;
+                for stat in &self.slowest {
+                    stderr.with_color(&theme.slow, |s| {
+                        write!(s, "  {:>9}", HumanDuration(stat.duration).to_string())
+                    })?;
+                    writeln!(stderr, "  {}", stat.description)?;
+                }
+            }
+            if !self.failures.is_empty() {
+                match self.failed as usize - self.failures.len() {
+                    0 => writeln!(stderr, "failed:")?,
+                    omitted => writeln!(stderr, "failed ({} earlier not shown):", omitted)?,
+                }
+                for stat in &self.failures {
+                    let status = match stat.status {
+                        Some(status) => StatusSummary(status).to_string(),
+                        None => "did not finish".to_string(),
+                    };
+                    stderr.with_color(&theme.failure_text, |s| write!(s, "  {}", status))?;
+                    writeln!(stderr, "  {}", stat.description)?;
+                }
+            }
+            stderr.flush()
+        });
+    }
+
+    fn record(&mut self, cmd: &Command, duration: Duration, status: Option<&ExitStatus>) {
+        let success = status.is_some_and(ExitStatus::success);
+        self.commands += 1;
+        self.total_duration += duration;
+        let slow = self.slowest.len() < SLOWEST_KEPT
+            || self.slowest.last().is_some_and(|slowest| duration > slowest.duration);
+        if success && !slow {
+            return;
+        }
+        let stat = CommandStat {
+            command: cmd.description().to_snapshot(),
+            description: cmd.description().shell(),
+            exit_code: status.and_then(ExitStatus::code),
+            duration,
+            status: status.copied(),
+        };
+        if slow {
+            let i = self.slowest.partition_point(|slower| slower.duration >= duration);
+            self.slowest.insert(i, stat.clone());
+            self.slowest.truncate(SLOWEST_KEPT);
+        }
+        if !success {
+            self.failed += 1;
+            if self.failures.len() == FAILURES_KEPT {
+                self.failures.pop_front();
+            }
+            self.failures.push_back(stat);
+        }
+    }
+}
+
+pub(crate) fn record(cmd: &Command, duration: Duration, status: Option<&ExitStatus>) {
+    if stats_enabled() {
+        STATS.lock().unwrap().record(cmd, duration, status);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shell;
+
+    #[test]
+    fn only_the_slowest_and_the_recent_failures_are_kept() {
+        let (ok, failed) = (shell("true").status().unwrap(), shell("exit 3").status().unwrap());
+        let mut stats = Stats::new();
+        for i in 0..1000u64 {
+            let status = if i % 5 == 0 { failed } else { ok };
+            let cmd = shell(format!("run {}", i));
+            stats.record(&cmd, Duration::from_millis(i % 97), Some(&status));
+        }
+        stats.record(&shell("spawn failure"), Duration::ZERO, None);
+        assert_eq!((stats.commands, stats.failed), (1001, 201));
+        let total: u64 = (0..1000).map(|i| i % 97).sum();
+        assert_eq!(stats.total_duration, Duration::from_millis(total));
+        assert_eq!(stats.slowest.len(), SLOWEST_KEPT);
+        assert!(stats.slowest.iter().all(|stat| stat.duration == Duration::from_millis(96)));
+        assert_eq!(stats.failures.len(), FAILURES_KEPT);
+        let last = stats.failures.back().unwrap();
+        assert_eq!((last.description.as_str(), last.exit_code), ("sh -c 'spawn failure'", None));
+        assert_eq!(stats.failures[FAILURES_KEPT - 2].exit_code, Some(3));
+        assert_eq!(stats.failures[FAILURES_KEPT - 2].description, "sh -c 'run 995'");
+    }
+}
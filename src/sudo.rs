@@ -0,0 +1,121 @@
+use std::ffi::{OsStr, OsString};
+use std::process::Command;
+
+use crate::{quote, redact, ConfiguredCommand};
+
+const SUDO_FLAGS: [&str; 1] = ["-n"];
+const PRESERVE_ENV: &str = "--preserve-env=";
+
+/// Rebuilds `inner` to run as root through `sudo -n`, which fails instead of prompting for a
+/// password. The variables set on `inner` are kept by naming them in `--preserve-env`, which the
+/// sudoers policy may still refuse. Descriptions show the program that is run, as in
+/// `sudo = "systemctl restart foo"`, and `validate` checks that it exists too.
+///
+/// Fails on platforms other than Unix, where there is no `sudo` to run the command with.
+pub fn sudo(inner: impl Into<ConfiguredCommand>) -> anyhow::Result<ConfiguredCommand> {
+    let inner = inner.into();
+    if !cfg!(unix) {
+        anyhow::bail!("Running commands elevated is only supported on Unix");
+    }
+    let mut cmd = crate::cmd("sudo");
+    cmd.args(SUDO_FLAGS);
+    let preserved: Vec<&OsStr> =
+        inner.get_envs().filter(|(_, value)| value.is_some()).map(|(key, _)| key).collect();
+    if !preserved.is_empty() {
+        let mut flag = OsString::from(PRESERVE_ENV);
+        flag.push(preserved.join(OsStr::new(",")));
+        cmd.arg(flag);
+    }
+    cmd.arg("--").arg(inner.get_program()).args(inner.get_args());
+    for (key, value) in inner.get_envs() {
+        match value {
+            Some(value) => cmd.env(key, value),
+            None => cmd.env_remove(key),
+        };
+    }
+    if let Some(dir) = inner.get_current_dir() {
+        cmd.current_dir(dir);
+    }
+    Ok(inner.rebuilt(cmd))
+}
+
+// The program and arguments `cmd` runs through sudo, if it was built by `sudo`.
+pub(crate) fn inner(cmd: &Command) -> Option<Vec<&OsStr>> {
+    if cmd.get_program() != "sudo" {
+        return None;
+    }
+    let mut args = cmd.get_args();
+    for flag in SUDO_FLAGS {
+        if args.next()? != flag {
+            return None;
+        }
+    }
+    let mut next = args.next()?;
+    if next.to_str().is_some_and(|arg| arg.starts_with(PRESERVE_ENV)) {
+        next = args.next()?;
+    }
+    if next != "--" {
+        return None;
+    }
+    let inner: Vec<&OsStr> = args.collect();
+    (!inner.is_empty()).then_some(inner)
+}
+
+// The inner command as a shell line, for descriptions.
+pub(crate) fn inner_line(cmd: &Command) -> Option<String> {
+    let mut line = String::new();
+    for (i, word) in inner(cmd)?.into_iter().enumerate() {
+        if i > 0 {
+            line.push(' ');
+        }
+        quote::write_quoted(&mut line, &redact::scrub(word)).unwrap();
+    }
+    Some(line)
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use super::*;
+    use crate::{cmd, CommandExt};
+
+    fn args(cmd: &Command) -> Vec<&str> {
+        cmd.get_args().map(|arg| arg.to_str().unwrap()).collect()
+    }
+
+    #[test]
+    fn the_inner_command_is_run_through_sudo() {
+        let mut systemctl = cmd("systemctl").args_(["restart", "foo"]).current_dir_("/etc");
+        systemctl.env("UNIT_A", "1").env("UNIT_B", "2").env_remove("UNIT_C");
+        let elevated = sudo(systemctl).unwrap();
+        assert_eq!(elevated.get_program(), "sudo");
+        let expected = ["-n", "--preserve-env=UNIT_A,UNIT_B", "--", "systemctl", "restart", "foo"];
+        assert_eq!(args(&elevated), expected);
+        assert_eq!(elevated.get_current_dir(), Some("/etc".as_ref()));
+        assert_eq!(elevated.get_envs().count(), 3);
+        assert_eq!(inner_line(&elevated).unwrap(), "systemctl restart foo");
+        let description = elevated.description().to_string();
+        assert!(description.contains(r#"sudo = "systemctl restart foo""#), "{}", description);
+
+        let plain = sudo(cmd("id").arg_("-u")).unwrap();
+        assert_eq!(args(&plain), ["-n", "--", "id", "-u"]);
+        assert_eq!(inner(&plain), Some(vec![OsStr::new("id"), OsStr::new("-u")]));
+    }
+
+    #[test]
+    fn only_commands_built_by_sudo_have_an_inner_command() {
+        assert_eq!(inner(&cmd("sudo").args_(["-n", "--"])), None);
+        assert_eq!(inner(&cmd("sudo").args_(["-u", "root", "--", "id"])), None);
+        assert_eq!(inner(&cmd("doas").args_(["-n", "--", "id"])), None);
+        assert_eq!(inner_line(&cmd("id")), None);
+        let quoted = sudo(cmd("echo").arg_("two words")).unwrap();
+        assert_eq!(inner_line(&quoted).unwrap(), "echo 'two words'");
+    }
+
+    #[test]
+    fn validate_checks_the_inner_program() {
+        let elevated = sudo(cmd("cmd-utils-no-such-program")).unwrap();
+        let problems = crate::which::problems(&elevated);
+        let missing = problems.iter().filter(|p| p.contains("'cmd-utils-no-such-program'")).count();
+        assert_eq!(missing, 1, "{:?}", problems);
+    }
+}
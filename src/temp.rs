@@ -0,0 +1,152 @@
+use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use anyhow::Context;
+
+use crate::capture::{check_redirected, Pipes};
+use crate::{warn, CommandExt, ConfiguredCommand, Output};
+
+static NEXT_ID: AtomicU64 = AtomicU64::new(0);
+
+/// A directory created by `exec_in_temp_dir`, removed with everything in it when this is dropped,
+/// unless it is kept.
+pub struct TempDir {
+    path: Option<PathBuf>,
+}
+
+impl TempDir {
+    fn create() -> anyhow::Result<Self> {
+        let nanos = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default().subsec_nanos();
+        loop {
+            let name = format!(
+                "cmd-utils-{}-{}-{:08x}",
+                std::process::id(),
+                NEXT_ID.fetch_add(1, Ordering::Relaxed),
+                nanos
+            );
+            let path = std::env::temp_dir().join(name);
+            match std::fs::create_dir(&path) {
+                Ok(()) => return Ok(TempDir { path: Some(path) }),
+                Err(e) if e.kind() == std::io::ErrorKind::AlreadyExists => continue,
+                Err(e) => {
+                    return Err(e).with_context(|| {
+                        format!("Failed to create temporary directory {}", path.display())
+                    })
+                }
+            }
+        }
+    }
+
+    pub fn path(&self) -> &Path {
+        self.path.as_ref().unwrap()
+    }
+
+    /// Leaves the directory in place and returns its path.
+    pub fn keep(mut self) -> PathBuf {
+        self.path.take().unwrap()
+    }
+}
+
+impl Drop for TempDir {
+    fn drop(&mut self) {
+        if let Some(path) = self.path.take() {
+            if let Err(e) = std::fs::remove_dir_all(&path) {
+                warn(format_args!(
+                    "Failed to remove temporary directory {}: {}",
+                    path.display(),
+                    e
+                ));
+            }
+        }
+    }
+}
+
+/// The output of `exec_in_temp_dir`, and the directory the command ran in.
+pub struct TempRun {
+    pub output: Output,
+    pub dir: TempDir,
+}
+
+impl TempRun {
+    pub fn path(&self) -> &Path {
+        self.dir.path()
+    }
+
+    pub fn keep(self) -> PathBuf {
+        self.dir.keep()
+    }
+}
+
+pub(crate) fn exec_in_temp_dir(cmd: &mut ConfiguredCommand) -> anyhow::Result<TempRun> {
+    // A copy runs in the directory, so that `cmd` isn't left pointing at it once it is removed.
+    // The copy can't be given the streams of `cmd`, so a command with any of them set is refused.
+    check_redirected(cmd, Pipes { stdin: true, stdout: true, stderr: true })?;
+    let dir = TempDir::create()?;
+    let mut copy = cmd.copy();
+    copy.current_dir(dir.path());
+    match copy.exec_stdout_string_ref() {
+        Ok(output) => Ok(TempRun { output, dir }),
+        Err(e) => {
+            let path = dir.keep();
+            Err(e.context(format!(
+                "Command failed in temporary directory {}, which is kept for inspection",
+                path.display()
+            )))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{shell, CommandExt};
+
+    #[test]
+    fn command_keeps_its_directory() {
+        let mut cmd = shell("pwd");
+        let run = cmd.exec_in_temp_dir().unwrap();
+        assert_eq!(
+            run.output.stdout.trim_end(),
+            run.path().canonicalize().unwrap().to_str().unwrap()
+        );
+        assert_eq!(cmd.get_current_dir(), None);
+        assert_eq!(run.output.command.get_current_dir(), Some(run.path()));
+    }
+
+    #[test]
+    fn directories_are_removed_unless_kept() {
+        let run =
+            shell("echo data > made.txt; mkdir sub; touch sub/more").exec_in_temp_dir().unwrap();
+        let path = run.path().to_path_buf();
+        assert_eq!(std::fs::read_to_string(path.join("made.txt")).unwrap(), "data\n");
+        drop(run);
+        assert!(!path.exists());
+
+        let kept = shell("touch kept.txt").exec_in_temp_dir().unwrap().keep();
+        assert!(kept.join("kept.txt").exists());
+        std::fs::remove_dir_all(&kept).unwrap();
+
+        let mut cmd = shell("true");
+        let (first, second) = (cmd.exec_in_temp_dir().unwrap(), cmd.exec_in_temp_dir().unwrap());
+        assert_ne!(first.path(), second.path());
+    }
+
+    #[test]
+    fn failures_keep_the_directory_for_inspection() {
+        let err = shell("echo half > partial.txt; exit 3").exec_in_temp_dir().err().unwrap();
+        let message = format!("{:#}", err);
+        let prefix = "Command failed in temporary directory ";
+        let rest = message.strip_prefix(prefix).unwrap_or_else(|| panic!("{}", message));
+        let path = rest.split(", which is kept for inspection").next().unwrap();
+        let path = std::path::Path::new(path);
+        assert_eq!(std::fs::read_to_string(path.join("partial.txt")).unwrap(), "half\n");
+        assert!(message.contains("exit code 3"), "{}", message);
+        std::fs::remove_dir_all(path).unwrap();
+    }
+
+    #[test]
+    fn commands_with_stdin_are_refused() {
+        let mut cmd = shell("cat").stdin_(std::process::Stdio::null());
+        assert!(cmd.exec_in_temp_dir().is_err());
+    }
+}
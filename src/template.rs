@@ -0,0 +1,233 @@
+use std::collections::{BTreeSet, HashMap};
+use std::ffi::{OsStr, OsString};
+use std::fmt::Display;
+
+use crate::{args_from_str, cmd, CommandExt};
+
+/// A command line with `{name}` placeholders, such as `git -C {repo} log --since {since}`, to be
+/// rendered into commands with different values. The line is split into tokens with the quoting
+/// of `args_from_str` when it is parsed, and a value always stays within its token, whatever
+/// spaces or quotes it has. A token with a `{name?}` placeholder is left out when `name` has no
+/// value. `{{` and `}}` stand for literal braces.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CommandTemplate {
+    line: String,
+    tokens: Vec<Vec<Part>>,
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+enum Part {
+    Literal(String),
+    Placeholder { name: String, optional: bool },
+}
+
+impl CommandTemplate {
+    pub fn parse(line: &str) -> anyhow::Result<Self> {
+        let tokens = args_from_str(line)?
+            .iter()
+            .map(|token| parse_token(token))
+            .collect::<Result<Vec<_>, _>>()
+            .map_err(|problem| anyhow::anyhow!("{} in command template {:?}", problem, line))?;
+        if tokens.is_empty() {
+            anyhow::bail!("Command template is empty ({:?})", line);
+        }
+        Ok(CommandTemplate { line: line.to_string(), tokens })
+    }
+
+    /// The names of the placeholders, in order and without repeats.
+    pub fn placeholders(&self) -> Vec<&str> {
+        let mut seen = BTreeSet::new();
+        self.parts()
+            .filter_map(|part| match part {
+                Part::Placeholder { name, .. } => Some(name.as_str()),
+                Part::Literal(_) => None,
+            })
+            .filter(|name| seen.insert(*name))
+            .collect()
+    }
+
+    /// Fails, naming them, if a placeholder that isn't optional has no value, or if a value has no
+    /// placeholder.
+    pub fn render(&self, vars: &HashMap<&str, &OsStr>) -> anyhow::Result<std::process::Command> {
+        let missing: BTreeSet<&str> = self
+            .parts()
+            .filter_map(|part| match part {
+                Part::Placeholder { name, optional: false }
+                    if !vars.contains_key(name.as_str()) =>
+                {
+                    Some(name.as_str())
+                }
+                _ => None,
+            })
+            .collect();
+        if !missing.is_empty() {
+            anyhow::bail!("No value for {} in command template {:?}", names(&missing), self.line);
+        }
+        let placeholders = self.placeholders();
+        let unused: BTreeSet<&str> =
+            vars.keys().copied().filter(|name| !placeholders.contains(name)).collect();
+        if !unused.is_empty() {
+            anyhow::bail!(
+                "No placeholder for {} in command template {:?}",
+                names(&unused),
+                self.line
+            );
+        }
+        let mut tokens = self.tokens.iter().filter_map(|token| render_token(token, vars));
+        let Some(program) = tokens.next() else {
+            anyhow::bail!("Command template {:?} has no program without its values", self.line);
+        };
+        Ok(cmd(program).args_(tokens))
+    }
+
+    fn parts(&self) -> impl Iterator<Item = &Part> {
+        self.tokens.iter().flatten()
+    }
+}
+
+impl Display for CommandTemplate {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(&self.line)
+    }
+}
+
+fn parse_token(token: &str) -> Result<Vec<Part>, String> {
+    let mut parts = Vec::new();
+    let mut literal = String::new();
+    let mut chars = token.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '{' if chars.peek() == Some(&'{') => {
+                chars.next();
+                literal.push('{');
+            }
+            '}' if chars.peek() == Some(&'}') => {
+                chars.next();
+                literal.push('}');
+            }
+            '}' => return Err(format!("Unmatched '}}' in {:?}", token)),
+            '{' => {
+                let mut name = String::new();
+                loop {
+                    match chars.next() {
+                        Some('}') => break,
+                        Some(c) => name.push(c),
+                        None => return Err(format!("Unclosed '{{' in {:?}", token)),
+                    }
+                }
+                let optional = name.ends_with('?');
+                if optional {
+                    name.pop();
+                }
+                let valid = !name.is_empty()
+                    && name.chars().all(|c| c.is_alphanumeric() || c == '_' || c == '-');
+                if !valid {
+                    return Err(format!("Invalid placeholder {{{}}} in {:?}", name, token));
+                }
+                if !literal.is_empty() {
+                    parts.push(Part::Literal(std::mem::take(&mut literal)));
+                }
+                parts.push(Part::Placeholder { name, optional });
+            }
+            c => literal.push(c),
+        }
+    }
+    if !literal.is_empty() || parts.is_empty() {
+        parts.push(Part::Literal(literal));
+    }
+    Ok(parts)
+}
+
+// `None` if the token has an optional placeholder without a value.
+fn render_token(token: &[Part], vars: &HashMap<&str, &OsStr>) -> Option<OsString> {
+    let mut rendered = OsString::new();
+    for part in token {
+        match part {
+            Part::Literal(literal) => rendered.push(literal),
+            Part::Placeholder { name, .. } => rendered.push(vars.get(name.as_str())?),
+        }
+    }
+    Some(rendered)
+}
+
+// "variable `repo`" or "variables `repo`, `since`"
+fn names(names: &BTreeSet<&str>) -> String {
+    let list = names.iter().map(|name| format!("`{}`", name)).collect::<Vec<_>>().join(", ");
+    match names.len() {
+        1 => format!("variable {}", list),
+        _ => format!("variables {}", list),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn args(cmd: &std::process::Command) -> Vec<&str> {
+        cmd.get_args().map(|arg| arg.to_str().unwrap()).collect()
+    }
+
+    fn vars<'a>(pairs: &[(&'a str, &'a str)]) -> HashMap<&'a str, &'a OsStr> {
+        pairs.iter().map(|&(name, value)| (name, OsStr::new(value))).collect()
+    }
+
+    #[test]
+    fn values_stay_single_tokens() {
+        let log =
+            CommandTemplate::parse("git -C {repo} log --since={since} --format {fmt}").unwrap();
+        assert_eq!(log.placeholders(), ["repo", "since", "fmt"]);
+        let values = vars(&[("repo", "/src/my repo"), ("since", "2 weeks ago"), ("fmt", "")]);
+        let git = log.render(&values).unwrap();
+        assert_eq!(git.get_program(), "git");
+        assert_eq!(
+            args(&git),
+            ["-C", "/src/my repo", "log", "--since=2 weeks ago", "--format", ""]
+        );
+        let quoted =
+            vars(&[("repo", "it's \"here\""), ("since", "$HOME; rm -rf /"), ("fmt", "%h %s")]);
+        assert_eq!(args(&log.render(&quoted).unwrap())[1], "it's \"here\"");
+        assert_eq!(args(&log.render(&quoted).unwrap())[3], "--since=$HOME; rm -rf /");
+        assert_eq!(log.to_string(), "git -C {repo} log --since={since} --format {fmt}");
+    }
+
+    #[test]
+    fn optional_placeholders_drop_their_token() {
+        let template =
+            CommandTemplate::parse("cargo build --target={target?} '{{literal}}' {x}").unwrap();
+        let without = template.render(&vars(&[("x", "1")])).unwrap();
+        assert_eq!(args(&without), ["build", "{literal}", "1"]);
+        let with = template.render(&vars(&[("target", "wasm32"), ("x", "1")])).unwrap();
+        assert_eq!(args(&with), ["build", "--target=wasm32", "{literal}", "1"]);
+        let message = CommandTemplate::parse("{tool?}").unwrap().render(&vars(&[])).unwrap_err();
+        assert!(message.to_string().ends_with("has no program without its values"));
+    }
+
+    #[test]
+    fn missing_and_unused_variables_are_named() {
+        let template = CommandTemplate::parse("scp {src} {host}:{dest}").unwrap();
+        let message = template.render(&vars(&[("src", "a")])).unwrap_err().to_string();
+        assert_eq!(
+            message,
+            r#"No value for variables `dest`, `host` in command template "scp {src} {host}:{dest}""#
+        );
+        let all = [("src", "a"), ("host", "h"), ("dest", "d"), ("port", "22")];
+        let message = template.render(&vars(&all)).unwrap_err().to_string();
+        assert!(message.starts_with("No placeholder for variable `port` in"), "{}", message);
+    }
+
+    #[test]
+    fn malformed_templates() {
+        let cases = [
+            ("", "Command template is empty"),
+            ("echo {name", "Unclosed '{' in \"{name\" in command template"),
+            ("echo name}", "Unmatched '}' in \"name}\" in command template"),
+            ("echo {a b}", "Unclosed '{' in \"{a\" in command template"),
+            ("echo {}", "Invalid placeholder {} in \"{}\" in command template"),
+            ("echo {a.b}", "Invalid placeholder {a.b} in"),
+        ];
+        for (line, expected) in cases {
+            let message = CommandTemplate::parse(line).unwrap_err().to_string();
+            assert!(message.starts_with(expected), "{:?}: {}", line, message);
+        }
+    }
+}
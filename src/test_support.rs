@@ -0,0 +1,47 @@
+// Helpers shared by the tests of several modules.
+
+use std::path::PathBuf;
+use std::process::{Command, ExitStatus};
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use termcolor::Buffer;
+
+use crate::{scoped_post_exec_hook, set_decoration_writer, take_decoration_writer, HookGuard};
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+// Held by tests that change process-wide settings, so that they don't undo each other's.
+pub(crate) fn serial() -> MutexGuard<'static, ()> {
+    SERIAL.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+// What is written with the decorations while `f` runs, colors included. The commands of other
+// tests running at the same time show up too, so tests only look at lines of their own.
+pub(crate) fn decorations(f: impl FnOnce()) -> String {
+    let _serial = serial();
+    set_decoration_writer(Buffer::ansi());
+    f();
+    let buffer = take_decoration_writer::<Buffer>().unwrap();
+    String::from_utf8_lossy(buffer.as_slice()).into_owned()
+}
+
+// The exit codes of the commands that finish while the guard lives and have `arg` among their
+// arguments, so that the commands of tests running at the same time don't show up.
+pub(crate) fn record_exits(arg: &'static str) -> (HookGuard, Arc<Mutex<Vec<Option<i32>>>>) {
+    let exits = Arc::new(Mutex::new(Vec::new()));
+    let guard = scoped_post_exec_hook({
+        let exits = Arc::clone(&exits);
+        move |cmd: &Command, status: &ExitStatus, _: Duration| {
+            if cmd.get_args().any(|a| a == arg) {
+                exits.lock().unwrap().push(status.code());
+            }
+        }
+    });
+    (guard, exits)
+}
+
+// A path in the temporary directory that no other test or test run uses, which the test removes.
+pub(crate) fn temp_path(name: &str) -> PathBuf {
+    std::env::temp_dir().join(format!("cmd-utils-test-{}-{}", std::process::id(), name))
+}
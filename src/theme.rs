@@ -0,0 +1,162 @@
+use std::sync::Mutex;
+
+use termcolor::{Color, ColorSpec};
+
+static THEME: Mutex<Option<Theme>> = Mutex::new(None);
+
+/// The colors of the banners written around executed commands. A spec that sets nothing writes
+/// no escape sequences at all.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Theme {
+    /// The inherited working directory in the banner.
+    pub cwd: ColorSpec,
+    /// A working directory configured on the command, marked with an arrow.
+    pub explicit_cwd: ColorSpec,
+    pub command: ColorSpec,
+    pub success: ColorSpec,
+    pub failure: ColorSpec,
+    pub dry_run: ColorSpec,
+    /// The END OUTPUT marker of a command interrupted by Ctrl-C.
+    pub interrupted: ColorSpec,
+    /// The "still running" warnings, and the duration in the `END OUTPUT` marker of a command that
+    /// ran past its warning threshold, which keeps the background of the marker.
+    pub slow: ColorSpec,
+    /// Heartbeat lines of commands given `heartbeat`.
+    pub heartbeat: ColorSpec,
+    /// The header of a `scope`, whose footer uses `success` or `failure`.
+    pub scope: ColorSpec,
+    /// The stderr of an attempt that `exec_retry` retries, when the policy looks at stderr.
+    pub retried_output: ColorSpec,
+    /// Summary lines such as "Command failed (exit code 1)".
+    pub success_text: ColorSpec,
+    pub failure_text: ColorSpec,
+}
+
+impl Default for Theme {
+    fn default() -> Self {
+        Theme {
+            cwd: block(Color::Cyan),
+            explicit_cwd: block(Color::Magenta),
+            command: ColorSpec::new(),
+            success: block(Color::Green),
+            failure: block(Color::Red),
+            dry_run: block(Color::Yellow),
+            interrupted: block(Color::Yellow),
+            slow: fg(Color::Yellow),
+            heartbeat: dimmed(),
+            scope: block(Color::Blue),
+            retried_output: dimmed(),
+            success_text: fg(Color::Green),
+            failure_text: fg(Color::Red),
+        }
+    }
+}
+
+impl Theme {
+    pub fn plain() -> Self {
+        Theme {
+            cwd: ColorSpec::new(),
+            explicit_cwd: ColorSpec::new(),
+            command: ColorSpec::new(),
+            success: ColorSpec::new(),
+            failure: ColorSpec::new(),
+            dry_run: ColorSpec::new(),
+            interrupted: ColorSpec::new(),
+            slow: ColorSpec::new(),
+            heartbeat: ColorSpec::new(),
+            scope: ColorSpec::new(),
+            retried_output: ColorSpec::new(),
+            success_text: ColorSpec::new(),
+            failure_text: ColorSpec::new(),
+        }
+    }
+
+    /// Foreground colors only, for terminal themes that make the background blocks unreadable.
+    pub fn minimal() -> Self {
+        Theme {
+            cwd: fg(Color::Cyan),
+            explicit_cwd: fg(Color::Magenta),
+            command: ColorSpec::new(),
+            success: fg(Color::Green),
+            failure: fg(Color::Red),
+            dry_run: fg(Color::Yellow),
+            interrupted: fg(Color::Yellow),
+            slow: fg(Color::Yellow),
+            heartbeat: dimmed(),
+            scope: fg(Color::Blue),
+            retried_output: dimmed(),
+            success_text: fg(Color::Green),
+            failure_text: fg(Color::Red),
+        }
+    }
+}
+
+pub fn set_theme(theme: Theme) {
+    *THEME.lock().unwrap() = Some(theme);
+}
+
+// `None` means the default theme.
+pub(crate) fn theme_override() -> Option<Theme> {
+    THEME.lock().unwrap().clone()
+}
+
+pub(crate) fn set_theme_override(theme: Option<Theme>) {
+    *THEME.lock().unwrap() = theme;
+}
+
+pub(crate) fn theme() -> Theme {
+    THEME.lock().unwrap().clone().unwrap_or_default()
+}
+
+fn block(bg: Color) -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    spec.set_bg(Some(bg)).set_fg(Some(Color::Black));
+    spec
+}
+
+fn dimmed() -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    spec.set_dimmed(true);
+    spec
+}
+
+fn fg(fg: Color) -> ColorSpec {
+    let mut spec = ColorSpec::new();
+    spec.set_fg(Some(fg));
+    spec
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::test_support::decorations;
+    use crate::{cmd, CommandExt};
+
+    // The escapes of the banner and the `END OUTPUT` marker of a command run under `theme`.
+    fn banner(theme: Theme, arg: &str) -> String {
+        let text = decorations(|| {
+            set_theme(theme);
+            cmd("echo").arg(arg).exec().unwrap();
+            cmd("sh").args(["-c", "exit 1", arg]).exec().unwrap_err();
+            set_theme_override(None);
+        });
+        text.lines().filter(|line| line.contains(arg) || line.contains("END OUTPUT")).collect()
+    }
+
+    #[test]
+    fn themes_change_the_escapes() {
+        let default = banner(Theme::default(), "theme-default");
+        assert!(default.contains("\x1b[46m") && default.contains("\x1b[42m"), "{:?}", default);
+        assert!(default.contains("\x1b[41m"), "{:?}", default);
+        let plain = banner(Theme::plain(), "theme-plain");
+        assert!(!plain.contains("\x1b[3") && !plain.contains("\x1b[4"), "{:?}", plain);
+        let minimal = banner(Theme::minimal(), "theme-minimal");
+        assert!(minimal.contains("\x1b[36m") && minimal.contains("\x1b[32m"), "{:?}", minimal);
+        assert!(minimal.contains("\x1b[31m") && !minimal.contains("\x1b[4"), "{:?}", minimal);
+        let mut cwd = fg(Color::Ansi256(208));
+        cwd.set_bold(true);
+        let custom = Theme { cwd, ..Theme::default() };
+        let custom = banner(custom, "theme-custom");
+        assert!(custom.contains("\x1b[38;5;208m") && custom.contains("\x1b[1m"), "{:?}", custom);
+    }
+}
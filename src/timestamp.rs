@@ -0,0 +1,153 @@
+use std::fmt::{self, Display};
+use std::sync::Mutex;
+use std::time::{SystemTime, UNIX_EPOCH};
+
+use crate::zone;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TimestampFormat {
+    Utc,
+    /// With the offset of each timestamp looked up in the time zone database, which is found
+    /// through `TZ` or `/etc/localtime` as the C library does. Where there is none, as on
+    /// Windows, UTC is used.
+    Local,
+}
+
+static TIMESTAMPS: Mutex<Option<TimestampFormat>> = Mutex::new(None);
+
+/// Prefixes every banner with the time the command started and adds the time it finished to the
+/// `END OUTPUT` marker, both as RFC 3339 with milliseconds. `None`, the default, turns them off.
+/// The format also applies to the `started_at` and `finished_at` fields of the log file, which
+/// are always written and otherwise in UTC.
+pub fn set_timestamps(format: Option<TimestampFormat>) {
+    *TIMESTAMPS.lock().unwrap() = format;
+}
+
+pub(crate) fn format() -> Option<TimestampFormat> {
+    *TIMESTAMPS.lock().unwrap()
+}
+
+// The current time if timestamps are enabled.
+pub(crate) fn now() -> Option<Rfc3339> {
+    let format = (*TIMESTAMPS.lock().unwrap())?;
+    Some(Rfc3339::new(SystemTime::now(), format))
+}
+
+pub(crate) fn log_format() -> TimestampFormat {
+    TIMESTAMPS.lock().unwrap().unwrap_or(TimestampFormat::Utc)
+}
+
+pub(crate) struct Rfc3339 {
+    time: SystemTime,
+    offset_minutes: Option<i32>,
+}
+
+impl Rfc3339 {
+    pub(crate) fn new(time: SystemTime, format: TimestampFormat) -> Self {
+        let offset_minutes = match format {
+            TimestampFormat::Utc => None,
+            TimestampFormat::Local => {
+                let since_epoch = time.duration_since(UNIX_EPOCH).unwrap_or_default();
+                zone::local_offset(since_epoch.as_secs() as i64).map(|secs| secs / 60)
+            }
+        };
+        Rfc3339 { time, offset_minutes }
+    }
+}
+
+impl Display for Rfc3339 {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let since_epoch = self.time.duration_since(UNIX_EPOCH).unwrap_or_default();
+        let offset_secs = i64::from(self.offset_minutes.unwrap_or(0)) * 60;
+        let secs = since_epoch.as_secs() as i64 + offset_secs;
+        let (year, month, day) = civil_from_days(secs.div_euclid(86400));
+        let secs_of_day = secs.rem_euclid(86400);
+        write!(
+            f,
+            "{:04}-{:02}-{:02}T{:02}:{:02}:{:02}.{:03}",
+            year,
+            month,
+            day,
+            secs_of_day / 3600,
+            secs_of_day % 3600 / 60,
+            secs_of_day % 60,
+            since_epoch.subsec_millis()
+        )?;
+        match self.offset_minutes {
+            None => write!(f, "Z"),
+            Some(minutes) => {
+                let sign = if minutes < 0 { '-' } else { '+' };
+                write!(f, "{}{:02}:{:02}", sign, minutes.abs() / 60, minutes.abs() % 60)
+            }
+        }
+    }
+}
+
+// Howard Hinnant's `civil_from_days`: the proleptic Gregorian date `days` after 1970-01-01.
+pub(crate) fn civil_from_days(days: i64) -> (i64, u32, u32) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u32;
+    let month = if mp < 10 { mp + 3 } else { mp - 9 } as u32;
+    let year = yoe + era * 400 + i64::from(month <= 2);
+    (year, month, day)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use super::*;
+    use crate::test_support::decorations;
+    use crate::{cmd, strip_ansi, CommandExt};
+
+    fn at(secs: u64, millis: u64, offset_minutes: Option<i32>) -> String {
+        let time = UNIX_EPOCH + Duration::from_secs(secs) + Duration::from_millis(millis);
+        Rfc3339 { time, offset_minutes }.to_string()
+    }
+
+    // Whether `text` has the shape of a UTC timestamp, such as `2024-02-29T12:34:56.789Z`.
+    fn is_utc_timestamp(text: &str) -> bool {
+        let template = "dddd-dd-ddTdd:dd:dd.dddZ";
+        text.len() == template.len()
+            && text.chars().zip(template.chars()).all(|(c, t)| match t {
+                'd' => c.is_ascii_digit(),
+                _ => c == t,
+            })
+    }
+
+    #[test]
+    fn formatting() {
+        assert_eq!(at(0, 0, None), "1970-01-01T00:00:00.000Z");
+        assert_eq!(at(951_827_696, 7, None), "2000-02-29T12:34:56.007Z");
+        assert_eq!(at(1_704_067_199, 999, None), "2023-12-31T23:59:59.999Z");
+        assert_eq!(at(1_704_067_199, 0, Some(90)), "2024-01-01T01:29:59.000+01:30");
+        assert_eq!(at(0, 0, Some(-300)), "1969-12-31T19:00:00.000-05:00");
+        assert_eq!(at(0, 0, Some(0)), "1970-01-01T00:00:00.000+00:00");
+        assert_eq!(civil_from_days(-719_468), (0, 3, 1));
+    }
+
+    #[test]
+    fn banners_with_and_without_timestamps() {
+        let text = decorations(|| {
+            set_timestamps(Some(TimestampFormat::Utc));
+            cmd("echo").arg("timestamped").exec().unwrap();
+            set_timestamps(None);
+            cmd("echo").arg("untimestamped").exec().unwrap();
+        });
+        let text = strip_ansi(&text);
+        let banner = text.lines().find(|line| line.ends_with(" echo timestamped")).unwrap();
+        assert!(banner.starts_with('[') && is_utc_timestamp(&banner[1..25]), "{}", banner);
+        assert!(banner[25..].starts_with("] "), "{}", banner);
+        let end = text.lines().find(|line| line.contains(", finished ")).unwrap();
+        let finished = &end[end.find(", finished ").unwrap() + 11..];
+        assert!(is_utc_timestamp(&finished[..24]) && &finished[24..] == ") ", "{:?}", end);
+        let banner = text.lines().find(|line| line.ends_with(" echo untimestamped")).unwrap();
+        let cwd = std::env::current_dir().unwrap();
+        assert_eq!(banner, format!("{} echo untimestamped", cwd.display()));
+    }
+}
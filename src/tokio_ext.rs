@@ -0,0 +1,135 @@
+use std::ffi::OsStr;
+use std::future::Future;
+use std::process::Stdio;
+
+use crate::capture::Captured;
+use crate::which::SpawnContext;
+use crate::{
+    copy_command, decoration_stream, dry_run, dry_run_output, exec_failed, execution, write_banner,
+    write_end_output, CommandExt, ConfiguredCommand, Output, StatusSummary,
+};
+
+/// Async counterparts of the `CommandExt` methods for `tokio::process::Command`. The child is
+/// killed if the returned future is dropped before it completes.
+pub trait AsyncCommandExt {
+    fn exec(&mut self) -> impl Future<Output = anyhow::Result<()>> + Send;
+    fn exec_args<I, S>(&mut self, args: I) -> impl Future<Output = anyhow::Result<()>> + Send
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>;
+
+    fn exec_stdout_string(self) -> impl Future<Output = anyhow::Result<Output>> + Send;
+}
+
+impl AsyncCommandExt for tokio::process::Command {
+    async fn exec(&mut self) -> anyhow::Result<()> {
+        let decorated = decorated(self);
+        if dry_run(&decorated)? {
+            return Ok(());
+        }
+        write_banner(&mut decoration_stream(), &decorated)?;
+        let execution = execution::start_detached(&decorated)?;
+        let status = execution.run(self.kill_on_drop(true).status()).await;
+        let duration = execution.finish(self.as_std(), status.as_ref().ok(), None);
+        let status = status.spawn_context(self.as_std(), || {
+            format!("Failed to execute command ({})", self.as_std().description())
+        })?;
+        write_end_output(&mut decoration_stream(), &decorated, status.success(), None, duration);
+        if !status.success() {
+            return Err(exec_failed(
+                self.as_std(),
+                status,
+                &[],
+                &[],
+                format!(
+                    "Process did not exit successfully, {} ({})",
+                    StatusSummary(status),
+                    self.as_std().description(),
+                ),
+            ));
+        }
+        Ok(())
+    }
+    fn exec_args<I, S>(&mut self, args: I) -> impl Future<Output = anyhow::Result<()>> + Send
+    where
+        I: IntoIterator<Item = S>,
+        S: AsRef<OsStr>,
+    {
+        self.args(args);
+        self.exec()
+    }
+
+    async fn exec_stdout_string(self) -> anyhow::Result<Output> {
+        let mut self_ = self;
+        let decorated = decorated(&self_);
+        if dry_run(&decorated)? {
+            return Ok(dry_run_output(self_.into_std()));
+        }
+        let execution = execution::start_detached(&decorated)?;
+        let out = execution
+            .run(self_.stdout(Stdio::piped()).stderr(Stdio::piped()).kill_on_drop(true).output())
+            .await;
+        let duration = execution.finish(
+            self_.as_std(),
+            out.as_ref().ok().map(|out| &out.status),
+            out.as_ref().ok().map(|out| (&out.stdout[..], &out.stderr[..])),
+        );
+        let std::process::Output { status, stdout, stderr } = out
+            .spawn_context(self_.as_std(), || {
+                format!("Failed to execute command ({})", self_.as_std().description())
+            })?;
+        let command = self_.into_std();
+        let captured = Captured::new(status, stdout, stderr, duration);
+        captured.check(&command)?;
+        captured.into_output(command)
+    }
+}
+
+// A `tokio::process::Command` has none of the settings of a `ConfiguredCommand`, so it is
+// decorated as a copy without any.
+fn decorated(cmd: &tokio::process::Command) -> ConfiguredCommand {
+    copy_command(cmd.as_std()).into()
+}
+
+#[cfg(test)]
+mod tests {
+    use std::time::Duration;
+
+    use tokio::process::Command;
+
+    use super::AsyncCommandExt;
+    use crate::test_support::temp_path;
+    use crate::CmdError;
+
+    fn shell(script: &str) -> Command {
+        Command::from(crate::shell(script))
+    }
+
+    #[tokio::test]
+    async fn exec_reports_success_and_failure() {
+        shell("true").exec().await.unwrap();
+        let err = shell("exit 3").exec().await.err().unwrap();
+        let status = err.downcast_ref::<CmdError>().and_then(CmdError::status);
+        assert_eq!(status.and_then(|status| status.code()), Some(3), "{:#}", err);
+        Command::new("test").exec_args(["-n", "args"]).await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn exec_stdout_string_captures_output() {
+        let out = shell("echo async").exec_stdout_string().await.unwrap();
+        assert_eq!(out.stdout, "async\n");
+        let err = shell("echo half; exit 1").exec_stdout_string().await.err().unwrap();
+        assert!(format!("{:#}", err).contains("half"), "{:#}", err);
+    }
+
+    #[tokio::test]
+    async fn dropping_the_future_kills_the_child() {
+        let marker = temp_path("async-cancelled");
+        let script = format!("sleep 0.5 && touch '{}'", marker.display());
+        let mut cmd = shell(&script);
+        let run = tokio::time::timeout(Duration::from_millis(100), cmd.exec()).await;
+        assert!(run.is_err());
+        tokio::time::sleep(Duration::from_secs(1)).await;
+        assert!(!marker.exists());
+    }
+}
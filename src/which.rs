@@ -0,0 +1,456 @@
+use std::borrow::Borrow;
+use std::env;
+use std::ffi::{OsStr, OsString};
+use std::fmt::Display;
+use std::io;
+use std::path::{Path, PathBuf};
+use std::process::Command;
+
+use crate::{effective_dir, sudo, CmdError, CommandExt};
+
+/// Returns the first executable named `program` on `PATH`, trying each `PATHEXT` extension on
+/// Windows. A program containing a path separator is checked as-is instead of being searched for.
+pub fn which(program: impl AsRef<OsStr>) -> anyhow::Result<PathBuf> {
+    let program = program.as_ref();
+    let path = env::var_os("PATH");
+    if let Some(found) = which_in(program, path.as_deref()).into_iter().next() {
+        return Ok(found);
+    }
+    if has_separator(program) {
+        anyhow::bail!("Program {:?} does not exist or is not executable", program);
+    }
+    let searched: Vec<_> = search_dirs(path.as_deref()).collect();
+    anyhow::bail!("Program {:?} not found on PATH (searched {:?})", program, searched)
+}
+
+/// Returns every executable named `program` on `PATH`, in search order, which is useful for
+/// finding out which binaries shadow each other.
+pub fn which_all(program: impl AsRef<OsStr>) -> Vec<PathBuf> {
+    which_in(program.as_ref(), env::var_os("PATH").as_deref())
+}
+
+fn which_in(program: &OsStr, path: Option<&OsStr>) -> Vec<PathBuf> {
+    if has_separator(program) {
+        return candidates(Path::new(program)).filter(|p| is_executable(p)).take(1).collect();
+    }
+    search_dirs(path)
+        .flat_map(|dir| candidates(&dir.join(program)).collect::<Vec<_>>())
+        .filter(|p| is_executable(p))
+        .collect()
+}
+
+fn search_dirs(path: Option<&OsStr>) -> impl Iterator<Item = PathBuf> + '_ {
+    path.into_iter().flat_map(env::split_paths).filter(|dir| !dir.as_os_str().is_empty())
+}
+
+fn has_separator(program: &OsStr) -> bool {
+    Path::new(program).components().count() > 1
+}
+
+#[cfg(not(windows))]
+fn candidates(path: &Path) -> impl Iterator<Item = PathBuf> {
+    std::iter::once(path.to_path_buf())
+}
+
+#[cfg(windows)]
+fn candidates(path: &Path) -> impl Iterator<Item = PathBuf> {
+    let pathext = env::var("PATHEXT").unwrap_or_else(|_| ".COM;.EXE;.BAT;.CMD".to_owned());
+    let mut paths = vec![path.to_path_buf()];
+    if path.extension().is_none() {
+        for ext in pathext.split(';').filter(|ext| !ext.is_empty()) {
+            let mut file_name = path.as_os_str().to_owned();
+            file_name.push(ext);
+            paths.push(PathBuf::from(file_name));
+        }
+    }
+    paths.into_iter()
+}
+
+#[cfg(unix)]
+fn is_executable(path: &Path) -> bool {
+    use std::os::unix::fs::PermissionsExt;
+    path.metadata().is_ok_and(|m| m.is_file() && m.permissions().mode() & 0o111 != 0)
+}
+
+#[cfg(not(unix))]
+fn is_executable(path: &Path) -> bool {
+    path.is_file()
+}
+
+// Explains an `ErrorKind::NotFound` spawn failure, which otherwise reads the same whether the
+// program, the working directory, or a script's interpreter is missing.
+fn not_found_hint(cmd: &Command) -> Option<String> {
+    if let Some(dir) = cmd.get_current_dir() {
+        if !dir.is_dir() {
+            return Some(format!("current directory {:?} does not exist", dir));
+        }
+    }
+    let program = cmd.get_program();
+    let path = child_path(cmd);
+    let found = which_in(program, path.as_deref()).into_iter().next();
+    let separator = has_separator(program);
+    let name = program.to_string_lossy();
+    Some(match found {
+        Some(found) => {
+            format!("program '{}' found at {:?}, but its interpreter may be missing", name, found)
+        }
+        None if separator => {
+            format!("program '{}' does not exist", name)
+        }
+        None => not_on_path(program, path.as_deref()),
+    })
+}
+
+// "program 'x' not found on PATH (searched N directories)", with the closest match found there.
+fn not_on_path(program: &OsStr, path: Option<&OsStr>) -> String {
+    let mut message = format!(
+        "program '{}' not found on PATH (searched {} directories)",
+        program.to_string_lossy(),
+        search_dirs(path).count()
+    );
+    if let Some(similar) = similar_program(program, path) {
+        message.push_str(&format!("; did you mean '{}'?", similar.to_string_lossy()));
+    }
+    message
+}
+
+/// Validates every command, as `CommandExt::validate` does, and fails with every problem found if
+/// any is invalid.
+pub fn validate_all<C: Borrow<Command>>(commands: &[C]) -> anyhow::Result<()> {
+    let invalid: Vec<_> = commands
+        .iter()
+        .map(Borrow::borrow)
+        .enumerate()
+        .map(|(index, cmd)| (index, cmd, problems(cmd)))
+        .filter(|(_, _, problems)| !problems.is_empty())
+        .collect();
+    if invalid.is_empty() {
+        return Ok(());
+    }
+    let mut message = format!("{} of {} commands are invalid:", invalid.len(), commands.len());
+    for (index, cmd, problems) in invalid {
+        message.push_str(&format!("\n  [{}] {:#}", index + 1, cmd.description()));
+        for problem in problems {
+            message.push_str(&format!("\n      {}", problem));
+        }
+    }
+    Err(anyhow::Error::msg(message))
+}
+
+pub(crate) fn validate(cmd: &Command) -> anyhow::Result<()> {
+    let problems = problems(cmd);
+    if !problems.is_empty() {
+        anyhow::bail!("Invalid command ({}): {}", cmd.description(), problems.join("; "));
+    }
+    Ok(())
+}
+
+// What would keep `cmd` from being spawned.
+pub(crate) fn problems(cmd: &Command) -> Vec<String> {
+    let mut problems = Vec::new();
+    let dir = effective_dir(cmd);
+    if let Some(dir) = &dir {
+        if !dir.exists() {
+            problems.push(format!("current directory {:?} does not exist", dir));
+        } else if !dir.is_dir() {
+            problems.push(format!("current directory {:?} is not a directory", dir));
+        }
+    }
+    let program = cmd.get_program();
+    if has_separator(program) {
+        // Relative to the command's directory, which is where a Unix child looks for it.
+        let path = match &dir {
+            Some(dir) => dir.join(program),
+            None => PathBuf::from(program),
+        };
+        if !candidates(&path).any(|path| is_executable(&path)) {
+            problems.push(format!(
+                "program '{}' does not exist or is not executable",
+                program.to_string_lossy()
+            ));
+        }
+    } else {
+        let path = child_path(cmd);
+        if which_in(program, path.as_deref()).is_empty() {
+            problems.push(not_on_path(program, path.as_deref()));
+        }
+    }
+    if let Some(inner) = sudo::inner(cmd) {
+        let program = inner[0];
+        let path = child_path(cmd);
+        if !has_separator(program) && which_in(program, path.as_deref()).is_empty() {
+            problems.push(not_on_path(program, path.as_deref()));
+        } else if has_separator(program) && !candidates(Path::new(program)).any(|p| p.exists()) {
+            problems.push(format!("program '{}' does not exist", program.to_string_lossy()));
+        }
+    }
+    for (key, value) in cmd.get_envs() {
+        let key_bytes = key.as_encoded_bytes();
+        if key_bytes.is_empty() || key_bytes.contains(&b'=') || key_bytes.contains(&0) {
+            problems.push(format!("environment variable name {:?} is invalid", key));
+        } else if value.is_some_and(|value| value.as_encoded_bytes().contains(&0)) {
+            problems.push(format!("environment variable {:?} has a NUL byte in its value", key));
+        }
+    }
+    problems
+}
+
+// Explains an `ErrorKind::PermissionDenied` spawn failure: a working directory that can't be
+// entered, or a program file without execute permission.
+fn permission_hint(cmd: &Command) -> Option<String> {
+    if let Some(dir) = cmd.get_current_dir() {
+        if let Err(e) = std::fs::read_dir(dir) {
+            if e.kind() == io::ErrorKind::PermissionDenied {
+                return Some(format!("current directory {:?} is not accessible", dir));
+            }
+        }
+    }
+    let program = cmd.get_program();
+    let file = match has_separator(program) {
+        true => Some(PathBuf::from(program)).filter(|path| path.is_file()),
+        false => search_dirs(child_path(cmd).as_deref())
+            .flat_map(|dir| candidates(&dir.join(program)).collect::<Vec<_>>())
+            .find(|path| path.is_file()),
+    };
+    let file = file?;
+    Some(format!(
+        "program '{}' found at {:?}, but it is not executable",
+        program.to_string_lossy(),
+        file
+    ))
+}
+
+// The `PATH` the child looks programs up in: the one set on the command, or the inherited one.
+fn child_path(cmd: &Command) -> Option<OsString> {
+    match cmd.get_envs().find(|(key, _)| *key == "PATH") {
+        Some((_, value)) => value.map(OsString::from),
+        None => env::var_os("PATH"),
+    }
+}
+
+// The executable on `path` whose name is closest to `program`, if it is only a typo or two away.
+fn similar_program(program: &OsStr, path: Option<&OsStr>) -> Option<OsString> {
+    let program = program.to_str()?;
+    let max_distance = (program.chars().count() / 3).max(1);
+    let mut best: Option<(usize, OsString)> = None;
+    for dir in search_dirs(path) {
+        let Ok(entries) = std::fs::read_dir(&dir) else {
+            continue;
+        };
+        for entry in entries.flatten() {
+            let file_name = entry.file_name();
+            let name = match Path::new(&file_name).file_stem() {
+                Some(stem) if cfg!(windows) => stem.to_owned(),
+                _ => file_name.clone(),
+            };
+            let Some(name) = name.to_str() else {
+                continue;
+            };
+            if name.len().abs_diff(program.len()) > max_distance {
+                continue;
+            }
+            let distance = edit_distance(program, name);
+            if distance <= max_distance
+                && best.as_ref().is_none_or(|(best, _)| distance < *best)
+                && is_executable(&entry.path())
+            {
+                best = Some((distance, OsString::from(name)));
+            }
+        }
+    }
+    best.map(|(_, name)| name)
+}
+
+// The number of characters to insert, delete, substitute, or swap with the next one to turn `a`
+// into `b` (the optimal string alignment distance).
+fn edit_distance(a: &str, b: &str) -> usize {
+    let a: Vec<char> = a.chars().collect();
+    let b: Vec<char> = b.chars().collect();
+    let mut before_previous: Vec<usize> = Vec::new();
+    let mut previous: Vec<usize> = (0..=b.len()).collect();
+    for i in 0..a.len() {
+        let mut current = vec![i + 1];
+        for j in 0..b.len() {
+            let mut distance = (previous[j] + usize::from(a[i] != b[j]))
+                .min(previous[j + 1] + 1)
+                .min(current[j] + 1);
+            if i > 0 && j > 0 && a[i] == b[j - 1] && a[i - 1] == b[j] {
+                distance = distance.min(before_previous[j - 1] + 1);
+            }
+            current.push(distance);
+        }
+        before_previous = std::mem::replace(&mut previous, current);
+    }
+    previous[b.len()]
+}
+
+pub(crate) trait SpawnContext<T> {
+    // Like `anyhow::Context::with_context`, but adds a hint about what is missing when spawning
+    // failed with `ErrorKind::NotFound`, or about what can't be used with
+    // `ErrorKind::PermissionDenied`.
+    fn spawn_context<C, F>(self, cmd: &Command, message: F) -> anyhow::Result<T>
+    where
+        C: Display + Send + Sync + 'static,
+        F: FnOnce() -> C;
+}
+
+impl<T> SpawnContext<T> for io::Result<T> {
+    fn spawn_context<C, F>(self, cmd: &Command, message: F) -> anyhow::Result<T>
+    where
+        C: Display + Send + Sync + 'static,
+        F: FnOnce() -> C,
+    {
+        self.map_err(|e| {
+            let hint = match e.kind() {
+                io::ErrorKind::NotFound => not_found_hint(cmd),
+                io::ErrorKind::PermissionDenied => permission_hint(cmd),
+                _ => None,
+            };
+            let failed =
+                CmdError::SpawnFailed { source: e, description: cmd.description().to_snapshot() };
+            match hint {
+                Some(hint) => {
+                    anyhow::Error::new(failed).context(format!("{}; {}", message(), hint))
+                }
+                None => anyhow::Error::new(failed).context(message()),
+            }
+        })
+    }
+}
+
+#[cfg(all(test, unix))]
+mod tests {
+    use std::os::unix::fs::PermissionsExt;
+
+    use super::*;
+    use crate::cmd;
+    use crate::test_support::temp_path;
+
+    // A directory with an executable `tool` and a `data` file that isn't executable.
+    struct Bin(PathBuf);
+
+    impl Bin {
+        fn new(name: &str) -> Self {
+            let dir = temp_path(name);
+            std::fs::create_dir_all(&dir).unwrap();
+            for (file, mode) in [("tool", 0o755), ("data", 0o644)] {
+                let path = dir.join(file);
+                std::fs::write(&path, "#!/bin/sh\necho fake\n").unwrap();
+                std::fs::set_permissions(&path, std::fs::Permissions::from_mode(mode)).unwrap();
+            }
+            Bin(dir)
+        }
+    }
+
+    impl Drop for Bin {
+        fn drop(&mut self) {
+            let _ = std::fs::remove_dir_all(&self.0);
+        }
+    }
+
+    #[test]
+    fn finds_executables_in_search_order() {
+        let (first, second) = (Bin::new("which-first"), Bin::new("which-second"));
+        let path = env::join_paths([&first.0, &second.0]).unwrap();
+        let found = which_in(OsStr::new("tool"), Some(&path));
+        assert_eq!(found, [first.0.join("tool"), second.0.join("tool")]);
+        assert!(which_in(OsStr::new("data"), Some(&path)).is_empty());
+        assert!(which_in(OsStr::new("tool"), Some(OsStr::new(""))).is_empty());
+        let direct = first.0.join("tool");
+        assert_eq!(which_in(direct.as_os_str(), None), [direct]);
+    }
+
+    #[test]
+    fn which_lists_the_directories_searched() {
+        assert!(which("sh").unwrap().is_absolute());
+        let message = which("definitely-not-a-program-xyz").err().unwrap().to_string();
+        assert!(message.starts_with(
+            r#"Program "definitely-not-a-program-xyz" not found on PATH (searched ["#
+        ));
+    }
+
+    #[test]
+    fn spawn_failures_say_that_the_program_is_not_on_path() {
+        let bin = Bin::new("which-hint");
+        let err = cmd("missing-tool").env("PATH", &bin.0).exec().err().unwrap();
+        let message = format!("{:#}", err);
+        assert!(
+            message.contains("program 'missing-tool' not found on PATH (searched 1 directories)"),
+            "{}",
+            message
+        );
+        let err = cmd("true").current_dir(temp_path("which-no-such-dir")).exec().err().unwrap();
+        assert!(format!("{:#}", err).contains("does not exist"), "{:#}", err);
+    }
+
+    #[test]
+    fn spawn_failures_suggest_similar_programs_and_keep_the_io_error() {
+        let bin = Bin::new("which-similar");
+        let err = cmd("tol").env("PATH", &bin.0).exec().err().unwrap();
+        let message = format!("{:#}", err);
+        assert!(message.contains("(searched 1 directories); did you mean 'tool'?"), "{}", message);
+        let io = err.chain().find_map(|e| e.downcast_ref::<io::Error>());
+        assert_eq!(io.map(io::Error::kind), Some(io::ErrorKind::NotFound));
+        let err = cmd("xyzzy").env("PATH", &bin.0).exec().err().unwrap();
+        assert!(!format!("{:#}", err).contains("did you mean"), "{:#}", err);
+    }
+
+    #[test]
+    fn spawn_failures_say_that_the_program_is_not_executable() {
+        let bin = Bin::new("which-denied");
+        for mut data in [cmd("data").env_("PATH", &bin.0), cmd(bin.0.join("data"))] {
+            let err = data.exec().err().unwrap();
+            let message = format!("{:#}", err);
+            assert!(message.contains("program '"), "{}", message);
+            assert!(message.contains("data' found at "), "{}", message);
+            assert!(message.contains(", but it is not executable"), "{}", message);
+            let io = err.chain().find_map(|e| e.downcast_ref::<io::Error>());
+            assert_eq!(io.map(io::Error::kind), Some(io::ErrorKind::PermissionDenied));
+        }
+    }
+
+    #[test]
+    fn validate_checks_programs_and_directories_without_spawning() {
+        let bin = Bin::new("which-validate");
+        cmd("tool").env_("PATH", &bin.0).validate().unwrap();
+        let message = cmd("missing-tool").env_("PATH", &bin.0).validate().unwrap_err().to_string();
+        assert!(message.starts_with("Invalid command (program = \"missing-tool\""), "{}", message);
+        assert!(message
+            .ends_with("): program 'missing-tool' not found on PATH (searched 1 directories)"));
+        let missing = temp_path("which-validate-missing");
+        let message = cmd("true").current_dir_(&missing).validate().unwrap_err().to_string();
+        assert!(message.contains(&format!("current directory {:?} does not exist", missing)));
+        let file = bin.0.join("data");
+        let message = cmd("true").current_dir_(&file).validate().unwrap_err().to_string();
+        assert!(message.contains("is not a directory"), "{}", message);
+        // Relative to the command's directory, not to the working directory of this process.
+        cmd("./tool").current_dir_(&bin.0).validate().unwrap();
+        assert!(cmd("./tool").validate().is_err());
+        assert!(cmd(bin.0.join("data")).validate().is_err());
+        let message = cmd("true").env_("A=B", "1").env_("NUL", "a\0b").validate().unwrap_err();
+        let message = message.to_string();
+        assert!(message.contains(r#"environment variable name "A=B" is invalid"#), "{}", message);
+        assert!(message.contains(r#"environment variable "NUL" has a NUL byte"#), "{}", message);
+    }
+
+    #[test]
+    fn validate_all_lists_every_problem() {
+        let bin = Bin::new("which-validate-all");
+        let commands = [
+            cmd("true"),
+            cmd("missing-a").env_("PATH", &bin.0),
+            cmd("missing-b").env_("PATH", &bin.0).current_dir_(bin.0.join("nope")),
+        ];
+        let message = validate_all(&commands).unwrap_err().to_string();
+        let lines: Vec<_> = message.lines().collect();
+        assert_eq!(lines[0], "2 of 3 commands are invalid:");
+        assert!(lines[1].starts_with("  [2] ") && lines[2].contains("'missing-a'"), "{}", message);
+        assert!(
+            lines[3].starts_with("  [3] ") && lines[4].contains("does not exist"),
+            "{}",
+            message
+        );
+        assert!(lines[5].contains("'missing-b'") && lines.len() == 6, "{}", message);
+        validate_all(&commands[..1]).unwrap();
+    }
+}
@@ -0,0 +1,384 @@
+use std::path::Path;
+use std::sync::OnceLock;
+
+// The local time zone, read from the time zone database the way the C library does: from `TZ`,
+// either a file under the zoneinfo directory or a POSIX rule such as `CET-1CEST,M3.5.0,M10.5.0/3`,
+// and from `/etc/localtime` when it is not set. Read once per process, so that every timestamp
+// only has to look its offset up.
+static LOCAL: OnceLock<Option<Zone>> = OnceLock::new();
+
+const ZONEINFO: &str = "/usr/share/zoneinfo";
+
+// The offset from UTC in seconds of local time at `secs` after the epoch, if the local time zone
+// could be read.
+pub(crate) fn local_offset(secs: i64) -> Option<i32> {
+    LOCAL.get_or_init(local_zone).as_ref().map(|zone| zone.offset(secs))
+}
+
+fn local_zone() -> Option<Zone> {
+    match std::env::var("TZ") {
+        Ok(tz) => {
+            let tz = tz.strip_prefix(':').unwrap_or(&tz);
+            if tz.is_empty() {
+                return Some(Zone::utc());
+            }
+            let path = Path::new(tz);
+            let file =
+                if path.is_absolute() { path.to_owned() } else { Path::new(ZONEINFO).join(tz) };
+            match std::fs::read(file) {
+                Ok(data) => parse_tzif(&data),
+                Err(_) => Rule::parse(tz).map(Zone::from),
+            }
+        }
+        Err(_) => parse_tzif(&std::fs::read("/etc/localtime").ok()?),
+    }
+}
+
+// The transitions of a TZif file (RFC 8536), as the time each offset starts at, and the rule of
+// its footer for the times after the last of them.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Zone {
+    initial: i32,
+    transitions: Vec<(i64, i32)>,
+    rule: Option<Rule>,
+}
+
+impl Zone {
+    fn utc() -> Self {
+        Zone { initial: 0, transitions: Vec::new(), rule: None }
+    }
+
+    pub(crate) fn offset(&self, secs: i64) -> i32 {
+        let after = self.transitions.partition_point(|&(at, _)| at <= secs);
+        match (&self.rule, after) {
+            (Some(rule), _) if after == self.transitions.len() => rule.offset(secs),
+            (_, 0) => self.initial,
+            _ => self.transitions[after - 1].1,
+        }
+    }
+}
+
+impl From<Rule> for Zone {
+    fn from(rule: Rule) -> Self {
+        Zone { initial: rule.std, transitions: Vec::new(), rule: Some(rule) }
+    }
+}
+
+pub(crate) fn parse_tzif(data: &[u8]) -> Option<Zone> {
+    let (version, counts) = tzif_header(data)?;
+    let block = &data[44..];
+    if version < b'2' {
+        return tzif_block(block, counts, 4, false);
+    }
+    // Version 2 and later repeat the data with 64-bit times after the version 1 data, followed
+    // by the footer.
+    let block = block.get(tzif_block_len(counts, 4)..)?;
+    let (_, counts) = tzif_header(block)?;
+    tzif_block(&block[44..], counts, 8, true)
+}
+
+// `isutcnt`, `isstdcnt`, `leapcnt`, `timecnt`, `typecnt` and `charcnt`.
+type Counts = [usize; 6];
+
+fn tzif_header(data: &[u8]) -> Option<(u8, Counts)> {
+    if data.len() < 44 || &data[..4] != b"TZif" {
+        return None;
+    }
+    let mut counts = [0; 6];
+    for (i, count) in counts.iter_mut().enumerate() {
+        let at = 20 + 4 * i;
+        *count = u32::from_be_bytes(data[at..at + 4].try_into().ok()?) as usize;
+    }
+    Some((data[4], counts))
+}
+
+fn tzif_block_len([isut, isstd, leap, time, types, chars]: Counts, time_size: usize) -> usize {
+    time * time_size + time + types * 6 + chars + leap * (time_size + 4) + isstd + isut
+}
+
+fn tzif_block(data: &[u8], counts: Counts, time_size: usize, footer: bool) -> Option<Zone> {
+    let [_, _, _, time, types, _] = counts;
+    let len = tzif_block_len(counts, time_size);
+    let block = data.get(..len)?;
+    let (times, rest) = block.split_at(time * time_size);
+    let (indices, rest) = rest.split_at(time);
+    let utoff = |index: usize| -> Option<i32> {
+        let info = rest.get(index * 6..index * 6 + 4)?;
+        Some(i32::from_be_bytes(info.try_into().ok()?))
+    };
+    if types == 0 {
+        return None;
+    }
+    let mut transitions = Vec::with_capacity(time);
+    for (i, &index) in indices.iter().enumerate() {
+        let at = &times[i * time_size..(i + 1) * time_size];
+        let at = match time_size {
+            4 => i64::from(i32::from_be_bytes(at.try_into().ok()?)),
+            _ => i64::from_be_bytes(at.try_into().ok()?),
+        };
+        transitions.push((at, utoff(usize::from(index))?));
+    }
+    // The footer is a POSIX rule between newlines, empty if there is none.
+    let mut rule = None;
+    if footer {
+        let footer = data[len..].strip_prefix(b"\n")?;
+        let end = footer.iter().position(|&b| b == b'\n')?;
+        rule = std::str::from_utf8(&footer[..end]).ok().and_then(Rule::parse);
+    }
+    Some(Zone { initial: utoff(0)?, transitions, rule })
+}
+
+// A POSIX `TZ` rule: the standard offset, and the daylight saving time offset with when it
+// starts and ends each year, all offsets east of UTC in seconds.
+#[derive(Debug, PartialEq)]
+pub(crate) struct Rule {
+    std: i32,
+    dst: Option<(i32, Date, Date)>,
+}
+
+// When a rule's daylight saving time starts or ends, as a day of the year and the local time of
+// day in seconds.
+#[derive(Debug, PartialEq, Clone, Copy)]
+enum Date {
+    // `Jn`: 1 to 365, never counting February 29.
+    Julian(u16, i32),
+    // `n`: 0 to 365, counting February 29.
+    Day(u16, i32),
+    // `Mm.w.d`: weekday `d` (0 is Sunday) of week `w` of month `m`, where week 5 is the last.
+    Weekday(u8, u8, u8, i32),
+}
+
+impl Rule {
+    pub(crate) fn parse(text: &str) -> Option<Rule> {
+        let mut rest = text;
+        name(&mut rest)?;
+        // POSIX offsets are west of UTC.
+        let std = -offset(&mut rest)?;
+        if rest.is_empty() {
+            return Some(Rule { std, dst: None });
+        }
+        name(&mut rest)?;
+        let dst =
+            if rest.starts_with(',') || rest.is_empty() { std + 3600 } else { -offset(&mut rest)? };
+        // Without dates, the United States rules that POSIX implementations default to.
+        let rest = match rest {
+            "" => ",M3.2.0,M11.1.0",
+            rest => rest,
+        };
+        let mut dates = rest.strip_prefix(',')?.split(',');
+        let start = date(dates.next()?)?;
+        let end = date(dates.next()?)?;
+        if dates.next().is_some() {
+            return None;
+        }
+        Some(Rule { std, dst: Some((dst, start, end)) })
+    }
+
+    fn offset(&self, secs: i64) -> i32 {
+        let Some((dst, start, end)) = self.dst else {
+            return self.std;
+        };
+        let (year, _, _) =
+            crate::timestamp::civil_from_days((secs + i64::from(self.std)).div_euclid(86400));
+        // The start is in standard time and the end in daylight saving time.
+        let start = start.utc(year, self.std);
+        let end = end.utc(year, dst);
+        let in_dst = if start < end {
+            start <= secs && secs < end
+        } else {
+            // The southern hemisphere, where daylight saving time spans the new year.
+            !(end <= secs && secs < start)
+        };
+        if in_dst {
+            dst
+        } else {
+            self.std
+        }
+    }
+}
+
+impl Date {
+    // The time it happens at in `year` when local time is `offset` east of UTC.
+    fn utc(self, year: i64, offset: i32) -> i64 {
+        let leap = year % 4 == 0 && (year % 100 != 0 || year % 400 == 0);
+        let jan1 = days_from_civil(year, 1, 1);
+        let (day, time) = match self {
+            Date::Julian(n, time) => {
+                let n = i64::from(n);
+                (jan1 + n - 1 + i64::from(leap && n >= 60), time)
+            }
+            Date::Day(n, time) => (jan1 + i64::from(n), time),
+            Date::Weekday(month, week, weekday, time) => {
+                let first = days_from_civil(year, u32::from(month), 1);
+                // 1970-01-01 was a Thursday.
+                let first_weekday = (first + 4).rem_euclid(7);
+                let mut day = first + (i64::from(weekday) - first_weekday).rem_euclid(7);
+                day += 7 * (i64::from(week) - 1);
+                let next_month = match month {
+                    12 => days_from_civil(year + 1, 1, 1),
+                    _ => days_from_civil(year, u32::from(month) + 1, 1),
+                };
+                while day >= next_month {
+                    day -= 7;
+                }
+                (day, time)
+            }
+        };
+        day * 86400 + i64::from(time) - i64::from(offset)
+    }
+}
+
+// A name such as `CET`, or `<+0530>` for one that isn't alphabetic.
+fn name(rest: &mut &str) -> Option<()> {
+    let len = match rest.strip_prefix('<') {
+        Some(quoted) => quoted.find('>')? + 2,
+        None => rest.find(|c: char| !c.is_ascii_alphabetic()).unwrap_or(rest.len()),
+    };
+    if len < 3 {
+        return None;
+    }
+    *rest = &rest[len..];
+    Some(())
+}
+
+// `[+-]hh[:mm[:ss]]`, in seconds.
+fn offset(rest: &mut &str) -> Option<i32> {
+    let (sign, unsigned) = match rest.as_bytes().first()? {
+        b'-' => (-1, &rest[1..]),
+        b'+' => (1, &rest[1..]),
+        _ => (1, *rest),
+    };
+    let len = unsigned.find(|c: char| !c.is_ascii_digit() && c != ':').unwrap_or(unsigned.len());
+    let mut secs = 0;
+    let mut scale = 3600;
+    for part in unsigned[..len].split(':') {
+        if part.is_empty() || part.len() > 3 || scale == 0 {
+            return None;
+        }
+        secs += part.parse::<i32>().ok()? * scale;
+        scale /= 60;
+    }
+    *rest = &unsigned[len..];
+    Some(sign * secs)
+}
+
+fn date(text: &str) -> Option<Date> {
+    let (day, time) = match text.split_once('/') {
+        Some((day, mut time)) => (day, offset(&mut time).filter(|_| time.is_empty())?),
+        None => (text, 7200),
+    };
+    if let Some(n) = day.strip_prefix('J') {
+        let n = n.parse().ok().filter(|n| (1..=365).contains(n))?;
+        return Some(Date::Julian(n, time));
+    }
+    if let Some(mwd) = day.strip_prefix('M') {
+        let mut parts = mwd.split('.').map(|part| part.parse::<u8>().ok());
+        let (month, week, weekday) = (parts.next()??, parts.next()??, parts.next()??);
+        let valid = (1..=12).contains(&month) && (1..=5).contains(&week) && weekday <= 6;
+        if !valid || parts.next().is_some() {
+            return None;
+        }
+        return Some(Date::Weekday(month, week, weekday, time));
+    }
+    let n = day.parse().ok().filter(|n| *n <= 365)?;
+    Some(Date::Day(n, time))
+}
+
+// Howard Hinnant's `days_from_civil`, the inverse of `civil_from_days` in `timestamp.rs`.
+fn days_from_civil(year: i64, month: u32, day: u32) -> i64 {
+    let year = if month <= 2 { year - 1 } else { year };
+    let era = year.div_euclid(400);
+    let yoe = year.rem_euclid(400);
+    let month = i64::from(month);
+    let doy = (153 * (if month > 2 { month - 3 } else { month + 9 }) + 2) / 5 + i64::from(day) - 1;
+    let doe = yoe * 365 + yoe / 4 - yoe / 100 + doy;
+    era * 146097 + doe - 719468
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{parse_tzif, Rule, Zone};
+
+    // 2024-01-15 and 2024-07-15, at noon UTC.
+    const WINTER: i64 = 1705320000;
+    const SUMMER: i64 = 1721044800;
+
+    fn offsets(zone: &Zone, times: &[i64]) -> Vec<i32> {
+        times.iter().map(|&secs| zone.offset(secs)).collect()
+    }
+
+    #[test]
+    fn rules() {
+        let cases: [(&str, [i32; 2]); 5] = [
+            ("UTC0", [0, 0]),
+            ("<+0530>-5:30", [19800, 19800]),
+            ("CET-1CEST,M3.5.0,M10.5.0/3", [3600, 7200]),
+            ("EST5EDT,M3.2.0,M11.1.0", [-18000, -14400]),
+            ("AEST-10AEDT,M10.1.0,M4.1.0/3", [39600, 36000]),
+        ];
+        for (text, expected) in cases {
+            let zone = Zone::from(Rule::parse(text).unwrap());
+            assert_eq!(offsets(&zone, &[WINTER, SUMMER]), expected, "{}", text);
+        }
+        for text in ["", "C-1", "CET-1CEST,M13.1.0,M10.5.0", "CET-1CEST,M3.5.0"] {
+            assert_eq!(Rule::parse(text), None, "{}", text);
+        }
+    }
+
+    #[test]
+    fn rule_transitions() {
+        let berlin = Zone::from(Rule::parse("CET-1CEST,M3.5.0,M10.5.0/3").unwrap());
+        // 2024-03-31 and 2024-10-27, at 01:00 UTC.
+        assert_eq!(offsets(&berlin, &[1711846799, 1711846800]), [3600, 7200]);
+        assert_eq!(offsets(&berlin, &[1729990799, 1729990800]), [7200, 3600]);
+        let sydney = Zone::from(Rule::parse("AEST-10AEDT,M10.1.0,M4.1.0/3").unwrap());
+        // 2024-04-06 at 16:00 UTC.
+        assert_eq!(offsets(&sydney, &[1712419199, 1712419200]), [39600, 36000]);
+    }
+
+    fn tzif(version: u8, transitions: &[(i64, u8)], types: &[i32], footer: &str) -> Vec<u8> {
+        let header = |time, types| {
+            let mut header = b"TZif".to_vec();
+            header.push(version);
+            header.extend([0; 15]);
+            for count in [0, 0, 0, time, types, 1u32] {
+                header.extend(count.to_be_bytes());
+            }
+            header
+        };
+        // An empty version 1 block, as written by `zic -b slim`.
+        let mut data = header(0, 1);
+        data.extend([0; 7]);
+        data.extend(header(transitions.len() as u32, types.len() as u32));
+        data.extend(transitions.iter().flat_map(|(at, _)| at.to_be_bytes()));
+        data.extend(transitions.iter().map(|(_, index)| index));
+        data.extend(types.iter().flat_map(|utoff| [&utoff.to_be_bytes()[..], &[0, 0]].concat()));
+        data.push(0);
+        data.extend(format!("\n{}\n", footer).bytes());
+        data
+    }
+
+    #[test]
+    fn tzif_transitions_then_footer() {
+        // Berlin from 2024-03-31, then its rule.
+        let data = tzif(b'2', &[(1711846800, 1)], &[3600, 7200], "CET-1CEST,M3.5.0,M10.5.0/3");
+        let zone = parse_tzif(&data).unwrap();
+        assert_eq!(
+            offsets(&zone, &[WINTER, 1711846800, SUMMER, WINTER + 366 * 86400]),
+            [3600, 7200, 7200, 3600]
+        );
+        let fixed = parse_tzif(&tzif(b'3', &[], &[-36000], "")).unwrap();
+        assert_eq!(offsets(&fixed, &[WINTER, SUMMER]), [-36000, -36000]);
+        assert_eq!(parse_tzif(&data[..data.len() - 20]), None);
+        assert_eq!(parse_tzif(b"not a time zone"), None);
+    }
+
+    #[test]
+    fn system_database() {
+        let Ok(data) = std::fs::read("/usr/share/zoneinfo/America/New_York") else {
+            return;
+        };
+        let zone = parse_tzif(&data).unwrap();
+        assert_eq!(offsets(&zone, &[WINTER, SUMMER]), [-18000, -14400]);
+    }
+}
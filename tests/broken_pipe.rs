@@ -0,0 +1,81 @@
+// A broken pipe turns decorations off for the rest of the process, so it is tested in a binary of
+// its own.
+
+use std::io::{self, Write};
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+
+use cmd_utils::{
+    set_decoration_writer, shell, take_decoration_writer, CommandExt, TermColorStandardStreamExt,
+};
+use termcolor::{Color, ColorSpec, WriteColor};
+
+// Fails every write and color change with `kind`, counting the attempts.
+struct Failing {
+    kind: io::ErrorKind,
+    attempts: Arc<AtomicUsize>,
+}
+
+impl Failing {
+    fn fail(&self) -> io::Error {
+        self.attempts.fetch_add(1, Ordering::SeqCst);
+        io::Error::new(self.kind, "failing writer")
+    }
+}
+
+impl Write for Failing {
+    fn write(&mut self, _: &[u8]) -> io::Result<usize> {
+        Err(self.fail())
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        Err(self.fail())
+    }
+}
+
+impl WriteColor for Failing {
+    fn supports_color(&self) -> bool {
+        true
+    }
+
+    fn set_color(&mut self, _: &ColorSpec) -> io::Result<()> {
+        Err(self.fail())
+    }
+
+    fn reset(&mut self) -> io::Result<()> {
+        Err(self.fail())
+    }
+}
+
+fn failing(kind: io::ErrorKind) -> Arc<AtomicUsize> {
+    let attempts = Arc::new(AtomicUsize::new(0));
+    set_decoration_writer(Failing { kind, attempts: Arc::clone(&attempts) });
+    attempts
+}
+
+#[test]
+fn decoration_errors_do_not_panic() {
+    let mut red = ColorSpec::new();
+    red.set_fg(Some(Color::Red));
+    let attempts = Arc::new(AtomicUsize::new(0));
+    let mut writer = Failing { kind: io::ErrorKind::Other, attempts: Arc::clone(&attempts) };
+    assert_eq!(writer.with_color(&red, |_| 7), 7);
+    assert_eq!(attempts.load(Ordering::SeqCst), 2);
+
+    // Other errors fail the command before it runs.
+    let attempts = failing(io::ErrorKind::Other);
+    let message = format!("{:#}", shell("exit 0").exec().err().unwrap());
+    assert!(message.contains("failing writer"), "{}", message);
+    assert!(attempts.load(Ordering::SeqCst) > 0);
+    take_decoration_writer::<Failing>().unwrap();
+
+    // A broken pipe lets commands run, and nothing is written after it.
+    let attempts = failing(io::ErrorKind::BrokenPipe);
+    shell("exit 0").exec().unwrap();
+    let after_first = attempts.load(Ordering::SeqCst);
+    assert!(after_first > 0);
+    assert_eq!(shell("echo ran").exec_stdout_string().unwrap().stdout, "ran\n");
+    shell("exit 0").exec().unwrap();
+    shell("exit 3").exec().unwrap_err();
+    assert_eq!(attempts.load(Ordering::SeqCst), after_first);
+}
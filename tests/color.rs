@@ -0,0 +1,68 @@
+// Color depends on where stderr goes, so the banners are printed by this test binary run again
+// as a child with its stderr piped, which is how it runs under CI no matter the terminal.
+
+use std::process::Command;
+
+use cmd_utils::{cmd, CommandExt};
+
+const CHILD: &str = "CMD_UTILS_COLOR_TEST_CHILD";
+
+// The stderr of `test`, rerun as a child with `envs`, which prints a banner there.
+fn banner_of(test: &str, envs: &[(&str, &str)]) -> String {
+    let mut child = Command::new(std::env::current_exe().unwrap());
+    child.args([test, "--exact", "--nocapture", "--test-threads=1"]).env(CHILD, "1");
+    child.env_remove("NO_COLOR").env_remove("CLICOLOR_FORCE").envs(envs.iter().copied());
+    let output = child.output().unwrap();
+    assert!(output.status.success(), "{:?}", output);
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(stderr.contains("color-test-banner"), "{}", stderr);
+    stderr
+}
+
+fn print_banner() -> bool {
+    if std::env::var_os(CHILD).is_none() {
+        return false;
+    }
+    cmd("echo").arg("color-test-banner").exec().unwrap();
+    true
+}
+
+#[test]
+fn banners_through_a_pipe_have_no_escapes() {
+    if print_banner() {
+        return;
+    }
+    let stderr = banner_of("banners_through_a_pipe_have_no_escapes", &[]);
+    assert!(!stderr.contains('\x1b'), "{:?}", stderr);
+}
+
+#[test]
+fn clicolor_force_colors_banners_through_a_pipe() {
+    if print_banner() {
+        return;
+    }
+    let test = "clicolor_force_colors_banners_through_a_pipe";
+    assert!(banner_of(test, &[("CLICOLOR_FORCE", "1")]).contains("\x1b[46m"));
+    let stderr = banner_of(test, &[("CLICOLOR_FORCE", "1"), ("NO_COLOR", "1")]);
+    assert!(!stderr.contains('\x1b'), "{:?}", stderr);
+}
+
+#[test]
+fn retried_stderr_is_dimmed() {
+    if std::env::var_os(CHILD).is_some() {
+        use cmd_utils::{Backoff, RetryPolicy};
+        let policy = RetryPolicy::new(2)
+            .backoff(Backoff::Fixed(std::time::Duration::ZERO))
+            .on_stderr_containing(&["429"]);
+        let script = "echo 'color-test-banner 429' >&2; exit 1";
+        assert!(cmd_utils::shell(script).exec_retry(policy).is_err());
+        return;
+    }
+    let stderr = banner_of("retried_stderr_is_dimmed", &[("CLICOLOR_FORCE", "1")]);
+    let attempts: Vec<&str> =
+        stderr.lines().filter(|l| l.contains("color-test-banner 429")).collect();
+    let output: Vec<&str> = attempts.into_iter().filter(|l| !l.contains(" sh -c ")).collect();
+    assert_eq!(output.len(), 2, "{:?}", stderr);
+    assert!(output[0].starts_with("\x1b[0m\x1b[2m"), "{:?}", output[0]);
+    assert!(!output[1].contains("\x1b[2m"), "{:?}", output[1]);
+}
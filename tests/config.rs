@@ -0,0 +1,65 @@
+// `Config` changes process-wide settings, so it is tested in a binary of its own, where it can't
+// change what the commands of other tests show.
+
+use std::sync::{Mutex, MutexGuard, PoisonError};
+use std::time::Duration;
+
+use cmd_utils::{
+    cmd, is_dry_run, set_decoration_writer, set_dry_run, set_redacted_env_keys, shell,
+    take_decoration_writer, CommandExt, Config,
+};
+use termcolor::Buffer;
+
+static SERIAL: Mutex<()> = Mutex::new(());
+
+// Held by each test, since a guard restores the settings of the other test too.
+fn serial() -> MutexGuard<'static, ()> {
+    SERIAL.lock().unwrap_or_else(PoisonError::into_inner)
+}
+
+fn description() -> String {
+    let mut token = cmd("true");
+    token.env("CMD_UTILS_CONFIG_PIN", "hunter2");
+    token.description().to_string()
+}
+
+#[test]
+fn scoped_configs_restore_what_was_there_before() {
+    let _serial = serial();
+    assert!(description().contains("hunter2"));
+    let outer = Config::new().redact_env(["CMD_UTILS_CONFIG_PIN"]).scoped();
+    assert!(!description().contains("hunter2"), "{}", description());
+    {
+        let _inner = Config::new().redact_env(Vec::<String>::new()).dry_run(true).scoped();
+        assert!(description().contains("hunter2") && is_dry_run());
+        // Changed by hand in the meantime, and still restored by the guard.
+        set_dry_run(false);
+        set_redacted_env_keys(["OTHER"]);
+    }
+    assert!(!description().contains("hunter2") && !is_dry_run());
+    drop(outer);
+    assert!(description().contains("hunter2") && !is_dry_run());
+
+    Config::new().redact_env(["CMD_UTILS_CONFIG_PIN"]).apply();
+    assert!(!description().contains("hunter2"));
+    // Settings a config doesn't give are left alone.
+    Config::new().dry_run(false).apply();
+    assert!(!description().contains("hunter2"));
+    drop(Config::new().scoped());
+    assert!(!description().contains("hunter2"));
+}
+
+#[test]
+fn per_command_thresholds_beat_the_config() {
+    let _serial = serial();
+    let _config = Config::new().slow_command_warning(Some(Duration::from_secs(60))).scoped();
+    set_decoration_writer(Buffer::no_color());
+    shell("sleep 0.5").label("slow-own").warn_after(Duration::from_millis(200)).exec().unwrap();
+    shell("sleep 0.5").label("slow-config").exec().unwrap();
+    let text = String::from_utf8(take_decoration_writer::<Buffer>().unwrap().into_inner()).unwrap();
+    let warnings = |label: &str| {
+        let tag = format!("[{}]", label);
+        text.lines().filter(|line| line.contains(&tag) && line.contains("still running")).count()
+    };
+    assert_eq!((warnings("slow-own"), warnings("slow-config")), (2, 0), "{}", text);
+}
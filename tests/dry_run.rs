@@ -0,0 +1,29 @@
+// Dry-run mode is process-wide, so it is tested in a binary of its own, where it can't stop the
+// commands of other tests from running.
+
+use std::path::PathBuf;
+
+use cmd_utils::{set_decoration_writer, set_dry_run, shell, take_decoration_writer, CommandExt};
+use termcolor::Buffer;
+
+#[test]
+fn dry_run_prints_commands_without_running_them() {
+    let marker = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("dry-run-marker");
+    let _ = std::fs::remove_file(&marker);
+    let script = format!("touch '{}'", marker.display());
+    set_decoration_writer(Buffer::no_color());
+    set_dry_run(true);
+    shell(&script).exec().unwrap();
+    let out = shell(&script).exec_stdout_string().unwrap();
+    let background = shell(&script).exec_background();
+    set_dry_run(false);
+    let text = String::from_utf8(take_decoration_writer::<Buffer>().unwrap().into_inner()).unwrap();
+    assert!(!marker.exists());
+    assert!(out.status.success() && out.stdout.is_empty() && out.stderr.is_empty());
+    let message = format!("{:#}", background.err().unwrap());
+    assert!(message.contains("Cannot start a background process in dry-run mode"), "{}", message);
+    assert_eq!(text.matches("touch").count(), 3, "{}", text);
+    assert_eq!(text.matches(" DRY RUN ").count(), 3, "{}", text);
+    shell(&script).exec().unwrap();
+    assert!(marker.exists());
+}
@@ -0,0 +1,34 @@
+// The interrupt handler is installed for the whole process, so it is tested in a binary of its
+// own. The child sends the Ctrl-C itself: SIGINT to this process, then to itself once the
+// handler has had time to note it.
+#![cfg(all(unix, feature = "ctrlc"))]
+
+use cmd_utils::{
+    install_interrupt_handler, set_decoration_writer, shell, take_decoration_writer, CmdError,
+    CommandExt,
+};
+use termcolor::Buffer;
+
+const INTERRUPTING: &str = "kill -INT $PPID; sleep 0.5; kill -INT $$";
+
+#[test]
+fn interrupted_children_are_reported_as_cancelled() {
+    install_interrupt_handler().unwrap();
+    set_decoration_writer(Buffer::ansi());
+    let err = shell(INTERRUPTING).exec().unwrap_err();
+    let text = String::from_utf8(take_decoration_writer::<Buffer>().unwrap().into_inner()).unwrap();
+    assert!(matches!(err.downcast_ref::<CmdError>(), Some(CmdError::Interrupted { .. })));
+    assert!(format!("{:#}", err).starts_with("Process was interrupted ("), "{:#}", err);
+    assert_eq!(err.downcast_ref::<CmdError>().unwrap().signal(), Some(2));
+    let end = text.lines().find(|line| line.contains(" END OUTPUT ")).unwrap();
+    assert!(end.contains("\x1b[43m") && !end.contains("\x1b[41m"), "{:?}", end);
+
+    let err = shell(INTERRUPTING).exec_stdout_string().err().unwrap();
+    assert!(matches!(err.downcast_ref::<CmdError>(), Some(CmdError::Interrupted { .. })));
+
+    // Without a Ctrl-C during the command, the same exit is a plain failure.
+    let err = shell("exit 130").exec().unwrap_err();
+    assert!(matches!(err.downcast_ref::<CmdError>(), Some(CmdError::UnsuccessfulExit { .. })));
+    let err = shell("kill -INT $$").exec_stdout_string().err().unwrap();
+    assert!(matches!(err.downcast_ref::<CmdError>(), Some(CmdError::UnsuccessfulExit { .. })));
+}
@@ -0,0 +1,37 @@
+// Forwarded output goes to the process's own stdout and stderr, so this test binary runs again as
+// a child with both piped to see the tags on every line.
+
+use std::process::Command;
+
+use cmd_utils::{set_color_choice, shell, CommandExt};
+use termcolor::ColorChoice;
+
+const CHILD: &str = "CMD_UTILS_LABELS_TEST_CHILD";
+
+#[test]
+fn forwarded_lines_are_tagged() {
+    if std::env::var_os(CHILD).is_some() {
+        set_color_choice(ColorChoice::Never);
+        let script = "echo one; echo two >&2; printf 'three\\nfour'; sleep 0.1; echo ' more'";
+        let output = shell(script).label("backend").exec_tee().unwrap();
+        assert_eq!(output.stdout, "one\nthree\nfour more\n");
+        shell("echo plain").exec_tee().unwrap();
+        return;
+    }
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args(["forwarded_lines_are_tagged", "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD, "1")
+        .output()
+        .unwrap();
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert!(output.status.success(), "{}\n{}", stdout, stderr);
+    // The harness starts its own line for the test before the forwarded output.
+    let stdout = stdout.replace("test forwarded_lines_are_tagged ... ", "\n");
+    let forwarded: Vec<&str> = stdout.lines().collect();
+    let expected = ["[backend] one", "[backend] three", "[backend] four more", "plain"];
+    assert!(forwarded.windows(4).any(|lines| lines == expected), "{}", stdout);
+    assert!(stderr.lines().any(|line| line == "[backend] two"), "{}", stderr);
+    let banners = stderr.lines().filter(|line| line.starts_with("[backend] ")).count();
+    assert_eq!(banners, 3, "{}", stderr);
+}
@@ -0,0 +1,64 @@
+// `pushd` changes the current directory of the whole process, so it is tested in a binary of its
+// own, with everything in one test.
+
+use std::env;
+use std::path::PathBuf;
+
+use cmd_utils::{cmd, pushd, set_decoration_writer, take_decoration_writer, CommandExt};
+use termcolor::Buffer;
+
+#[test]
+fn pushd_nests_and_reports_a_deleted_directory() {
+    let start = env::current_dir().unwrap();
+    let root = PathBuf::from(env!("CARGO_TARGET_TMPDIR")).join("pushd-test");
+    let _ = std::fs::remove_dir_all(&root);
+    std::fs::create_dir_all(root.join("outer/inner")).unwrap();
+    let root = root.canonicalize().unwrap();
+
+    let outer = pushd(root.join("outer")).unwrap();
+    assert_eq!(outer.previous(), start);
+    let inner = pushd("inner").unwrap();
+    assert_eq!(env::current_dir().unwrap(), root.join("outer/inner"));
+    set_decoration_writer(Buffer::no_color());
+    cmd("true").exec().unwrap();
+    let banner = take_decoration_writer::<Buffer>().unwrap().into_inner();
+    let banner = String::from_utf8(banner).unwrap();
+    assert!(banner.starts_with(&format!("{} true\n", root.join("outer/inner").display())));
+    drop(inner);
+    assert_eq!(env::current_dir().unwrap(), root.join("outer"));
+    outer.restore().unwrap();
+    assert_eq!(env::current_dir().unwrap(), start);
+
+    let err = pushd(root.join("missing")).err().unwrap();
+    let message = format!("{:#}", err);
+    let expected = format!("from {} to {}", start.display(), root.join("missing").display());
+    assert!(message.contains(&expected), "{}", message);
+    assert_eq!(env::current_dir().unwrap(), start);
+
+    // The directory to go back to is deleted while inside the guard.
+    let outer = pushd(root.join("outer")).unwrap();
+    let inner = pushd("inner").unwrap();
+    std::fs::remove_dir_all(root.join("outer")).unwrap();
+    let message = format!("{:#}", inner.restore().unwrap_err());
+    let expected = format!(
+        "Failed to change the current directory back from {} to {}",
+        root.join("outer/inner").display(),
+        root.join("outer").display()
+    );
+    assert!(message.starts_with(&expected), "{}", message);
+    outer.restore().unwrap();
+    assert_eq!(env::current_dir().unwrap(), start);
+
+    // Dropping the guard instead only warns.
+    std::fs::create_dir_all(root.join("outer/inner")).unwrap();
+    let outer = pushd(root.join("outer")).unwrap();
+    let inner = pushd("inner").unwrap();
+    std::fs::remove_dir_all(root.join("outer")).unwrap();
+    set_decoration_writer(Buffer::no_color());
+    drop(inner);
+    let warning = take_decoration_writer::<Buffer>().unwrap().into_inner();
+    let warning = String::from_utf8(warning).unwrap();
+    assert!(warning.starts_with(&format!("cmd_utils: warning: {}", expected)), "{}", warning);
+    drop(outer);
+    assert_eq!(env::current_dir().unwrap(), start);
+}
@@ -0,0 +1,64 @@
+// `exec_replace` and `exit_with_status` end the process that calls them, so this test binary runs
+// again as a child to call them.
+#![cfg(unix)]
+
+use std::process::{Command, Output};
+
+use cmd_utils::{cmd, exit_with_status, set_color_choice, shell, CommandExt};
+use termcolor::ColorChoice;
+
+const CHILD: &str = "CMD_UTILS_REPLACE_TEST_CHILD";
+
+fn run_child(test: &str) -> Output {
+    Command::new(std::env::current_exe().unwrap())
+        .args([test, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD, "1")
+        .output()
+        .unwrap()
+}
+
+#[test]
+fn exec_replace_becomes_the_command() {
+    if std::env::var_os(CHILD).is_some() {
+        set_color_choice(ColorChoice::Never);
+        let mut script = shell("echo \"replaced $$ $REPLACE_VAR\"; exit 7");
+        script.env("REPLACE_VAR", "kept");
+        println!("pid {}", std::process::id());
+        let err = script.exec_replace();
+        panic!("exec_replace returned: {:#}", err);
+    }
+    let output = run_child("exec_replace_becomes_the_command");
+    let stdout = String::from_utf8(output.stdout).unwrap();
+    let stderr = String::from_utf8(output.stderr).unwrap();
+    assert_eq!(output.status.code(), Some(7), "{}\n{}", stdout, stderr);
+    // The shell has the pid of the test process it replaced.
+    let pid = stdout.split("pid ").nth(1).unwrap().lines().next().unwrap();
+    assert!(stdout.contains(&format!("replaced {} kept\n", pid)), "{}", stdout);
+    assert!(stderr.contains("sh -c "), "{}", stderr);
+}
+
+#[test]
+fn exec_replace_fails_with_the_description() {
+    let err = cmd("cmd-utils-no-such-program").arg_("replace-arg").exec_replace();
+    let message = format!("{:#}", err);
+    assert!(message.starts_with("Failed to execute command (program = "), "{}", message);
+    assert!(message.contains(r#"args = ["replace-arg"]"#), "{}", message);
+}
+
+#[test]
+fn exit_with_status_maps_signals() {
+    if std::env::var_os(CHILD).is_some() {
+        let killed = shell("kill -s TERM $$").status().unwrap();
+        exit_with_status(killed);
+    }
+    let output = run_child("exit_with_status_maps_signals");
+    assert_eq!(output.status.code(), Some(128 + 15));
+}
+
+#[test]
+fn exit_with_status_keeps_exit_codes() {
+    if std::env::var_os(CHILD).is_some() {
+        exit_with_status(shell("exit 42").status().unwrap());
+    }
+    assert_eq!(run_child("exit_with_status_keeps_exit_codes").status.code(), Some(42));
+}
@@ -0,0 +1,72 @@
+// `scoped_env` changes the environment of the whole process, so it is tested in a binary of its
+// own, with everything in one test.
+
+use std::env;
+use std::ffi::OsString;
+
+use cmd_utils::{
+    cmd, scoped_env, set_decoration_writer, set_show_scoped_env, shell, take_decoration_writer,
+    CommandExt,
+};
+use termcolor::Buffer;
+
+fn set(key: &str, value: &str) -> (OsString, Option<OsString>) {
+    (key.into(), Some(value.into()))
+}
+
+fn child_sees(key: &str) -> String {
+    shell(format!("printf %s \"${{{}-unset}}\"", key)).exec_stdout_string().unwrap().stdout
+}
+
+#[test]
+fn scoped_env_sets_removes_restores_and_shows_settings() {
+    env::remove_var("SCOPED_NEW");
+    env::set_var("SCOPED_EXISTING", "old");
+    env::set_var("SCOPED_REMOVED", "kept");
+
+    let guard = scoped_env([
+        set("SCOPED_NEW", "new"),
+        set("SCOPED_EXISTING", "override"),
+        ("SCOPED_REMOVED".into(), None),
+    ]);
+    assert_eq!(child_sees("SCOPED_NEW"), "new");
+    assert_eq!(child_sees("SCOPED_EXISTING"), "override");
+    assert_eq!(child_sees("SCOPED_REMOVED"), "unset");
+    let previous: Vec<_> = guard.previous().collect();
+    assert_eq!(previous.len(), 3);
+    assert_eq!(previous[0], ("SCOPED_NEW".as_ref(), None));
+    assert_eq!(previous[1], ("SCOPED_EXISTING".as_ref(), Some("old".as_ref())));
+    assert_eq!(previous[2], ("SCOPED_REMOVED".as_ref(), Some("kept".as_ref())));
+    drop(guard);
+    assert_eq!(env::var_os("SCOPED_NEW"), None);
+    assert_eq!(env::var("SCOPED_EXISTING").unwrap(), "old");
+    assert_eq!(env::var("SCOPED_REMOVED").unwrap(), "kept");
+
+    // The same variable set twice in one guard goes back to the value from before the first.
+    let guard = scoped_env([set("SCOPED_EXISTING", "a"), set("SCOPED_EXISTING", "b")]);
+    assert_eq!(child_sees("SCOPED_EXISTING"), "b");
+    drop(guard);
+    assert_eq!(env::var("SCOPED_EXISTING").unwrap(), "old");
+
+    // Nested guards dropped in order.
+    let outer = scoped_env([set("SCOPED_EXISTING", "outer")]);
+    let inner = scoped_env([set("SCOPED_EXISTING", "inner"), ("SCOPED_NEW".into(), None)]);
+    assert_eq!(child_sees("SCOPED_EXISTING"), "inner");
+    drop(inner);
+    assert_eq!(child_sees("SCOPED_EXISTING"), "outer");
+    drop(outer);
+    assert_eq!(env::var("SCOPED_EXISTING").unwrap(), "old");
+
+    // Banners show the active settings once asked to.
+    let guard = scoped_env([set("SCOPED_FLAGS", "-D warnings"), ("SCOPED_HOME".into(), None)]);
+    set_decoration_writer(Buffer::no_color());
+    cmd("true").exec().unwrap();
+    set_show_scoped_env(true);
+    cmd("true").exec().unwrap();
+    set_show_scoped_env(false);
+    let text = String::from_utf8(take_decoration_writer::<Buffer>().unwrap().into_inner()).unwrap();
+    drop(guard);
+    assert_eq!(text.matches(" true\n").count(), 1, "{}", text);
+    let expected = " true [scoped env: SCOPED_FLAGS='-D warnings', SCOPED_HOME unset]\n";
+    assert_eq!(text.matches(expected).count(), 1, "{}", text);
+}
@@ -0,0 +1,67 @@
+// `exec_silent_unless_failure` replays output on the process's own stdout and stderr, so this
+// test binary runs again as a child with both piped to see exactly what it writes.
+
+use std::process::Command;
+
+use cmd_utils::{set_color_choice, shell, CommandExt};
+use termcolor::ColorChoice;
+
+const CHILD: &str = "CMD_UTILS_SILENT_TEST_CHILD";
+
+// The stdout and stderr of the child between the markers it prints around the commands.
+fn run_child(test: &str) -> (String, String) {
+    let output = Command::new(std::env::current_exe().unwrap())
+        .args([test, "--exact", "--nocapture", "--test-threads=1"])
+        .env(CHILD, "1")
+        .output()
+        .unwrap();
+    let between = |bytes: Vec<u8>| {
+        let text = String::from_utf8(bytes).unwrap();
+        let start = text.find("<<<\n").unwrap_or_else(|| panic!("{}", text)) + 4;
+        text[start..text.find(">>>\n").unwrap()].to_owned()
+    };
+    assert!(output.status.success(), "{}", String::from_utf8_lossy(&output.stderr));
+    (between(output.stdout), between(output.stderr))
+}
+
+fn markers(f: impl FnOnce()) {
+    set_color_choice(ColorChoice::Never);
+    println!("<<<");
+    eprintln!("<<<");
+    f();
+    println!(">>>");
+    eprintln!(">>>");
+}
+
+#[test]
+fn successes_write_nothing() {
+    if std::env::var_os(CHILD).is_some() {
+        markers(|| {
+            let script = "seq 1000; echo 'progress' >&2";
+            shell(script).label("silent").exec_silent_unless_failure().unwrap();
+            shell("true").exec_silent_unless_failure().unwrap();
+        });
+        return;
+    }
+    assert_eq!(run_child("successes_write_nothing"), (String::new(), String::new()));
+}
+
+#[test]
+fn failures_replay_everything() {
+    if std::env::var_os(CHILD).is_some() {
+        markers(|| {
+            let script = "seq 3; printf 'no newline'; printf 'warning\\nfatal: boom' >&2; exit 4";
+            let err = shell(script).exec_silent_unless_failure().unwrap_err();
+            assert!(format!("{:#}", err).contains("exit code 4"), "{:#}", err);
+        });
+        return;
+    }
+    let (stdout, stderr) = run_child("failures_replay_everything");
+    assert_eq!(stdout, "1\n2\n3\nno newline\n");
+    let lines: Vec<&str> = stderr.lines().collect();
+    assert!(lines[0].ends_with(" sh -c 'seq 3; printf '\\''no newline'\\''; printf '\\''warning\\nfatal: boom'\\'' >&2; exit 4'"), "{}", stderr);
+    assert_eq!(&lines[1..3], ["warning", "fatal: boom"], "{}", stderr);
+    assert!(lines[3].starts_with(" END OUTPUT ("), "{}", stderr);
+    assert!(lines[3].ends_with(") "), "{}", stderr);
+    assert_eq!(lines.len(), 4, "{}", stderr);
+}
@@ -0,0 +1,45 @@
+// Statistics are collected for every command of the process, so they are tested in a binary of
+// their own, where no other test runs commands.
+
+use std::time::Duration;
+
+use cmd_utils::{
+    cmd, reset_stats, set_decoration_writer, set_stats_enabled, shell, stats,
+    take_decoration_writer, CommandExt,
+};
+use termcolor::Buffer;
+
+#[test]
+fn stats_add_up_the_commands_run() {
+    set_decoration_writer(Buffer::no_color());
+    shell("true").exec().unwrap();
+    set_stats_enabled(true);
+    shell("sleep 0.2").exec().unwrap();
+    shell("echo captured").exec_stdout_string().unwrap();
+    assert!(shell("exit 4").exec().is_err());
+    assert!(cmd("cmd-utils-no-such-program").exec().is_err());
+    let collected = stats();
+    set_stats_enabled(false);
+    shell("true").exec().unwrap();
+    assert_eq!((collected.commands, collected.failed), (4, 2));
+    assert!(collected.total_duration >= Duration::from_millis(200));
+    assert_eq!(collected.slowest[0].description, "sh -c 'sleep 0.2'");
+    let codes: Vec<_> = collected.failures.iter().map(|stat| stat.exit_code).collect();
+    assert_eq!(codes, [Some(4), None]);
+    assert_eq!(collected.failures[1].command.program, "cmd-utils-no-such-program");
+
+    take_decoration_writer::<Buffer>();
+    set_decoration_writer(Buffer::no_color());
+    collected.print_summary();
+    let text = String::from_utf8(take_decoration_writer::<Buffer>().unwrap().into_inner()).unwrap();
+    let lines: Vec<&str> = text.lines().collect();
+    assert!(lines[0].starts_with("ran 4 commands, 2 failed, total child time "), "{}", text);
+    assert_eq!(lines[1], "slowest:");
+    assert!(lines[2].ends_with("  sh -c 'sleep 0.2'"), "{}", text);
+    assert_eq!(lines[6], "failed:", "{}", text);
+    assert_eq!(lines[7], "  exit code 4  sh -c 'exit 4'");
+    assert_eq!(lines[8], "  did not finish  cmd-utils-no-such-program");
+
+    reset_stats();
+    assert_eq!((stats().commands, stats().slowest.len()), (0, 0));
+}